@@ -0,0 +1,920 @@
+// Creates and extracts archives of many files.
+// Author: Will Morris
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use crate::codec::block::{block_dictionary, block_kind, BlockKind, compress_block, decompress_block, Method, read_index};
+use crate::diagnostics::report::Report;
+use crate::file::archive::{Archive, ChunkHash, Details, Extents, hash_chunk, Link, LinkKind, Member, Owner};
+use crate::file::blocks::split_blocks;
+use crate::file::bytestream::ByteStream;
+use crate::file::checkpoint::fingerprint;
+use crate::file::chunking::content_defined_chunks;
+use crate::file::names::{link_target, member_name, member_path, target_dirs, target_path};
+use crate::file::zip::{is_zip, read_contents, read_entries};
+use crate::ordering::dictionary::Dictionary;
+use crate::system::deadline::expired;
+use crate::system::links::{file_id, symlink};
+use crate::system::owners::{chown, file_owner, group_id, group_name, user_id, user_name};
+use crate::system::sparse::data_extents;
+use crate::system::throttle::Throttled;
+use crate::system::workers::run_all;
+use crate::system::xattrs::{read_xattrs, write_xattr};
+
+// Average size of the chunks that members are split into.
+// Smaller chunks find more duplicates, but each one pays for its own frequency table.
+const ARCHIVE_CHUNK_AVERAGE: usize = 1 << 16;
+
+// What archiving does with symbolic links.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Symlinks {
+    // Archive what they point at, as if it were there instead of the link.
+    Follow,
+    // Archive the links themselves, to be made again on extraction.
+    Preserve,
+}
+
+// What archiving records, and extraction restores, besides contents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ArchiveOptions {
+    pub(crate) symlinks: Symlinks,
+    // Whether extended attributes, and the ACLs among them, are kept.
+    pub(crate) xattrs: bool,
+    // Whether who owned each file is kept. Only root can give them back.
+    pub(crate) owners: bool,
+    // When creating, the time by which the archive must be finished, with whatever files it has by then.
+    pub(crate) deadline: Option<Instant>,
+    // When creating, the most bytes a second files may be read at.
+    pub(crate) rate_limit: Option<u64>,
+    // When creating, how many files may be read, and chunks compressed, at once.
+    pub(crate) threads: usize,
+}
+
+impl ArchiveOptions {
+    pub(crate) fn new() -> Self {
+        ArchiveOptions { symlinks: Symlinks::Follow, xattrs: false, owners: false, deadline: None, rate_limit: None, threads: 1 }
+    }
+}
+
+// Archive every file under paths, recursing into directories.
+// No chunk is ever larger than max_chunk bytes, and chunks are compressed by method.
+// Files are read, and chunks compressed, on several threads at once, but the archive holds the same things however many there are.
+// Returns the archive, and how many files and links were left out of it because its deadline passed.
+pub(crate) fn create_archive(paths: &[String], max_chunk: usize, method: &Method, options: &ArchiveOptions,
+                             report: &mut Report) -> Result<(Vec<u8>, usize), String> {
+    let (files, links) = gather(paths, options.symlinks)?;
+    let total = files.len() + links.len();
+
+    // Once time is up, nothing more is read or compressed, and what already has been is archived.
+    // Each file is read through a throttle of its own, so with a rate limit, they're read one at a time.
+    let readers = match options.rate_limit {
+        Some(_) => { 1 }
+        None => { options.threads }
+    };
+    let read = run_all(&files, readers, report, |file, report| {
+        if expired(options.deadline) {
+            return Ok(None)
+        }
+        report.time("i/o", || read_member(file, options.rate_limit)).map(Some)
+            .map_err(|e| format!("Could not read {}: {}", file.display(), e))
+    });
+    let mut contents = vec![];
+    for (file, read) in files.iter().zip(read) {
+        if let Some((bytes, extents)) = read? {
+            contents.push((file, bytes, extents));
+        }
+    }
+
+    // Plain Huffman chunks share one table, trained on every member and stored once.
+    // Small members would otherwise spend most of their space on tables.
+    let mut archive = Archive::new();
+    let mut method = method.clone();
+    if method == Method::new() {
+        let samples = distinct(contents.iter().map(|(_, bytes, _)| bytes.as_slice()));
+        let dictionary = report.time("counting", || Dictionary::train(&samples));
+        method.dictionary = Some(Arc::new(dictionary.clone()));
+        archive.add_dictionary(dictionary);
+    }
+
+    // Identical members are only chunked once. Later ones take the chunks of the first, and are stored as copies of it.
+    let mut firsts: HashMap<ChunkHash, usize> = HashMap::new();
+    let mut pieces: Vec<Vec<(ChunkHash, &[u8])>> = vec![];
+    for (index, (_, bytes, _)) in contents.iter().enumerate() {
+        let first = *firsts.entry(hash_chunk(bytes)).or_insert(index);
+        let hashed = match first == index {
+            true => {
+                report.time("chunking", || content_defined_chunks(bytes, ARCHIVE_CHUNK_AVERAGE, max_chunk)).into_iter()
+                    .map(|piece| (hash_chunk(piece), piece))
+                    .collect()
+            }
+            false => { pieces[first].clone() }
+        };
+        pieces.push(hashed);
+    }
+
+    // Identical chunks are only compressed and stored once, in the order they first appear.
+    let mut seen = HashSet::new();
+    let unique: Vec<(ChunkHash, &[u8])> = pieces.iter().flatten()
+        .filter(|(hash, _)| seen.insert(*hash))
+        .copied()
+        .collect();
+    let compressed = run_all(&unique, options.threads, report, |(_, piece), report| {
+        (!expired(options.deadline)).then(|| compress_block(piece, &method, report))
+    });
+    let compressed: HashMap<ChunkHash, Vec<u8>> = unique.iter().map(|(hash, _)| *hash).zip(compressed)
+        .filter_map(|(hash, compressed)| Some((hash, compressed?)))
+        .collect();
+
+    // Only members whose every chunk was compressed in time are archived.
+    for ((file, _, extents), pieces) in contents.iter().zip(pieces) {
+        let chunks: Vec<ChunkHash> = pieces.iter().map(|(hash, _)| *hash).collect();
+        if !chunks.iter().all(|hash| compressed.contains_key(hash)) {
+            continue
+        }
+        for hash in &chunks {
+            if !archive.has_chunk(*hash) {
+                archive.add_chunk(*hash, compressed[hash].clone());
+            }
+        }
+        let name = member_name(file)?;
+        let details = read_details(file, options)?;
+        archive.add_member(Member { name, chunks, extents: extents.clone(), details });
+    }
+    // Hard links can only be made to members which are there.
+    let archived: HashSet<&str> = archive.members().iter().map(|member| member.name.as_str()).collect();
+    let links: Vec<Link> = links.into_iter()
+        .filter(|link| link.kind == LinkKind::Symbolic || archived.contains(link.target.as_str()))
+        .collect();
+    let left_out = total - archive.members().len() - links.len();
+    links.into_iter().for_each(|link| archive.add_link(link));
+
+    Ok((archive.to_stream(), left_out))
+}
+
+// How many members a sync left alone, changed, added, and removed.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct SyncSummary {
+    pub(crate) unchanged: usize,
+    pub(crate) changed: usize,
+    pub(crate) added: usize,
+    pub(crate) removed: usize,
+}
+
+// Bring an archive up to date with every file under paths, as if it had just been created from them.
+// Files are chunked and hashed as when creating, but only chunks the archive doesn't already hold are compressed.
+// Everything else is copied across still compressed, so unchanged members cost no more than reading them.
+// Members whose files are gone are dropped, along with any chunks only they used.
+// A file which changes while this runs would leave the archive matching no state it was ever in, so that's an error.
+pub(crate) fn sync_archive(bytes: Option<&[u8]>, paths: &[String], max_chunk: usize, options: &ArchiveOptions,
+                           report: &mut Report) -> Result<(Vec<u8>, SyncSummary), String> {
+    let old = match bytes {
+        Some(bytes) => { report.time("parsing", || Archive::from_whole_stream(bytes))? }
+        None => { Archive::new() }
+    };
+    let (files, links) = gather(paths, options.symlinks)?;
+    let mut contents = vec![];
+    let mut fingerprints = vec![];
+    for file in &files {
+        fingerprints.push(fingerprint(&file.to_string_lossy())?);
+        let read = report.time("i/o", || read_member(file, options.rate_limit))
+            .map_err(|e| format!("Could not read {}: {}", file.display(), e))?;
+        contents.push(read);
+    }
+
+    // New chunks share the archive's table, which has a code for every byte.
+    // Archives without one get one trained on every file, as when creating.
+    let mut archive = Archive::new();
+    old.dictionaries().iter().for_each(|dictionary| archive.add_dictionary(dictionary.clone()));
+    let dictionary = match old.dictionaries().first() {
+        Some(dictionary) => { dictionary.clone() }
+        None => {
+            let samples = distinct(contents.iter().map(|(bytes, _)| bytes.as_slice()));
+            let dictionary = report.time("counting", || Dictionary::train(&samples));
+            archive.add_dictionary(dictionary.clone());
+            dictionary
+        }
+    };
+    let method = Method { dictionary: Some(Arc::new(dictionary)), ..Method::new() };
+
+    let mut summary = SyncSummary::default();
+    // The chunks of each distinct file so far, so identical files are only chunked once.
+    let mut chunked: HashMap<ChunkHash, Vec<ChunkHash>> = HashMap::new();
+    for (file, (bytes, extents)) in files.iter().zip(contents) {
+        let name = member_name(file)?;
+        let details = read_details(file, options)?;
+
+        let payload = hash_chunk(&bytes);
+        let chunks = match chunked.get(&payload) {
+            Some(chunks) => { chunks.clone() }
+            None => {
+                let pieces = report.time("chunking", || content_defined_chunks(&bytes, ARCHIVE_CHUNK_AVERAGE, max_chunk));
+                let mut chunks = vec![];
+                for piece in pieces {
+                    let hash = hash_chunk(piece);
+                    if !archive.has_chunk(hash) {
+                        let compressed = match old.chunk(hash) {
+                            Some(compressed) => { compressed.to_vec() }
+                            None => { compress_block(piece, &method, report) }
+                        };
+                        archive.add_chunk(hash, compressed);
+                    }
+                    chunks.push(hash);
+                }
+                chunked.insert(payload, chunks.clone());
+                chunks
+            }
+        };
+
+        match old.members().iter().find(|member| member.name == name) {
+            Some(member) if member.chunks == chunks && member.extents == extents && member.details == details => {
+                summary.unchanged += 1
+            }
+            Some(_) => { summary.changed += 1 }
+            None if old.links().iter().any(|link| link.name == name) => { summary.changed += 1 }
+            None => { summary.added += 1 }
+        }
+        archive.add_member(Member { name, chunks, extents, details });
+    }
+    for link in links {
+        match old.links().iter().find(|old| old.name == link.name) {
+            Some(old) if *old == link => { summary.unchanged += 1 }
+            Some(_) => { summary.changed += 1 }
+            None if old.members().iter().any(|member| member.name == link.name) => { summary.changed += 1 }
+            None => { summary.added += 1 }
+        }
+        archive.add_link(link);
+    }
+    let kept = |name: &str| archive.members().iter().any(|member| member.name == name) ||
+        archive.links().iter().any(|link| link.name == name);
+    summary.removed = old.members().iter().map(|member| &member.name)
+        .chain(old.links().iter().map(|link| &link.name))
+        .filter(|name| !kept(name))
+        .count();
+
+    check_unchanged(&files, &fingerprints)?;
+    Ok((archive.to_stream(), summary))
+}
+
+// Extract every member of an archive into the destination directory.
+pub(crate) fn extract_archive(bytes: &[u8], destination: &str, dictionary: Option<&Dictionary>,
+                              options: &ArchiveOptions, report: &mut Report) -> Result<(), String> {
+    let archive = report.time("parsing", || Archive::from_whole_stream(bytes))?;
+    // Shared chunks are only decompressed once.
+    let mut decompressed: HashMap<ChunkHash, Vec<u8>> = HashMap::new();
+
+    for member in archive.members() {
+        let path = Path::new(destination).join(member_path(&member.name)?);
+
+        let mut contents = vec![];
+        for hash in &member.chunks {
+            if !decompressed.contains_key(hash) {
+                let chunk = archive.chunk(*hash)
+                    .ok_or(format!("Archive is missing a chunk of {}", member.name))?;
+                // A dictionary stored in the archive takes the place of one given by the user.
+                let dictionary = block_dictionary(chunk).and_then(|id| archive.dictionary(id)).or(dictionary);
+                decompressed.insert(*hash, decompress_block(chunk, dictionary, report)?);
+            }
+            contents.extend_from_slice(&decompressed[hash]);
+        }
+        if member.extents.as_ref().is_some_and(|extents| !extents.fits(contents.len())) {
+            return Err(format!("Member {} doesn't fit where its extents say it goes", member.name))
+        }
+
+        report.time("i/o", || {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            match &member.extents {
+                Some(extents) => { write_sparse(&path, &contents, extents) }
+                None => { fs::write(&path, contents) }
+            }
+        }).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+        restore_details(&path, &member.details, options);
+    }
+
+    // Links are made last, so nothing else in the archive is written through one.
+    // They're all checked before any are made, so a bad one leaves none behind.
+    let mut links = vec![];
+    for link in archive.links() {
+        // A link inside another could lead anywhere, since its target is only checked against its own name.
+        if archive.links().iter().any(|outer| link.name.starts_with(&format!("{}/", outer.name))) {
+            return Err(format!("Refusing to extract link {}: it's inside another link", link.name))
+        }
+        let target = match link.kind {
+            LinkKind::Symbolic => {
+                let target = target_path(&link.name, &link.target)?;
+                // Nor is a target leading through another link, i.e. up2 -> up/../.. beside up -> ..
+                let through = target_dirs(&link.name, &link.target);
+                if archive.links().iter().any(|other| through.contains(&other.name)) {
+                    return Err(format!("Refusing to extract link {} to {}: it leads through another link", link.name, link.target))
+                }
+                target
+            }
+            // Only members' contents can be shared, or a hard link could reach any file at all.
+            LinkKind::Hard => {
+                if !archive.members().iter().any(|member| member.name == link.target) {
+                    return Err(format!("Refusing to extract hard link {} to {}: it isn't a member of the archive",
+                                       link.name, link.target))
+                }
+                Path::new(destination).join(member_path(&link.target)?)
+            }
+        };
+        links.push((Path::new(destination).join(member_path(&link.name)?), link.kind, target));
+    }
+    for (path, kind, target) in links {
+        report.time("i/o", || {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // Extracting again replaces the link, rather than failing on it.
+            if fs::symlink_metadata(&path).is_ok() {
+                fs::remove_file(&path)?;
+            }
+            match kind {
+                LinkKind::Symbolic => { symlink(&target, &path) }
+                LinkKind::Hard => { fs::hard_link(&target, &path) }
+            }
+        }).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+// Have any of files changed since their fingerprints were taken?
+fn check_unchanged(files: &[PathBuf], fingerprints: &[(u64, u64)]) -> Result<(), String> {
+    for (file, before) in files.iter().zip(fingerprints) {
+        if fingerprint(&file.to_string_lossy()).ok().as_ref() != Some(before) {
+            return Err(format!("{} changed while it was being archived, so the archive was left as it was!", file.display()))
+        }
+    }
+    Ok(())
+}
+
+// Each distinct file's contents once, to train a dictionary on.
+// Duplicated files would otherwise count for more than they're stored as.
+fn distinct<'a>(contents: impl Iterator<Item = &'a [u8]>) -> Vec<&'a [u8]> {
+    let mut seen = HashSet::new();
+    contents.filter(|bytes| seen.insert(hash_chunk(bytes))).collect()
+}
+
+// Read a file to archive. Only the data of sparse files is read, along with where it lies. Their holes never are.
+// While it's read, the file is locked, so writers which lock it too can't change it halfway through.
+fn read_member(path: &Path, rate_limit: Option<u64>) -> io::Result<(Vec<u8>, Option<Extents>)> {
+    let file = File::open(path)?;
+    file.try_lock_shared().map_err(|e| match e {
+        TryLockError::WouldBlock => { io::Error::new(io::ErrorKind::WouldBlock, "another process has it locked") }
+        TryLockError::Error(e) => { e }
+    })?;
+    let size = file.metadata()?.len();
+    let mut bytes = vec![];
+    let extents = data_extents(&file, size)?;
+    let mut file = Throttled::new(file, rate_limit);
+    match extents {
+        None => {
+            file.rewind()?;
+            file.read_to_end(&mut bytes)?;
+            Ok((bytes, None))
+        }
+        Some(data) => {
+            for (offset, len) in &data {
+                file.seek(SeekFrom::Start(*offset))?;
+                let read = Read::by_ref(&mut file).take(*len).read_to_end(&mut bytes)?;
+                if read as u64 != *len {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the file shrank while it was read"))
+                }
+            }
+            Ok((bytes, Some(Extents { size, data })))
+        }
+    }
+}
+
+// What's to be kept about a file besides its contents.
+fn read_details(path: &Path, options: &ArchiveOptions) -> Result<Details, String> {
+    let mut details = Details::default();
+    if options.xattrs {
+        details.xattrs = read_xattrs(path)
+            .map_err(|e| format!("Could not read extended attributes of {}: {}", path.display(), e))?;
+    }
+    if options.owners {
+        let (uid, gid) = fs::metadata(path).ok().and_then(|metadata| file_owner(&metadata))
+            .ok_or(format!("Could not find the owner of {}", path.display()))?;
+        details.owner = Some(Owner { uid, gid, user: user_name(uid), group: group_name(gid) });
+    }
+    Ok(details)
+}
+
+// Give an extracted file back what options ask for of what was kept about it. Like tar, what can't be restored
+// is reported, since some of it needs privileges to set, or a file system which takes it, and the contents are still good.
+fn restore_details(path: &Path, details: &Details, options: &ArchiveOptions) {
+    // Changing owners clears some attributes, such as file capabilities, so it comes first.
+    if let Some(owner) = details.owner.as_ref().filter(|_| options.owners) {
+        let uid = owner.user.as_deref().and_then(user_id).unwrap_or(owner.uid);
+        let gid = owner.group.as_deref().and_then(group_id).unwrap_or(owner.gid);
+        if let Err(e) = chown(path, uid, gid) {
+            println!("Could not restore the owner of {}: {}", path.display(), e);
+        }
+    }
+    if !options.xattrs {
+        return
+    }
+    for (name, value) in &details.xattrs {
+        if let Err(e) = write_xattr(path, name, value) {
+            println!("Could not restore {} on {}: {}", String::from_utf8_lossy(name), path.display(), e);
+        }
+    }
+}
+
+// Write a sparse member: each run of data where it lies, and holes everywhere else.
+// The extents must already fit contents.
+fn write_sparse(path: &Path, contents: &[u8], extents: &Extents) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.set_len(extents.size)?;
+    let mut rest = contents;
+    for (offset, len) in &extents.data {
+        let (run, after) = rest.split_at(*len as usize);
+        file.seek(SeekFrom::Start(*offset))?;
+        file.write_all(run)?;
+        rest = after;
+    }
+    Ok(())
+}
+
+// Extract every member of a zip file into the destination directory.
+pub(crate) fn extract_zip(bytes: &[u8], destination: &str, report: &mut Report) -> Result<(), String> {
+    let entries = report.time("parsing", || read_entries(bytes))?;
+    for entry in entries {
+        // Directories are listed with a trailing '/', and may hold nothing at all.
+        let path = Path::new(destination).join(member_path(entry.name.trim_end_matches('/'))?);
+        if entry.is_dir() {
+            fs::create_dir_all(&path).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+            continue
+        }
+
+        let contents = report.time("inflate", || read_contents(bytes, &entry))?;
+        report.time("i/o", || {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, contents)
+        }).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+// Extract a single member of a compressed tar stream, using the index at its end.
+// Only the blocks holding the member are decompressed.
+pub(crate) fn extract_tar_member(bytes: &[u8], name: &str, dictionary: Option<&Dictionary>,
+                                 report: &mut Report) -> Result<Vec<u8>, String> {
+    // The index counts only blocks of data, not checksums.
+    let blocks: Vec<&[u8]> = report.time("parsing", || split_blocks(bytes))?.into_iter()
+        .filter(|block| block_kind(block) != Some(BlockKind::Checksum))
+        .collect();
+    // Every stream has an index, but only those from tar streams have members.
+    let index = blocks.last().and_then(|block| read_index(block)).filter(|index| !index.members().is_empty())
+        .ok_or("This file has no tar index: it wasn't compressed from a tar stream!")?;
+    let member = index.members().iter().find(|member| member.name == name)
+        .ok_or(format!("No member named {}", name))?;
+
+    let (needed, skip) = index.locate(member);
+    let mut contents = vec![];
+    for block in needed {
+        contents.append(&mut decompress_block(blocks[block], dictionary, report)?);
+    }
+    Ok(contents.into_iter().skip(skip).take(member.size as usize).collect())
+}
+
+// Name every member of a wz archive or zip file.
+pub(crate) fn list_archive(bytes: &[u8]) -> Result<Vec<String>, String> {
+    if is_zip(bytes) {
+        return Ok(read_entries(bytes)?.into_iter().map(|entry| entry.name).collect())
+    }
+    let archive = Archive::from_whole_stream(bytes)?;
+    Ok(archive.members().iter().map(|member| member.name.clone())
+        .chain(archive.links().iter().map(|link| match link.kind {
+            LinkKind::Symbolic => { format!("{} -> {}", link.name, link.target) }
+            LinkKind::Hard => { format!("{} link to {}", link.name, link.target) }
+        }))
+        .collect())
+}
+
+// Find the files under paths, and the links to record in place of some of them.
+// A file with several names is stored under the first, and hard links to it under the rest.
+// With Symlinks::Preserve, symbolic links are recorded too, rather than read through.
+fn gather(paths: &[String], symlinks: Symlinks) -> Result<(Vec<PathBuf>, Vec<Link>), String> {
+    let mut found = vec![];
+    for path in paths {
+        collect_files(Path::new(path), symlinks, &mut found)?;
+    }
+    let mut files = vec![];
+    let mut links = vec![];
+    // The name each file with several was first found under.
+    let mut first_names: HashMap<(u64, u64), String> = HashMap::new();
+    for path in found {
+        let metadata = fs::symlink_metadata(&path).map_err(|_| format!("File not found: {}", path.display()))?;
+        if symlinks == Symlinks::Preserve && metadata.is_symlink() {
+            let target = fs::read_link(&path).map_err(|e| format!("Could not read link {}: {}", path.display(), e))?;
+            links.push(Link { name: member_name(&path)?, target: link_target(&path, &target)?, kind: LinkKind::Symbolic });
+            continue
+        }
+        if let Some(id) = file_id(&metadata) {
+            let name = member_name(&path)?;
+            match first_names.get(&id) {
+                // The same path given twice is still just a file.
+                Some(first) if *first != name => {
+                    links.push(Link { name, target: first.clone(), kind: LinkKind::Hard });
+                    continue
+                }
+                Some(_) => {}
+                None => { first_names.insert(id, name); }
+            }
+        }
+        files.push(path);
+    }
+    Ok((files, links))
+}
+
+// Find every file at or below path, in a stable order.
+// Followed links to directories are searched like any other, unless they lead back to where they are.
+// Preserved links are files like any other, and are never searched.
+pub(crate) fn collect_files(path: &Path, symlinks: Symlinks, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    collect_below(path, symlinks, &mut vec![], files)
+}
+
+// ancestors holds every directory being searched, canonically, so that a loop of links is noticed.
+fn collect_below(path: &Path, symlinks: Symlinks, ancestors: &mut Vec<PathBuf>,
+                 files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let metadata = match symlinks {
+        Symlinks::Follow => { fs::metadata(path) }
+        Symlinks::Preserve => { fs::symlink_metadata(path) }
+    }.map_err(|_| format!("File not found: {}", path.display()))?;
+    if !metadata.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(())
+    }
+
+    let canonical = path.canonicalize().map_err(|e| format!("Could not read directory {}: {}", path.display(), e))?;
+    if ancestors.contains(&canonical) {
+        return Err(format!("Links loop back on themselves at {}", path.display()))
+    }
+    let mut entries = fs::read_dir(path)
+        .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Could not read directory {}: {}", path.display(), e))?;
+    entries.sort();
+    ancestors.push(canonical);
+    for entry in entries {
+        collect_below(&entry, symlinks, ancestors, files)?;
+    }
+    ancestors.pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+    use std::time::Instant;
+    use crate::codec::archiver::{ArchiveOptions, check_unchanged, create_archive, extract_archive, sync_archive, SyncSummary};
+    use crate::codec::block::{compress_block, Method};
+    use crate::diagnostics::report::Report;
+    use crate::file::archive::{Archive, Details, Extents, hash_chunk, Link, LinkKind, Member};
+    use crate::file::bytestream::ByteStream;
+    use crate::file::checkpoint::fingerprint;
+    use crate::file::names::member_name;
+
+    // Many small members share one table, stored once.
+    #[test]
+    fn test_shared_dictionary() {
+        let mut report = Report::new(false);
+        let source = temp_dir().join(format!("wz_shared_{}", std::process::id()));
+        fs::create_dir_all(&source).unwrap();
+        for i in 0..50 {
+            fs::write(source.join(format!("{}.txt", i)), format!("record {} of fifty, all alike", i)).unwrap();
+        }
+
+        let paths = vec![source.to_str().unwrap().to_string()];
+        let bytes = create_archive(&paths, 1 << 20, &Method::new(), &ArchiveOptions::new(), &mut report).unwrap().0;
+        assert_eq!(1, Archive::from_whole_stream(&bytes).unwrap().dictionaries().len());
+        // Working on several files at once makes the same members, of the same chunks.
+        let threaded = ArchiveOptions { threads: 4, ..ArchiveOptions::new() };
+        let threaded = create_archive(&paths, 1 << 20, &Method::new(), &threaded, &mut report).unwrap().0;
+        assert_eq!(Archive::from_whole_stream(&bytes).unwrap().members(), Archive::from_whole_stream(&threaded).unwrap().members());
+
+        let destination = source.with_extension("out");
+        extract_archive(&bytes, destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).unwrap();
+        let extracted = destination.join(member_name(&source).unwrap()).join("7.txt");
+        assert_eq!("record 7 of fifty, all alike", fs::read_to_string(extracted).unwrap());
+
+        // Out of time before starting, the archive is finished empty, and says what was left out.
+        let late = ArchiveOptions { deadline: Some(Instant::now()), ..ArchiveOptions::new() };
+        let (bytes, left_out) = create_archive(&paths, 1 << 20, &Method::new(), &late, &mut report).unwrap();
+        assert_eq!(50, left_out);
+        assert!(Archive::from_whole_stream(&bytes).unwrap().members().is_empty());
+
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_dir_all(&destination).unwrap();
+    }
+
+    // Duplicated assets are stored once, and the copies cost next to nothing.
+    #[test]
+    fn test_duplicates() {
+        let mut report = Report::new(false);
+        let source = temp_dir().join(format!("wz_duplicates_{}", std::process::id()));
+        for dir in ["a", "b", "c"] {
+            fs::create_dir_all(source.join(dir)).unwrap();
+        }
+        let asset: Vec<u8> = (0..1u32 << 18).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        fs::write(source.join("a/logo.png"), &asset).unwrap();
+        let paths = vec![source.to_str().unwrap().to_string()];
+        let once = create_archive(&paths, 1 << 16, &Method::new(), &ArchiveOptions::new(), &mut report).unwrap().0;
+
+        fs::write(source.join("b/logo.png"), &asset).unwrap();
+        fs::write(source.join("c/icon.png"), &asset).unwrap();
+        let bytes = create_archive(&paths, 1 << 16, &Method::new(), &ArchiveOptions::new(), &mut report).unwrap().0;
+        let archive = Archive::from_whole_stream(&bytes).unwrap();
+        assert_eq!(3, archive.members().len());
+        assert!(archive.members().iter().all(|member| member.chunks == archive.members()[0].chunks));
+        // The copies add their names and a few numbers, but none of the asset again.
+        assert!(bytes.len() < once.len() + 256);
+
+        let synced = sync_archive(Some(&once), &paths, 1 << 16, &ArchiveOptions::new(), &mut report).unwrap().0;
+        assert!(synced.len() < once.len() + 256);
+
+        let destination = source.with_extension("out");
+        extract_archive(&bytes, destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).unwrap();
+        let extracted = destination.join(member_name(&source).unwrap());
+        for name in ["a/logo.png", "b/logo.png", "c/icon.png"] {
+            assert_eq!(asset, fs::read(extracted.join(name)).unwrap());
+        }
+
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_dir_all(&destination).unwrap();
+    }
+
+    #[test]
+    fn test_sync() {
+        let mut report = Report::new(false);
+        let source = temp_dir().join(format!("wz_sync_{}", std::process::id()));
+        fs::create_dir_all(source.join("nested")).unwrap();
+        fs::write(source.join("same.txt"), b"never changes ".repeat(100)).unwrap();
+        fs::write(source.join("edited.txt"), b"before the edit ".repeat(100)).unwrap();
+        fs::write(source.join("nested/gone.txt"), b"about to be deleted").unwrap();
+        let paths = vec![source.to_str().unwrap().to_string()];
+        let (before, summary) = sync_archive(None, &paths, 1 << 20, &ArchiveOptions::new(), &mut report).unwrap();
+        assert_eq!(SyncSummary { added: 3, ..SyncSummary::default() }, summary);
+
+        fs::write(source.join("edited.txt"), b"after the edit ".repeat(100)).unwrap();
+        fs::remove_file(source.join("nested/gone.txt")).unwrap();
+        fs::write(source.join("new.txt"), b"just arrived").unwrap();
+        let (after, summary) = sync_archive(Some(&before), &paths, 1 << 20, &ArchiveOptions::new(), &mut report).unwrap();
+        assert_eq!(SyncSummary { unchanged: 1, changed: 1, added: 1, removed: 1 }, summary);
+
+        // Unchanged members are copied across exactly, and the result is what creating it afresh would hold.
+        let (old, new) = (Archive::from_whole_stream(&before).unwrap(), Archive::from_whole_stream(&after).unwrap());
+        let same = new.members().iter().find(|member| member.name.ends_with("same.txt")).unwrap();
+        assert_eq!(old.chunk(same.chunks[0]), new.chunk(same.chunks[0]));
+        let destination = source.with_extension("out");
+        extract_archive(&after, destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).unwrap();
+        let extracted = destination.join(member_name(&source).unwrap());
+        assert_eq!(b"after the edit ".repeat(100), fs::read(extracted.join("edited.txt")).unwrap());
+        assert_eq!(b"just arrived".to_vec(), fs::read(extracted.join("new.txt")).unwrap());
+        assert!(!extracted.join("nested/gone.txt").exists());
+
+        // Files locked by a writer aren't read, and files which change while they're read aren't archived.
+        let locked = fs::File::options().append(true).open(source.join("new.txt")).unwrap();
+        locked.lock().unwrap();
+        assert!(sync_archive(Some(&after), &paths, 1 << 20, &ArchiveOptions::new(), &mut report).is_err());
+        drop(locked);
+        let files = vec![source.join("same.txt")];
+        let fingerprints = vec![fingerprint(&files[0].to_string_lossy()).unwrap()];
+        assert!(check_unchanged(&files, &fingerprints).is_ok());
+        fs::write(&files[0], b"changed behind our back").unwrap();
+        assert!(check_unchanged(&files, &fingerprints).is_err());
+
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_dir_all(&destination).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        use std::os::unix::fs::symlink;
+        use std::path::Path;
+        use crate::codec::archiver::{list_archive, Symlinks};
+        let mut report = Report::new(false);
+        let source = temp_dir().join(format!("wz_symlinks_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&source);
+        fs::create_dir_all(source.join("logs")).unwrap();
+        fs::write(source.join("logs/today.log"), b"the latest entries").unwrap();
+        symlink("logs/today.log", source.join("latest")).unwrap();
+        symlink("logs", source.join("all")).unwrap();
+        let paths = vec![source.to_str().unwrap().to_string()];
+        let name = member_name(&source).unwrap();
+        let preserve = ArchiveOptions { symlinks: Symlinks::Preserve, ..ArchiveOptions::new() };
+
+        // Followed, links are archived as copies of what they point at.
+        let followed = create_archive(&paths, 1 << 20, &Method::new(), &ArchiveOptions::new(), &mut report).unwrap().0;
+        let listed = list_archive(&followed).unwrap();
+        assert_eq!(vec![format!("{}/all/today.log", name), format!("{}/latest", name), format!("{}/logs/today.log", name)],
+                   listed);
+
+        // Preserved, they're made again on extraction, pointing where they did.
+        let preserved = create_archive(&paths, 1 << 20, &Method::new(), &preserve, &mut report).unwrap().0;
+        assert_eq!(vec![format!("{}/logs/today.log", name), format!("{}/all -> logs", name),
+                        format!("{}/latest -> logs/today.log", name)], list_archive(&preserved).unwrap());
+        let destination = source.with_extension("out");
+        for _ in 0..2 {
+            extract_archive(&preserved, destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).unwrap();
+        }
+        let extracted = destination.join(&name);
+        assert_eq!(Path::new("logs/today.log"), fs::read_link(extracted.join("latest")).unwrap());
+        assert_eq!(b"the latest entries".to_vec(), fs::read(extracted.join("all/today.log")).unwrap());
+
+        // A link back to a directory it's in would go on forever.
+        symlink("..", source.join("logs/up")).unwrap();
+        assert!(create_archive(&paths, 1 << 20, &Method::new(), &ArchiveOptions::new(), &mut report).is_err());
+        assert!(create_archive(&paths, 1 << 20, &Method::new(), &preserve, &mut report).is_ok());
+
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_dir_all(&destination).unwrap();
+    }
+
+    // Links from elsewhere may point anywhere, so where they'd lead is checked before they're made.
+    #[test]
+    fn test_unsafe_links() {
+        let mut report = Report::new(false);
+        let destination = temp_dir().join(format!("wz_unsafe_links_{}", std::process::id()));
+        for (name, target) in [("escape", "../outside"), ("absolute", "/etc/passwd")] {
+            let mut archive = Archive::new();
+            archive.add_link(Link { name: String::from(name), target: String::from(target), kind: LinkKind::Symbolic });
+            assert!(extract_archive(&archive.to_stream(), destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).is_err());
+        }
+        // Hard links may only share the contents of members.
+        let mut archive = Archive::new();
+        archive.add_link(Link { name: String::from("passwd"), target: String::from("etc/passwd"), kind: LinkKind::Hard });
+        assert!(extract_archive(&archive.to_stream(), destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).is_err());
+        // Each target is fine on its own, but the second is made inside the first.
+        let mut archive = Archive::new();
+        archive.add_link(Link { name: String::from("here"), target: String::from("."), kind: LinkKind::Symbolic });
+        archive.add_link(Link { name: String::from("here/up"), target: String::from(".."), kind: LinkKind::Symbolic });
+        assert!(extract_archive(&archive.to_stream(), destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).is_err());
+        // Again each is fine on its own, but the second is followed through the first, which climbs out of a.
+        let mut archive = Archive::new();
+        archive.add_link(Link { name: String::from("a/up"), target: String::from(".."), kind: LinkKind::Symbolic });
+        archive.add_link(Link { name: String::from("a/up2"), target: String::from("up/../.."), kind: LinkKind::Symbolic });
+        assert!(extract_archive(&archive.to_stream(), destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).is_err());
+        assert!(fs::symlink_metadata(destination.join("a/up")).is_err());
+        let _ = fs::remove_dir_all(&destination);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hard_links() {
+        use std::os::unix::fs::MetadataExt;
+        use crate::codec::archiver::list_archive;
+        let mut report = Report::new(false);
+        let source = temp_dir().join(format!("wz_hard_links_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&source);
+        fs::create_dir_all(source.join("monday")).unwrap();
+        fs::create_dir_all(source.join("tuesday")).unwrap();
+        fs::write(source.join("monday/data.bin"), b"backed up once, kept twice ".repeat(100)).unwrap();
+        fs::hard_link(source.join("monday/data.bin"), source.join("tuesday/data.bin")).unwrap();
+        let paths = vec![source.to_str().unwrap().to_string()];
+        let name = member_name(&source).unwrap();
+
+        let bytes = create_archive(&paths, 1 << 20, &Method::new(), &ArchiveOptions::new(), &mut report).unwrap().0;
+        assert_eq!(1, Archive::from_whole_stream(&bytes).unwrap().members().len());
+        assert_eq!(vec![format!("{}/monday/data.bin", name), format!("{}/tuesday/data.bin link to {}/monday/data.bin", name, name)],
+                   list_archive(&bytes).unwrap());
+
+        // Extracted, both names are the same file again.
+        let destination = source.with_extension("out");
+        extract_archive(&bytes, destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).unwrap();
+        let extracted = destination.join(&name);
+        let (monday, tuesday) = (fs::metadata(extracted.join("monday/data.bin")).unwrap(),
+                                 fs::metadata(extracted.join("tuesday/data.bin")).unwrap());
+        assert_eq!(monday.ino(), tuesday.ino());
+        assert_eq!(b"backed up once, kept twice ".repeat(100), fs::read(extracted.join("tuesday/data.bin")).unwrap());
+
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_dir_all(&destination).unwrap();
+    }
+
+    // Holes are never read or stored, and come back as holes.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sparse() {
+        use std::fs::File;
+        use std::io::{Seek, SeekFrom, Write};
+        use std::os::unix::fs::MetadataExt;
+        let mut report = Report::new(false);
+        let source = temp_dir().join(format!("wz_sparse_archive_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&source);
+        fs::create_dir_all(&source).unwrap();
+        let mut file = File::create(source.join("disk.img")).unwrap();
+        file.write_all(b"boot sector").unwrap();
+        file.seek(SeekFrom::Start(256 << 20)).unwrap();
+        file.write_all(b"partition table").unwrap();
+        file.set_len(512 << 20).unwrap();
+        file.sync_all().unwrap();
+        let paths = vec![source.to_str().unwrap().to_string()];
+
+        let bytes = create_archive(&paths, 1 << 20, &Method::new(), &ArchiveOptions::new(), &mut report).unwrap().0;
+        let archive = Archive::from_whole_stream(&bytes).unwrap();
+        let destination = source.with_extension("out");
+        extract_archive(&bytes, destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).unwrap();
+        let extracted = destination.join(member_name(&source).unwrap()).join("disk.img");
+        let contents = fs::read(&extracted).unwrap();
+        assert_eq!(512 << 20, contents.len());
+        assert_eq!(b"boot sector", &contents[..11]);
+        assert_eq!(b"partition table", &contents[256 << 20..(256 << 20) + 15]);
+
+        // File systems without holes store the zeros, and have nothing more to check.
+        if archive.members()[0].extents.is_some() {
+            assert!(bytes.len() < 1 << 20);
+            assert!(fs::metadata(&extracted).unwrap().blocks() * 512 < 64 << 20);
+        }
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_dir_all(&destination).unwrap();
+    }
+
+    #[test]
+    fn test_bad_extents() {
+        let mut report = Report::new(false);
+        let destination = temp_dir().join(format!("wz_bad_extents_{}", std::process::id()));
+        let hash = hash_chunk(b"data");
+        let mut archive = Archive::new();
+        archive.add_chunk(hash, compress_block(b"data", &Method::new(), &mut report));
+        // Runs which would hold more than the member has, or lie past the end of the file, are refused.
+        for data in [vec![(0, 2), (8, 4)], vec![(8, 4)]] {
+            let mut archive = archive.clone();
+            let extents = Some(Extents { size: 10, data });
+            archive.add_member(Member { name: String::from("bad"), chunks: vec![hash], extents, details: Details::default() });
+            assert!(extract_archive(&archive.to_stream(), destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).is_err());
+        }
+        let _ = fs::remove_dir_all(&destination);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_xattrs() {
+        use crate::system::xattrs::{read_xattrs, write_xattr};
+        let mut report = Report::new(false);
+        let source = temp_dir().join(format!("wz_xattrs_archive_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&source);
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("tagged.txt"), b"has attributes").unwrap();
+        // Not every file system takes user attributes. Those that don't have nothing to test.
+        if write_xattr(&source.join("tagged.txt"), b"user.origin", b"camera").is_err() {
+            fs::remove_dir_all(&source).unwrap();
+            return
+        }
+        let paths = vec![source.to_str().unwrap().to_string()];
+        let xattrs = ArchiveOptions { xattrs: true, ..ArchiveOptions::new() };
+
+        // Attributes are only recorded when asked for, and only restored when asked for.
+        let without = create_archive(&paths, 1 << 20, &Method::new(), &ArchiveOptions::new(), &mut report).unwrap().0;
+        assert!(Archive::from_whole_stream(&without).unwrap().members()[0].details.is_empty());
+        let bytes = create_archive(&paths, 1 << 20, &Method::new(), &xattrs, &mut report).unwrap().0;
+        let destination = source.with_extension("out");
+        let extracted = destination.join(member_name(&source).unwrap()).join("tagged.txt");
+        extract_archive(&bytes, destination.to_str().unwrap(), None, &ArchiveOptions::new(), &mut report).unwrap();
+        assert!(!read_xattrs(&extracted).unwrap().contains(&(b"user.origin".to_vec(), b"camera".to_vec())));
+        fs::remove_dir_all(&destination).unwrap();
+        extract_archive(&bytes, destination.to_str().unwrap(), None, &xattrs, &mut report).unwrap();
+        assert!(read_xattrs(&extracted).unwrap().contains(&(b"user.origin".to_vec(), b"camera".to_vec())));
+
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_dir_all(&destination).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_owners() {
+        use std::os::unix::fs::MetadataExt;
+        let mut report = Report::new(false);
+        let source = temp_dir().join(format!("wz_owners_archive_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&source);
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("owned.txt"), b"whose is this?").unwrap();
+        let metadata = fs::metadata(source.join("owned.txt")).unwrap();
+        let paths = vec![source.to_str().unwrap().to_string()];
+        let owners = ArchiveOptions { owners: true, ..ArchiveOptions::new() };
+
+        let bytes = create_archive(&paths, 1 << 20, &Method::new(), &owners, &mut report).unwrap().0;
+        let archive = Archive::from_whole_stream(&bytes).unwrap();
+        let owner = archive.members()[0].details.owner.clone().unwrap();
+        assert_eq!((metadata.uid(), metadata.gid()), (owner.uid, owner.gid));
+
+        // Restoring who owns a file we just wrote, as whoever we are, works with or without privileges.
+        let destination = source.with_extension("out");
+        extract_archive(&bytes, destination.to_str().unwrap(), None, &owners, &mut report).unwrap();
+        let extracted = fs::metadata(destination.join(member_name(&source).unwrap()).join("owned.txt")).unwrap();
+        assert_eq!((metadata.uid(), metadata.gid()), (extracted.uid(), extracted.gid()));
+
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_dir_all(&destination).unwrap();
+    }
+}
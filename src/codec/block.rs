@@ -0,0 +1,1626 @@
+// Compresses and decompresses single blocks.
+// Each block is standalone -- usually a wzfile, with its own frequency table.
+// Author: Will Morris
+
+/*
+  CONTENTS:
+  -- block kind
+  -- block contents, stored as the kind says.
+ */
+
+use crate::codec::coder::{coder_for_kind, EntropyCoder, FILTERED, HUFFMAN};
+use crate::diagnostics::report::Report;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::io;
+use std::io::{Read, Write};
+use crate::encoding::bitreader::BitReader;
+use crate::encoding::bitsequence::{BitOrder, BitSequence, Encoding};
+use crate::encoding::bitwriter::BitWriter;
+use crate::encoding::canonical::{byte_lengths, canonical_decoding, canonical_encoding, LENGTHS_LEN, lengths_from_stream, lengths_to_stream};
+use crate::encoding::decodingtable::DecodingTable;
+use crate::encoding::lzw::{lzw_decode, lzw_encode};
+use crate::encoding::rangecoder::{MAX_TOTAL, Model, RangeDecoder, RangeEncoder};
+use crate::file::bytestream::{append_sized, ByteStream, LONG_LEN, read_bytes, read_long, read_sized, slice_to_long};
+use crate::file::checksum::{Checksum, checksum_from_id};
+use crate::file::error::WzError;
+use crate::file::format::{ENTRY_WIDTH_LEN, read_bit_count, read_entry_width, read_map_len, read_sequence_len};
+use crate::file::tar::TarIndex;
+use crate::file::wzfile::Wzfile;
+use crate::ordering::freq::{gen_frequency, scale_frequencies};
+use crate::ordering::dictionary::{Dictionary, DictionaryId, ID_LEN};
+use crate::ordering::freqmap::{Freqmap, SymbolFreqmap};
+use crate::ordering::presets::Preset;
+use crate::ordering::symbolfreq::Symbol;
+use crate::transform::bwt::{bwt, unbwt};
+use crate::transform::chars::{from_chars, to_chars};
+use crate::transform::filter::{Filter, filter_from_stream, filter_to_stream};
+use crate::transform::lz77::{bucket, bucket_base, expand, find_matches, MIN_MATCH, Token, WINDOW_SIZE};
+use crate::transform::mtf::{mtf, unmtf};
+use crate::transform::words::{dictionary_from_stream, dictionary_to_stream, tokenize};
+use crate::tree::adaptive::AdaptiveTree;
+use crate::tree::node::{huffman, huffman_symbols, Tree};
+
+// How the contents of a block are stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockKind {
+    // A wzfile: frequency table, followed by Huffman coded bits.
+    Huffman = 0,
+    // The original bytes, untouched.
+    // Used whenever Huffman coding would make a block bigger, i.e. for random data.
+    Raw = 1,
+    // Adaptive Huffman coded bits. The code is learned as the block is read, so there's no table.
+    Adaptive = 2,
+    // A range coded block: scaled frequency table, byte count, then the range coder's output.
+    Range = 3,
+    // Order-1 adaptive Huffman coded bits.
+    // Each byte is coded with a tree chosen by the byte before it, so common pairs get short codes.
+    Context = 4,
+    // Bytes transformed by Burrows-Wheeler and move-to-front: original row, then an inner block.
+    // Only written by older versions: the transform is a filter now.
+    Bwt = 5,
+    // LZ77 tokens, Huffman coded deflate-style:
+    // literal/length frequencies, distance frequencies, then the coded bits.
+    Lz77 = 6,
+    // LZW codes. The dictionary is rebuilt while decoding, so nothing else is stored.
+    Lzw = 7,
+    // Bytes passed through a filter: which filter, anything needed to undo it, then an inner block.
+    Filtered = 8,
+    // Words Huffman coded as single symbols: the words, their frequencies, word count, then the coded bits.
+    Words = 9,
+    // Pairs of bytes Huffman coded as 16 bit symbols: frequencies, byte count, then the coded bits.
+    Pairs = 10,
+    // Huffman coded with a built in table: which preset, then the coded bits.
+    Preset = 11,
+    // Huffman coded with a trained dictionary's table: the dictionary's id, then the coded bits.
+    Dictionary = 12,
+    // Unicode characters Huffman coded as single symbols: frequencies, character count, then the coded bits.
+    Chars = 13,
+    // Half bytes Huffman coded as symbols: frequencies, nibble count, then the coded bits.
+    Nibbles = 14,
+    // Not data at all, but an index of the tar members in the blocks before it. Decompresses to nothing.
+    Index = 15,
+    // A wzfile, with its bits packed from the high end of each byte rather than the low.
+    HuffmanMsb = 16,
+    // Huffman coded with canonical codes: bit order, the code length of every byte value, then the coded bits.
+    // Anyone can rebuild the codes from the lengths alone, without building our tree.
+    Canonical = 17,
+    // Huffman coded with a table the user gave: bit order, each byte's code length, every code, then the coded bits.
+    Table = 18,
+    // Not data either, but a checksum of everything decompressed since the last one: which checksum, then its digest.
+    Checksum = 19,
+}
+
+impl BlockKind {
+    fn from_byte(byte: u8) -> Option<BlockKind> {
+        match byte {
+            0 => { Some(BlockKind::Huffman) }
+            1 => { Some(BlockKind::Raw) }
+            2 => { Some(BlockKind::Adaptive) }
+            3 => { Some(BlockKind::Range) }
+            4 => { Some(BlockKind::Context) }
+            5 => { Some(BlockKind::Bwt) }
+            6 => { Some(BlockKind::Lz77) }
+            7 => { Some(BlockKind::Lzw) }
+            8 => { Some(BlockKind::Filtered) }
+            9 => { Some(BlockKind::Words) }
+            10 => { Some(BlockKind::Pairs) }
+            11 => { Some(BlockKind::Preset) }
+            12 => { Some(BlockKind::Dictionary) }
+            13 => { Some(BlockKind::Chars) }
+            14 => { Some(BlockKind::Nibbles) }
+            15 => { Some(BlockKind::Index) }
+            16 => { Some(BlockKind::HuffmanMsb) }
+            17 => { Some(BlockKind::Canonical) }
+            18 => { Some(BlockKind::Table) }
+            19 => { Some(BlockKind::Checksum) }
+            _ => { None }
+        }
+    }
+}
+
+// Everything about how a block should be compressed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Method {
+    pub coder: &'static dyn EntropyCoder,
+    // Filters to run over the bytes before anything else, in order.
+    pub filters: Vec<Arc<dyn Filter>>,
+    // A built in table for the preset coder to Huffman code with, instead of storing one.
+    pub preset: Option<Preset>,
+    // A trained table for the dictionary coder to Huffman code with. Shared, since every block uses the same one.
+    pub dictionary: Option<Arc<Dictionary>>,
+    // Which end of each byte Huffman coded bits are packed from, for the coders which store their bits plainly.
+    pub bit_order: BitOrder,
+    // A code for each byte for the table coder, given by the user rather than counted. Every byte coded must have one.
+    pub table: Option<Arc<Encoding>>,
+    // Frequencies counted elsewhere, to plain Huffman code with rather than counting each block.
+    pub frequencies: Option<Arc<HashMap<u8, u64>>>,
+}
+
+impl Method {
+    // Plain old Huffman coding.
+    pub fn new() -> Self {
+        Method {
+            coder: HUFFMAN, filters: vec![], preset: None, dictionary: None,
+            bit_order: BitOrder::Lsb, table: None, frequencies: None,
+        }
+    }
+}
+
+impl Default for Method {
+    fn default() -> Self {
+        Method::new()
+    }
+}
+
+// Compress a single block as the method says, falling back to raw storage if that would be smaller.
+pub fn compress_block(bytes: &[u8], method: &Method, report: &mut Report) -> Vec<u8> {
+    // Empty blocks stay empty.
+    if bytes.is_empty() {
+        return vec![]
+    }
+
+    // Filters run first, and the method's coder codes what they leave.
+    let coder = match method.filters.is_empty() {
+        true => { method.coder }
+        false => { FILTERED }
+    };
+    let (kind, compressed) = coder.encode(bytes, method, report);
+    with_kind(bytes, kind, compressed)
+}
+
+// Huffman code a block with frequencies counted elsewhere: over many blocks, in an earlier pass, or on another machine.
+// The frequencies are stored in the block, so it extracts like any other Huffman block.
+// Every byte in the block needs a frequency, or it would have no code.
+pub fn compress_with_frequencies(bytes: &[u8], frequencies: &HashMap<u8, u64>, order: BitOrder,
+                                        report: &mut Report) -> Result<Vec<u8>, String> {
+    if bytes.is_empty() {
+        return Ok(vec![])
+    }
+    let compressed = frequencies_block(bytes, frequencies, order, report)?;
+    Ok(with_kind(bytes, huffman_kind(order), compressed))
+}
+
+// Huffman code a block with frequencies counted elsewhere. The result doesn't include the kind.
+fn frequencies_block(bytes: &[u8], frequencies: &HashMap<u8, u64>, order: BitOrder,
+                     report: &mut Report) -> Result<Vec<u8>, String> {
+    let mut known = [false; 256];
+    frequencies.keys().for_each(|byte| known[*byte as usize] = true);
+    if let Some(byte) = bytes.iter().find(|byte| !known[**byte as usize]) {
+        return Err(format!("Byte {} has no frequency!", byte))
+    }
+
+    counted_huffman_block(bytes, frequencies.clone(), order, report)
+        .ok_or(String::from("These frequencies are so skewed that their codes are too long to use!"))
+}
+
+// Put the kind in front of a compressed block, unless storing the bytes raw would be smaller.
+fn with_kind(bytes: &[u8], kind: BlockKind, compressed: Vec<u8>) -> Vec<u8> {
+    let (kind, mut contents) = match compressed.len() > bytes.len() {
+        true => { (BlockKind::Raw, bytes.to_vec()) }
+        false => { (kind, compressed) }
+    };
+
+    let mut retval = vec![kind as u8];
+    retval.append(&mut contents);
+    retval
+}
+
+// Plain Huffman blocks say which way their bits are packed.
+fn huffman_kind(order: BitOrder) -> BlockKind {
+    match order {
+        BitOrder::Lsb => { BlockKind::Huffman }
+        BitOrder::Msb => { BlockKind::HuffmanMsb }
+    }
+}
+
+// Compress a block with each method, keeping whichever came out smallest.
+pub fn compress_smallest(bytes: &[u8], methods: &[Method], report: &mut Report) -> Vec<u8> {
+    methods.iter()
+        .map(|method| compress_block(bytes, method, report))
+        .min_by_key(|block| block.len())
+        .unwrap_or_default()
+}
+
+// Which dictionary a block was compressed with, if any.
+pub fn block_dictionary(bytes: &[u8]) -> Option<DictionaryId> {
+    match block_kind(bytes) {
+        Some(BlockKind::Dictionary) => { Some(slice_to_long(&bytes[1..1 + ID_LEN]) as DictionaryId) }
+        _ => { None }
+    }
+}
+
+// A block holding a tar index, rather than any data.
+pub fn index_block(index: TarIndex) -> Vec<u8> {
+    let mut retval = vec![BlockKind::Index as u8];
+    retval.append(&mut index.to_stream());
+    retval
+}
+
+// Read the index out of a block, if it is one.
+pub fn read_index(bytes: &[u8]) -> Option<TarIndex> {
+    match block_kind(bytes) {
+        Some(BlockKind::Index) => { TarIndex::from_whole_stream(&bytes[1..]).ok() }
+        _ => { None }
+    }
+}
+
+// A block holding a checksum of bytes, rather than any data.
+pub fn checksum_block(checksum: &dyn Checksum, bytes: &[u8]) -> Vec<u8> {
+    let mut retval = vec![BlockKind::Checksum as u8, checksum.id()];
+    retval.append(&mut checksum.digest(bytes));
+    retval
+}
+
+// Which checksum a checksum block holds, and its digest.
+// Checksums added by newer versions aren't known.
+pub fn read_checksum(block: &[u8]) -> Option<(&'static dyn Checksum, &[u8])> {
+    match block_kind(block)? {
+        BlockKind::Checksum => { Some((checksum_from_id(*block.get(1)?)?, &block[2..])) }
+        _ => { None }
+    }
+}
+
+// Check bytes against a checksum block.
+pub fn verify_checksum(block: &[u8], bytes: &[u8]) -> Result<(), String> {
+    let (checksum, digest) = read_checksum(block)
+        .ok_or("Unknown checksum: this file needs a newer version to check it!")?;
+    match checksum.digest(bytes) == digest {
+        true => { Ok(()) }
+        false => { Err(format!("Decompressed output failed its {} checksum!", checksum.name())) }
+    }
+}
+
+// What kind of block this is. Empty blocks have no kind.
+pub fn block_kind(bytes: &[u8]) -> Option<BlockKind> {
+    bytes.first().and_then(|kind| BlockKind::from_byte(*kind))
+}
+
+// The tree a Huffman block was coded with, and the frequencies it was built from.
+// Other kinds of blocks don't carry the counts of what they coded.
+pub fn block_tree(bytes: &[u8]) -> Option<(Tree, HashMap<u8, u64>)> {
+    match block_kind(bytes)? {
+        BlockKind::Huffman | BlockKind::HuffmanMsb => {
+            let (ordering, _) = Wzfile::from_whole_stream(&bytes[1..]).ok()?.deconstruct();
+            Some((huffman(&ordering)?, ordering))
+        }
+        _ => { None }
+    }
+}
+
+// The code each byte was given in a block, for the kinds that give bytes codes from a table.
+// Transformed and filtered blocks give the codes of the block inside them.
+pub fn block_codes(bytes: &[u8], dictionary: Option<&Dictionary>) -> Option<Encoding> {
+    match block_kind(bytes)? {
+        BlockKind::Huffman | BlockKind::HuffmanMsb => { block_tree(bytes).and_then(|(tree, _)| tree.gen_encoding()).map(|table| table.to_encoding()) }
+        BlockKind::Canonical => { bytes.get(2..2 + LENGTHS_LEN).map(|lens| canonical_encoding(&lengths_from_stream(lens))) }
+        BlockKind::Table => { read_table(bytes, &mut 2).ok() }
+        BlockKind::Preset => {
+            let preset = Preset::from_byte(*bytes.get(1)?)?;
+            huffman(&preset.frequencies()).and_then(|tree| tree.gen_encoding()).map(|table| table.to_encoding())
+        }
+        BlockKind::Dictionary => {
+            let id = slice_to_long(bytes.get(1..1 + ID_LEN)?) as DictionaryId;
+            let dictionary = dictionary.filter(|dictionary| dictionary.id() == id)?;
+            huffman(dictionary.frequencies()).and_then(|tree| tree.gen_encoding()).map(|table| table.to_encoding())
+        }
+        BlockKind::Bwt => { block_codes(bytes.get(1 + LONG_LEN..)?, dictionary) }
+        BlockKind::Filtered => {
+            let mut i = 1;
+            read_sized(bytes, &mut i).ok()?;
+            read_sized(bytes, &mut i).ok()?;
+            block_codes(&bytes[i..], dictionary)
+        }
+        _ => { None }
+    }
+}
+
+// What a block asks of a decoder before it yields any output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockHeader {
+    // Bytes before the coded data: tables, counts, and the headers of any blocks inside it.
+    pub len: usize,
+    // Entries in its frequency maps or code tables, which decoders build trees and tables from.
+    pub map_entries: usize,
+}
+
+// Measure a block's header, without building anything from it.
+// Decoders given untrusted blocks can turn away ones with outsized tables before spending anything on them.
+pub fn block_header(bytes: &[u8]) -> Result<BlockHeader, WzError> {
+    // Empty blocks are written for empty inputs.
+    let kind = match bytes.first() {
+        Some(kind) => { BlockKind::from_byte(*kind).ok_or(WzError::Malformed(format!("Unknown block kind: {}", kind)))? }
+        None => { return Ok(BlockHeader { len: 0, map_entries: 0 }) }
+    };
+    let mut i = 1;
+    let map_entries = match kind {
+        BlockKind::Huffman | BlockKind::HuffmanMsb => {
+            let len = read_map_len(bytes, &mut i)?;
+            let map = read_bytes(bytes, &mut i, len, "a frequency map")?;
+            read_sequence_len(bytes, &mut i)?;
+            read_bit_count(bytes, &mut i)?;
+            freqmap_entries(map)?
+        }
+        BlockKind::Range => {
+            let map = read_sized(bytes, &mut i)?;
+            read_long(bytes, &mut i)?;
+            freqmap_entries(map)?
+        }
+        BlockKind::Words | BlockKind::Pairs | BlockKind::Chars | BlockKind::Nibbles => {
+            // Words come with the words themselves, before their frequencies.
+            if kind == BlockKind::Words {
+                read_sized(bytes, &mut i)?;
+            }
+            let map = read_sized(bytes, &mut i)?;
+            read_long(bytes, &mut i)?;
+            read_bit_count(bytes, &mut i)?;
+            symbol_map_entries(map)?
+        }
+        BlockKind::Lz77 => {
+            let lengths = read_sized(bytes, &mut i)?;
+            let distances = read_sized(bytes, &mut i)?;
+            read_bit_count(bytes, &mut i)?;
+            symbol_map_entries(lengths)? + symbol_map_entries(distances)?
+        }
+        BlockKind::Canonical => {
+            read_bytes(bytes, &mut i, 1, "a bit order")?;
+            let lens = lengths_from_stream(read_bytes(bytes, &mut i, LENGTHS_LEN, "code lengths")?);
+            read_bit_count(bytes, &mut i)?;
+            lens.iter().filter(|len| **len > 0).count()
+        }
+        BlockKind::Table => {
+            read_bytes(bytes, &mut i, 1, "a bit order")?;
+            let lens = read_bytes(bytes, &mut i, 256, "code lengths")?;
+            read_sized(bytes, &mut i)?;
+            read_bit_count(bytes, &mut i)?;
+            lens.iter().filter(|len| **len > 0).count()
+        }
+        BlockKind::Preset => {
+            read_bytes(bytes, &mut i, 1, "a preset")?;
+            0
+        }
+        BlockKind::Dictionary => {
+            read_bytes(bytes, &mut i, ID_LEN, "a dictionary id")?;
+            0
+        }
+        // Transformed and filtered blocks have the header of the block inside them too.
+        BlockKind::Bwt => {
+            read_long(bytes, &mut i)?;
+            return nested(bytes, i)
+        }
+        BlockKind::Filtered => {
+            read_sized(bytes, &mut i)?;
+            read_sized(bytes, &mut i)?;
+            return nested(bytes, i)
+        }
+        BlockKind::Raw | BlockKind::Adaptive | BlockKind::Context | BlockKind::Lzw => { 0 }
+        // Indexes and checksums are all header, with nothing to decode.
+        BlockKind::Index | BlockKind::Checksum => {
+            i = bytes.len();
+            0
+        }
+    };
+    Ok(BlockHeader { len: i, map_entries })
+}
+
+// The header of the block starting at i, counting everything before it.
+fn nested(bytes: &[u8], i: usize) -> Result<BlockHeader, WzError> {
+    block_header(&bytes[i..]).map(|inner| BlockHeader { len: i + inner.len, ..inner })
+}
+
+// Entries in a stored Freqmap: a width, then a byte and a count of that width for each.
+fn freqmap_entries(map: &[u8]) -> Result<usize, WzError> {
+    let mut i = 0;
+    match map.is_empty() {
+        true => { Ok(0) }
+        false => { Ok((map.len() - ENTRY_WIDTH_LEN) / (1 + read_entry_width(map, &mut i)?)) }
+    }
+}
+
+// Entries in a stored SymbolFreqmap: two widths, then a symbol and a count of those widths for each.
+fn symbol_map_entries(map: &[u8]) -> Result<usize, WzError> {
+    let mut i = 0;
+    let symbol_width = read_entry_width(map, &mut i)?;
+    let count_width = read_entry_width(map, &mut i)?;
+    Ok((map.len() - i) / (symbol_width + count_width))
+}
+
+// Decompress a single block, whatever its kind.
+// Blocks coded with a dictionary can only be decompressed with that same dictionary.
+// Blocks come from outside, so one that can't be decoded is an error, never a panic.
+pub fn decompress_block(bytes: &[u8], dictionary: Option<&Dictionary>, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    decompress_block_with_limit(bytes, dictionary, usize::MAX, report)
+}
+
+// Decompress a single block, so long as it decodes to no more than limit bytes.
+// Decoding stops as soon as the output would pass the limit, so a block can't be made to fill memory.
+pub fn decompress_block_with_limit(bytes: &[u8], dictionary: Option<&Dictionary>, limit: usize,
+                                          report: &mut Report) -> Result<Vec<u8>, WzError> {
+    // Empty blocks are written for empty inputs.
+    if bytes.is_empty() {
+        return Ok(vec![])
+    }
+
+    let kind = block_kind(bytes).ok_or_else(|| WzError::Malformed(format!("Unknown block kind: {}", bytes[0])))?;
+    match (kind, coder_for_kind(kind)) {
+        // Indexes and checksums aren't data, so they decompress to nothing.
+        (BlockKind::Index | BlockKind::Checksum, _) => { Ok(vec![]) }
+        (_, Some(coder)) => { coder.decode(&bytes[1..], dictionary, limit, report) }
+        (kind, None) => { Err(WzError::Malformed(format!("No coder for block kind {:?}", kind))) }
+    }
+}
+
+// Run a block through each of the method's filters, then compress the result as a whole block of its own.
+// Filters can't shrink everything, so this falls back to compressing the block unfiltered.
+pub fn filtered_block(bytes: &[u8], method: &Method, report: &mut Report) -> (BlockKind, Vec<u8>) {
+    let inner = Method { filters: vec![], ..method.clone() };
+    let filtered = filter_chain(bytes, &method.filters, &inner, report);
+    let unfiltered = compress_block(bytes, &inner, report);
+    let mut smaller = match unfiltered.len() <= filtered.len() {
+        true => { unfiltered }
+        false => { filtered }
+    };
+    // Both are whole blocks, so the kind comes off the front.
+    match block_kind(&smaller) {
+        Some(kind) => { (kind, smaller.split_off(1)) }
+        None => { (BlockKind::Raw, smaller) }
+    }
+}
+
+// Each filter's output is a filtered block, holding the next filter's block inside it.
+fn filter_chain(bytes: &[u8], filters: &[Arc<dyn Filter>], inner: &Method, report: &mut Report) -> Vec<u8> {
+    let (filter, rest) = match filters.split_first() {
+        Some(first) => { first }
+        None => { return compress_block(bytes, inner, report) }
+    };
+    let (filtered, mut extra) = report.time("filtering", || filter.encode(bytes));
+
+    let mut retval = vec![BlockKind::Filtered as u8];
+    append_sized(&mut retval, &mut filter_to_stream(filter.as_ref()));
+    append_sized(&mut retval, &mut extra);
+    retval.append(&mut filter_chain(&filtered, rest, inner, report));
+    retval
+}
+
+// Decompress the inner block, then undo its filter.
+// No filter makes a block more than a quarter bigger, so the inner block is held to twice the limit.
+pub fn unfiltered_block(bytes: &[u8], dictionary: Option<&Dictionary>, limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut i = 0;
+    let filter = read_sized(bytes, &mut i).and_then(filter_from_stream)?;
+    let extra = read_sized(bytes, &mut i)?;
+    let filtered = decompress_block_with_limit(&bytes[i..], dictionary, limit.saturating_mul(2), report)?;
+    let retval = report.time("filtering", || filter.decode(&filtered, extra, limit))?;
+    within(retval.len(), limit)?;
+    Ok(retval)
+}
+
+// Apply Burrows-Wheeler and move-to-front, then compress the ranks as a whole block of their own.
+// Filters do this now, so it's only here to write the blocks older versions did.
+pub fn bwt_block(bytes: &[u8], method: &Method, report: &mut Report) -> Vec<u8> {
+    let (last, primary) = report.time("transforming", || bwt(bytes));
+    let ranks = report.time("transforming", || mtf(&last));
+    let mut retval = (primary as u64).to_le_bytes().to_vec();
+    retval.append(&mut compress_block(&ranks, &Method { filters: vec![], ..method.clone() }, report));
+    retval
+}
+
+// Decompress the inner block, then undo move-to-front and Burrows-Wheeler.
+pub fn unbwt_block(bytes: &[u8], dictionary: Option<&Dictionary>, limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut i = 0;
+    let primary = usize::try_from(read_long(bytes, &mut i)?).map_err(|_| WzError::Invalid("Burrows-Wheeler row"))?;
+    let ranks = decompress_block_with_limit(&bytes[i..], dictionary, limit, report)?;
+    report.time("transforming", || unbwt(&unmtf(&ranks), primary))
+}
+
+// Output may only grow so far. Decoders check as they go, and stop once they'd pass limit.
+pub fn within(len: usize, limit: usize) -> Result<(), WzError> {
+    match len <= limit {
+        true => { Ok(()) }
+        false => { Err(WzError::Full("decompressed output")) }
+    }
+}
+
+// Decode bytes until the bits run out.
+fn decode_bytes<R: Read>(decoding: &DecodingTable, reader: &mut BitReader<R>, limit: usize) -> Result<Vec<u8>, WzError> {
+    let mut retval = vec![];
+    while let Some(byte) = decoding.decode_next(reader) {
+        retval.push(byte);
+        within(retval.len(), limit)?;
+    }
+    Ok(retval)
+}
+
+// Huffman code a single block into a standalone wzfile, its bits packed in the given order.
+pub fn huffman_block(bytes: &[u8], order: BitOrder, report: &mut Report) -> Vec<u8> {
+    let ordering = report.time("counting", || gen_frequency(bytes));
+    // A code d bits long takes counts adding up to the (d + 2)th Fibonacci number, which passes 2^64 long before 128 bits.
+    // A block's own counts add up to its length, so its codes always fit, and this always codes it.
+    counted_huffman_block(bytes, ordering, order, report).unwrap_or_default()
+}
+
+// Huffman code a block with frequencies that have already been counted.
+// None if the frequencies make codes too long to use.
+// NOTE: every byte must have a frequency!
+fn counted_huffman_block(bytes: &[u8], ordering: HashMap<u8, u64>, order: BitOrder, report: &mut Report) -> Option<Vec<u8>> {
+    let heap = report.time("tree building", || huffman(&ordering));
+
+    // Create an empty file, do not do any additional work.
+    // This allows future encoding to rely on no "nones" being present.
+    let Some(heap) = heap else { return Some(vec![]) };
+
+    let table = report.time("encoding", || heap.gen_encoding())?;
+    let (encoded, seq_len) = report.time("encoding", || {
+        let seq = packed_stream(order, |writer| table.write(bytes, writer));
+        let seq_len = seq.len();
+        (Wzfile::packed_to_stream(ordering, seq), seq_len)
+    });
+    report.measure("tree", heap.memory_size());
+    report.measure("code table", table.memory_size());
+    report.measure("bit buffer", seq_len);
+    Some(encoded)
+}
+
+// Write a block's bits straight into a stored sequence, packed in the given order.
+fn packed_stream(order: BitOrder, write: impl FnOnce(&mut BitWriter<Vec<u8>>) -> io::Result<()>) -> Vec<u8> {
+    let mut writer = BitWriter::with_order(vec![], order);
+    // Writing to a vector never fails.
+    write(&mut writer).unwrap();
+    writer.into_packed_stream()
+}
+
+// Write the code of each symbol in turn.
+// NOTE: every symbol must have a code!
+fn write_codes<W: Write>(symbols: impl IntoIterator<Item = usize>, codes: &[Option<BitSequence>],
+                         writer: &mut BitWriter<W>) -> io::Result<()> {
+    symbols.into_iter().try_for_each(|symbol| writer.write_seq(codes[symbol].as_ref().unwrap()))
+}
+
+// Decode a single wzfile, its bits packed in the given order.
+pub fn unhuffman_block(bytes: &[u8], order: BitOrder, limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let (ordering, mut reader) = report.time("parsing", || Wzfile::read_packed(bytes, order))?;
+    let heap = report.time("tree building", || huffman(&ordering.into()));
+
+    if heap.is_none() {
+        return Ok(vec![])
+    }
+
+    let heap = heap.unwrap();
+    // Need to gen decoding.
+    let decoding = report.time("decoding", || heap.gen_decoding());
+    // Now, need to turn each bit in bitsequence into a regular byte in output file.
+    let bytes = report.time("decoding", || decode_bytes(&decoding, &mut reader, limit))?;
+    report.measure("tree", heap.memory_size());
+    report.measure("decoding table", decoding.memory_size());
+    Ok(bytes)
+}
+
+// Plain Huffman code a block, its bits packed as the method says.
+// Blocks are counted unless the method has frequencies, and still are if the frequencies never saw one of their bytes.
+pub fn plain_huffman_block(bytes: &[u8], method: &Method, report: &mut Report) -> (BlockKind, Vec<u8>) {
+    let counted = method.frequencies.as_ref()
+        .and_then(|frequencies| frequencies_block(bytes, frequencies, method.bit_order, report).ok());
+    let compressed = counted.unwrap_or_else(|| huffman_block(bytes, method.bit_order, report));
+    (huffman_kind(method.bit_order), compressed)
+}
+
+// Huffman code a block with canonical codes, storing only their lengths.
+pub fn canonical_block(bytes: &[u8], order: BitOrder, report: &mut Report) -> Vec<u8> {
+    let ordering = report.time("counting", || gen_frequency(bytes));
+    let lens = report.time("tree building", || byte_lengths(&ordering));
+    report.time("encoding", || {
+        let encoding = canonical_encoding(&lens);
+        let mut retval = vec![order as u8];
+        retval.append(&mut lengths_to_stream(&lens));
+        retval.append(&mut packed_stream(order, |writer| write_codes(bytes.iter().map(|byte| *byte as usize), &encoding, writer)));
+        retval
+    })
+}
+
+// Rebuild the codes of a canonical block from their lengths, then decode it.
+pub fn uncanonical_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut i = 0;
+    let order = read_bit_order(bytes, &mut i)?;
+    let lens = lengths_from_stream(read_bytes(bytes, &mut i, LENGTHS_LEN, "code lengths")?);
+    let decoding = report.time("tree building", || canonical_decoding(&lens)).map_err(WzError::Malformed)?;
+    let mut reader = report.time("parsing", || BitReader::from_packed_stream(&bytes[i..], order))?;
+
+    report.time("decoding", || decode_bytes(&decoding, &mut reader, limit))
+}
+
+fn read_bit_order(bytes: &[u8], i: &mut usize) -> Result<BitOrder, WzError> {
+    let byte = read_bytes(bytes, i, 1, "a bit order")?[0];
+    BitOrder::from_byte(byte).ok_or_else(|| WzError::Malformed(format!("Unknown bit order: {}", byte)))
+}
+
+// Huffman code a block with the user's own table, stored with it so that it can be decoded.
+// Methods without a table count the block's own.
+pub fn table_block(bytes: &[u8], method: &Method, report: &mut Report) -> (BlockKind, Vec<u8>) {
+    match &method.table {
+        Some(table) => { (BlockKind::Table, coded_with_table(bytes, table, method.bit_order, report)) }
+        None => { plain_huffman_block(bytes, method, report) }
+    }
+}
+
+fn coded_with_table(bytes: &[u8], table: &Encoding, order: BitOrder, report: &mut Report) -> Vec<u8> {
+    let mut retval = vec![order as u8];
+    retval.extend(table.iter().map(|code| code.as_ref().map_or(0, |code| code.len() as u8)));
+    let mut codes = packed_stream(BitOrder::Lsb, |writer| table.iter().flatten().try_for_each(|code| writer.write_seq(code)));
+    append_sized(&mut retval, &mut codes);
+
+    report.time("encoding", || {
+        retval.append(&mut packed_stream(order, |writer| write_codes(bytes.iter().map(|byte| *byte as usize), table, writer)));
+    });
+    retval
+}
+
+// Read a table of codes: a length for each byte, then all the codes.
+fn read_table(bytes: &[u8], i: &mut usize) -> Result<Encoding, WzError> {
+    let lens = read_bytes(bytes, i, 256, "code lengths")?;
+    let codes = read_sized(bytes, i).and_then(BitSequence::from_whole_stream)?;
+    let total: u64 = lens.iter().map(|len| *len as u64).sum();
+    if total != codes.len() {
+        return Err(WzError::Malformed(format!("Code lengths add up to {} bits, but there are {}!", total, codes.len())))
+    }
+
+    let mut table: Encoding = std::array::from_fn(|_| None);
+    let mut position = 0;
+    for (byte, len) in lens.iter().enumerate().filter(|(_, len)| **len > 0) {
+        table[byte] = Some(codes.slice(position..position + *len as u64));
+        position += *len as u64;
+    }
+    Ok(table)
+}
+
+// Decode a block coded with the user's own table.
+// The table is only as good as whoever wrote the block, so codes that aren't prefix free are an error.
+pub fn untable_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut i = 0;
+    let order = read_bit_order(bytes, &mut i)?;
+    let table = report.time("parsing", || read_table(bytes, &mut i))?;
+    let mut reader = report.time("parsing", || BitReader::from_packed_stream(&bytes[i..], order))?;
+
+    let mut decoding = DecodingTable::new();
+    for (byte, code) in table.iter().enumerate() {
+        if let Some(code) = code {
+            if !decoding.try_insert(code, byte as Symbol) {
+                return Err(WzError::Malformed(format!("The code for byte {} overlaps another!", byte)))
+            }
+        }
+    }
+    report.time("decoding", || decode_bytes(&decoding, &mut reader, limit))
+}
+
+// Huffman code a block with the method's built in table, or its own table if the method has none.
+pub fn preset_block(bytes: &[u8], method: &Method, report: &mut Report) -> (BlockKind, Vec<u8>) {
+    match method.preset {
+        Some(preset) => { shared_table_block(bytes, BlockKind::Preset, vec![preset as u8], &preset.frequencies(), report) }
+        None => { (BlockKind::Huffman, huffman_block(bytes, BitOrder::Lsb, report)) }
+    }
+}
+
+// Decode a block coded with a built in table: which preset, then the bits.
+pub fn unpreset_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let byte = read_bytes(bytes, &mut 0, 1, "a preset")?[0];
+    let preset = Preset::from_byte(byte).ok_or_else(|| WzError::Malformed(format!("Unknown preset: {}", byte)))?;
+    unshared_table_block(&bytes[1..], &preset.frequencies(), limit, report)
+}
+
+// Huffman code a block with the method's trained dictionary, or its own table if the method has none.
+pub fn dictionary_block(bytes: &[u8], method: &Method, report: &mut Report) -> (BlockKind, Vec<u8>) {
+    match &method.dictionary {
+        Some(dictionary) => {
+            let id = dictionary.id().to_le_bytes().to_vec();
+            shared_table_block(bytes, BlockKind::Dictionary, id, dictionary.frequencies(), report)
+        }
+        None => { (BlockKind::Huffman, huffman_block(bytes, BitOrder::Lsb, report)) }
+    }
+}
+
+// Decode a block coded with a trained dictionary, which must be the one it names.
+pub fn undictionary_block(bytes: &[u8], dictionary: Option<&Dictionary>, limit: usize,
+                          report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let id = slice_to_long(read_bytes(bytes, &mut 0, ID_LEN, "a dictionary id")?) as DictionaryId;
+    match dictionary {
+        Some(dictionary) if dictionary.id() == id => {
+            unshared_table_block(&bytes[ID_LEN..], dictionary.frequencies(), limit, report)
+        }
+        // Not corrupt, just asked for the wrong way, so the message says how to ask.
+        Some(_) => { Err(WzError::Malformed(String::from("This block was compressed with a different dictionary!"))) }
+        None => { Err(WzError::Malformed(String::from("This block was compressed with a dictionary: use --dict!"))) }
+    }
+}
+
+// Huffman code a block with a table that isn't stored in it, i.e. a preset or dictionary.
+// The header says which table. Big blocks can afford a table of their own, so whichever is smaller wins.
+// A table without a usable code for every byte in the block can't code it at all, so the block gets its own.
+fn shared_table_block(bytes: &[u8], kind: BlockKind, header: Vec<u8>, frequencies: &HashMap<u8, u64>,
+                      report: &mut Report) -> (BlockKind, Vec<u8>) {
+    let own = huffman_block(bytes, BitOrder::Lsb, report);
+    let table = report.time("tree building", || huffman(frequencies).and_then(|tree| tree.gen_encoding()))
+        .filter(|_| bytes.iter().all(|byte| frequencies.contains_key(byte)));
+    let Some(table) = table else { return (BlockKind::Huffman, own) };
+    let mut retval = header;
+    report.time("encoding", || {
+        retval.append(&mut packed_stream(BitOrder::Lsb, |writer| table.write(bytes, writer)));
+    });
+
+    match own.len() < retval.len() {
+        true => { (BlockKind::Huffman, own) }
+        false => { (kind, retval) }
+    }
+}
+
+// Decode the bits of a block coded with a table that isn't stored in it.
+fn unshared_table_block(bytes: &[u8], frequencies: &HashMap<u8, u64>, limit: usize,
+                        report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut reader = report.time("parsing", || BitReader::from_packed_stream(bytes, BitOrder::Lsb))?;
+    let tree = report.time("tree building", || huffman(frequencies))
+        .ok_or_else(|| WzError::Malformed(String::from("The block's shared table codes no bytes!")))?;
+    let decoding = tree.gen_decoding();
+    report.time("decoding", || decode_bytes(&decoding, &mut reader, limit))
+}
+
+// Adaptive Huffman code a single block.
+// Only one pass is needed, and only the bits are stored.
+pub fn adaptive_block(bytes: &[u8], report: &mut Report) -> Vec<u8> {
+    report.time("encoding", || {
+        let mut tree = AdaptiveTree::new();
+        packed_stream(BitOrder::Lsb, |writer| bytes.iter().try_for_each(|byte| tree.encode(*byte, writer)))
+    })
+}
+
+// Decode a single adaptive Huffman block, relearning the code as it goes.
+pub fn unadaptive_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut reader = report.time("parsing", || BitReader::from_packed_stream(bytes, BitOrder::Lsb))?;
+    report.time("decoding", || {
+        let mut tree = AdaptiveTree::new();
+        let mut retval = vec![];
+        while let Some(byte) = tree.decode(&mut reader) {
+            retval.push(byte);
+            within(retval.len(), limit)?;
+        }
+        Ok(retval)
+    })
+}
+
+// Code a single block with a separate adaptive tree for each previous byte.
+// Trees are only created for contexts which actually appear.
+pub fn context_block(bytes: &[u8], report: &mut Report) -> Vec<u8> {
+    report.time("encoding", || {
+        let mut trees: Vec<Option<AdaptiveTree>> = (0..256).map(|_| None).collect();
+        // Before the first byte, pretend the previous byte was zero.
+        let mut previous = 0;
+        packed_stream(BitOrder::Lsb, |writer| {
+            for byte in bytes {
+                let tree = trees[previous as usize].get_or_insert_with(AdaptiveTree::new);
+                tree.encode(*byte, writer)?;
+                previous = *byte;
+            }
+            Ok(())
+        })
+    })
+}
+
+// Decode a single order-1 block, relearning every context's code as it goes.
+pub fn uncontext_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut reader = report.time("parsing", || BitReader::from_packed_stream(bytes, BitOrder::Lsb))?;
+    report.time("decoding", || {
+        let mut trees: Vec<Option<AdaptiveTree>> = (0..256).map(|_| None).collect();
+        let mut retval = vec![];
+        let mut previous = 0;
+        loop {
+            let tree = trees[previous as usize].get_or_insert_with(AdaptiveTree::new);
+            match tree.decode(&mut reader) {
+                Some(byte) => {
+                    retval.push(byte);
+                    within(retval.len(), limit)?;
+                    previous = byte;
+                }
+                None => { break }
+            }
+        }
+        Ok(retval)
+    })
+}
+
+// Range code a single block.
+// The frequency table is counted just as for Huffman, then scaled to fit the range coder.
+pub fn range_block(bytes: &[u8], report: &mut Report) -> Vec<u8> {
+    let ordering = report.time("counting", || {
+        scale_frequencies(&gen_frequency(bytes), MAX_TOTAL)
+    });
+
+    report.time("encoding", || {
+        let model = Model::new(&ordering);
+        let mut encoder = RangeEncoder::new();
+        for byte in bytes {
+            encoder.encode(*byte, &model);
+        }
+
+        let mut retval = vec![];
+        append_sized(&mut retval, &mut Freqmap::from(ordering).to_stream());
+        retval.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        retval.append(&mut encoder.finish());
+        retval
+    })
+}
+
+// Decode a single range coded block.
+pub fn unrange_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut i = 0;
+    let (ordering, len): (HashMap<u8, u64>, u64) = report.time("parsing", || {
+        let ordering = read_sized(bytes, &mut i).and_then(Freqmap::from_whole_stream)?.into();
+        Ok::<_, WzError>((ordering, read_long(bytes, &mut i)?))
+    })?;
+    // The model can only split the range so finely, and there must be something to split it between.
+    let total = ordering.values().try_fold(0u64, |total, freq| total.checked_add(*freq));
+    if total.is_none_or(|total| total > MAX_TOTAL || (total == 0 && len > 0)) {
+        return Err(WzError::Invalid("range coder frequencies"))
+    }
+    // The byte count is stored, so a block too big is caught before anything is decoded.
+    within(usize::try_from(len).unwrap_or(usize::MAX), limit)?;
+
+    Ok(report.time("decoding", || {
+        let model = Model::new(&ordering);
+        let mut decoder = RangeDecoder::new(&bytes[i..]);
+        (0..len).map(|_| decoder.decode(&model)).collect()
+    }))
+}
+
+// Split a block into words, then Huffman code each word as a single symbol.
+pub fn words_block(bytes: &[u8], report: &mut Report) -> Vec<u8> {
+    let (dictionary, tokens) = report.time("counting", || tokenize(bytes));
+    let mut retval = vec![];
+    append_sized(&mut retval, &mut dictionary_to_stream(&dictionary));
+    retval.append(&mut symbols_block(&tokens, report));
+    retval
+}
+
+// Decode a single block of words.
+pub fn unwords_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut i = 0;
+    let dictionary = report.time("parsing", || read_sized(bytes, &mut i).and_then(dictionary_from_stream))?;
+    // Every word is at least a byte, so there can't be more words than bytes of output.
+    let tokens = unsymbols_block(&bytes[i..], limit, report)?;
+    let mut retval = vec![];
+    for token in tokens {
+        let word = dictionary.get(token as usize)
+            .ok_or_else(|| WzError::Malformed(format!("Word {} isn't in the block's dictionary!", token)))?;
+        retval.extend_from_slice(word);
+        within(retval.len(), limit)?;
+    }
+    Ok(retval)
+}
+
+// Huffman code each character of UTF-8 text as a single symbol.
+pub fn chars_block(bytes: &[u8], report: &mut Report) -> Vec<u8> {
+    let chars = report.time("counting", || to_chars(bytes));
+    symbols_block(&chars, report)
+}
+
+pub fn unchars_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let chars = unsymbols_block(bytes, limit, report)?;
+    let retval = report.time("decoding", || from_chars(&chars))?;
+    within(retval.len(), limit)?;
+    Ok(retval)
+}
+
+// Huffman code each half of each byte as a separate symbol.
+// With only sixteen symbols, the table is tiny, which suits hex dumps and DNA.
+pub fn nibbles_block(bytes: &[u8], report: &mut Report) -> Vec<u8> {
+    let nibbles: Vec<Symbol> = bytes.iter()
+        .flat_map(|byte| [(byte >> 4) as Symbol, (byte & 0xf) as Symbol])
+        .collect();
+    symbols_block(&nibbles, report)
+}
+
+pub fn unnibbles_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let nibbles = unsymbols_block(bytes, limit.saturating_mul(2), report)?;
+    Ok(nibbles.chunks_exact(2).map(|pair| (pair[0] << 4 | pair[1]) as u8).collect())
+}
+
+// Huffman code a series of symbols from any alphabet.
+/*
+  CONTENTS:
+  -- symbol frequencies
+  -- number of symbols
+  -- coded bits.
+ */
+fn symbols_block(symbols: &[Symbol], report: &mut Report) -> Vec<u8> {
+    let ordering = report.time("counting", || {
+        let mut ordering: HashMap<Symbol, u64> = HashMap::new();
+        symbols.iter().for_each(|symbol| *ordering.entry(*symbol).or_default() += 1);
+        ordering
+    });
+    let tree = report.time("tree building", || huffman_symbols(&ordering)).unwrap();
+
+    report.time("encoding", || {
+        let encoding = tree.gen_symbol_encoding();
+        let mut retval = vec![];
+        append_sized(&mut retval, &mut SymbolFreqmap::new(ordering).to_stream());
+        retval.extend_from_slice(&(symbols.len() as u64).to_le_bytes());
+        retval.append(&mut packed_stream(BitOrder::Lsb, |writer| {
+            write_codes(symbols.iter().map(|symbol| *symbol as usize), &encoding, writer)
+        }));
+        retval
+    })
+}
+
+fn unsymbols_block(bytes: &[u8], max_count: usize, report: &mut Report) -> Result<Vec<Symbol>, WzError> {
+    let mut i = 0;
+    let (ordering, count) = report.time("parsing", || {
+        let ordering = read_sized(bytes, &mut i).and_then(SymbolFreqmap::from_whole_stream)?.take();
+        Ok::<_, WzError>((ordering, read_long(bytes, &mut i)?))
+    })?;
+    let mut reader = BitReader::from_packed_stream(&bytes[i..], BitOrder::Lsb)?;
+    within(usize::try_from(count).unwrap_or(usize::MAX), max_count)?;
+    // Every symbol takes at least a bit, so there can't be more symbols than bits.
+    if count > reader.limit() {
+        return Err(WzError::Truncated("a symbol block"))
+    }
+    let decoding = report.time("tree building", || symbol_decoding(&ordering))?;
+
+    report.time("decoding", || {
+        (0..count).map(|_| decoding.decode_symbol(&mut reader).ok_or(WzError::Truncated("a symbol block"))).collect()
+    })
+}
+
+// Every block of symbols codes at least one, so there's always a tree to build.
+fn symbol_decoding(ordering: &HashMap<Symbol, u64>) -> Result<DecodingTable, WzError> {
+    huffman_symbols(ordering).map(|tree| tree.gen_decoding()).ok_or(WzError::Invalid("symbol frequencies"))
+}
+
+// Huffman code a block two bytes at a time, so that common pairs get short codes.
+// An odd byte at the end is padded with a zero.
+pub fn pairs_block(bytes: &[u8], report: &mut Report) -> Vec<u8> {
+    let symbols: Vec<Symbol> = bytes.chunks(2)
+        .map(|pair| (pair[0] as Symbol) << 8 | *pair.get(1).unwrap_or(&0) as Symbol)
+        .collect();
+    let ordering = report.time("counting", || {
+        let mut ordering: HashMap<Symbol, u64> = HashMap::new();
+        symbols.iter().for_each(|symbol| *ordering.entry(*symbol).or_default() += 1);
+        ordering
+    });
+    let tree = report.time("tree building", || huffman_symbols(&ordering)).unwrap();
+
+    report.time("encoding", || {
+        let encoding = tree.gen_symbol_encoding();
+        let mut retval = vec![];
+        append_sized(&mut retval, &mut SymbolFreqmap::new(ordering).to_stream());
+        retval.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        retval.append(&mut packed_stream(BitOrder::Lsb, |writer| {
+            write_codes(symbols.iter().map(|symbol| *symbol as usize), &encoding, writer)
+        }));
+        retval
+    })
+}
+
+// Decode a single block of byte pairs, dropping any padding.
+pub fn unpairs_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut i = 0;
+    let (ordering, len) = report.time("parsing", || {
+        let ordering = read_sized(bytes, &mut i).and_then(SymbolFreqmap::from_whole_stream)?.take();
+        Ok::<_, WzError>((ordering, read_long(bytes, &mut i)?))
+    })?;
+    within(usize::try_from(len).unwrap_or(usize::MAX), limit)?;
+    let mut reader = BitReader::from_packed_stream(&bytes[i..], BitOrder::Lsb)?;
+    let decoding = report.time("tree building", || symbol_decoding(&ordering))?;
+
+    report.time("decoding", || {
+        let mut retval = vec![];
+        while (retval.len() as u64) < len {
+            let symbol = decoding.decode_symbol(&mut reader).ok_or(WzError::Truncated("a pairs block"))?;
+            retval.push((symbol >> 8) as u8);
+            retval.push(symbol as u8);
+        }
+        retval.truncate(len as usize);
+        Ok(retval)
+    })
+}
+
+// LZW code a single block.
+pub fn lzw_block(bytes: &[u8], report: &mut Report) -> Vec<u8> {
+    report.time("encoding", || packed_stream(BitOrder::Lsb, |writer| lzw_encode(bytes, writer)))
+}
+
+// Decode a single LZW block.
+pub fn unlzw_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut reader = report.time("parsing", || BitReader::from_packed_stream(bytes, BitOrder::Lsb))?;
+    report.time("decoding", || lzw_decode(&mut reader, limit))
+}
+
+// In the literal/length alphabet, bytes are themselves, followed by the end marker.
+// Match lengths are bucketed after that.
+const END_OF_BLOCK: Symbol = 256;
+const FIRST_LENGTH: Symbol = 257;
+
+// Find repeated strings in a block, then Huffman code the literals, lengths and distances.
+// Lengths and distances are bucketed, so only the bucket codes need frequencies.
+pub fn lz77_block(bytes: &[u8], report: &mut Report) -> Vec<u8> {
+    let tokens = report.time("matching", || find_matches(bytes, WINDOW_SIZE));
+
+    let (lengths, distances) = report.time("counting", || {
+        let mut lengths: HashMap<Symbol, u64> = HashMap::new();
+        let mut distances: HashMap<Symbol, u64> = HashMap::new();
+        for token in &tokens {
+            match token {
+                Token::Literal(byte) => { *lengths.entry(*byte as Symbol).or_default() += 1 }
+                Token::Match { length, distance } => {
+                    *lengths.entry(FIRST_LENGTH + bucket(length - MIN_MATCH).0).or_default() += 1;
+                    *distances.entry(bucket(distance - 1).0).or_default() += 1;
+                }
+            }
+        }
+        lengths.insert(END_OF_BLOCK, 1);
+        (lengths, distances)
+    });
+
+    // There's always an end marker, but there may not be any matches.
+    let length_tree = report.time("tree building", || huffman_symbols(&lengths)).unwrap();
+    let distance_tree = report.time("tree building", || huffman_symbols(&distances));
+
+    report.time("encoding", || {
+        let length_codes = length_tree.gen_symbol_encoding();
+        let distance_codes = distance_tree.map(|tree| tree.gen_symbol_encoding()).unwrap_or_default();
+        let seq = packed_stream(BitOrder::Lsb, |writer| {
+            for token in &tokens {
+                match token {
+                    Token::Literal(byte) => { write_codes([*byte as usize], &length_codes, writer)? }
+                    Token::Match { length, distance } => {
+                        let (code, extra_bits, extra) = bucket(length - MIN_MATCH);
+                        write_codes([(FIRST_LENGTH + code) as usize], &length_codes, writer)?;
+                        writer.write_bits(extra as u64, extra_bits)?;
+
+                        let (code, extra_bits, extra) = bucket(distance - 1);
+                        write_codes([code as usize], &distance_codes, writer)?;
+                        writer.write_bits(extra as u64, extra_bits)?;
+                    }
+                }
+            }
+            write_codes([END_OF_BLOCK as usize], &length_codes, writer)
+        });
+
+        let mut retval = vec![];
+        append_sized(&mut retval, &mut SymbolFreqmap::new(lengths).to_stream());
+        append_sized(&mut retval, &mut SymbolFreqmap::new(distances).to_stream());
+        retval.extend_from_slice(&seq);
+        retval
+    })
+}
+
+// Decode a single LZ77 block, stopping at the end marker.
+pub fn unlz77_block(bytes: &[u8], limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+    let mut i = 0;
+    let (lengths, distances, mut reader) = report.time("parsing", || {
+        let lengths = read_sized(bytes, &mut i).and_then(SymbolFreqmap::from_whole_stream)?.take();
+        let distances = read_sized(bytes, &mut i).and_then(SymbolFreqmap::from_whole_stream)?.take();
+        Ok::<_, WzError>((lengths, distances, BitReader::from_packed_stream(&bytes[i..], BitOrder::Lsb)?))
+    })?;
+
+    let length_table = report.time("tree building", || symbol_decoding(&lengths))?;
+    let distance_table = report.time("tree building", || huffman_symbols(&distances).map(|tree| tree.gen_decoding()));
+
+    let tokens = report.time("decoding", || {
+        let mut tokens = vec![];
+        loop {
+            let symbol = length_table.decode_symbol(&mut reader).ok_or(WzError::Truncated("an LZ77 block"))?;
+            match symbol {
+                END_OF_BLOCK => { break }
+                byte if byte < END_OF_BLOCK => { tokens.push(Token::Literal(byte as u8)) }
+                code => {
+                    let length = read_bucketed(&mut reader, code - FIRST_LENGTH)? + MIN_MATCH;
+                    let code = distance_table.as_ref()
+                        .and_then(|table| table.decode_symbol(&mut reader))
+                        .ok_or(WzError::Truncated("an LZ77 match distance"))?;
+                    let distance = read_bucketed(&mut reader, code)? + 1;
+                    tokens.push(Token::Match { length, distance });
+                }
+            }
+        }
+        Ok::<_, WzError>(tokens)
+    })?;
+
+    report.time("transforming", || expand(&tokens, limit))
+}
+
+// No match is anywhere near 4GB long, or that far back, so bigger buckets only turn up in corrupt blocks.
+const MAX_BUCKET: Symbol = 64;
+
+// Read the extra bits for a bucket code, returning the value they stand for.
+fn read_bucketed<R: Read>(reader: &mut BitReader<R>, code: Symbol) -> Result<usize, WzError> {
+    if code >= MAX_BUCKET {
+        return Err(WzError::Malformed(format!("No LZ77 bucket has code {}!", code)))
+    }
+    let (base, extra_bits) = bucket_base(code);
+    let extra = reader.read_bits(extra_bits).ok_or(WzError::Truncated("an LZ77 match"))?;
+    Ok(base + extra as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::block::{block_codes, block_dictionary, block_header, block_kind, block_tree, BlockHeader, BlockKind, checksum_block, compress_block, compress_smallest, compress_with_frequencies, decompress_block, decompress_block_with_limit, index_block, Method, read_index, shared_table_block, unshared_table_block, verify_checksum, with_kind};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use crate::codec::coder::{ADAPTIVE, CANONICAL, CHARS, CONTEXT, DICTIONARY, EntropyCoder, HUFFMAN, LZ77, LZW, NIBBLES, PAIRS, PRESET, RANGE, TABLE, WORDS};
+    use crate::diagnostics::report::Report;
+    use crate::encoding::bitsequence::{BitOrder, BitSequence, Encoding};
+    use crate::file::tar::{TarIndex, TarMember};
+    use crate::ordering::dictionary::Dictionary;
+    use crate::ordering::presets::Preset;
+    use crate::file::bytestream::{append_sized, ByteStream, read_sized};
+    use crate::file::checksum::{Checksum, Crc32, Sha256, XxHash64};
+    use crate::file::error::WzError;
+    use crate::file::format::packed_len;
+    use crate::file::wzfile::Wzfile;
+    use crate::transform::bwt::bwt;
+    use crate::transform::filter::{Bpe, Bwt, Delta, Rle, Transpose};
+    use crate::transform::mtf::mtf;
+    use crate::tree::node::huffman;
+
+    #[test]
+    fn test_huffman_block() {
+        let mut report = Report::new(false);
+        let bytes = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbbbbbbbbbbbbc".to_vec();
+        let block = compress_block(&bytes, &Method { coder: HUFFMAN, ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::Huffman as u8, block[0]);
+        assert!(block.len() < bytes.len());
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_msb_block() {
+        let mut report = Report::new(false);
+        let bytes = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbbbbbbbbbbbbc".to_vec();
+        let lsb = compress_block(&bytes, &Method::new(), &mut report);
+        let msb = compress_block(&bytes, &Method { bit_order: BitOrder::Msb, ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::HuffmanMsb as u8, msb[0]);
+        // Same codes, same table, so only the packing of the bits differs.
+        assert_eq!(lsb.len(), msb.len());
+        assert_ne!(lsb[1..], msb[1..]);
+        assert_eq!(bytes, decompress_block(&msb, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_canonical_block() {
+        let mut report = Report::new(false);
+        let bytes = b"abracadabra, the canonical codes are rebuilt from their lengths alone".repeat(10);
+        for bit_order in [BitOrder::Lsb, BitOrder::Msb] {
+            let block = compress_block(&bytes, &Method { coder: CANONICAL, bit_order, ..Method::new() }, &mut report);
+            assert_eq!(BlockKind::Canonical as u8, block[0]);
+            assert_eq!(bit_order as u8, block[1]);
+            assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_block_codes() {
+        let mut report = Report::new(false);
+        // Big enough to be worth a table, so not stored raw.
+        let bytes = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbbbbbbbbbbbbc".repeat(10);
+        for method in [Method::new(), Method { coder: CANONICAL, ..Method::new() }] {
+            let block = compress_block(&bytes, &method, &mut report);
+            let codes = block_codes(&block, None).unwrap();
+            assert!(codes[b'a' as usize].is_some());
+            assert!(codes[b'z' as usize].is_none());
+        }
+
+        // After move-to-front, repeats are coded as zeroes.
+        let block = compress_block(&bytes, &Method { filters: vec![Arc::new(Bwt)], ..Method::new() }, &mut report);
+        assert!(block_codes(&block, None).unwrap()[0].is_some());
+
+        let block = compress_block(&bytes, &Method { coder: LZ77, ..Method::new() }, &mut report);
+        assert_eq!(Some(BlockKind::Lz77), block_kind(&block));
+        assert!(block_codes(&block, None).is_none());
+        assert_eq!(None, block_kind(&[]));
+    }
+
+    #[test]
+    fn test_block_header() {
+        let mut report = Report::new(false);
+        let bytes = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbbbbbbbbbbbbc".repeat(4);
+        for coder in [HUFFMAN as &dyn EntropyCoder, RANGE, LZ77, PAIRS, CHARS, NIBBLES, WORDS, ADAPTIVE, CONTEXT, LZW] {
+            let block = compress_block(&bytes, &Method { coder, ..Method::new() }, &mut report);
+            assert!(block_header(&block).unwrap().len < block.len());
+        }
+
+        // A wzfile's header is its map, its sequence's length and its bit count.
+        let huffman = compress_block(&bytes, &Method::new(), &mut report);
+        let header = block_header(&huffman).unwrap();
+        assert_eq!(3, header.map_entries);
+        let bits = u64::from_le_bytes(huffman[header.len - 8..header.len].try_into().unwrap());
+        assert_eq!(packed_len(bits) as usize, huffman.len() - header.len);
+        let canonical = compress_block(&bytes, &Method { coder: CANONICAL, ..Method::new() }, &mut report);
+        assert_eq!(3, block_header(&canonical).unwrap().map_entries);
+
+        // Filtered blocks count their filter, and the block inside.
+        let filtered = compress_block(&bytes, &Method { filters: vec![Arc::new(Rle)], ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::Filtered as u8, filtered[0]);
+        let mut i = 1;
+        read_sized(&filtered, &mut i).unwrap();
+        read_sized(&filtered, &mut i).unwrap();
+        let inner = block_header(&filtered[i..]).unwrap();
+        assert_eq!(BlockHeader { len: i + inner.len, map_entries: inner.map_entries }, block_header(&filtered).unwrap());
+
+        assert_eq!(BlockHeader { len: 0, map_entries: 0 }, block_header(&[]).unwrap());
+        assert_eq!(1, block_header(&[BlockKind::Raw as u8, 1, 2, 3]).unwrap().len);
+        assert!(block_header(&huffman[..header.len - 1]).is_err());
+        assert!(block_header(&[200]).is_err());
+    }
+
+    #[test]
+    fn test_table_block() {
+        let mut report = Report::new(false);
+        let bytes = b"abcabcabcaaaa".repeat(50);
+        let mut table: Encoding = std::array::from_fn(|_| None);
+        table[b'a' as usize] = Some(BitSequence::from_bits(&[1]));
+        table[b'b' as usize] = Some(BitSequence::from_bits(&[0, 1]));
+        table[b'c' as usize] = Some(BitSequence::from_bits(&[0, 0, 1]));
+        // Codes which are never used still count as part of the table.
+        table[b'z' as usize] = Some(BitSequence::from_bits(&[0, 0, 0]));
+        let method = Method { coder: TABLE, table: Some(Arc::new(table.clone())), ..Method::new() };
+
+        let block = compress_block(&bytes, &method, &mut report);
+        assert_eq!(BlockKind::Table as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+        assert_eq!(Some(table), block_codes(&block, None));
+    }
+
+    // Every byte appearing once can't be compressed, so it should be stored raw.
+    #[test]
+    fn test_raw_block() {
+        let mut report = Report::new(false);
+        let bytes: Vec<u8> = (0..=255).collect();
+        let block = compress_block(&bytes, &Method { coder: HUFFMAN, ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::Raw as u8, block[0]);
+        assert_eq!(bytes.len() + 1, block.len());
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_adaptive_block() {
+        let mut report = Report::new(false);
+        let bytes = b"she sells sea shells by the sea shore, she sells sea shells".to_vec();
+        let block = compress_block(&bytes, &Method { coder: ADAPTIVE, ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::Adaptive as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_range_block() {
+        let mut report = Report::new(false);
+        let mut bytes = vec![b'x'; 3000];
+        bytes.extend_from_slice(b"with a little bit of variety at the end");
+        let block = compress_block(&bytes, &Method { coder: RANGE, ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::Range as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_context_block() {
+        let mut report = Report::new(false);
+        let bytes = b"qu qu qu, the queen quietly quit the quarry. quite!".repeat(20);
+        let block = compress_block(&bytes, &Method { coder: CONTEXT, ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::Context as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_bwt_block() {
+        let mut report = Report::new(false);
+        let bytes = b"how much wood would a woodchuck chuck if a woodchuck could chuck wood".repeat(10);
+        for coder in [HUFFMAN as &dyn EntropyCoder, RANGE] {
+            let block = compress_block(&bytes, &Method { coder, filters: vec![Arc::new(Bwt)], ..Method::new() }, &mut report);
+            assert_eq!(BlockKind::Filtered as u8, block[0]);
+            assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+        }
+
+        // Older versions wrote transformed blocks as a kind of their own.
+        let (last, primary) = bwt(&bytes);
+        let mut block = vec![BlockKind::Bwt as u8];
+        block.extend_from_slice(&(primary as u64).to_le_bytes());
+        block.append(&mut compress_block(&mtf(&last), &Method::new(), &mut report));
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_lz77_block() {
+        let mut report = Report::new(false);
+        let bytes = b"to be or not to be, that is the question. ".repeat(50);
+        let block = compress_block(&bytes, &Method { coder: LZ77, ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::Lz77 as u8, block[0]);
+        // Repeats cost a few bits apiece.
+        assert!(block.len() < bytes.len() / 10);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+
+        // No repeats at all, so there are no distances.
+        let bytes = b"abcdefghijklmnopqrstuvwxyzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz".to_vec();
+        let block = compress_block(&bytes, &Method { coder: LZ77, ..Method::new() }, &mut report);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+        let bytes = b"aabbccddeeffgghh".to_vec();
+        let block = compress_block(&bytes, &Method { coder: LZ77, ..Method::new() }, &mut report);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_lzw_block() {
+        let mut report = Report::new(false);
+        let bytes = b"abababababababababababababababababababab, abababababab".repeat(10);
+        let block = compress_block(&bytes, &Method { coder: LZW, ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::Lzw as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_words_block() {
+        let mut report = Report::new(false);
+        let bytes = b"It was the best of times, it was the worst of times, it was the age of wisdom. ".repeat(40);
+        let block = compress_block(&bytes, &Method { coder: WORDS, ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::Words as u8, block[0]);
+        assert!(block.len() < compress_block(&bytes, &Method::new(), &mut report).len());
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_chars_block() {
+        let mut report = Report::new(false);
+        let bytes = "Съешь же ещё этих мягких французских булок, да выпей чаю. ".repeat(10).into_bytes();
+        let block = compress_block(&bytes, &Method { coder: CHARS, ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::Chars as u8, block[0]);
+        assert!(block.len() < compress_block(&bytes, &Method::new(), &mut report).len());
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_nibbles_block() {
+        let mut report = Report::new(false);
+        let bytes = b"GATTACACCGTAGGCTTAACGTAGCTAGCTAGGATCGATCGATTTACGAGCATCGACTAGCATCAG".repeat(4);
+        let block = compress_block(&bytes, &Method { coder: NIBBLES, ..Method::new() }, &mut report);
+        assert_eq!(BlockKind::Nibbles as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_pairs_block() {
+        let mut report = Report::new(false);
+        for bytes in [b"abcdabcdabcdabcdabcdacbdabcdabcd".repeat(5), b"abcdabcdabcdabcdabcdacbdabcdabc".repeat(5)] {
+            let block = compress_block(&bytes, &Method { coder: PAIRS, ..Method::new() }, &mut report);
+            assert_eq!(BlockKind::Pairs as u8, block[0]);
+            assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_preset_block() {
+        let mut report = Report::new(false);
+        let method = Method { coder: PRESET, preset: Some(Preset::Json), ..Method::new() };
+        let bytes = br#"{"name": "wz", "version": "1.0.0", "tags": ["small"]}"#.to_vec();
+        let block = compress_block(&bytes, &method, &mut report);
+        assert_eq!(BlockKind::Preset as u8, block[0]);
+        assert!(block.len() < compress_block(&bytes, &Method::new(), &mut report).len());
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+
+        // Lots of one byte is better served by a table of its own.
+        let bytes = vec![b'q'; 5000];
+        let block = compress_block(&bytes, &method, &mut report);
+        assert_eq!(BlockKind::Huffman as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_dictionary_block() {
+        let mut report = Report::new(false);
+        let dictionary = Dictionary::train(&[&b"key=value\nname=wz\nsize=12\n".repeat(100)]);
+        let method = Method { coder: DICTIONARY, dictionary: Some(Arc::new(dictionary.clone())), ..Method::new() };
+        let bytes = b"name=value\nsize=12\nkey=wz\n".to_vec();
+        let block = compress_block(&bytes, &method, &mut report);
+        assert_eq!(BlockKind::Dictionary as u8, block[0]);
+        assert_eq!(Some(dictionary.id()), block_dictionary(&block));
+        assert_eq!(bytes, decompress_block(&block, Some(&dictionary), &mut report).unwrap());
+    }
+
+    // A table missing a byte in the block can't code it, and one with no bytes at all can't decode anything.
+    #[test]
+    fn test_partial_table() {
+        let mut report = Report::new(false);
+        let bytes = b"text with bytes the table lacks".to_vec();
+        let frequencies = HashMap::from([(b'a', 5)]);
+        let (kind, block) = shared_table_block(&bytes, BlockKind::Dictionary, vec![0; 4], &frequencies, &mut report);
+        assert_eq!(BlockKind::Huffman, kind);
+        assert_eq!(bytes, decompress_block(&with_kind(&bytes, kind, block), None, &mut report).unwrap());
+        assert!(shared_table_block(b"", BlockKind::Dictionary, vec![0; 4], &HashMap::new(), &mut report).1.is_empty());
+        assert!(unshared_table_block(&[0; 9], &HashMap::new(), usize::MAX, &mut report).is_err());
+    }
+
+    #[test]
+    fn test_missing_dictionary() {
+        let mut report = Report::new(false);
+        let dictionary = Dictionary::train(&[&b"abc".repeat(100)]);
+        let method = Method { coder: DICTIONARY, dictionary: Some(Arc::new(dictionary)), ..Method::new() };
+        let block = compress_block(b"abcabcabcabcabcabcbacabcabcabcabcabcabcabc", &method, &mut report);
+        assert!(decompress_block(&block, None, &mut report).is_err());
+        let other = Dictionary::train(&[&b"xyz".repeat(100)]);
+        assert!(decompress_block(&block, Some(&other), &mut report).is_err());
+    }
+
+    // Counts of u64::MAX build a chain of codes too long to code with, but a block made of them still decodes.
+    #[test]
+    fn test_saturated_counts() {
+        let mut report = Report::new(false);
+        let ordering: HashMap<u8, u64> = (0..=255).map(|byte| (byte, u64::MAX)).collect();
+        let seq = huffman(&ordering).unwrap().gen_symbol_encoding()[b'a' as usize].clone().unwrap();
+        let mut block = vec![BlockKind::Huffman as u8];
+        block.append(&mut Wzfile::new(ordering.clone(), seq, BitOrder::Lsb).to_stream());
+        assert!(block_codes(&block, None).is_none());
+        assert_eq!(b"a".to_vec(), decompress_block(&block, None, &mut report).unwrap());
+        assert!(compress_with_frequencies(b"a", &ordering, BitOrder::Lsb, &mut report).is_err());
+    }
+
+    #[test]
+    fn test_compress_with_frequencies() {
+        let mut report = Report::new(false);
+        let bytes = b"aaaabbc".repeat(100);
+        // Counted elsewhere, so they needn't match the block.
+        let frequencies = HashMap::from([(b'a', 1), (b'b', 1), (b'c', 50), (b'd', 50)]);
+
+        let block = compress_with_frequencies(&bytes, &frequencies, BitOrder::Lsb, &mut report).unwrap();
+        assert_eq!(BlockKind::Huffman as u8, block[0]);
+        assert_eq!(Some(frequencies.clone()), block_tree(&block).map(|(_, ordering)| ordering));
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+
+        let block = compress_with_frequencies(&bytes, &frequencies, BitOrder::Msb, &mut report).unwrap();
+        assert_eq!(BlockKind::HuffmanMsb as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+
+        assert!(compress_with_frequencies(b"abce", &frequencies, BitOrder::Lsb, &mut report).is_err());
+        assert_eq!(Ok(vec![]), compress_with_frequencies(b"", &frequencies, BitOrder::Lsb, &mut report));
+
+        // Through a method, blocks with bytes the frequencies never saw are counted instead.
+        let method = Method { frequencies: Some(Arc::new(frequencies)), ..Method::new() };
+        let block = compress_block(b"abcexyzabcexyzabcexyzabcexyz", &method, &mut report);
+        assert_eq!(b"abcexyzabcexyzabcexyzabcexyz".to_vec(), decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_compress_smallest() {
+        let mut report = Report::new(false);
+        let bytes = b"abcdefgh".repeat(500);
+        let methods = [Method::new(), Method { coder: LZ77, ..Method::new() }];
+        let block = compress_smallest(&bytes, &methods, &mut report);
+        assert_eq!(BlockKind::Lz77 as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_index_block() {
+        let mut report = Report::new(false);
+        let member = TarMember { name: String::from("a.txt"), offset: 512, size: 3 };
+        let index = TarIndex::new(vec![1024], vec![member]);
+        let block = index_block(index.clone());
+        assert_eq!(Some(index), read_index(&block));
+        assert!(decompress_block(&block, None, &mut report).unwrap().is_empty());
+        assert_eq!(None, read_index(&compress_block(b"abc", &Method::new(), &mut report)));
+    }
+
+    #[test]
+    fn test_checksum_block() {
+        let mut report = Report::new(false);
+        for checksum in [&Crc32 as &dyn Checksum, &XxHash64, &Sha256] {
+            let block = checksum_block(checksum, b"checked");
+            assert_eq!(Some(BlockKind::Checksum), block_kind(&block));
+            assert!(decompress_block(&block, None, &mut report).unwrap().is_empty());
+            assert_eq!(Ok(()), verify_checksum(&block, b"checked"));
+            assert!(verify_checksum(&block, b"chucked").is_err());
+        }
+        assert!(verify_checksum(&[BlockKind::Checksum as u8, 200, 0, 0, 0, 0], b"").is_err());
+    }
+
+    #[test]
+    fn test_filtered_block() {
+        let mut report = Report::new(false);
+        let mut bytes = vec![0; 5000];
+        bytes.extend_from_slice(b"a short interruption");
+        bytes.extend(vec![255; 5000]);
+        let method = Method { filters: vec![Arc::new(Rle)], ..Method::new() };
+        let block = compress_block(&bytes, &method, &mut report);
+        assert_eq!(BlockKind::Filtered as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+
+        // Without runs, filtering doesn't help.
+        let bytes = b"abcabcabcabcabcabcabcabcabcabcabcabcabc".to_vec();
+        let block = compress_block(&bytes, &method, &mut report);
+        assert_eq!(BlockKind::Huffman as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+
+        // A table of records: a counter and a constant tag.
+        // Transposing doesn't change how often each byte appears, but it does put like next to like.
+        let bytes: Vec<u8> = (0..2000u32).flat_map(|i| [i.to_le_bytes(), [0xab, 0xcd, 0, 1]].concat()).collect();
+        let plain = Method { coder: CONTEXT, ..Method::new() };
+        let method = Method { filters: vec![Arc::new(Transpose(8))], ..plain.clone() };
+        let block = compress_block(&bytes, &method, &mut report);
+        assert_eq!(BlockKind::Filtered as u8, block[0]);
+        assert!(block.len() < compress_block(&bytes, &plain, &mut report).len());
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+
+        let bytes = b"in the thick of the theatre, there they thought it through. ".repeat(30);
+        let method = Method { filters: vec![Arc::new(Bpe)], ..Method::new() };
+        let block = compress_block(&bytes, &method, &mut report);
+        assert_eq!(BlockKind::Filtered as u8, block[0]);
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_filter_chain() {
+        let mut report = Report::new(false);
+        // Records of a slowly rising counter: transposed, each column rises by one, so its deltas are all the same.
+        let bytes: Vec<u8> = (0..3000u32).flat_map(|i| [(i / 3) as u8, 7, (i % 5) as u8]).collect();
+        let method = Method { filters: vec![Arc::new(Transpose(3)), Arc::new(Delta), Arc::new(Rle)], ..Method::new() };
+        let block = compress_block(&bytes, &method, &mut report);
+        assert_eq!(BlockKind::Filtered as u8, block[0]);
+        assert!(block.len() < compress_block(&bytes, &Method::new(), &mut report).len());
+        assert_eq!(bytes, decompress_block(&block, None, &mut report).unwrap());
+
+        // The last filter's block holds the coded bytes.
+        let mut depth = 0;
+        let mut inner = &block[..];
+        while block_kind(inner) == Some(BlockKind::Filtered) {
+            let mut i = 1;
+            read_sized(inner, &mut i).unwrap();
+            read_sized(inner, &mut i).unwrap();
+            inner = &inner[i..];
+            depth += 1;
+        }
+        assert_eq!(3, depth);
+    }
+
+    #[test]
+    fn test_empty_block() {
+        let mut report = Report::new(false);
+        assert!(compress_block(&[], &Method::new(), &mut report).is_empty());
+        assert!(decompress_block(&[], None, &mut report).unwrap().is_empty());
+    }
+
+    // Blocks that can't be decoded are errors, whatever their kind.
+    #[test]
+    fn test_corrupt_block() {
+        let mut report = Report::new(false);
+        // Two codes, one a prefix of the other.
+        let mut table = vec![BlockKind::Table as u8, BitOrder::Lsb as u8];
+        let mut lens = [0; 256];
+        lens[b'a' as usize] = 1;
+        lens[b'b' as usize] = 2;
+        table.extend_from_slice(&lens);
+        append_sized(&mut table, &mut BitSequence::from_bits(&[1, 1, 0]).to_stream());
+        table.append(&mut BitSequence::from_bits(&[1, 1, 0]).to_stream());
+        // A row past the end of the block.
+        let mut bwt = vec![BlockKind::Bwt as u8];
+        bwt.extend_from_slice(&100u64.to_le_bytes());
+        bwt.extend_from_slice(&[BlockKind::Raw as u8, b'a', b'b']);
+
+        let blocks = [vec![99], vec![BlockKind::Canonical as u8], vec![BlockKind::Canonical as u8, 9, 0], table, bwt,
+                      vec![BlockKind::Preset as u8, 200, 0], vec![BlockKind::Filtered as u8, 1]];
+        for block in blocks {
+            assert!(decompress_block(&block, None, &mut report).is_err(), "{:?} decoded", block);
+        }
+    }
+
+    // A single block of zeros squashes down to almost nothing, whatever it's coded with.
+    // Decoding gives up as soon as it would pass the limit, rather than after filling memory.
+    #[test]
+    fn test_bomb_block() {
+        let mut report = Report::new(false);
+        let bytes = vec![0; 1 << 20];
+        let methods = [
+            Method::new(), Method { coder: LZ77, ..Method::new() }, Method { coder: LZW, ..Method::new() },
+            Method { coder: RANGE, ..Method::new() }, Method { coder: PAIRS, ..Method::new() },
+            Method { coder: ADAPTIVE, ..Method::new() }, Method { coder: WORDS, ..Method::new() },
+            Method { filters: vec![Arc::new(Rle)], ..Method::new() },
+            Method { filters: vec![Arc::new(Bpe)], ..Method::new() },
+        ];
+        for method in methods {
+            let block = compress_block(&bytes, &method, &mut report);
+            assert_eq!(bytes, decompress_block_with_limit(&block, None, bytes.len(), &mut report).unwrap());
+            assert!(matches!(decompress_block_with_limit(&block, None, 1024, &mut report), Err(WzError::Full(_))),
+                    "{} decoded past its limit", method.coder.name());
+        }
+    }
+}
@@ -0,0 +1,292 @@
+// Entropy coders: the ways the bytes of a block can be coded.
+// Each writes its own kind of block, so the kind byte says which coder to read it back with.
+// Every coder is found through CODERS, so adding one means implementing EntropyCoder, giving it a BlockKind, and listing it there.
+// The command line offers a flag for each coder that codes bytes on its own. The rest are chosen by the options they need.
+// Author: Will Morris
+
+use std::fmt::Debug;
+use crate::codec::block::{adaptive_block, BlockKind, bwt_block, canonical_block, chars_block, context_block,
+                          dictionary_block, filtered_block, huffman_block, lz77_block, lzw_block, Method, nibbles_block,
+                          pairs_block, plain_huffman_block, preset_block, range_block, table_block, unadaptive_block,
+                          unbwt_block, uncanonical_block, unchars_block, uncontext_block, undictionary_block,
+                          unfiltered_block, unhuffman_block, unlz77_block, unlzw_block, unnibbles_block, unpairs_block,
+                          unpreset_block, unrange_block, untable_block, unwords_block, within, words_block};
+use crate::diagnostics::report::Report;
+use crate::encoding::bitsequence::BitOrder;
+use crate::file::error::WzError;
+use crate::ordering::dictionary::Dictionary;
+
+// Coders are shared by every thread compressing at once, so they can't hold anything a thread could change.
+pub trait EntropyCoder: Debug + Sync {
+    // The kind of block this coder writes.
+    fn kind(&self) -> BlockKind;
+
+    // Its name, and what it does. Coders with a flag on the command line are asked for by name.
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    fn flag(&self) -> bool;
+
+    // Code a whole block as the method says. The result doesn't include the kind, which is returned with it:
+    // usually this coder's own, but a coder may hand the block to another that codes it better.
+    fn encode(&self, bytes: &[u8], method: &Method, report: &mut Report) -> (BlockKind, Vec<u8>);
+
+    // Decode a whole block, without its kind. Blocks may be corrupt, so this must never panic.
+    // Decoding stops with WzError::Full as soon as the output would pass limit bytes.
+    // Blocks coded with a dictionary need that same dictionary to decode.
+    fn decode(&self, bytes: &[u8], dictionary: Option<&Dictionary>, limit: usize,
+              report: &mut Report) -> Result<Vec<u8>, WzError>;
+}
+
+// Coders are the same if they write the same kind of block.
+impl PartialEq for dyn EntropyCoder {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind() == other.kind()
+    }
+}
+
+// Decodes a block, without its kind, to no more than a limit of bytes.
+type BlockDecoder = fn(&[u8], usize, &mut Report) -> Result<Vec<u8>, WzError>;
+
+// A coder which codes bytes on its own, needing nothing but a pair of block functions.
+// Each of these has a flag on the command line.
+#[derive(Debug)]
+pub struct BlockCoder {
+    kind: BlockKind,
+    name: &'static str,
+    description: &'static str,
+    encode: fn(&[u8], &mut Report) -> Vec<u8>,
+    decode: BlockDecoder,
+}
+
+impl EntropyCoder for BlockCoder {
+    fn kind(&self) -> BlockKind {
+        self.kind
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn flag(&self) -> bool {
+        true
+    }
+
+    fn encode(&self, bytes: &[u8], _: &Method, report: &mut Report) -> (BlockKind, Vec<u8>) {
+        (self.kind, (self.encode)(bytes, report))
+    }
+
+    fn decode(&self, bytes: &[u8], _: Option<&Dictionary>, limit: usize, report: &mut Report) -> Result<Vec<u8>, WzError> {
+        (self.decode)(bytes, limit, report)
+    }
+}
+
+// Codes a block as a method says, returning the kind of block it wrote and its contents.
+type MethodEncoder = fn(&[u8], &Method, &mut Report) -> (BlockKind, Vec<u8>);
+// Decodes a block, without its kind, with the dictionary it may have been coded with.
+type MethodDecoder = fn(&[u8], Option<&Dictionary>, usize, &mut Report) -> Result<Vec<u8>, WzError>;
+
+// A coder which needs more than the bytes: the method's table, preset, filters or bit order, or a dictionary to decode.
+// None of these has a flag of its own. They're chosen by the options they need, or only ever read.
+#[derive(Debug)]
+pub struct MethodCoder {
+    kind: BlockKind,
+    name: &'static str,
+    description: &'static str,
+    encode: MethodEncoder,
+    decode: MethodDecoder,
+}
+
+impl EntropyCoder for MethodCoder {
+    fn kind(&self) -> BlockKind {
+        self.kind
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn flag(&self) -> bool {
+        false
+    }
+
+    fn encode(&self, bytes: &[u8], method: &Method, report: &mut Report) -> (BlockKind, Vec<u8>) {
+        (self.encode)(bytes, method, report)
+    }
+
+    fn decode(&self, bytes: &[u8], dictionary: Option<&Dictionary>, limit: usize,
+              report: &mut Report) -> Result<Vec<u8>, WzError> {
+        (self.decode)(bytes, dictionary, limit, report)
+    }
+}
+
+// Plain Huffman coding, with a frequency table in each block. Used unless another coder is asked for.
+// Its bits are packed in the method's order, and counted from the method's frequencies when it has them.
+pub const HUFFMAN: &MethodCoder = &MethodCoder {
+    kind: BlockKind::Huffman, name: "huffman", description: "Huffman code each block with its own frequency table",
+    encode: plain_huffman_block,
+    decode: |bytes, _, limit, report| unhuffman_block(bytes, BitOrder::Lsb, limit, report),
+};
+pub const HUFFMAN_MSB: &MethodCoder = &MethodCoder {
+    kind: BlockKind::HuffmanMsb, name: "huffman-msb", description: "Huffman code, packing bits from the high end of each byte",
+    encode: |bytes, _, report| (BlockKind::HuffmanMsb, huffman_block(bytes, BitOrder::Msb, report)),
+    decode: |bytes, _, limit, report| unhuffman_block(bytes, BitOrder::Msb, limit, report),
+};
+pub const CANONICAL: &MethodCoder = &MethodCoder {
+    kind: BlockKind::Canonical, name: "canonical", description: "Huffman code with canonical codes, storing only their lengths",
+    encode: |bytes, method, report| (BlockKind::Canonical, canonical_block(bytes, method.bit_order, report)),
+    decode: |bytes, _, limit, report| uncanonical_block(bytes, limit, report),
+};
+pub const TABLE: &MethodCoder = &MethodCoder {
+    kind: BlockKind::Table, name: "table", description: "Huffman code with the method's own table of codes",
+    encode: table_block,
+    decode: |bytes, _, limit, report| untable_block(bytes, limit, report),
+};
+pub const PRESET: &MethodCoder = &MethodCoder {
+    kind: BlockKind::Preset, name: "preset", description: "Huffman code with the method's built in table",
+    encode: preset_block,
+    decode: |bytes, _, limit, report| unpreset_block(bytes, limit, report),
+};
+pub const DICTIONARY: &MethodCoder = &MethodCoder {
+    kind: BlockKind::Dictionary, name: "dictionary", description: "Huffman code with the method's trained dictionary",
+    encode: dictionary_block, decode: undictionary_block,
+};
+pub const FILTERED: &MethodCoder = &MethodCoder {
+    kind: BlockKind::Filtered, name: "filtered", description: "run the method's filters, then code with its coder",
+    encode: filtered_block, decode: unfiltered_block,
+};
+pub const BWT: &MethodCoder = &MethodCoder {
+    kind: BlockKind::Bwt, name: "bwt", description: "apply Burrows-Wheeler and move-to-front, then code with the method's coder",
+    encode: |bytes, method, report| (BlockKind::Bwt, bwt_block(bytes, method, report)),
+    decode: unbwt_block,
+};
+pub const RAW: &MethodCoder = &MethodCoder {
+    kind: BlockKind::Raw, name: "raw", description: "store the bytes untouched",
+    encode: |bytes, _, _| (BlockKind::Raw, bytes.to_vec()),
+    decode: |bytes, _, limit, _| within(bytes.len(), limit).map(|_| bytes.to_vec()),
+};
+pub const ADAPTIVE: &BlockCoder = &BlockCoder {
+    kind: BlockKind::Adaptive, name: "adaptive", description: "use single-pass adaptive Huffman coding, with no stored table",
+    encode: adaptive_block, decode: unadaptive_block,
+};
+pub const RANGE: &BlockCoder = &BlockCoder {
+    kind: BlockKind::Range, name: "range", description: "use range coding, which beats Huffman on skewed data",
+    encode: range_block, decode: unrange_block,
+};
+pub const CONTEXT: &BlockCoder = &BlockCoder {
+    kind: BlockKind::Context, name: "order1", description: "use adaptive Huffman coding, with a code for each previous byte",
+    encode: context_block, decode: uncontext_block,
+};
+pub const LZ77: &BlockCoder = &BlockCoder {
+    kind: BlockKind::Lz77, name: "lz77", description: "replace repeated strings with back references, then Huffman code",
+    encode: lz77_block, decode: unlz77_block,
+};
+pub const LZW: &BlockCoder = &BlockCoder {
+    kind: BlockKind::Lzw, name: "lzw", description: "use LZW dictionary coding, which suits very repetitive data",
+    encode: lzw_block, decode: unlzw_block,
+};
+pub const WORDS: &BlockCoder = &BlockCoder {
+    kind: BlockKind::Words, name: "words", description: "Huffman code whole words of text, rather than bytes",
+    encode: words_block, decode: unwords_block,
+};
+pub const PAIRS: &BlockCoder = &BlockCoder {
+    kind: BlockKind::Pairs, name: "pairs", description: "Huffman code pairs of bytes as 16 bit symbols",
+    encode: pairs_block, decode: unpairs_block,
+};
+pub const CHARS: &BlockCoder = &BlockCoder {
+    kind: BlockKind::Chars, name: "chars", description: "Huffman code the characters of UTF-8 text, rather than bytes",
+    encode: chars_block, decode: unchars_block,
+};
+pub const NIBBLES: &BlockCoder = &BlockCoder {
+    kind: BlockKind::Nibbles, name: "nibbles", description: "Huffman code half bytes, for tiny alphabets like hex or DNA",
+    encode: nibbles_block, decode: unnibbles_block,
+};
+
+// Every coder there is. The first is the default.
+pub const CODERS: [&dyn EntropyCoder; 18] = [
+    HUFFMAN, ADAPTIVE, RANGE, CONTEXT, LZ77, LZW, WORDS, PAIRS, CHARS, NIBBLES,
+    HUFFMAN_MSB, CANONICAL, TABLE, PRESET, DICTIONARY, FILTERED, BWT, RAW,
+];
+
+// Find the coder that wrote a kind of block. Indexes and checksums aren't data, so they have none.
+pub fn coder_for_kind(kind: BlockKind) -> Option<&'static dyn EntropyCoder> {
+    CODERS.into_iter().find(|coder| coder.kind() == kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::block::{block_kind, BlockKind, Method};
+    use crate::codec::coder::{coder_for_kind, CODERS};
+    use crate::diagnostics::report::Report;
+    use crate::ordering::dictionary::Dictionary;
+    use crate::ordering::freq::gen_frequency;
+    use crate::ordering::presets::Preset;
+    use crate::transform::filter::Rle;
+    use crate::tree::node::huffman;
+    use std::sync::Arc;
+
+    // A method with everything any coder could ask for.
+    fn method(bytes: &[u8]) -> Method {
+        let table = huffman(&gen_frequency(bytes)).and_then(|tree| tree.gen_encoding()).unwrap().to_encoding();
+        Method {
+            filters: vec![Arc::new(Rle)], preset: Some(Preset::Text), dictionary: Some(Arc::new(Dictionary::train(&[bytes]))),
+            table: Some(Arc::new(table)), ..Method::new()
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut report = Report::new(false);
+        let bytes = b"the rain in spain falls mainly on the plain. ".repeat(20);
+        let method = method(&bytes);
+        for coder in CODERS {
+            // Coders may hand the block to another, which then reads it back.
+            let (kind, encoded) = coder.encode(&bytes, &method, &mut report);
+            let decoder = coder_for_kind(kind).unwrap();
+            let decoded = decoder.decode(&encoded, method.dictionary.as_deref(), usize::MAX, &mut report).unwrap();
+            assert_eq!(bytes, decoded, "{} didn't round trip", coder.name());
+        }
+    }
+
+    // A block cut short anywhere is an error, or at worst decodes to less. It never panics.
+    #[test]
+    fn test_truncated() {
+        let mut report = Report::new(false);
+        let bytes = b"she sells sea shells by the sea shore".repeat(4);
+        let method = method(&bytes);
+        for coder in CODERS {
+            let (kind, encoded) = coder.encode(&bytes, &method, &mut report);
+            let decoder = coder_for_kind(kind).unwrap();
+            for len in 0..encoded.len() {
+                if let Ok(decoded) = decoder.decode(&encoded[..len], method.dictionary.as_deref(), usize::MAX, &mut report) {
+                    assert_ne!(bytes, decoded, "{} decoded a truncated block", coder.name());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        for coder in CODERS {
+            assert_eq!(Some(coder.name()), coder_for_kind(coder.kind()).map(|found| found.name()));
+        }
+        // Every coder has its own kind, and its own name.
+        let mut names: Vec<&str> = CODERS.iter().map(|coder| coder.name()).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(CODERS.len(), names.len());
+
+        // Every kind of block holding data has a coder to read it.
+        for kind in (0..=u8::MAX).filter_map(|byte| block_kind(&[byte])) {
+            let data = !matches!(kind, BlockKind::Index | BlockKind::Checksum);
+            assert_eq!(data, coder_for_kind(kind).is_some(), "{:?}", kind);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// Compares decompressed output against the file it came from, as it's decoded, without writing any of it.
+// Decoding stops at the first difference, so a bad file is found without decoding the rest of it.
+// Author: Will Morris
+
+use std::io::{self, ErrorKind, Read, Write};
+
+// Where decompressed output first parted ways with the original.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Difference {
+    // The byte at offset wasn't the same.
+    Byte { offset: u64, original: u8, decompressed: u8 },
+    // The original ended at offset, with more output still to come.
+    OriginalShorter(u64),
+    // The output ended at offset, with more of the original still to come.
+    OriginalLonger(u64),
+}
+
+// Takes decompressed output as it's written, reading the same amount of original to check it against.
+// Once the two differ, writes fail, so whatever is decoding stops.
+pub(crate) struct Comparer<R> {
+    original: R,
+    // How many bytes have matched so far.
+    offset: u64,
+    difference: Option<Difference>,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> Comparer<R> {
+    pub(crate) fn new(original: R) -> Self {
+        Comparer { original, offset: 0, difference: None, buf: vec![] }
+    }
+
+    // How many bytes matched before the output ended or went wrong.
+    pub(crate) fn matched(&self) -> u64 {
+        self.offset
+    }
+
+    // Once the output is done, check that the original is too. Returns the first difference, if there was one.
+    pub(crate) fn finish(&mut self) -> io::Result<Option<Difference>> {
+        if self.difference.is_none() && self.fill(1)? > 0 {
+            self.difference = Some(Difference::OriginalLonger(self.offset));
+        }
+        Ok(self.difference)
+    }
+
+    // Read up to len bytes of original into buf, stopping short only at its end. Returns how many were read.
+    fn fill(&mut self, len: usize) -> io::Result<usize> {
+        self.buf.resize(len, 0);
+        let mut read = 0;
+        while read < len {
+            match self.original.read(&mut self.buf[read..]) {
+                Ok(0) => { break }
+                Ok(n) => { read += n }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => { return Err(e) }
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Read> Write for Comparer<R> {
+    fn write(&mut self, decompressed: &[u8]) -> io::Result<usize> {
+        if self.difference.is_some() {
+            return Err(io::Error::other("Output already differs from the original!"))
+        }
+        let read = self.fill(decompressed.len())?;
+        let mismatch = self.buf[..read].iter().zip(decompressed).position(|(original, decompressed)| original != decompressed);
+        self.difference = match (mismatch, read < decompressed.len()) {
+            (Some(i), _) => {
+                Some(Difference::Byte { offset: self.offset + i as u64, original: self.buf[i], decompressed: decompressed[i] })
+            }
+            (None, true) => { Some(Difference::OriginalShorter(self.offset + read as u64)) }
+            (None, false) => { None }
+        };
+        match self.difference {
+            Some(_) => { Err(io::Error::other("Output differs from the original!")) }
+            None => {
+                self.offset += read as u64;
+                Ok(read)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use crate::codec::comparer::{Comparer, Difference};
+
+    #[test]
+    fn test_same() {
+        let original: Vec<u8> = (0..100).collect();
+        let mut comparer = Comparer::new(&original[..]);
+        original.chunks(7).for_each(|chunk| comparer.write_all(chunk).unwrap());
+        assert_eq!(None, comparer.finish().unwrap());
+        assert_eq!(100, comparer.matched());
+
+        let mut empty = Comparer::new(&[][..]);
+        assert_eq!(None, empty.finish().unwrap());
+    }
+
+    #[test]
+    fn test_differences() {
+        let original: Vec<u8> = (0..100).collect();
+        let mut changed = original.clone();
+        changed[42] = 0xff;
+        let mut comparer = Comparer::new(&original[..]);
+        comparer.write_all(&changed[..40]).unwrap();
+        assert!(comparer.write_all(&changed[40..]).is_err());
+        // Nothing more is taken once they differ.
+        assert!(comparer.write_all(&changed[..1]).is_err());
+        assert_eq!(Some(Difference::Byte { offset: 42, original: 42, decompressed: 0xff }), comparer.finish().unwrap());
+        assert_eq!(40, comparer.matched());
+
+        let mut comparer = Comparer::new(&original[..50]);
+        assert!(comparer.write_all(&original).is_err());
+        assert_eq!(Some(Difference::OriginalShorter(50)), comparer.finish().unwrap());
+
+        let mut comparer = Comparer::new(&original[..]);
+        comparer.write_all(&original[..60]).unwrap();
+        assert_eq!(Some(Difference::OriginalLonger(60)), comparer.finish().unwrap());
+    }
+}
@@ -0,0 +1,135 @@
+// Joins compressed files together, without decompressing them.
+// Blocks and chunks are copied as they are. Only the indexes around them are rewritten.
+// Author: Will Morris
+
+use crate::codec::block::{index_block, read_index};
+use crate::file::archive::Archive;
+use crate::file::blocks::{frame_block, split_blocks};
+use crate::file::bytestream::ByteStream;
+use crate::file::tar::TarIndex;
+
+// Join compressed streams, so that the result decompresses to each of their contents in turn.
+// If every stream has an index, their indexes are merged into one at the end.
+pub(crate) fn concat_streams(inputs: &[Vec<u8>]) -> Result<Vec<u8>, String> {
+    let mut retval = vec![];
+    let mut index = Some(TarIndex::new(vec![], vec![]));
+    let mut indexed = false;
+
+    for input in inputs {
+        let mut blocks = split_blocks(input)?;
+        match blocks.last().and_then(|block| read_index(block)) {
+            Some(other) => {
+                blocks.pop();
+                indexed = true;
+                if let Some(index) = &mut index {
+                    index.append(other);
+                }
+            }
+            // Members can't be found in a stream without an index, so no index can cover them all.
+            None if !blocks.is_empty() => { index = None }
+            None => {}
+        }
+
+        for block in blocks {
+            retval.append(&mut frame_block(block.to_vec()));
+        }
+    }
+
+    if let Some(index) = index.filter(|_| indexed) {
+        retval.append(&mut frame_block(index_block(index)));
+    }
+    Ok(retval)
+}
+
+// Join archives into one holding every member of each.
+// Chunks stored in more than one archive are only kept once.
+pub(crate) fn concat_archives(inputs: &[Vec<u8>]) -> Result<Vec<u8>, String> {
+    let mut retval = Archive::new();
+    for input in inputs {
+        let archive = Archive::from_whole_stream(input)?;
+
+        // Chunks name the dictionary they were compressed with, so every dictionary can be kept.
+        for dictionary in archive.dictionaries() {
+            retval.add_dictionary(dictionary.clone());
+        }
+
+        for member in archive.members() {
+            for hash in &member.chunks {
+                let chunk = archive.chunk(*hash)
+                    .ok_or(format!("Archive is missing a chunk of {}", member.name))?;
+                retval.add_chunk(*hash, chunk.to_vec());
+            }
+            retval.add_member(member.clone());
+        }
+        archive.links().iter().for_each(|link| retval.add_link(link.clone()));
+    }
+    Ok(retval.to_stream())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::block::{compress_block, index_block, Method, read_index};
+    use crate::codec::concat::{concat_archives, concat_streams};
+    use crate::diagnostics::report::Report;
+    use crate::file::archive::{Archive, Details, hash_chunk, Member};
+    use crate::file::blocks::{frame_block, split_blocks};
+    use crate::file::bytestream::ByteStream;
+    use crate::file::tar::{TarIndex, TarMember};
+    use crate::ordering::dictionary::Dictionary;
+
+    fn stream(contents: &[u8], index: Option<TarIndex>) -> Vec<u8> {
+        let mut report = Report::new(false);
+        let mut retval = frame_block(compress_block(contents, &Method::new(), &mut report));
+        if let Some(index) = index {
+            retval.append(&mut frame_block(index_block(index)));
+        }
+        retval
+    }
+
+    #[test]
+    fn test_concat_streams() {
+        let member = |offset| TarMember { name: String::from("a"), offset, size: 1 };
+        let first = stream(b"first", Some(TarIndex::new(vec![5], vec![member(0)])));
+        let second = stream(b"second", Some(TarIndex::new(vec![6], vec![member(1)])));
+
+        let joined = concat_streams(&[first.clone(), second.clone()]).unwrap();
+        let blocks = split_blocks(&joined).unwrap();
+        assert_eq!(3, blocks.len());
+        let mut expected = TarIndex::new(vec![5], vec![member(0)]);
+        expected.append(TarIndex::new(vec![6], vec![member(1)]));
+        assert_eq!(Some(expected), read_index(blocks[2]));
+
+        // Without every index, there's no index at all.
+        let joined = concat_streams(&[first.clone(), stream(b"plain", None), second]).unwrap();
+        let blocks = split_blocks(&joined).unwrap();
+        assert_eq!(3, blocks.len());
+        assert_eq!(None, read_index(blocks[2]));
+
+        // A stream cut short can't be joined to anything.
+        assert!(concat_streams(&[first[..first.len() - 1].to_vec()]).is_err());
+    }
+
+    #[test]
+    fn test_concat_archives() {
+        let archive = |name: &str, contents: &[u8]| {
+            let mut archive = Archive::new();
+            let hash = hash_chunk(contents);
+            archive.add_chunk(hash, contents.to_vec());
+            archive.add_member(Member { name: String::from(name), chunks: vec![hash], extents: None, details: Details::default() });
+            archive
+        };
+        let first = archive("a", b"same");
+        let second = archive("b", b"same");
+
+        let joined = Archive::from_whole_stream(&concat_archives(&[first.to_stream(), second.to_stream()]).unwrap()).unwrap();
+        assert_eq!(2, joined.members().len());
+        assert_eq!(joined.members()[0].chunks, joined.members()[1].chunks);
+
+        let mut third = archive("c", b"other");
+        third.add_dictionary(Dictionary::train(&[b"other"]));
+        let mut fourth = archive("d", b"else");
+        fourth.add_dictionary(Dictionary::train(&[b"else"]));
+        let joined = Archive::from_whole_stream(&concat_archives(&[third.to_stream(), fourth.to_stream()]).unwrap()).unwrap();
+        assert_eq!(2, joined.dictionaries().len());
+    }
+}
@@ -0,0 +1,489 @@
+// Lets a frontend drive wz over stdio, without parsing what wz prints for people.
+// Requests come in as JSON-RPC 2.0, one a line, and answers, progress and results go out the same way.
+// Jobs run on threads of their own, so requests are answered while they work.
+// Author: Will Morris
+
+/*
+  REQUESTS (one JSON-RPC 2.0 object a line):
+  -- submit {"action": "compress" or "decompress", "input": path, "output": path, "block_size": bytes, "checksum": name}
+     answers {"job": id}. block_size and checksum are optional, and only for compressing.
+  -- cancel {"job": id}: answers the job's status. The job stops after the block it's on.
+  -- result {"job": id}: answers the job's status.
+
+  STATUS:
+  -- {"job": id, "state": "running", "done", "failed" or "cancelled", "done": bytes, "total": bytes,
+      "output_size": bytes or null, "error": a failure, as --errors json prints them, or null}
+  -- done and total count input bytes, compressed or not.
+
+  NOTIFICATIONS (sent with no id):
+  -- progress {"job": id, "done": bytes, "total": bytes}, after each block.
+  -- finished: the job's status, once it's done, failed or been cancelled.
+
+  Jobs that fail or are cancelled leave no output. Once input ends, wz exits when running jobs finish.
+  If output can't be written, the frontend has gone, so running jobs are cancelled.
+ */
+
+use std::io::{BufRead, Write};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use crate::diagnostics::failure::{Failure, FailureKind};
+use crate::file::blocks::DEFAULT_BLOCK_SIZE;
+use crate::file::checksum::{Checksum, parse_checksum};
+use crate::file::json::{Json, json_string, parse_json};
+
+// Error codes JSON-RPC reserves.
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Action {
+    Compress,
+    Decompress,
+}
+
+// A job, as submitted.
+pub(crate) struct Task {
+    pub(crate) action: Action,
+    pub(crate) input: String,
+    pub(crate) output: String,
+    pub(crate) block_size: usize,
+    pub(crate) checksum: Option<&'static dyn Checksum>,
+}
+
+impl Task {
+    // What a job doing this task is doing, if it fails.
+    pub(crate) fn phase(&self) -> &'static str {
+        match self.action {
+            Action::Compress => { "compressing" }
+            Action::Decompress => { "decompressing" }
+        }
+    }
+}
+
+// Handed to whatever runs a task, to say how far it's got, and to find out whether to stop.
+pub(crate) struct Progress<'a> {
+    cancel: &'a AtomicBool,
+    report: &'a (dyn Fn(u64, u64) + Sync),
+}
+
+impl Progress<'_> {
+    pub(crate) fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+
+    // done of total input bytes have been dealt with.
+    pub(crate) fn report(&self, done: u64, total: u64) {
+        (self.report)(done, total)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            State::Running => { "running" }
+            State::Done => { "done" }
+            State::Failed => { "failed" }
+            State::Cancelled => { "cancelled" }
+        }
+    }
+}
+
+struct Job {
+    state: State,
+    done: u64,
+    total: u64,
+    output_size: Option<u64>,
+    failure: Option<Failure>,
+    cancel: Arc<AtomicBool>,
+}
+
+// What a request asks for.
+enum Call {
+    Submit(Task),
+    Cancel(u64),
+    Result(u64),
+}
+
+// A request, read from a line. Requests with no id are notifications, which get no answer.
+struct Request {
+    id: Option<String>,
+    call: Result<Call, (i32, String)>,
+}
+
+struct Server<W: Write> {
+    output: Mutex<W>,
+    // Job n is jobs[n - 1].
+    jobs: Mutex<Vec<Job>>,
+    // Set once output can't be written, since there's no one left to write it to.
+    gone: AtomicBool,
+}
+
+// Answer each request read from input on output, running jobs with run.
+// run returns the size of what it wrote, or None if it was cancelled.
+// Fails only if input can't be read, or output written.
+pub(crate) fn serve_requests<R, W, F>(input: R, output: W, run: F) -> Result<(), String>
+    where R: BufRead, W: Write + Send, F: Fn(&Task, &Progress) -> Result<Option<u64>, Failure> + Sync {
+    let server = Server { output: Mutex::new(output), jobs: Mutex::new(vec![]), gone: AtomicBool::new(false) };
+    let mut result = Ok(());
+    thread::scope(|scope| {
+        for line in input.lines() {
+            let line = match line {
+                Ok(line) => { line }
+                Err(e) => {
+                    result = Err(format!("Could not read requests: {}", e));
+                    break
+                }
+            };
+            if line.trim().is_empty() {
+                continue
+            }
+            let request = read_request(&line);
+            let answer = match request.call {
+                Ok(Call::Submit(task)) => {
+                    let (id, cancel) = server.add_job();
+                    let (server, run) = (&server, &run);
+                    scope.spawn(move || {
+                        let report = |done, total| server.progress(id, done, total);
+                        let progress = Progress { cancel: &cancel, report: &report };
+                        // A job that panics fails on its own, rather than taking every other job with it.
+                        let outcome = catch_unwind(AssertUnwindSafe(|| run(&task, &progress))).unwrap_or_else(|_| {
+                            Err(Failure::new(task.phase(), FailureKind::Failed, "wz failed unexpectedly!").in_file(Some(&task.input)))
+                        });
+                        server.finish(id, outcome);
+                    });
+                    Ok(format!("{{\"job\": {}}}", id))
+                }
+                Ok(Call::Cancel(id)) => { server.cancel(id) }
+                Ok(Call::Result(id)) => { server.status(id) }
+                Err(error) => { Err(error) }
+            };
+            if let Some(id) = request.id {
+                server.send(answer_json(&id, answer));
+            }
+            if server.gone.load(Ordering::SeqCst) {
+                break
+            }
+        }
+    });
+    match server.gone.load(Ordering::SeqCst) {
+        true => { Err(String::from("Could not write to the frontend!")) }
+        false => { result }
+    }
+}
+
+impl<W: Write> Server<W> {
+    fn lock_jobs(&self) -> MutexGuard<'_, Vec<Job>> {
+        self.jobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn add_job(&self) -> (u64, Arc<AtomicBool>) {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut jobs = self.lock_jobs();
+        jobs.push(Job { state: State::Running, done: 0, total: 0, output_size: None, failure: None, cancel: cancel.clone() });
+        (jobs.len() as u64, cancel)
+    }
+
+    fn cancel(&self, id: u64) -> Result<String, (i32, String)> {
+        if let Some(job) = self.lock_jobs().get(id as usize - 1) {
+            job.cancel.store(true, Ordering::SeqCst);
+        }
+        self.status(id)
+    }
+
+    fn status(&self, id: u64) -> Result<String, (i32, String)> {
+        let jobs = self.lock_jobs();
+        let job = jobs.get(id as usize - 1).ok_or((INVALID_PARAMS, format!("There is no job {}!", id)))?;
+        let output_size = job.output_size.map_or(String::from("null"), |size| size.to_string());
+        let error = job.failure.as_ref().map_or(String::from("null"), |failure| failure.to_json());
+        Ok(format!("{{\"job\": {}, \"state\": \"{}\", \"done\": {}, \"total\": {}, \"output_size\": {}, \"error\": {}}}",
+                   id, job.state.name(), job.done, job.total, output_size, error))
+    }
+
+    fn progress(&self, id: u64, done: u64, total: u64) {
+        if let Some(job) = self.lock_jobs().get_mut(id as usize - 1) {
+            job.done = done;
+            job.total = total;
+        }
+        self.send(notification_json("progress", &format!("{{\"job\": {}, \"done\": {}, \"total\": {}}}", id, done, total)));
+    }
+
+    fn finish(&self, id: u64, outcome: Result<Option<u64>, Failure>) {
+        if let Some(job) = self.lock_jobs().get_mut(id as usize - 1) {
+            match outcome {
+                Ok(Some(size)) => {
+                    job.state = State::Done;
+                    job.output_size = Some(size);
+                }
+                Ok(None) => { job.state = State::Cancelled }
+                Err(failure) => {
+                    job.state = State::Failed;
+                    job.failure = Some(failure);
+                }
+            }
+        }
+        if let Ok(status) = self.status(id) {
+            self.send(notification_json("finished", &status));
+        }
+    }
+
+    // Write a line, straight away, since the frontend may be waiting on it.
+    fn send(&self, line: String) {
+        let mut output = self.output.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // No one is left to ask for results, so nothing more is worth doing.
+        if writeln!(output, "{}", line).and_then(|_| output.flush()).is_err() {
+            self.gone.store(true, Ordering::SeqCst);
+            self.lock_jobs().iter().for_each(|job| job.cancel.store(true, Ordering::SeqCst));
+        }
+    }
+}
+
+// Read a request from a line of JSON.
+fn read_request(line: &str) -> Request {
+    let json = match parse_json(line) {
+        Ok(json) => { json }
+        Err(message) => { return Request { id: Some(String::from("null")), call: Err((PARSE_ERROR, message)) } }
+    };
+    let id = match json.field("id") {
+        None => { None }
+        Some(Json::Null) => { Some(String::from("null")) }
+        Some(Json::Number(id)) => { Some(id.to_string()) }
+        Some(Json::String(id)) => { Some(json_string(id)) }
+        Some(_) => {
+            let message = String::from("Request ids must be numbers or strings!");
+            return Request { id: Some(String::from("null")), call: Err((INVALID_REQUEST, message)) }
+        }
+    };
+    let call = match (json.field("jsonrpc"), json.field("method")) {
+        (Some(Json::String(version)), Some(Json::String(method))) if version == "2.0" => {
+            read_call(method, json.field("params"))
+        }
+        _ => { Err((INVALID_REQUEST, String::from("Requests must be JSON-RPC 2.0 objects, with a method!"))) }
+    };
+    Request { id, call }
+}
+
+fn read_call(method: &str, params: Option<&Json>) -> Result<Call, (i32, String)> {
+    let params = params.unwrap_or(&Json::Null);
+    match method {
+        "submit" => { read_task(params).map(Call::Submit).map_err(|message| (INVALID_PARAMS, message)) }
+        "cancel" => { read_job(params).map(Call::Cancel) }
+        "result" => { read_job(params).map(Call::Result) }
+        _ => { Err((METHOD_NOT_FOUND, format!("There is no method {}!", method))) }
+    }
+}
+
+fn read_job(params: &Json) -> Result<u64, (i32, String)> {
+    match params.field("job") {
+        Some(Json::Number(id)) if id.fract() == 0.0 && *id >= 1.0 => { Ok(*id as u64) }
+        _ => { Err((INVALID_PARAMS, String::from("A job must be given by its number!"))) }
+    }
+}
+
+fn read_task(params: &Json) -> Result<Task, String> {
+    let string = |name: &str| match params.field(name) {
+        Some(Json::String(value)) if !value.is_empty() => { Ok(value.clone()) }
+        _ => { Err(format!("Jobs need a {}!", name)) }
+    };
+    let action = match string("action")?.as_str() {
+        "compress" => { Action::Compress }
+        "decompress" => { Action::Decompress }
+        action => { return Err(format!("Jobs can compress or decompress, not {}!", action)) }
+    };
+    let (input, output) = (string("input")?, string("output")?);
+    let block_size = match params.field("block_size") {
+        None => { DEFAULT_BLOCK_SIZE }
+        Some(Json::Number(size)) if action == Action::Compress && size.fract() == 0.0 && *size >= 1.0 => { *size as usize }
+        Some(_) => { return Err(String::from("Block sizes must be whole numbers of bytes, and only for compressing!")) }
+    };
+    let checksum = match params.field("checksum") {
+        None => { None }
+        Some(Json::String(name)) if action == Action::Compress => {
+            Some(parse_checksum(name).ok_or(format!("Unknown checksum: {}", name))?)
+        }
+        Some(_) => { return Err(String::from("Checksums must be named, and only for compressing!")) }
+    };
+    Ok(Task { action, input, output, block_size, checksum })
+}
+
+fn answer_json(id: &str, answer: Result<String, (i32, String)>) -> String {
+    match answer {
+        Ok(result) => { format!("{{\"jsonrpc\": \"2.0\", \"id\": {}, \"result\": {}}}", id, result) }
+        Err((code, message)) => {
+            format!("{{\"jsonrpc\": \"2.0\", \"id\": {}, \"error\": {{\"code\": {}, \"message\": {}}}}}", id, code, json_string(&message))
+        }
+    }
+}
+
+fn notification_json(method: &str, params: &str) -> String {
+    format!("{{\"jsonrpc\": \"2.0\", \"method\": \"{}\", \"params\": {}}}", method, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, ErrorKind, Write};
+    use std::thread;
+    use std::time::Duration;
+    use crate::codec::control::{Action, serve_requests};
+    use crate::diagnostics::failure::{Failure, FailureKind};
+    use crate::file::json::{Json, parse_json};
+
+    // Serve input with run, and parse every line written.
+    fn serve<F>(input: &str, run: F) -> Vec<Json>
+        where F: Fn(&super::Task, &super::Progress) -> Result<Option<u64>, Failure> + Sync {
+        let mut output = vec![];
+        serve_requests(input.as_bytes(), &mut output, run).unwrap();
+        String::from_utf8(output).unwrap().lines().map(|line| parse_json(line).unwrap()).collect()
+    }
+
+    fn with_id(lines: &[Json], id: f64) -> &Json {
+        lines.iter().find(|line| line.field("id") == Some(&Json::Number(id))).unwrap()
+    }
+
+    fn finished(lines: &[Json]) -> Vec<&Json> {
+        lines.iter().filter(|line| line.field("method") == Some(&Json::String(String::from("finished")))).collect()
+    }
+
+    #[test]
+    fn test_jobs() {
+        let input = "{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"submit\", \"params\": \
+                     {\"action\": \"compress\", \"input\": \"in\", \"output\": \"in.wz\", \"block_size\": 5, \"checksum\": \"crc32\"}}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"submit\", \"params\": \
+                     {\"action\": \"decompress\", \"input\": \"bad.wz\", \"output\": \"bad\"}}\n";
+        let lines = serve(input, |task, progress| match task.action {
+            Action::Compress => {
+                assert_eq!(("in", "in.wz", 5, true), (task.input.as_str(), task.output.as_str(), task.block_size, task.checksum.is_some()));
+                progress.report(5, 10);
+                progress.report(10, 10);
+                Ok(Some(42))
+            }
+            Action::Decompress => { Err(Failure::new("decompressing", FailureKind::Corrupt, "Bad block!").in_file(Some("bad.wz"))) }
+        });
+
+        let result = |id| with_id(&lines, id).field("result").and_then(|result| result.field("job")).cloned();
+        assert_eq!(Some(Json::Number(1.0)), result(1.0));
+        assert_eq!(Some(Json::Number(2.0)), result(2.0));
+        assert_eq!(2, lines.iter().filter(|line| line.field("method") == Some(&Json::String(String::from("progress")))).count());
+
+        let finished = finished(&lines);
+        assert_eq!(2, finished.len());
+        let status = |job| finished.iter().filter_map(|line| line.field("params"))
+            .find(|status| status.field("job") == Some(&Json::Number(job))).unwrap();
+        assert_eq!(Some(&Json::String(String::from("done"))), status(1.0).field("state"));
+        assert_eq!(Some(&Json::Number(42.0)), status(1.0).field("output_size"));
+        assert_eq!(Some(&Json::Number(10.0)), status(1.0).field("done"));
+        assert_eq!(Some(&Json::String(String::from("failed"))), status(2.0).field("state"));
+        let error = status(2.0).field("error").unwrap();
+        assert_eq!(Some(&Json::String(String::from("corrupt"))), error.field("kind"));
+        assert_eq!(Some(&Json::String(String::from("bad.wz"))), error.field("file"));
+    }
+
+    #[test]
+    fn test_cancel() {
+        // The job runs until it's cancelled.
+        let input = "{\"jsonrpc\": \"2.0\", \"id\": \"a\", \"method\": \"submit\", \"params\": \
+                     {\"action\": \"compress\", \"input\": \"in\", \"output\": \"out\"}}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"result\", \"params\": {\"job\": 1}}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": 3, \"method\": \"cancel\", \"params\": {\"job\": 1}}\n";
+        let lines = serve(input, |_, progress| {
+            while !progress.cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Ok(None)
+        });
+        assert!(lines.iter().any(|line| line.field("id") == Some(&Json::String(String::from("a")))));
+        let state = |id| with_id(&lines, id).field("result").and_then(|result| result.field("state")).cloned();
+        assert_eq!(Some(Json::String(String::from("running"))), state(2.0));
+        let status = finished(&lines)[0].field("params").unwrap();
+        assert_eq!(Some(&Json::String(String::from("cancelled"))), status.field("state"));
+
+        // Jobs still running when input ends are finished first.
+        let input = "{\"jsonrpc\": \"2.0\", \"method\": \"submit\", \"params\": {\"action\": \"decompress\", \"input\": \"i\", \"output\": \"o\"}}";
+        let lines = serve(input, |_, _| {
+            thread::sleep(Duration::from_millis(50));
+            Ok(Some(1))
+        });
+        // Submitted as a notification, so only its result is sent.
+        assert_eq!(1, lines.len());
+        assert_eq!(Some(&Json::String(String::from("done"))), finished(&lines)[0].field("params").unwrap().field("state"));
+    }
+
+    // Output to a frontend which has gone away.
+    struct Gone;
+
+    impl Write for Gone {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::from(ErrorKind::BrokenPipe))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_gone() {
+        // Once progress can't be sent, the job is cancelled, rather than running on for no one.
+        let input = "{\"jsonrpc\": \"2.0\", \"method\": \"submit\", \"params\": {\"action\": \"compress\", \"input\": \"i\", \"output\": \"o\"}}";
+        let result = serve_requests(input.as_bytes(), Gone, |_, progress| {
+            progress.report(1, 2);
+            while !progress.cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Ok(None)
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_bad_requests() {
+        let input = "not json\n\
+                     {\"jsonrpc\": \"1.0\", \"id\": 1, \"method\": \"result\"}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"explode\"}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": 3, \"method\": \"result\", \"params\": {\"job\": 1}}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": 4, \"method\": \"cancel\", \"params\": {\"job\": 1.5}}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": 5, \"method\": \"submit\", \"params\": {\"action\": \"zip\", \"input\": \"i\", \"output\": \"o\"}}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": 6, \"method\": \"submit\", \"params\": {\"action\": \"compress\", \"input\": \"i\"}}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": 7, \"method\": \"submit\", \"params\": \
+                     {\"action\": \"decompress\", \"input\": \"i\", \"output\": \"o\", \"block_size\": 4}}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": 8, \"method\": \"submit\", \"params\": \
+                     {\"action\": \"compress\", \"input\": \"i\", \"output\": \"o\", \"checksum\": \"md5\"}}\n\
+                     {\"jsonrpc\": \"2.0\", \"id\": [9], \"method\": \"result\"}\n\
+                     {\"jsonrpc\": \"2.0\", \"method\": \"explode\"}\n\
+                     \n";
+        let lines = serve(input, |_, _| panic!("No job should run!"));
+        let code = |line: &Json| line.field("error").and_then(|error| error.field("code")).cloned();
+        // The last two get no answer: one is a notification, and the other is blank.
+        assert_eq!(10, lines.len());
+        assert_eq!(Some(Json::Number(-32700.0)), code(&lines[0]));
+        assert_eq!(Some(&Json::Null), lines[0].field("id"));
+        assert_eq!(Some(Json::Number(-32600.0)), code(&lines[1]));
+        assert_eq!(Some(Json::Number(-32601.0)), code(&lines[2]));
+        for (i, line) in lines.iter().enumerate().take(9).skip(3) {
+            assert_eq!(Some(Json::Number(-32602.0)), code(line), "request {}", i + 1);
+        }
+        assert_eq!(Some(Json::Number(-32600.0)), code(&lines[9]));
+        assert_eq!(Some(&Json::Null), lines[9].field("id"));
+    }
+
+    #[test]
+    fn test_panic() {
+        let input = "{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"submit\", \"params\": \
+                     {\"action\": \"decompress\", \"input\": \"in.wz\", \"output\": \"out\"}}\n";
+        let lines = serve(input, |_, _| panic!("Corrupt block!"));
+        let status = finished(&lines)[0].field("params").unwrap();
+        assert_eq!(Some(&Json::String(String::from("failed"))), status.field("state"));
+        assert_eq!(Some(&Json::String(String::from("decompressing"))), status.field("error").and_then(|error| error.field("phase")));
+    }
+}
@@ -0,0 +1,281 @@
+// Writes DEFLATE streams (RFC 1951), the format inside gzip and zip files.
+// Matches come from the same LZ77 stage as --lz77. Only the way they're coded differs:
+// deflate has its own length buckets, and its codes are canonical and at most 15 bits long.
+// Author: Will Morris
+
+use std::io;
+use std::io::Write;
+use crate::encoding::bitsequence::BitSequence;
+use crate::encoding::bitwriter::BitWriter;
+use crate::encoding::canonical::{canonical_codes, limited_lengths};
+use crate::transform::lz77::{find_matches, Token};
+
+// Deflate can only point 32K back.
+pub(crate) const DEFLATE_WINDOW: usize = 1 << 15;
+// How many tokens go in each deflate block. Each block has its own codes.
+const TOKENS_PER_BLOCK: usize = 1 << 16;
+// A stored block holds at most this many bytes.
+const MAX_STORED: usize = u16::MAX as usize;
+
+pub(crate) const END_OF_BLOCK: usize = 256;
+// Literal/length and distance codes may be at most this long.
+pub(crate) const MAX_CODE_LEN: u32 = 15;
+// Code length codes may be at most this long.
+const MAX_LENGTH_CODE_LEN: u32 = 7;
+
+// (smallest length, extra bits) for each length code, starting at 257.
+pub(crate) const LENGTHS: [(usize, u32); 29] = [
+    (3, 0), (4, 0), (5, 0), (6, 0), (7, 0), (8, 0), (9, 0), (10, 0),
+    (11, 1), (13, 1), (15, 1), (17, 1), (19, 2), (23, 2), (27, 2), (31, 2),
+    (35, 3), (43, 3), (51, 3), (59, 3), (67, 4), (83, 4), (99, 4), (115, 4),
+    (131, 5), (163, 5), (195, 5), (227, 5), (258, 0),
+];
+
+// (smallest distance, extra bits) for each distance code.
+pub(crate) const DISTANCES: [(usize, u32); 30] = [
+    (1, 0), (2, 0), (3, 0), (4, 0), (5, 1), (7, 1), (9, 2), (13, 2),
+    (17, 3), (25, 3), (33, 4), (49, 4), (65, 5), (97, 5), (129, 6), (193, 6),
+    (257, 7), (385, 7), (513, 8), (769, 8), (1025, 9), (1537, 9), (2049, 10), (3073, 10),
+    (4097, 11), (6145, 11), (8193, 12), (12289, 12), (16385, 13), (24577, 13),
+];
+
+// The order code length code lengths are sent in. Rarely used lengths go last, so they can be trimmed.
+pub(crate) const LENGTH_CODE_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Compress bytes into a complete deflate stream, writing each block to out as soon as it's done.
+// Returns out once the stream is finished.
+pub(crate) fn deflate<W: Write>(bytes: &[u8], out: W) -> io::Result<W> {
+    let mut writer = BitWriter::new(out);
+
+    // An empty stream is still a block: a final fixed block, with nothing but the end code.
+    if bytes.is_empty() {
+        writer.write_bits(0b011, 3)?;
+        writer.write_bits(0, 7)?;
+        return writer.finish()
+    }
+
+    let tokens = find_matches(bytes, DEFLATE_WINDOW);
+    let groups: Vec<&[Token]> = tokens.chunks(TOKENS_PER_BLOCK).collect();
+    let mut start = 0;
+    for (i, group) in groups.iter().enumerate() {
+        let final_block = i == groups.len() - 1;
+        let len: usize = group.iter().map(token_len).sum();
+        let block = &bytes[start..start + len];
+
+        // Anything that won't compress is stored as it is.
+        let dynamic = dynamic_block(group, final_block);
+        match dynamic.len() <= stored_len(block.len()) {
+            true => { writer.write_seq(&dynamic)? }
+            false => { write_stored(&mut writer, block, final_block)? }
+        }
+        start += len;
+    }
+    writer.finish()
+}
+
+fn token_len(token: &Token) -> usize {
+    match token {
+        Token::Literal(_) => { 1 }
+        Token::Match { length, .. } => { *length }
+    }
+}
+
+// A block coded with codes made for it, which are described at its start.
+fn dynamic_block(tokens: &[Token], final_block: bool) -> BitSequence {
+    let mut literal_freqs = vec![0u64; 286];
+    let mut distance_freqs = vec![0u64; 30];
+    for token in tokens {
+        match token {
+            Token::Literal(byte) => { literal_freqs[*byte as usize] += 1 }
+            Token::Match { length, distance } => {
+                literal_freqs[257 + length_code(*length).0] += 1;
+                distance_freqs[distance_code(*distance).0] += 1;
+            }
+        }
+    }
+    literal_freqs[END_OF_BLOCK] = 1;
+    // A code needs two symbols to be complete, so make sure there are at least two of each.
+    ensure_two(&mut literal_freqs);
+    ensure_two(&mut distance_freqs);
+
+    let literal_lens = limited_lengths(&literal_freqs, MAX_CODE_LEN);
+    let distance_lens = limited_lengths(&distance_freqs, MAX_CODE_LEN);
+    let literal_codes = canonical_codes(&literal_lens);
+    let distance_codes = canonical_codes(&distance_lens);
+
+    let mut seq = BitSequence::new();
+    seq.append_bit(final_block as u8);
+    seq.append_bits(&[0, 1]);
+    append_lengths(&mut seq, &literal_lens, &distance_lens);
+
+    for token in tokens {
+        match token {
+            Token::Literal(byte) => {
+                let byte = *byte as usize;
+                append_code(&mut seq, literal_codes[byte], literal_lens[byte]);
+            }
+            Token::Match { length, distance } => {
+                let (code, extra_bits, extra) = length_code(*length);
+                append_code(&mut seq, literal_codes[257 + code], literal_lens[257 + code]);
+                append_extra(&mut seq, extra, extra_bits);
+
+                let (code, extra_bits, extra) = distance_code(*distance);
+                append_code(&mut seq, distance_codes[code], distance_lens[code]);
+                append_extra(&mut seq, extra, extra_bits);
+            }
+        }
+    }
+    append_code(&mut seq, literal_codes[END_OF_BLOCK], literal_lens[END_OF_BLOCK]);
+    seq
+}
+
+// Describe the codes of a dynamic block by their lengths.
+// The lengths are themselves Huffman coded, with runs squashed by codes 16, 17 and 18.
+fn append_lengths(seq: &mut BitSequence, literal_lens: &[u32], distance_lens: &[u32]) {
+    let literal_count = trimmed_len(literal_lens, 257);
+    let distance_count = trimmed_len(distance_lens, 1);
+    let mut lens = literal_lens[..literal_count].to_vec();
+    lens.extend_from_slice(&distance_lens[..distance_count]);
+
+    let runs = run_lengths(&lens);
+    let mut length_freqs = vec![0u64; 19];
+    runs.iter().for_each(|(symbol, _, _)| length_freqs[*symbol] += 1);
+    ensure_two(&mut length_freqs);
+    let length_lens = limited_lengths(&length_freqs, MAX_LENGTH_CODE_LEN);
+    let length_codes = canonical_codes(&length_lens);
+
+    let ordered: Vec<u32> = LENGTH_CODE_ORDER.iter().map(|symbol| length_lens[*symbol]).collect();
+    let length_count = trimmed_len(&ordered, 4);
+
+    append_extra(seq, literal_count - 257, 5);
+    append_extra(seq, distance_count - 1, 5);
+    append_extra(seq, length_count - 4, 4);
+    for len in &ordered[..length_count] {
+        append_extra(seq, *len as usize, 3);
+    }
+    for (symbol, extra_bits, extra) in runs {
+        append_code(seq, length_codes[symbol], length_lens[symbol]);
+        append_extra(seq, extra, extra_bits);
+    }
+}
+
+// Squash runs in a list of code lengths. Returns (symbol, extra bits, extra) for each.
+// 16 repeats the previous length 3-6 times. 17 and 18 are 3-10 and 11-138 zeros.
+fn run_lengths(lens: &[u32]) -> Vec<(usize, u32, usize)> {
+    let mut retval = vec![];
+    let mut i = 0;
+    while i < lens.len() {
+        let len = lens[i];
+        let run = lens[i..].iter().take_while(|other| **other == len).count();
+        if len == 0 && run >= 11 {
+            let run = run.min(138);
+            retval.push((18, 7, run - 11));
+            i += run;
+        } else if len == 0 && run >= 3 {
+            retval.push((17, 3, run - 3));
+            i += run;
+        } else {
+            retval.push((len as usize, 0, 0));
+            i += 1;
+            let mut repeats = run - 1;
+            while repeats >= 3 {
+                let count = repeats.min(6);
+                retval.push((16, 2, count - 3));
+                i += count;
+                repeats -= count;
+            }
+        }
+    }
+    retval
+}
+
+// At most how many bits storing len bytes takes: a header, padding and lengths for each block.
+fn stored_len(len: usize) -> u64 {
+    let pieces = len.div_ceil(MAX_STORED) as u64;
+    pieces * (3 + 7 + 32) + len as u64 * 8
+}
+
+// Store bytes as they are, in as many blocks as it takes.
+fn write_stored<W: Write>(writer: &mut BitWriter<W>, bytes: &[u8], final_block: bool) -> io::Result<()> {
+    let pieces: Vec<&[u8]> = bytes.chunks(MAX_STORED).collect();
+    for (i, piece) in pieces.iter().enumerate() {
+        writer.write_bits((final_block && i == pieces.len() - 1) as u64, 1)?;
+        writer.write_bits(0, 2)?;
+        // Stored blocks start on a byte boundary.
+        writer.align_to_byte()?;
+        let len = piece.len() as u16;
+        writer.write_bits(len as u64, 16)?;
+        writer.write_bits(!len as u64, 16)?;
+        for byte in *piece {
+            writer.write_bits(*byte as u64, 8)?;
+        }
+    }
+    Ok(())
+}
+
+// Make sure at least two symbols are used, by adding the first unused ones.
+fn ensure_two(freqs: &mut [u64]) {
+    let mut used = freqs.iter().filter(|freq| **freq > 0).count();
+    for freq in freqs.iter_mut() {
+        if used >= 2 {
+            break
+        }
+        if *freq == 0 {
+            *freq = 1;
+            used += 1;
+        }
+    }
+}
+
+// How many lengths to send: up to the last nonzero one, but no fewer than min.
+fn trimmed_len(lens: &[u32], min: usize) -> usize {
+    let last = lens.iter().rposition(|len| *len > 0).map_or(0, |last| last + 1);
+    last.max(min)
+}
+
+// Returns (code, extra bits, extra) for a match length. Codes start from zero, not 257.
+fn length_code(length: usize) -> (usize, u32, usize) {
+    let code = LENGTHS.iter().rposition(|(base, _)| *base <= length).unwrap();
+    let (base, extra_bits) = LENGTHS[code];
+    (code, extra_bits, length - base)
+}
+
+fn distance_code(distance: usize) -> (usize, u32, usize) {
+    let code = DISTANCES.iter().rposition(|(base, _)| *base <= distance).unwrap();
+    let (base, extra_bits) = DISTANCES[code];
+    (code, extra_bits, distance - base)
+}
+
+// Huffman codes are sent most significant bit first, so they're reversed to be appended.
+fn append_code(seq: &mut BitSequence, code: u32, len: u32) {
+    if len > 0 {
+        seq.append_bits_u64((code.reverse_bits() >> (u32::BITS - len)) as u64, len);
+    }
+}
+
+// Everything else is sent least significant bit first.
+fn append_extra(seq: &mut BitSequence, value: usize, bits: u32) {
+    seq.append_bits_u64(value as u64, bits);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::deflate::{distance_code, length_code, run_lengths};
+
+    #[test]
+    fn test_codes() {
+        assert_eq!((0, 0, 0), length_code(3));
+        assert_eq!((8, 1, 1), length_code(12));
+        assert_eq!((28, 0, 0), length_code(258));
+        assert_eq!((27, 5, 30), length_code(257));
+        assert_eq!((0, 0, 0), distance_code(1));
+        assert_eq!((29, 13, 8191), distance_code(32768));
+    }
+
+    #[test]
+    fn test_run_lengths() {
+        let lens = [5, 5, 5, 5, 5, 0, 0, 0, 0, 1];
+        assert_eq!(vec![(5, 0, 0), (16, 2, 1), (17, 3, 1), (1, 0, 0)], run_lengths(&lens));
+        assert_eq!(vec![(18, 7, 127), (0, 0, 0)], run_lengths(&[0; 139]));
+    }
+}
@@ -0,0 +1,88 @@
+// Makes patches between versions of a file.
+// Author: Will Morris
+
+use crate::codec::block::{compress_smallest, decompress_block, Method};
+use crate::codec::coder::LZ77;
+use crate::diagnostics::report::Report;
+use crate::file::archive::hash_chunk;
+use crate::file::bytestream::ByteStream;
+use crate::file::patch::{is_patch, Patch};
+use crate::transform::delta::{apply, diff, from_streams, to_streams};
+
+// Describe new in terms of old, and compress the description.
+pub(crate) fn make_patch(old: &[u8], new: &[u8], report: &mut Report) -> Vec<u8> {
+    let instructions = report.time("matching", || diff(old, new));
+    let (control, data) = to_streams(&instructions);
+
+    // Inserted bytes are whatever's new, which may well repeat itself.
+    let methods = [Method::new(), Method { coder: LZ77, ..Method::new() }];
+    Patch {
+        old_len: old.len() as u64,
+        old_hash: hash_chunk(old),
+        new_len: new.len() as u64,
+        new_hash: hash_chunk(new),
+        control: compress_smallest(&control, &methods, report),
+        data: compress_smallest(&data, &methods, report),
+    }.to_stream()
+}
+
+// Turn old into the new version a patch was made from.
+// Both versions are checked against the patch, so a patch applied to the wrong file fails rather than making garbage.
+pub(crate) fn apply_patch(old: &[u8], patch: &[u8], report: &mut Report) -> Result<Vec<u8>, String> {
+    if !is_patch(patch) {
+        return Err(String::from("Not a patch!"))
+    }
+    let patch = Patch::from_whole_stream(patch)?;
+    if patch.old_len != old.len() as u64 || patch.old_hash != hash_chunk(old) {
+        return Err(String::from("Patch was made from a different file!"))
+    }
+
+    let control = decompress_block(&patch.control, None, report)?;
+    let data = decompress_block(&patch.data, None, report)?;
+    let new = report.time("patching", || from_streams(&control, &data).and_then(|instructions| apply(old, &instructions)))?;
+    if patch.new_len != new.len() as u64 || patch.new_hash != hash_chunk(&new) {
+        return Err(String::from("Patched file does not match what the patch was made from!"))
+    }
+    Ok(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::differ::{apply_patch, make_patch};
+    use crate::diagnostics::report::Report;
+
+    #[test]
+    fn test_small_edit() {
+        let mut report = Report::new(false);
+        let old: Vec<u8> = (0..100000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let mut new = old.clone();
+        new.splice(50000..50000, b"a small insertion".iter().copied());
+        // A small edit makes a small patch, even though the file can't be compressed.
+        let patch = make_patch(&old, &new, &mut report);
+        assert!(patch.len() < 200);
+        assert_eq!(new, apply_patch(&old, &patch, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let mut report = Report::new(false);
+        let versions: Vec<(&[u8], &[u8])> = vec![
+            (b"", b""),
+            (b"", b"all new"),
+            (b"all gone", b""),
+            (b"the quick brown fox jumps over the lazy dog", b"the quick red fox jumps over the lazy dog!"),
+        ];
+        for (old, new) in versions {
+            let patch = make_patch(old, new, &mut report);
+            assert_eq!(new.to_vec(), apply_patch(old, &patch, &mut report).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_wrong_file() {
+        let mut report = Report::new(false);
+        let patch = make_patch(b"old version", b"new version", &mut report);
+        assert!(apply_patch(b"another version", &patch, &mut report).is_err());
+        assert!(apply_patch(b"old version", b"not a patch", &mut report).is_err());
+    }
+}
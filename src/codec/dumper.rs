@@ -0,0 +1,274 @@
+// Hex dumps of compressed streams, with every field named, for debugging interop or corruption by hand.
+// Fields are found with the same layout functions used to read them, so the dump shows just what a reader would see.
+// Where a field can't be read, the dump says why, and shows the rest of the block unannotated.
+// Author: Will Morris
+
+use crate::codec::block::{block_kind, BlockKind, read_checksum};
+use crate::encoding::canonical::LENGTHS_LEN;
+use crate::file::blocks::blocks;
+use crate::file::bytestream::{ByteStream, read_bytes, read_sized};
+use crate::file::error::WzError;
+use crate::file::format::{read_bit_count, read_entry, read_entry_width, read_map_len, read_sequence_len};
+use crate::file::gzip::is_gzip;
+use crate::file::patch::is_patch;
+use crate::file::tar::TarIndex;
+use crate::file::zip::is_zip;
+use crate::transform::filter::filter_from_stream;
+
+// Bytes on each line of the dump.
+const LINE_BYTES: usize = 16;
+// Fields longer than this many lines show only their first and last, unless the whole dump is asked for.
+const MAX_FIELD_LINES: usize = 4;
+
+// Dumps a stream, field by field.
+struct Dump<'a> {
+    bytes: &'a [u8],
+    full: bool,
+    lines: Vec<String>,
+}
+
+impl Dump<'_> {
+    // Show len bytes from start, with note on the first line. Nested fields are indented by depth.
+    fn field(&mut self, start: usize, len: usize, depth: usize, note: &str) {
+        let rows: Vec<usize> = (start..start + len.max(1)).step_by(LINE_BYTES).collect();
+        let elide = !self.full && rows.len() > MAX_FIELD_LINES;
+        for (n, row) in rows.iter().enumerate() {
+            if elide && n == 1 {
+                self.lines.push(format!("{:>8}", "..."));
+            }
+            if elide && n > 0 && n + 1 < rows.len() {
+                continue
+            }
+            let hex: Vec<String> = self.bytes[*row..(row + LINE_BYTES).min(start + len)].iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            let note = match n {
+                0 => { format!("{}{}", "  ".repeat(depth), note) }
+                _ => { String::new() }
+            };
+            self.lines.push(format!("{:08x}  {:<w$}  {}", row, hex.join(" "), note, w = LINE_BYTES * 3 - 1).trim_end().to_string());
+        }
+    }
+
+    // Show a field read by read, which moves i past it. Fields which can't be read end the block.
+    fn read<T, F>(&mut self, i: &mut usize, end: usize, depth: usize, read: F, note: impl Fn(&T) -> String) -> Result<T, ()>
+        where F: FnOnce(&[u8], &mut usize) -> Result<T, WzError> {
+        let start = *i;
+        let mut within = *i;
+        match read(&self.bytes[..end], &mut within) {
+            Ok(value) => {
+                *i = within;
+                self.field(start, within - start, depth, &note(&value));
+                Ok(value)
+            }
+            Err(error) => {
+                self.field(start, end - start, depth, &format!("unreadable: {}", error));
+                *i = end;
+                Err(())
+            }
+        }
+    }
+
+    // Annotate a block which runs from start to end, according to its kind.
+    fn block(&mut self, start: usize, end: usize, depth: usize) {
+        let contents = &self.bytes[start..end];
+        let Some(kind) = block_kind(contents) else {
+            match contents.is_empty() {
+                true => { self.lines.push(format!("{:>8}  {:<w$}  {}(empty block)", "", "", "  ".repeat(depth), w = LINE_BYTES * 3 - 1)) }
+                false => { self.field(start, end - start, depth, &format!("unknown block kind {}", contents[0])) }
+            }
+            return
+        };
+        self.field(start, 1, depth, &format!("block kind: {:?}", kind));
+        let mut i = start + 1;
+        // A field which couldn't be read has already said so.
+        let _ = match kind {
+            BlockKind::Huffman | BlockKind::HuffmanMsb => {
+                self.wzfile(&mut i, end, depth + 1).map(|_| self.trailing(i, end, depth + 1))
+            }
+            BlockKind::Canonical => { self.canonical(&mut i, end, depth + 1).map(|_| self.trailing(i, end, depth + 1)) }
+            BlockKind::Filtered => { self.filtered(&mut i, end, depth + 1) }
+            BlockKind::Raw => {
+                self.field(i, end - i, depth + 1, &format!("raw bytes ({})", end - i));
+                Ok(())
+            }
+            BlockKind::Checksum => {
+                let note = read_checksum(contents).map_or(String::from("unknown checksum"), |(checksum, _)| checksum.name().to_string());
+                self.field(i, 1, depth + 1, &format!("checksum: {}", note));
+                self.field(i + 1, end - i - 1, depth + 1, "digest");
+                Ok(())
+            }
+            BlockKind::Index => {
+                let note = match TarIndex::from_whole_stream(&self.bytes[i..end]) {
+                    Ok(index) => { format!("index of {} members", index.members().len()) }
+                    Err(error) => { format!("unreadable index: {}", error) }
+                };
+                self.field(i, end - i, depth + 1, &note);
+                Ok(())
+            }
+            kind => {
+                self.field(i, end - i, depth + 1, &format!("{:?} coded contents ({} bytes)", kind, end - i));
+                Ok(())
+            }
+        };
+    }
+
+    // Anything left in a block once all of its fields are read.
+    fn trailing(&mut self, i: usize, end: usize, depth: usize) {
+        if i < end {
+            self.field(i, end - i, depth, &format!("{} unexpected bytes after the block", end - i));
+        }
+    }
+
+    // A frequency map, then a bit sequence.
+    fn wzfile(&mut self, i: &mut usize, end: usize, depth: usize) -> Result<(), ()> {
+        let map_len = self.read(i, end, depth, read_map_len, |len| format!("frequency map length: {}", len))?;
+        let map_end = (*i + map_len).min(end);
+        let width = self.read(i, map_end, depth, read_entry_width, |width| format!("frequency width: {} bytes", width))?;
+        while *i < map_end {
+            let (start, mut within) = (*i, *i);
+            match read_entry(&self.bytes[..map_end], &mut within, 1).and_then(|byte| {
+                read_entry(&self.bytes[..map_end], &mut within, width).map(|count| (byte, count))
+            }) {
+                Ok((byte, count)) => {
+                    self.field(start, within - start, depth + 1, &format!("byte {:#04x} {}: {}", byte, printable(byte as u8), count));
+                    *i = within;
+                }
+                Err(error) => {
+                    self.field(start, map_end - start, depth + 1, &format!("unreadable: {}", error));
+                    *i = map_end;
+                }
+            }
+        }
+        let seq_len = self.read(i, end, depth, read_sequence_len, |len| format!("sequence length: {}", len))?;
+        self.sequence(i, (*i + seq_len).min(end), depth)
+    }
+
+    // A count of bits, then the bits.
+    fn sequence(&mut self, i: &mut usize, end: usize, depth: usize) -> Result<(), ()> {
+        let (bits, len) = self.read(i, end, depth, read_bit_count, |(bits, _)| format!("bit count: {}", bits))?;
+        self.read(i, end, depth, |bytes, i| read_bytes(bytes, i, len, "a bit sequence").map(|_| ()),
+                  |_| format!("bit payload ({} bits in {} bytes)", bits, len))
+    }
+
+    // A bit order, each byte's code length, then a bit sequence.
+    fn canonical(&mut self, i: &mut usize, end: usize, depth: usize) -> Result<(), ()> {
+        self.read(i, end, depth, |bytes, i| read_bytes(bytes, i, 1, "a bit order").map(|order| order[0]),
+                  |order| format!("bit order: {}", order))?;
+        self.read(i, end, depth, |bytes, i| read_bytes(bytes, i, LENGTHS_LEN, "code lengths").map(|_| ()),
+                  |_| String::from("code lengths, a nibble per byte"))?;
+        self.sequence(i, end, depth)
+    }
+
+    // Which filter, anything needed to undo it, then the block inside.
+    fn filtered(&mut self, i: &mut usize, end: usize, depth: usize) -> Result<(), ()> {
+        self.read(i, end, depth, |bytes, i| read_sized(bytes, i).map(|filter| match filter_from_stream(filter) {
+            Ok(filter) => { format!("filter: {:?}", filter) }
+            Err(error) => { format!("unknown filter: {}", error) }
+        }), |note| note.clone())?;
+        self.read(i, end, depth, |bytes, i| read_sized(bytes, i).map(|extra| extra.len()),
+                  |len| format!("filter data ({} bytes)", len))?;
+        self.block(*i, end, depth);
+        Ok(())
+    }
+}
+
+// Bytes which print as themselves are shown that way.
+fn printable(byte: u8) -> String {
+    match byte.is_ascii_graphic() {
+        true => { format!("'{}'", byte as char) }
+        false => { String::new() }
+    }
+}
+
+// An annotated hex dump of every frame and block in a compressed stream.
+// Long fields are shortened to their first and last lines, unless full.
+pub(crate) fn dump_stream(bytes: &[u8], full: bool) -> Result<String, String> {
+    if is_gzip(bytes) || is_zip(bytes) || is_patch(bytes) {
+        return Err(String::from("Only wz streams can be dumped!"))
+    }
+
+    let mut dump = Dump { bytes, full, lines: vec![] };
+    // Where the next frame starts. A frame which can't be read takes the rest of the stream with it.
+    let mut next = 0;
+    for (n, block) in blocks(bytes).enumerate() {
+        match block {
+            Ok(block) => {
+                let header_len = block.framed_len - block.contents.len();
+                dump.field(block.offset, header_len, 0, &format!("block {}: frame of {} bytes", n, block.contents.len()));
+                dump.block(block.offset + header_len, block.offset + block.framed_len, 1);
+                next = block.offset + block.framed_len;
+            }
+            Err(error) => { dump.field(next, bytes.len() - next, 0, &format!("block {}: unreadable frame: {}", n, error)) }
+        }
+    }
+    Ok(dump.lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::block::{compress_block, Method};
+    use crate::codec::coder::CANONICAL;
+    use crate::codec::dumper::dump_stream;
+    use crate::diagnostics::report::Report;
+    use crate::file::blocks::frame_block;
+
+    fn stream(bytes: &[u8], method: &Method) -> Vec<u8> {
+        frame_block(compress_block(bytes, method, &mut Report::new(false)))
+    }
+
+    // a takes one bit, b and c two each.
+    fn skewed() -> Vec<u8> {
+        [vec![b'a'; 640], vec![b'b'; 320], vec![b'c'; 320]].concat()
+    }
+
+    #[test]
+    fn test_huffman() {
+        let dump = dump_stream(&stream(&skewed(), &Method::new()), false).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+        // Frame length as a varint, then block kind, map length and map, sequence length, bit count and bits.
+        assert!(lines[0].starts_with("00000000  87 02 "));
+        assert!(lines[0].ends_with("block 0: frame of 263 bytes"), "{}", dump);
+        assert!(lines[1].ends_with("  block kind: Huffman"));
+        assert!(dump.contains("frequency map length: 10"));
+        assert!(dump.contains("frequency width: 2 bytes"));
+        assert!(dump.contains("byte 0x61 'a': 640"));
+        assert!(dump.contains("bit count: 1920"));
+        assert!(dump.contains("bit payload (1920 bits in 240 bytes)"));
+        assert!(!dump.contains("unexpected"));
+    }
+
+    #[test]
+    fn test_canonical() {
+        let dump = dump_stream(&stream(&skewed(), &Method { coder: CANONICAL, ..Method::new() }), false).unwrap();
+        assert!(dump.contains("block kind: Canonical"), "{}", dump);
+        assert!(dump.contains("code lengths"));
+        assert!(dump.contains("bit count: 1920"));
+        assert!(!dump.contains("unexpected"));
+    }
+
+    #[test]
+    fn test_long_fields() {
+        let bytes: Vec<u8> = (0..=255).cycle().take(4096).collect();
+        let raw = stream(&bytes, &Method::new());
+        let short = dump_stream(&raw, false).unwrap();
+        assert!(short.contains("raw bytes (4096)"));
+        assert!(short.contains("..."));
+        let full = dump_stream(&raw, true).unwrap();
+        assert_eq!(1 + 4097usize.div_ceil(16), full.lines().count());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        // A frequency map which says it's longer than any could be.
+        let mut bytes = frame_block(vec![0, 0xff, 0xff, 1, 2]);
+        let dump = dump_stream(&bytes, false).unwrap();
+        assert!(dump.contains("unreadable: A frequency map can't be 65535 bytes long!"), "{}", dump);
+
+        // A frame which runs past the end.
+        bytes.truncate(bytes.len() - 1);
+        let dump = dump_stream(&bytes, false).unwrap();
+        assert!(dump.contains("block 0: unreadable frame"), "{}", dump);
+        assert!(dump_stream(&[0x1f, 0x8b, 8], false).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+// Follows a growing file, like tail -f, compressing whatever is appended to it into new blocks.
+// Blocks are written as soon as they're full, or sooner if what's been read has waited long enough.
+// Author: Will Morris
+
+use std::io::{ErrorKind, Read, Write};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::codec::block::{checksum_block, compress_block, Method};
+use crate::diagnostics::report::Report;
+use crate::file::blocks::frame_block;
+use crate::file::checksum::Checksum;
+
+// How long to wait before looking for more input, once there's none.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// When to write what's been read: once there's a whole block, or once the oldest of it has waited interval.
+pub(crate) struct Flush {
+    pub(crate) block_size: usize,
+    pub(crate) interval: Duration,
+}
+
+// Compress everything reader gives, then keep reading as it grows, until stop says to.
+// Each block is followed by its own checksum, if there is one, since the stream never ends to put one after.
+// The stream has no index either, for the same reason, so ranges of it are found by decoding from the start.
+// Returns how many bytes were read.
+pub(crate) fn follow<R: Read, W: Write, S: FnMut() -> bool>(reader: &mut R, writer: &mut W, flush: &Flush,
+                                                            method: &Method, checksum: Option<&dyn Checksum>,
+                                                            report: &mut Report, mut stop: S) -> Result<u64, String> {
+    let Flush { block_size, interval } = *flush;
+    let mut pending = vec![];
+    // When the oldest byte in pending was read.
+    let mut waiting_since = None;
+    let mut buf = vec![0; block_size];
+    let mut total = 0;
+    loop {
+        let read = match report.time("i/o", || reader.read(&mut buf)) {
+            Ok(read) => { read }
+            Err(e) if e.kind() == ErrorKind::Interrupted => { continue }
+            Err(e) => { return Err(format!("Could not read input: {}", e)) }
+        };
+        total += read as u64;
+        pending.extend_from_slice(&buf[..read]);
+        if read > 0 && waiting_since.is_none() {
+            waiting_since = Some(Instant::now());
+        }
+
+        while pending.len() >= block_size {
+            let rest = pending.split_off(block_size);
+            write_block(writer, &pending, method, checksum, report)?;
+            pending = rest;
+            waiting_since = (!pending.is_empty()).then(Instant::now);
+        }
+        if waiting_since.is_some_and(|since| since.elapsed() >= interval) {
+            write_block(writer, &pending, method, checksum, report)?;
+            pending.clear();
+            waiting_since = None;
+        }
+
+        // Nothing more for now. Either it's time to finish, or to wait for more.
+        if read == 0 {
+            if stop() {
+                if !pending.is_empty() {
+                    write_block(writer, &pending, method, checksum, report)?;
+                }
+                return Ok(total)
+            }
+            sleep(POLL_INTERVAL.min(interval));
+        }
+    }
+}
+
+// Compress and write one block, flushed so that whoever reads the output has it right away.
+fn write_block<W: Write>(writer: &mut W, block: &[u8], method: &Method, checksum: Option<&dyn Checksum>,
+                         report: &mut Report) -> Result<(), String> {
+    let mut framed = frame_block(compress_block(block, method, report));
+    if let Some(checksum) = checksum {
+        framed.append(&mut frame_block(report.time("checksumming", || checksum_block(checksum, block))));
+    }
+    report.time("i/o", || writer.write_all(&framed).and_then(|_| writer.flush()))
+        .map_err(|e| format!("Could not write output: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::time::Duration;
+    use crate::codec::block::{decompress_block, Method};
+    use crate::codec::follow::{Flush, follow};
+    use crate::codec::pipe::receive;
+    use crate::diagnostics::report::Report;
+    use crate::file::blocks::blocks;
+    use crate::file::checksum::XxHash64;
+
+    // A file that grows a piece at a time. Each read gives the next piece, or nothing if it isn't there yet.
+    struct Growing {
+        pieces: Vec<Vec<u8>>,
+    }
+
+    impl Read for Growing {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.pieces.first_mut() {
+                Some(piece) if !piece.is_empty() => {
+                    let len = piece.len().min(buf.len());
+                    buf[..len].copy_from_slice(&piece[..len]);
+                    piece.drain(..len);
+                    Ok(len)
+                }
+                Some(_) => {
+                    self.pieces.remove(0);
+                    Ok(0)
+                }
+                None => { Ok(0) }
+            }
+        }
+    }
+
+    #[test]
+    fn test_follow() {
+        let mut report = Report::new(false);
+        let pieces = vec![b"first line\n".repeat(50), b"second line\n".to_vec(), b"third line\n".repeat(30)];
+        let expected = pieces.concat();
+        let mut reader = Growing { pieces };
+        let mut written = vec![];
+        let mut polls = 0;
+        let flush = Flush { block_size: 256, interval: Duration::ZERO };
+        let read = follow(&mut reader, &mut written, &flush, &Method::new(), Some(&XxHash64), &mut report,
+                          || { polls += 1; polls > 3 }).unwrap();
+        assert_eq!(expected.len() as u64, read);
+
+        // With a checksum after every block, it reads back as it would have arrived over a pipe.
+        let mut received = vec![];
+        receive(&mut written.as_slice(), &mut received, None, &mut report).unwrap();
+        assert_eq!(expected, received);
+    }
+
+    #[test]
+    fn test_interval() {
+        let mut report = Report::new(false);
+        // With a long interval, small pieces wait to fill a block. With none, each is written as it comes.
+        for (interval, expected) in [(Duration::from_secs(3600), 1), (Duration::ZERO, 3)] {
+            let mut reader = Growing { pieces: vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()] };
+            let mut written = vec![];
+            let mut polls = 0;
+            follow(&mut reader, &mut written, &Flush { block_size: 1024, interval }, &Method::new(), None, &mut report,
+                   || { polls += 1; polls > 3 }).unwrap();
+            let contents: Vec<u8> = blocks(&written)
+                .flat_map(|block| decompress_block(block.unwrap().contents, None, &mut report).unwrap()).collect();
+            assert_eq!(expected, blocks(&written).count());
+            assert_eq!(b"abc".to_vec(), contents);
+        }
+    }
+}
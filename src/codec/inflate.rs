@@ -0,0 +1,230 @@
+// Reads DEFLATE streams (RFC 1951), whoever wrote them.
+// Codes are rebuilt from their lengths, then decoded with the same tables as our own blocks.
+// Author: Will Morris
+
+use std::io::Read;
+use crate::codec::block::within;
+use crate::codec::deflate::{DISTANCES, END_OF_BLOCK, LENGTH_CODE_ORDER, LENGTHS, MAX_CODE_LEN};
+use crate::encoding::canonical::canonical_codes;
+use crate::encoding::bitreader::BitReader;
+use crate::encoding::bitsequence::BitSequence;
+use crate::encoding::decodingtable::DecodingTable;
+use crate::file::error::WzError;
+use crate::ordering::symbolfreq::Symbol;
+
+// Decompress a deflate stream, so long as it decompresses to no more than limit bytes.
+// Returns the decompressed bytes, and how many bytes of input the stream took up.
+pub(crate) fn inflate(bytes: &[u8], limit: usize) -> Result<(Vec<u8>, usize), WzError> {
+    let mut reader = BitReader::new(bytes);
+    let retval = inflate_stream(&mut reader, limit)?;
+    Ok((retval, reader.position().div_ceil(8) as usize))
+}
+
+// Decompress a deflate stream as it's read, stopping just past its end.
+// Decoding stops as soon as the output would pass limit, so a small stream can't be made to fill memory.
+pub(crate) fn inflate_stream<R: Read>(reader: &mut BitReader<R>, limit: usize) -> Result<Vec<u8>, WzError> {
+    let mut retval = vec![];
+
+    loop {
+        let final_block = read_bits(reader, 1)? == 1;
+        match read_bits(reader, 2)? {
+            0 => { read_stored(reader, &mut retval, limit)? }
+            1 => {
+                let (literals, distances) = fixed_tables()?;
+                read_codes(reader, &literals, &distances, &mut retval, limit)?
+            }
+            2 => {
+                let (literals, distances) = read_tables(reader)?;
+                read_codes(reader, &literals, &distances, &mut retval, limit)?
+            }
+            _ => { return Err(WzError::Invalid("deflate block type")) }
+        }
+        if final_block {
+            break
+        }
+    }
+    Ok(retval)
+}
+
+// Stored blocks are byte aligned, and start with their length and its complement.
+fn read_stored<R: Read>(reader: &mut BitReader<R>, retval: &mut Vec<u8>, limit: usize) -> Result<(), WzError> {
+    reader.align_to_byte();
+    let len = read_bits(reader, 16)?;
+    let complement = read_bits(reader, 16)?;
+    if len != !complement & 0xffff {
+        return Err(WzError::Invalid("stored block length"))
+    }
+    within(retval.len() + len, limit)?;
+    for _ in 0..len {
+        retval.push(read_bits(reader, 8)? as u8);
+    }
+    Ok(())
+}
+
+// Decode literals and matches until the end of the block.
+fn read_codes<R: Read>(reader: &mut BitReader<R>, literals: &DecodingTable, distances: &DecodingTable,
+              retval: &mut Vec<u8>, limit: usize) -> Result<(), WzError> {
+    loop {
+        let symbol = read_symbol(reader, literals)?;
+        if symbol < END_OF_BLOCK {
+            within(retval.len() + 1, limit)?;
+            retval.push(symbol as u8);
+            continue
+        }
+        if symbol == END_OF_BLOCK {
+            return Ok(())
+        }
+
+        let (base, extra_bits) = *LENGTHS.get(symbol - END_OF_BLOCK - 1)
+            .ok_or(WzError::Invalid("length code in deflate stream"))?;
+        let length = base + read_bits(reader, extra_bits)?;
+        let (base, extra_bits) = *DISTANCES.get(read_symbol(reader, distances)?)
+            .ok_or(WzError::Invalid("distance code in deflate stream"))?;
+        let distance = base + read_bits(reader, extra_bits)?;
+        if distance > retval.len() {
+            return Err(WzError::Malformed(String::from("Deflate stream refers back past its start!")))
+        }
+        within(retval.len() + length, limit)?;
+
+        // The copy may overlap what it's producing, so it goes a byte at a time.
+        let start = retval.len() - distance;
+        for i in 0..length {
+            retval.push(retval[start + i]);
+        }
+    }
+}
+
+// The tables for fixed blocks, which RFC 1951 lays out in advance.
+fn fixed_tables() -> Result<(DecodingTable, DecodingTable), WzError> {
+    let mut lens = vec![8; 144];
+    lens.resize(256, 9);
+    lens.resize(280, 7);
+    lens.resize(288, 8);
+    Ok((table_from_lengths(&lens)?, table_from_lengths(&[5; 30])?))
+}
+
+// Dynamic blocks describe their own codes by their lengths.
+fn read_tables<R: Read>(reader: &mut BitReader<R>) -> Result<(DecodingTable, DecodingTable), WzError> {
+    let literal_count = read_bits(reader, 5)? + 257;
+    let distance_count = read_bits(reader, 5)? + 1;
+    let length_count = read_bits(reader, 4)? + 4;
+
+    let mut length_lens = [0u32; 19];
+    for symbol in &LENGTH_CODE_ORDER[..length_count] {
+        length_lens[*symbol] = read_bits(reader, 3)? as u32;
+    }
+    let length_table = table_from_lengths(&length_lens)?;
+
+    // Runs may carry over from the literal lengths into the distance lengths.
+    let mut lens: Vec<u32> = vec![];
+    while lens.len() < literal_count + distance_count {
+        let (len, count) = match read_symbol(reader, &length_table)? {
+            16 => {
+                let previous = *lens.last()
+                    .ok_or_else(|| WzError::Malformed(String::from("Deflate stream repeats a length before any were sent!")))?;
+                (previous, read_bits(reader, 2)? + 3)
+            }
+            17 => { (0, read_bits(reader, 3)? + 3) }
+            18 => { (0, read_bits(reader, 7)? + 11) }
+            len => { (len as u32, 1) }
+        };
+        lens.resize(lens.len() + count, len);
+    }
+    if lens.len() > literal_count + distance_count {
+        return Err(WzError::Malformed(String::from("Deflate code lengths run past their end!")))
+    }
+    if lens[END_OF_BLOCK] == 0 {
+        return Err(WzError::Malformed(String::from("Deflate block has no end code!")))
+    }
+
+    let (literal_lens, distance_lens) = lens.split_at(literal_count);
+    Ok((table_from_lengths(literal_lens)?, table_from_lengths(distance_lens)?))
+}
+
+// Rebuild a code from its lengths.
+// Lengths which would give two symbols the same code are rejected, rather than trusted.
+fn table_from_lengths(lens: &[u32]) -> Result<DecodingTable, WzError> {
+    let space: u64 = lens.iter().filter(|len| **len > 0).map(|len| 1 << (MAX_CODE_LEN - len)).sum();
+    if space > 1 << MAX_CODE_LEN {
+        return Err(WzError::Malformed(String::from("Deflate code lengths are oversubscribed!")))
+    }
+
+    let mut table = DecodingTable::new();
+    for (symbol, (code, len)) in canonical_codes(lens).iter().zip(lens).enumerate() {
+        if *len > 0 {
+            let bits: Vec<u8> = (0..*len).rev().map(|i| ((code >> i) & 1) as u8).collect();
+            table.insert(&BitSequence::from_bits(&bits), symbol as Symbol);
+        }
+    }
+    Ok(table)
+}
+
+fn read_symbol<R: Read>(reader: &mut BitReader<R>, table: &DecodingTable) -> Result<usize, WzError> {
+    table.decode_symbol(reader).map(|symbol| symbol as usize)
+        .ok_or(WzError::Invalid("code in deflate stream"))
+}
+
+// Read a value sent least significant bit first.
+fn read_bits<R: Read>(reader: &mut BitReader<R>, bits: u32) -> Result<usize, WzError> {
+    match reader.read_bits(bits) {
+        Some(value) => { Ok(value as usize) }
+        None => {
+            match reader.error() {
+                Some(error) => { Err(WzError::Io(error.to_string())) }
+                None => { Err(WzError::Truncated("a deflate stream")) }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::deflate::deflate;
+    use crate::codec::inflate::inflate;
+    use crate::file::error::WzError;
+
+    #[test]
+    fn test_round_trip() {
+        let text = b"the rain in spain falls mainly on the plain. ".repeat(100);
+        for bytes in [&b""[..], b"a", &text, &(0..=255).collect::<Vec<u8>>()] {
+            let compressed = deflate(bytes, vec![]).unwrap();
+            assert_eq!((bytes.to_vec(), compressed.len()), inflate(&compressed, usize::MAX).unwrap());
+        }
+    }
+
+    // A stream made by zlib, with a fixed block.
+    #[test]
+    fn test_fixed() {
+        let compressed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00];
+        assert_eq!(b"hello hello hello".to_vec(), inflate(&compressed, usize::MAX).unwrap().0);
+    }
+
+    // A stored block, with a trailing byte that isn't part of the stream.
+    #[test]
+    fn test_stored() {
+        let compressed = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0xff];
+        assert_eq!((b"abc".to_vec(), 8), inflate(&compressed, usize::MAX).unwrap());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        assert!(inflate(&[], usize::MAX).is_err());
+        // Block type 3 is reserved.
+        assert!(inflate(&[0x07], usize::MAX).is_err());
+        // A stored block whose length doesn't match its complement.
+        assert!(inflate(&[0x01, 0x03, 0x00, 0x00, 0x00], usize::MAX).is_err());
+    }
+
+    // A little deflate stream can stand for a lot of output, so decoding stops as soon as it passes the limit.
+    #[test]
+    fn test_bomb() {
+        let zeros = vec![0; 1 << 20];
+        let compressed = deflate(&zeros, vec![]).unwrap();
+        assert_eq!(zeros, inflate(&compressed, zeros.len()).unwrap().0);
+        assert_eq!(Err(WzError::Full("decompressed output")), inflate(&compressed, 1024));
+
+        // Stored blocks are refused before they're read.
+        let compressed = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+        assert_eq!(Err(WzError::Full("decompressed output")), inflate(&compressed, 2));
+    }
+}
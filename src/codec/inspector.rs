@@ -0,0 +1,145 @@
+// Shows how a compressed file was coded, for debugging, teaching, and writing other decoders.
+// Author: Will Morris
+
+use crate::codec::block::{block_codes, block_kind, block_tree, read_checksum};
+use crate::file::blocks::{blocks, split_blocks};
+use crate::file::codetable::codes_to_json;
+use crate::file::gzip::is_gzip;
+use crate::file::patch::is_patch;
+use crate::file::zip::is_zip;
+use crate::ordering::dictionary::Dictionary;
+
+// The codes of every block in a compressed stream, as JSON.
+// Blocks which don't give bytes codes from a table, like raw or LZ77 blocks, have null codes.
+// Huffman blocks also describe their tree, and how many bits each byte took on average.
+pub(crate) fn inspect_codes(bytes: &[u8], dictionary: Option<&Dictionary>) -> Result<String, String> {
+    if is_gzip(bytes) || is_zip(bytes) || is_patch(bytes) {
+        return Err(String::from("Only wz streams can be inspected!"))
+    }
+
+    let blocks: Vec<String> = split_blocks(bytes)?.iter().enumerate().map(|(i, block)| {
+        let kind = block_kind(block).map_or(String::from("Empty"), |kind| format!("{:?}", kind));
+        let codes = block_codes(block, dictionary)
+            .map_or(String::from("null"), |codes| codes_to_json(&codes).replace('\n', "\n  "));
+        let tree = block_tree(block).map_or(String::from("null"), |(tree, ordering)| format!(
+            "{{\"leaves\": {}, \"max_depth\": {}, \"bits\": {}, \"bits_per_byte\": {:.3}}}",
+            tree.leaf_count(), tree.max_depth(), tree.weighted_path_length(), tree.expected_bits(&ordering).unwrap_or(0.0)));
+        format!("{{\"block\": {}, \"kind\": \"{}\", \"codes\": {}, \"tree\": {}}}", i, kind, codes, tree)
+    }).collect();
+    match blocks.is_empty() {
+        true => { Ok(String::from("[]")) }
+        false => { Ok(format!("[\n  {}\n]", blocks.join(",\n  "))) }
+    }
+}
+
+// Where every block in a compressed stream is, how big it is, and what kind it is, as JSON.
+// Checksum blocks give their checksum too. Nothing is decompressed, so even huge files are quick to walk.
+pub(crate) fn inspect_blocks(bytes: &[u8]) -> Result<String, String> {
+    if is_gzip(bytes) || is_zip(bytes) || is_patch(bytes) {
+        return Err(String::from("Only wz streams can be inspected!"))
+    }
+
+    let mut lines = vec![];
+    for (i, block) in blocks(bytes).enumerate() {
+        let block = block?;
+        let kind = block_kind(block.contents).map_or(String::from("Empty"), |kind| format!("{:?}", kind));
+        let checksum = read_checksum(block.contents).map_or(String::from("null"), |(checksum, digest)| {
+            let digest: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("{{\"algorithm\": \"{}\", \"digest\": \"{}\"}}", checksum.name(), digest)
+        });
+        lines.push(format!("{{\"block\": {}, \"offset\": {}, \"size\": {}, \"kind\": \"{}\", \"checksum\": {}}}",
+                           i, block.offset, block.framed_len, kind, checksum));
+    }
+    match lines.is_empty() {
+        true => { Ok(String::from("[]")) }
+        false => { Ok(format!("[\n  {}\n]", lines.join(",\n  "))) }
+    }
+}
+
+// The Huffman tree of every block in a compressed stream, as indented text or Graphviz DOT.
+// Blocks without a tree of their own are noted, but not drawn.
+pub(crate) fn inspect_trees(bytes: &[u8], format: &str) -> Result<String, String> {
+    if is_gzip(bytes) || is_zip(bytes) || is_patch(bytes) {
+        return Err(String::from("Only wz streams can be inspected!"))
+    }
+    if format != "text" && format != "dot" {
+        return Err(format!("Unknown tree format: {}! Use text or dot.", format))
+    }
+
+    let mut retval = String::new();
+    for (i, block) in split_blocks(bytes)?.iter().enumerate() {
+        let kind = block_kind(block).map_or(String::from("Empty"), |kind| format!("{:?}", kind));
+        let tree = block_tree(block).map(|(tree, _)| tree);
+        match (format, tree) {
+            ("text", Some(tree)) => { retval.push_str(&format!("Block {} ({}):\n{}", i, kind, tree)) }
+            ("text", None) => { retval.push_str(&format!("Block {} ({}): no tree\n", i, kind)) }
+            (_, Some(tree)) => { retval.push_str(&format!("// Block {} ({})\n{}", i, kind, tree.to_dot())) }
+            (_, None) => { retval.push_str(&format!("// Block {} ({}): no tree\n", i, kind)) }
+        }
+    }
+    Ok(retval)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::block::{checksum_block, compress_block, Method};
+    use crate::codec::coder::LZ77;
+    use crate::codec::inspector::{inspect_blocks, inspect_codes, inspect_trees};
+    use crate::diagnostics::report::Report;
+    use crate::file::blocks::frame_block;
+    use crate::file::checksum::Crc32;
+    use crate::file::gzip::gzip;
+
+    #[test]
+    fn test_inspect() {
+        let mut report = Report::new(false);
+        let contents = b"aaaab".repeat(100);
+        let mut bytes = frame_block(compress_block(&contents, &Method::new(), &mut report));
+        bytes.append(&mut frame_block(compress_block(&contents, &Method { coder: LZ77, ..Method::new() }, &mut report)));
+
+        let json = inspect_codes(&bytes, None).unwrap();
+        assert!(json.starts_with("[\n  {\"block\": 0, \"kind\": \"Huffman\", \"codes\": [\n    {\"byte\": 97"));
+        // 400 a's take a bit each, and 100 b's take a bit each.
+        assert!(json.contains("], \"tree\": {\"leaves\": 2, \"max_depth\": 1, \"bits\": 500, \"bits_per_byte\": 1.000}}"));
+        assert!(json.contains("{\"block\": 1, \"kind\": \"Lz77\", \"codes\": null, \"tree\": null}"));
+
+        assert_eq!("[]", inspect_codes(&[], None).unwrap());
+        assert!(inspect_codes(&gzip(b"aaaab"), None).is_err());
+    }
+
+    #[test]
+    fn test_inspect_blocks() {
+        let mut report = Report::new(false);
+        let contents = b"aaaab".repeat(100);
+        let mut bytes = frame_block(compress_block(&contents, &Method::new(), &mut report));
+        let first_len = bytes.len();
+        bytes.append(&mut frame_block(checksum_block(&Crc32, b"123456789")));
+
+        let json = inspect_blocks(&bytes).unwrap();
+        assert!(json.starts_with(&format!("[\n  {{\"block\": 0, \"offset\": 0, \"size\": {}, \"kind\": \"Huffman\", \"checksum\": null}}", first_len)));
+        // The standard CRC-32 check value, stored little endian.
+        assert!(json.ends_with(&format!("{{\"block\": 1, \"offset\": {}, \"size\": 7, \"kind\": \"Checksum\", \"checksum\": {{\"algorithm\": \"crc32\", \"digest\": \"2639f4cb\"}}}}\n]", first_len)));
+
+        assert_eq!("[]", inspect_blocks(&[]).unwrap());
+        // A truncated frame can't be walked past.
+        assert!(inspect_blocks(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_inspect_trees() {
+        let mut report = Report::new(false);
+        let contents = b"aaaab".repeat(100);
+        let mut bytes = frame_block(compress_block(&contents, &Method::new(), &mut report));
+        bytes.append(&mut frame_block(compress_block(&contents, &Method { coder: LZ77, ..Method::new() }, &mut report)));
+
+        let text = inspect_trees(&bytes, "text").unwrap();
+        assert_eq!("Block 0 (Huffman):\n(500)\n  0 98: 100\n  1 97: 400\nBlock 1 (Lz77): no tree\n", text);
+        let dot = inspect_trees(&bytes, "dot").unwrap();
+        assert!(dot.starts_with("// Block 0 (Huffman)\ndigraph tree {\n"));
+        assert!(dot.ends_with("}\n// Block 1 (Lz77): no tree\n"));
+
+        assert!(inspect_trees(&bytes, "svg").is_err());
+        assert!(inspect_trees(&[], "svg").is_err());
+        assert_eq!("", inspect_trees(&[], "dot").unwrap());
+    }
+}
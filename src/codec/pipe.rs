@@ -0,0 +1,126 @@
+// Sends compressed blocks over a connection, and receives them at the other end, with no file in between.
+// What's sent is an ordinary wz stream, with a checksum after every block, so damage is noticed as soon as it arrives.
+// Author: Will Morris
+
+use std::io::{Read, Write};
+use crate::codec::block::{block_kind, BlockKind, checksum_block, compress_block, decompress_block, Method, verify_checksum};
+use crate::diagnostics::report::Report;
+use crate::file::blocks::{frame_block, read_block};
+use crate::file::checksum::XxHash64;
+use crate::ordering::dictionary::Dictionary;
+
+// Compress everything reader gives, a block at a time, sending each block as soon as it's ready.
+// Returns how many bytes were read.
+pub(crate) fn send<R: Read, W: Write>(reader: &mut R, writer: &mut W, block_size: usize, method: &Method,
+                                      report: &mut Report) -> Result<u64, String> {
+    let mut total = 0;
+    loop {
+        let mut block = vec![];
+        report.time("i/o", || reader.by_ref().take(block_size as u64).read_to_end(&mut block))
+            .map_err(|e| format!("Could not read input: {}", e))?;
+        if block.is_empty() {
+            break
+        }
+        total += block.len() as u64;
+
+        // XXH64 is cheap enough to check every block with.
+        let mut framed = frame_block(compress_block(&block, method, report));
+        framed.append(&mut frame_block(report.time("checksumming", || checksum_block(&XxHash64, &block))));
+        report.time("i/o", || writer.write_all(&framed)).map_err(|e| format!("Could not send: {}", e))?;
+    }
+    writer.flush().map_err(|e| format!("Could not send: {}", e))?;
+    Ok(total)
+}
+
+// Decompress blocks as they arrive. Each is written out once its checksum has been checked.
+// A stream which stops short of a checksum has lost something, so that's an error.
+// Returns how many bytes were written.
+pub(crate) fn receive<R: Read, W: Write>(reader: &mut R, writer: &mut W, dictionary: Option<&Dictionary>,
+                                         report: &mut Report) -> Result<u64, String> {
+    let mut unchecked = vec![];
+    let mut total = 0;
+    while let Some(block) = report.time("i/o", || read_block(reader))? {
+        match block_kind(&block) {
+            Some(BlockKind::Checksum) => {
+                report.time("checksumming", || verify_checksum(&block, &unchecked))?;
+                report.time("i/o", || writer.write_all(&unchecked)).map_err(|e| format!("Could not write output: {}", e))?;
+                total += unchecked.len() as u64;
+                unchecked.clear();
+            }
+            _ => { unchecked.append(&mut decompress_block(&block, dictionary, report)?) }
+        }
+    }
+    match unchecked.is_empty() {
+        true => { Ok(total) }
+        false => { Err(String::from("The stream ended before its last checksum!")) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use crate::codec::block::Method;
+    use crate::codec::pipe::{receive, send};
+    use crate::diagnostics::report::Report;
+
+    fn sample() -> Vec<u8> {
+        b"It was the best of times, it was the worst of times. ".repeat(200)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut report = Report::new(false);
+        let bytes = sample();
+        let mut sent = vec![];
+        assert_eq!(bytes.len() as u64, send(&mut Cursor::new(&bytes), &mut sent, 1000, &Method::new(), &mut report).unwrap());
+        assert!(sent.len() < bytes.len());
+
+        let mut received = vec![];
+        assert_eq!(bytes.len() as u64, receive(&mut Cursor::new(&sent), &mut received, None, &mut report).unwrap());
+        assert_eq!(bytes, received);
+
+        // Nothing to send is still a stream.
+        let mut sent = vec![];
+        send(&mut Cursor::new(vec![]), &mut sent, 1000, &Method::new(), &mut report).unwrap();
+        assert_eq!(0, receive(&mut Cursor::new(&sent), &mut vec![], None, &mut report).unwrap());
+    }
+
+    #[test]
+    fn test_damage() {
+        let mut report = Report::new(false);
+        let bytes = sample();
+        let mut sent = vec![];
+        send(&mut Cursor::new(&bytes), &mut sent, 1000, &Method::new(), &mut report).unwrap();
+
+        // Cut off before the last checksum, everything checked so far still comes out.
+        let mut received = vec![];
+        assert!(receive(&mut Cursor::new(&sent[..sent.len() - 12]), &mut received, None, &mut report).is_err());
+        assert_eq!(bytes[..received.len()], received);
+        assert!(!received.is_empty());
+
+        // A changed digest is noticed.
+        let last = sent.len() - 1;
+        sent[last] ^= 1;
+        assert!(receive(&mut Cursor::new(&sent), &mut vec![], None, &mut report).is_err());
+    }
+
+    #[test]
+    fn test_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bytes = sample();
+        let to_send = bytes.clone();
+        let sender = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            send(&mut Cursor::new(&to_send), &mut stream, 4096, &Method::new(), &mut Report::new(false)).unwrap();
+        });
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = vec![];
+        receive(&mut stream, &mut received, None, &mut Report::new(false)).unwrap();
+        sender.join().unwrap();
+        assert_eq!(bytes, received);
+    }
+}
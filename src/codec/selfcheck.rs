@@ -0,0 +1,99 @@
+// Round trips awkward inputs through every way this build can compress them, as a quick check that it works here.
+// Packagers can run it on each platform they build for, without any test files to hand.
+// Author: Will Morris
+
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::Arc;
+use crate::codec::block::{compress_block, decompress_block, Method};
+use crate::codec::coder::{CANONICAL, CODERS, EntropyCoder, HUFFMAN, PRESET};
+use crate::diagnostics::report::Report;
+use crate::encoding::bitsequence::BitOrder;
+use crate::file::gzip::{gunzip, gzip};
+use crate::ordering::presets::Preset;
+use crate::transform::filter::{Bpe, Bwt, Delta, Filter, Rle, Transpose};
+
+// How one pattern fared with one way of compressing it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Check {
+    pub(crate) pattern: &'static str,
+    pub(crate) method: String,
+    pub(crate) passed: bool,
+}
+
+// The inputs most likely to trip a coder up: nothing, a single symbol, every symbol once, no pattern, and almost all one byte.
+pub(crate) fn patterns() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("empty", vec![]),
+        ("single byte", vec![b'a']),
+        ("all 256 bytes", (0..=255).collect()),
+        ("random", random(1 << 16, 1)),
+        // One byte in a thousand isn't a zero.
+        ("highly skewed", random(1 << 16, 2).into_iter().map(|byte| if byte < 250 { 0 } else { byte }).collect()),
+        ("one long run", vec![0xff; 1 << 16]),
+    ]
+}
+
+// The same bytes every time, on every platform, so a failure can be reproduced.
+pub(crate) fn random(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len).map(|_| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) as u8
+    }).collect()
+}
+
+// Plain Huffman and every coder with a flag of its own, Huffman coding's variations, and Huffman coding after each filter.
+fn methods() -> Vec<(String, Method)> {
+    let mut retval: Vec<(String, Method)> = CODERS.iter()
+        .filter(|coder| coder.flag() || coder.kind() == HUFFMAN.kind())
+        .map(|coder| (String::from(coder.name()), Method { coder: *coder, ..Method::new() }))
+        .collect();
+    retval.push((String::from("huffman, msb first"), Method { bit_order: BitOrder::Msb, ..Method::new() }));
+    retval.push((String::from("huffman, canonical"), Method { coder: CANONICAL, ..Method::new() }));
+    retval.push((String::from("huffman, text preset"), Method { coder: PRESET, preset: Some(Preset::Text), ..Method::new() }));
+
+    let filters: [(&str, Arc<dyn Filter>); 5] = [
+        ("rle", Arc::new(Rle)), ("transpose:4", Arc::new(Transpose(4))), ("bpe", Arc::new(Bpe)),
+        ("delta", Arc::new(Delta)), ("bwt", Arc::new(Bwt)),
+    ];
+    for (name, filter) in filters {
+        retval.push((format!("huffman after {}", name), Method { filters: vec![filter], ..Method::new() }));
+    }
+    retval
+}
+
+// Round trip every pattern through every method, and gzip too.
+// Panics count as failures, so one broken coder can't hide how the rest did.
+pub(crate) fn self_check() -> Vec<Check> {
+    let mut retval = vec![];
+    let methods = methods();
+    for (pattern, bytes) in patterns() {
+        for (name, method) in &methods {
+            let passed = catch_unwind(AssertUnwindSafe(|| {
+                let mut report = Report::new(false);
+                let block = compress_block(&bytes, method, &mut report);
+                decompress_block(&block, None, &mut report).is_ok_and(|decompressed| decompressed == bytes)
+            })).unwrap_or(false);
+            retval.push(Check { pattern, method: name.clone(), passed });
+        }
+        let passed = catch_unwind(|| gunzip(&gzip(&bytes), usize::MAX).is_ok_and(|unzipped| unzipped == bytes)).unwrap_or(false);
+        retval.push(Check { pattern, method: String::from("gzip"), passed });
+    }
+    retval
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::coder::CODERS;
+    use crate::codec::selfcheck::{patterns, self_check};
+
+    #[test]
+    fn test_self_check() {
+        let checks = self_check();
+        // Plain Huffman, every flagged coder, three Huffman variations, five filters and gzip, for each pattern.
+        let flagged = CODERS.iter().filter(|coder| coder.flag()).count();
+        assert_eq!(patterns().len() * (flagged + 10), checks.len());
+        let failed: Vec<_> = checks.iter().filter(|check| !check.passed).collect();
+        assert!(failed.is_empty(), "{:?}", failed);
+    }
+}
@@ -0,0 +1,26 @@
+// Trains dictionaries on sample files.
+// Author: Will Morris
+
+use std::fs;
+use std::path::Path;
+use crate::codec::archiver::{collect_files, Symlinks};
+use crate::diagnostics::report::Report;
+use crate::ordering::dictionary::Dictionary;
+
+// Train a dictionary on every file under paths, recursing into directories.
+pub(crate) fn train_dictionary(paths: &[String], report: &mut Report) -> Result<Dictionary, String> {
+    let mut files = vec![];
+    for path in paths {
+        collect_files(Path::new(path), Symlinks::Follow, &mut files)?;
+    }
+
+    let mut samples = vec![];
+    for file in files {
+        let bytes = report.time("i/o", || fs::read(&file))
+            .map_err(|e| format!("Could not read {}: {}", file.display(), e))?;
+        samples.push(bytes);
+    }
+
+    let samples: Vec<&[u8]> = samples.iter().map(|sample| sample.as_slice()).collect();
+    Ok(report.time("counting", || Dictionary::train(&samples)))
+}
@@ -0,0 +1,155 @@
+// Test vectors, for checking other implementations of the format against this one.
+// Each vector is an input, the .wz stream this implementation makes of it, and the codes each of its blocks used.
+// Inputs come from fixed seeds and blocks are canonically Huffman coded, so the same vectors are made every time.
+// Author: Will Morris
+
+/*
+  CONTENTS of the directory written:
+  -- vectors.json: an array, with an object for each vector:
+     -- "name", "seed" (null for inputs which need none), and "description"
+     -- "input", "wz" and "codes": the files holding the input, the expected stream, and its codes.
+  -- for each vector, NAME.in, NAME.wz, and NAME.codes.json, as wz inspect --codes json prints them.
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::codec::block::{compress_block, index_block, Method};
+use crate::codec::coder::CANONICAL;
+use crate::codec::inspector::inspect_codes;
+use crate::codec::selfcheck::random;
+use crate::diagnostics::report::Report;
+use crate::file::blocks::frame_block;
+use crate::file::tar::TarIndex;
+
+// Small blocks, so that even small vectors span several of them.
+pub(crate) const VECTOR_BLOCK_SIZE: usize = 1 << 12;
+
+const WORDS: [&str; 16] = [
+    "the", "of", "and", "a", "to", "in", "is", "huffman", "tree", "code", "byte", "block", "\n", "bits", "wz", "zip",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Vector {
+    pub(crate) name: &'static str,
+    pub(crate) seed: Option<u64>,
+    pub(crate) description: &'static str,
+    pub(crate) input: Vec<u8>,
+}
+
+// Every vector, from the inputs decoders get wrong most to the ones they see most.
+pub(crate) fn vectors() -> Vec<Vector> {
+    vec![
+        Vector { name: "empty", seed: None, description: "No bytes at all", input: vec![] },
+        Vector { name: "single-byte", seed: None, description: "One byte, so one symbol", input: vec![b'a'] },
+        Vector { name: "all-bytes", seed: None, description: "Every byte once, in order", input: (0..=255).collect() },
+        Vector { name: "one-symbol", seed: None, description: "A whole block of one byte", input: vec![b'z'; VECTOR_BLOCK_SIZE] },
+        Vector { name: "random", seed: Some(1), description: "Bytes with no pattern, stored raw", input: random(VECTOR_BLOCK_SIZE, 1) },
+        Vector {
+            name: "skewed", seed: Some(2), description: "Mostly zeroes, for long and short codes at once",
+            input: random(VECTOR_BLOCK_SIZE * 2, 2).into_iter().map(|byte| if byte < 240 { 0 } else { byte }).collect(),
+        },
+        Vector { name: "text", seed: Some(3), description: "Words, over several blocks", input: text(VECTOR_BLOCK_SIZE * 5 / 2, 3) },
+    ]
+}
+
+// Words picked from WORDS, separated by spaces, until there are len bytes.
+fn text(len: usize, seed: u64) -> Vec<u8> {
+    let mut retval = vec![];
+    for choice in random(len, seed) {
+        retval.extend_from_slice(WORDS[choice as usize % WORDS.len()].as_bytes());
+        retval.push(b' ');
+        if retval.len() >= len {
+            break
+        }
+    }
+    retval.truncate(len);
+    retval
+}
+
+// The stream wz -z --canonical --block-size 4K would write for input.
+pub(crate) fn expected_stream(input: &[u8]) -> Vec<u8> {
+    let mut report = Report::new(false);
+    let method = Method { coder: CANONICAL, ..Method::new() };
+    let mut retval = vec![];
+    let mut block_lens = vec![];
+    for block in input.chunks(VECTOR_BLOCK_SIZE) {
+        retval.append(&mut frame_block(compress_block(block, &method, &mut report)));
+        block_lens.push(block.len() as u64);
+    }
+    retval.append(&mut frame_block(index_block(TarIndex::new(block_lens, vec![]))));
+    retval
+}
+
+// Write every vector into dir, making it if need be. Returns how many there were.
+pub(crate) fn write_vectors(dir: &Path) -> Result<usize, String> {
+    let error = |path: &Path, e: io::Error| format!("Could not write {}: {}", path.display(), e);
+    fs::create_dir_all(dir).map_err(|e| error(dir, e))?;
+
+    let vectors = vectors();
+    let mut entries = vec![];
+    for vector in &vectors {
+        let stream = expected_stream(&vector.input);
+        let codes = inspect_codes(&stream, None)?;
+        let files = [
+            (format!("{}.in", vector.name), vector.input.clone()),
+            (format!("{}.wz", vector.name), stream),
+            (format!("{}.codes.json", vector.name), codes.into_bytes()),
+        ];
+        for (name, contents) in &files {
+            let path = dir.join(name);
+            fs::write(&path, contents).map_err(|e| error(&path, e))?;
+        }
+
+        let seed = vector.seed.map_or(String::from("null"), |seed| seed.to_string());
+        entries.push(format!("{{\"name\": \"{}\", \"seed\": {}, \"description\": \"{}\", \"input\": \"{}\", \"wz\": \"{}\", \"codes\": \"{}\"}}",
+                             vector.name, seed, vector.description, files[0].0, files[1].0, files[2].0));
+    }
+
+    let path = dir.join("vectors.json");
+    fs::write(&path, format!("[\n  {}\n]\n", entries.join(",\n  "))).map_err(|e| error(&path, e))?;
+    Ok(vectors.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+    use std::process;
+    use crate::codec::block::decompress_block;
+    use crate::codec::vectors::{expected_stream, vectors, write_vectors};
+    use crate::diagnostics::report::Report;
+    use crate::file::blocks::split_blocks;
+
+    #[test]
+    fn test_round_trip() {
+        let mut report = Report::new(false);
+        for vector in vectors() {
+            let stream = expected_stream(&vector.input);
+            let decompressed: Vec<u8> = split_blocks(&stream).unwrap().iter()
+                .flat_map(|block| decompress_block(block, None, &mut report).unwrap())
+                .collect();
+            assert_eq!(vector.input, decompressed, "{} didn't round trip", vector.name);
+        }
+    }
+
+    #[test]
+    fn test_deterministic() {
+        assert_eq!(vectors(), vectors());
+        for vector in vectors() {
+            assert_eq!(expected_stream(&vector.input), expected_stream(&vector.input), "{} changed", vector.name);
+        }
+    }
+
+    #[test]
+    fn test_write_vectors() {
+        let dir = temp_dir().join(format!("wz_vectors_{}", process::id()));
+        assert_eq!(vectors().len(), write_vectors(&dir).unwrap());
+        let text = vectors().into_iter().find(|vector| vector.name == "text").unwrap();
+        assert_eq!(text.input, fs::read(dir.join("text.in")).unwrap());
+        assert_eq!(expected_stream(&text.input), fs::read(dir.join("text.wz")).unwrap());
+        assert!(fs::read_to_string(dir.join("text.codes.json")).unwrap().contains("\"kind\": \"Canonical\""));
+        assert!(fs::read_to_string(dir.join("vectors.json")).unwrap().contains("\"seed\": 3"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+// How hard the library's encoder works, named as flate2 names its levels, so code written for it carries over.
+// Author: Will Morris
+
+/// How much to compress, from not at all to as much as the encoder can.
+/// Huffman coding costs the same per byte at every level. Higher levels code bigger blocks,
+/// so fewer tables are stored, at the cost of buffering more before anything is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Stored as it is, in raw blocks.
+    None,
+    /// Small blocks, so output starts soon and little is buffered.
+    Fast,
+    #[default]
+    Default,
+    /// Blocks as big as wz makes them itself.
+    Best,
+}
+
+impl Compression {
+    /// A level from 0 to 9, as flate2 takes them. 0 stores, 1 to 3 are fast, 4 to 6 are the default, and higher is best.
+    pub fn new(level: u32) -> Self {
+        match level {
+            0 => { Compression::None }
+            1..=3 => { Compression::Fast }
+            4..=6 => { Compression::Default }
+            _ => { Compression::Best }
+        }
+    }
+
+    pub fn none() -> Self {
+        Compression::None
+    }
+
+    pub fn fast() -> Self {
+        Compression::Fast
+    }
+
+    pub fn best() -> Self {
+        Compression::Best
+    }
+
+    /// The level, from 0 to 9, that new takes this to be.
+    pub fn level(&self) -> u32 {
+        match self {
+            Compression::None => { 0 }
+            Compression::Fast => { 1 }
+            Compression::Default => { 6 }
+            Compression::Best => { 9 }
+        }
+    }
+
+    // How many bytes of input go into each block.
+    pub(crate) fn block_size(&self) -> usize {
+        match self {
+            Compression::None | Compression::Fast => { 64 * 1024 }
+            Compression::Default => { 1024 * 1024 }
+            Compression::Best => { 16 * 1024 * 1024 }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compression::Compression;
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(Compression::Default, Compression::default());
+        for level in 0..=9 {
+            assert_eq!(Compression::new(level), Compression::new(Compression::new(level).level()));
+        }
+        assert_eq!(Compression::none(), Compression::new(0));
+        assert_eq!(Compression::fast(), Compression::new(1));
+        assert_eq!(Compression::best(), Compression::new(9));
+        assert!(Compression::fast().block_size() < Compression::best().block_size());
+    }
+}
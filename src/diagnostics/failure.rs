@@ -0,0 +1,114 @@
+// Describes why a run failed, for whoever has to sort it out.
+// People get a sentence on stdout. With --errors json, tools get a record on stderr, one line each, with the same sentence in it.
+// Author: Will Morris
+
+use crate::file::error::WzError;
+use crate::file::json::json_string;
+
+// What sort of thing went wrong, so tools can tell what's worth trying again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FailureKind {
+    // The arguments asked for something that can't be done.
+    Usage,
+    NotFound,
+    // Reading or writing failed partway.
+    Io,
+    // The input isn't what it claims to be.
+    Corrupt,
+    // The output would have been bigger than allowed.
+    Limit,
+    Timeout,
+    // Anything else.
+    Failed,
+}
+
+impl FailureKind {
+    fn name(&self) -> &'static str {
+        match self {
+            FailureKind::Usage => { "usage" }
+            FailureKind::NotFound => { "not_found" }
+            FailureKind::Io => { "io" }
+            FailureKind::Corrupt => { "corrupt" }
+            FailureKind::Limit => { "limit" }
+            FailureKind::Timeout => { "timeout" }
+            FailureKind::Failed => { "failed" }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Failure {
+    // What was being done, i.e. reading, decompressing, or writing.
+    pub(crate) phase: &'static str,
+    pub(crate) kind: FailureKind,
+    pub(crate) message: String,
+    // The file being worked on, if there was one.
+    pub(crate) file: Option<String>,
+    // How far into the input things went wrong, for failures partway through a stream.
+    pub(crate) offset: Option<u64>,
+}
+
+impl Failure {
+    pub(crate) fn new(phase: &'static str, kind: FailureKind, message: impl Into<String>) -> Self {
+        Failure { phase, kind, message: message.into(), file: None, offset: None }
+    }
+
+    // Streams which don't parse are corrupt, unless they couldn't even be read.
+    pub(crate) fn from_stream(phase: &'static str, error: WzError) -> Self {
+        let kind = match error {
+            WzError::Io(_) => { FailureKind::Io }
+            _ => { FailureKind::Corrupt }
+        };
+        Failure::new(phase, kind, error.to_string())
+    }
+
+    pub(crate) fn in_file(mut self, file: Option<&str>) -> Self {
+        self.file = file.map(String::from);
+        self
+    }
+
+    pub(crate) fn at(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    // Print the failure the way it was asked for.
+    pub(crate) fn print(&self, json: bool) {
+        match json {
+            true => { eprintln!("{}", self.to_json()) }
+            false => { println!("{}", self.message) }
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        let file = self.file.as_deref().map_or(String::from("null"), json_string);
+        let offset = self.offset.map_or(String::from("null"), |offset| offset.to_string());
+        format!("{{\"file\": {}, \"phase\": \"{}\", \"kind\": \"{}\", \"offset\": {}, \"message\": {}}}",
+                file, self.phase, self.kind.name(), offset, json_string(&self.message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostics::failure::{Failure, FailureKind};
+    use crate::file::error::WzError;
+
+    #[test]
+    fn test_json() {
+        let failure = Failure::new("decompressing", FailureKind::Corrupt, "Bad \"block\"\n\tat the end\\")
+            .in_file(Some("dir/in.wz"))
+            .at(1024);
+        assert_eq!("{\"file\": \"dir/in.wz\", \"phase\": \"decompressing\", \"kind\": \"corrupt\", \"offset\": 1024, \
+                    \"message\": \"Bad \\\"block\\\"\\n\\u0009at the end\\\\\"}", failure.to_json());
+
+        let failure = Failure::new("writing", FailureKind::Io, "Disk full");
+        assert_eq!("{\"file\": null, \"phase\": \"writing\", \"kind\": \"io\", \"offset\": null, \"message\": \"Disk full\"}",
+                   failure.to_json());
+    }
+
+    #[test]
+    fn test_from_stream() {
+        assert_eq!(FailureKind::Corrupt, Failure::from_stream("parsing", WzError::Truncated("header")).kind);
+        assert_eq!(FailureKind::Io, Failure::from_stream("parsing", WzError::Io(String::from("gone"))).kind);
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs;
+use std::time::{Duration, Instant};
+
+// A Report tracks how long each phase of the program took, and how much memory it used.
+// The tables and buffers used to code each block are measured too, to see what shrinking them would save.
+// This makes it easy to see where large inputs actually spend their resources.
+// Author: Will Morris
+
+pub struct Report {
+    // Disabled reports do no bookkeeping at all.
+    enabled: bool,
+    // Phases are kept in the order they were first run.
+    phases: Vec<(&'static str, Duration)>,
+    // The most bytes each kind of table or buffer ever took, in the order they were first measured.
+    sizes: Vec<(&'static str, usize)>,
+}
+
+impl Report {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, phases: vec![], sizes: vec![] }
+    }
+
+    // Run phase_fn, charging the time it takes to the phase with the given name.
+    // Running the same phase more than once accumulates its time.
+    pub fn time<T>(&mut self, name: &'static str, phase_fn: impl FnOnce() -> T) -> T {
+        // Phases are traced even when they aren't reported, since a subscriber may want them.
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("phase", name).entered();
+        if !self.enabled {
+            return phase_fn()
+        }
+
+        let start = Instant::now();
+        let retval = phase_fn();
+        let elapsed = start.elapsed();
+
+        match self.phases.iter_mut().find(|(phase, _)| *phase == name) {
+            Some((_, total)) => { *total += elapsed }
+            None => { self.phases.push((name, elapsed)) }
+        }
+        retval
+    }
+
+    // An empty report for another thread, to be merged back once it's done.
+    pub fn split(&self) -> Report {
+        Report::new(self.enabled)
+    }
+
+    // Take in what another thread reported. Time on several threads at once adds up, like CPU time.
+    pub fn merge(&mut self, other: Report) {
+        for (name, elapsed) in other.phases {
+            match self.phases.iter_mut().find(|(phase, _)| *phase == name) {
+                Some((_, total)) => { *total += elapsed }
+                None => { self.phases.push((name, elapsed)) }
+            }
+        }
+        for (name, bytes) in other.sizes {
+            self.measure(name, bytes);
+        }
+    }
+
+    // Record that a table or buffer with the given name took this many bytes.
+    // Only the largest of each is kept, since that's what memory must be found for.
+    pub fn measure(&mut self, name: &'static str, bytes: usize) {
+        if !self.enabled {
+            return
+        }
+
+        match self.sizes.iter_mut().find(|(table, _)| *table == name) {
+            Some((_, largest)) => { *largest = (*largest).max(bytes) }
+            None => { self.sizes.push((name, bytes)) }
+        }
+    }
+
+    // Print the report to stderr, so that it never mixes with output sent to stdout.
+    pub fn print(&self) {
+        if !self.enabled {
+            return
+        }
+
+        eprintln!("Resource report:");
+        let mut total = Duration::ZERO;
+        for (name, elapsed) in &self.phases {
+            eprintln!("  {:<16}{:>12.3} ms", name, elapsed.as_secs_f64() * 1000.0);
+            total += *elapsed;
+        }
+        eprintln!("  {:<16}{:>12.3} ms", "total", total.as_secs_f64() * 1000.0);
+
+        match peak_rss() {
+            Some(kb) => { eprintln!("  {:<16}{:>12} KiB", "peak rss", kb) }
+            None => { eprintln!("  {:<16}{:>12}", "peak rss", "unavailable") }
+        }
+        for (name, bytes) in &self.sizes {
+            eprintln!("  {:<16}{:>12} B", name, bytes);
+        }
+    }
+}
+
+// Get the peak resident set size of this process, in kilobytes.
+// Only supported where /proc is available.
+fn peak_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    parse_peak_rss(&status)
+}
+
+// Pull the VmHWM ("high water mark") field out of a /proc status file.
+fn parse_peak_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostics::report::{parse_peak_rss, Report};
+
+    #[test]
+    fn test_accumulate() {
+        let mut report = Report::new(true);
+        assert_eq!(4, report.time("counting", || 2 + 2));
+        report.time("encoding", || ());
+        report.time("counting", || ());
+
+        let names: Vec<&str> = report.phases.iter().map(|(name, _)| *name).collect();
+        assert_eq!(vec!["counting", "encoding"], names);
+    }
+
+    #[test]
+    fn test_measure() {
+        let mut report = Report::new(true);
+        report.measure("code table", 100);
+        report.measure("tree", 10);
+        report.measure("code table", 50);
+        assert_eq!(vec![("code table", 100), ("tree", 10)], report.sizes);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut report = Report::new(true);
+        report.time("counting", || ());
+        report.measure("tree", 10);
+        let mut other = report.split();
+        assert!(other.phases.is_empty());
+        other.time("encoding", || ());
+        other.measure("tree", 20);
+        report.merge(other);
+
+        let names: Vec<&str> = report.phases.iter().map(|(name, _)| *name).collect();
+        assert_eq!(vec!["counting", "encoding"], names);
+        assert_eq!(vec![("tree", 20)], report.sizes);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut report = Report::new(false);
+        assert_eq!(4, report.time("counting", || 2 + 2));
+        report.measure("tree", 10);
+        assert!(report.phases.is_empty());
+        assert!(report.sizes.is_empty());
+    }
+
+    // Phases are traced even when the report is off.
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_traced() {
+        use std::fmt::Debug;
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        // Keeps each span's name and fields, and nothing else.
+        struct Spans(Arc<Mutex<Vec<String>>>);
+        struct Fields(String);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                self.0 += &format!(" {}={:?}", field.name(), value);
+            }
+        }
+
+        impl Subscriber for Spans {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = Fields(span.metadata().name().to_string());
+                span.record(&mut fields);
+                let mut spans = self.0.lock().unwrap();
+                spans.push(fields.0);
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let spans = Arc::new(Mutex::new(vec![]));
+        tracing::subscriber::with_default(Spans(spans.clone()), || {
+            let mut report = Report::new(false);
+            report.time("counting", || ());
+            report.time("encoding", || ());
+        });
+        assert_eq!(vec!["phase name=\"counting\"", "phase name=\"encoding\""], *spans.lock().unwrap());
+    }
+
+    #[test]
+    fn test_parse_peak_rss() {
+        let status = "Name:\twz\nVmPeak:\t  10000 kB\nVmHWM:\t    2048 kB\n";
+        assert_eq!(Some(2048), parse_peak_rss(status));
+        assert_eq!(None, parse_peak_rss("Name:\twz\n"));
+    }
+}
@@ -0,0 +1,225 @@
+use std::io;
+use std::io::{ErrorKind, Read};
+use crate::encoding::bitsequence::{Bit, BitOrder, BitSequence};
+use crate::file::bytestream::read_bytes;
+use crate::file::error::WzError;
+use crate::file::format::read_bit_count;
+
+// A BitReader walks over a stream of bits one bit at a time, remembering where it left off.
+// Bits are taken from each byte starting with the low bit, just as a BitSequence packs them, unless they were packed from the high bit.
+// The stream is read a buffer at a time, so it never has to be in memory all at once.
+// Blocks are decoded straight from their stored bytes this way, rather than copying their bits into a BitSequence first.
+// Author: Will Morris
+
+const BUFFER_SIZE: usize = 8192;
+
+pub struct BitReader<R: Read> {
+    inner: R,
+    buffer: Box<[u8]>,
+    // The unread part of the buffer.
+    start: usize,
+    end: usize,
+    // What's left of the byte being read, shifted down so the next bit is the low one.
+    current: u8,
+    // Which end of each byte the bits were packed from.
+    order: BitOrder,
+    bits_left: u32,
+    position: u64,
+    // Bits past this are never read, e.g. the padding at the end of a sequence.
+    limit: u64,
+    // Why the stream stopped early, if it did.
+    error: Option<io::Error>,
+}
+
+impl<R: Read> BitReader<R> {
+    // Start reading from the first bit of inner, carrying on until it runs dry.
+    // NOTE: inner is read ahead, so it may be past the last bit read.
+    pub fn new(inner: R) -> Self {
+        Self { inner, buffer: vec![0; BUFFER_SIZE].into_boxed_slice(), start: 0, end: 0, current: 0,
+            order: BitOrder::Lsb, bits_left: 0, position: 0, limit: u64::MAX, error: None }
+    }
+
+    // Get the next bit, or none if every bit has already been read.
+    pub fn read_bit(&mut self) -> Option<Bit> {
+        if self.position >= self.limit || (self.bits_left == 0 && !self.next_byte()) {
+            return None
+        }
+        let bit = self.current & 1;
+        self.current >>= 1;
+        self.bits_left -= 1;
+        self.position += 1;
+        Some(bit)
+    }
+
+    // Read a value of up to 64 bits, sent least significant bit first.
+    // Returns none if the stream runs dry partway through.
+    pub fn read_bits(&mut self, count: u32) -> Option<u64> {
+        assert!(count <= u64::BITS, "Can't read {} bits into a u64!", count);
+        let mut value = 0;
+        for i in 0..count {
+            value |= (self.read_bit()? as u64) << i;
+        }
+        Some(value)
+    }
+
+    // Skip whatever is left of the current byte, so the next read starts on a byte boundary.
+    pub fn align_to_byte(&mut self) {
+        self.position += self.bits_left as u64;
+        self.bits_left = 0;
+    }
+
+    // How many bits have been read so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // The bit reading stops at. Readers over a stream of unknown length carry on until it runs dry.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    // The error that stopped the stream, if it didn't just end.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    // Move on to the next byte, refilling the buffer from inner if need be.
+    // Returns whether there was another byte.
+    fn next_byte(&mut self) -> bool {
+        if self.start == self.end && !self.fill() {
+            return false
+        }
+        self.current = match self.order {
+            BitOrder::Lsb => { self.buffer[self.start] }
+            BitOrder::Msb => { self.buffer[self.start].reverse_bits() }
+        };
+        self.bits_left = 8;
+        self.start += 1;
+        true
+    }
+
+    // Refill the buffer from inner, returning whether there's anything left to read.
+    fn fill(&mut self) -> bool {
+        if self.error.is_some() {
+            return false
+        }
+        loop {
+            match self.inner.read(&mut self.buffer) {
+                Ok(len) => {
+                    self.start = 0;
+                    self.end = len;
+                    return len > 0
+                }
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => {
+                    self.error = Some(error);
+                    return false
+                }
+            }
+        }
+    }
+}
+
+impl<'a> BitReader<&'a [u8]> {
+    // Start reading from the first bit of seq, stopping at its last.
+    pub fn from_seq(seq: &'a BitSequence) -> Self {
+        Self { limit: seq.len(), ..Self::new(seq.as_bytes()) }
+    }
+
+    // Read a stored sequence where it lies, its bits packed in the given order.
+    // The whole stream must be the sequence, just as BitSequence::from_packed_stream expects.
+    pub fn from_packed_stream(bytes: &'a [u8], order: BitOrder) -> Result<Self, WzError> {
+        let mut i = 0;
+        let (num_bits, len) = read_bit_count(bytes, &mut i)?;
+        let data = read_bytes(bytes, &mut i, len, "a bit sequence")?;
+        match i == bytes.len() {
+            true => { Ok(Self { limit: num_bits, order, ..Self::new(data) }) }
+            false => { Err(WzError::Malformed(format!("{} unexpected bytes at the end of the stream!", bytes.len() - i))) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::{ErrorKind, Read};
+    use crate::encoding::bitreader::{BitReader, BUFFER_SIZE};
+    use crate::encoding::bitsequence::{BitOrder, BitSequence};
+    use crate::file::bytestream::ByteStream;
+
+    // Hands out a byte per read, then fails.
+    struct Trickle(std::vec::IntoIter<u8>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            buf[0] = self.0.next().ok_or(io::Error::other("broken pipe"))?;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_read_all() {
+        let seq = BitSequence::from_bits(&[1, 0, 1]);
+        let mut reader = BitReader::from_seq(&seq);
+        assert_eq!(Some(1), reader.read_bit());
+        assert_eq!(Some(0), reader.read_bit());
+        assert_eq!(Some(1), reader.read_bit());
+        assert_eq!(None, reader.read_bit());
+        assert!(reader.error().is_none());
+    }
+
+    #[test]
+    fn test_align() {
+        let seq = BitSequence::from(16, &[0b1, 0b10]);
+        let mut reader = BitReader::from_seq(&seq);
+        reader.align_to_byte();
+        assert_eq!(0, reader.position());
+        assert_eq!(Some(1), reader.read_bit());
+        reader.align_to_byte();
+        assert_eq!(8, reader.position());
+        assert_eq!(Some(0), reader.read_bit());
+        assert_eq!(Some(1), reader.read_bit());
+    }
+
+    #[test]
+    fn test_read_bits() {
+        let bytes = [0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xff];
+        let mut reader = BitReader::new(&bytes[..]);
+        assert_eq!(Some(0xb), reader.read_bits(4));
+        assert_eq!(Some(0), reader.read_bits(0));
+        assert_eq!(Some(0xa), reader.read_bits(4));
+        assert_eq!(Some(0xff89_6745_2301_efcd), reader.read_bits(64));
+        assert_eq!(None, reader.read_bits(1));
+    }
+
+    // Streams longer than the buffer, arriving a little at a time, read the same as any other.
+    #[test]
+    fn test_stream() {
+        let bytes: Vec<u8> = (0..BUFFER_SIZE * 2 + 3).map(|i| i as u8).collect();
+        let mut reader = BitReader::new(Trickle(bytes.clone().into_iter()));
+        for byte in &bytes {
+            assert_eq!(Some(*byte as u64), reader.read_bits(8));
+        }
+        assert_eq!(None, reader.read_bit());
+        assert_eq!(ErrorKind::Other, reader.error().unwrap().kind());
+    }
+
+    // Stored sequences read the same where they lie as they do copied into a BitSequence, in either order.
+    #[test]
+    fn test_packed_stream() {
+        let seq = BitSequence::from_bits(&[1, 1, 0, 1, 0, 0, 0, 1, 1, 0, 1]);
+        for order in [BitOrder::Lsb, BitOrder::Msb] {
+            let stream = seq.to_packed_stream(order);
+            let mut reader = BitReader::from_packed_stream(&stream, order).unwrap();
+            assert_eq!(seq.len(), reader.limit());
+            let bits: Vec<u8> = std::iter::from_fn(|| reader.read_bit()).collect();
+            assert_eq!(seq, BitSequence::from_bits(&bits));
+
+            let mut longer = stream.clone();
+            longer.push(0);
+            assert!(BitReader::from_packed_stream(&longer, order).is_err());
+            assert!(BitReader::from_packed_stream(&stream[..stream.len() - 1], order).is_err());
+        }
+        assert!(BitReader::from_packed_stream(&seq.clone().to_stream(), BitOrder::Lsb).is_ok());
+    }
+}
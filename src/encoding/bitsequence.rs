@@ -1,14 +1,45 @@
-use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
-use crate::file::bytestream::{ByteStream, LONG_LEN, slice_to_long};
+use std::ops::{Bound, Index, RangeBounds};
+use crate::file::bytestream::{ByteStream, read_bytes};
+use crate::file::error::WzError;
+use crate::file::format::{read_bit_count, write_bit_count};
 
 // A BitSequence encapsulates a string of bits and methods for interacting with them.
+// It's part of the library, as well as the compressor, so other crates can use it too.
 // Author: Will Morris
 // Big credit to Dr. Nathan Sprague for making a java version of this.
-type Bit = u8;
 
-#[derive(Clone, PartialEq, Eq, Hash)]
-pub(crate) struct BitSequence {
+/// A single bit: always 0 or 1.
+pub type Bit = u8;
+
+/// Maps each byte to its code.
+/// Indexed directly by byte value, so encoding never has to hash anything.
+pub type Encoding = [Option<BitSequence>; 256];
+
+/// Which end of each byte bits are packed from.
+/// BitSequences always pack from the low bit, but other formats and decoders may expect the high bit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOrder {
+    /// The first bit goes in the lowest bit of each byte.
+    Lsb = 0,
+    /// The first bit goes in the highest bit of each byte.
+    Msb = 1,
+}
+
+impl BitOrder {
+    /// The order a byte stands for, as stored in a header.
+    pub fn from_byte(byte: u8) -> Option<BitOrder> {
+        match byte {
+            0 => { Some(BitOrder::Lsb) }
+            1 => { Some(BitOrder::Msb) }
+            _ => { None }
+        }
+    }
+}
+
+/// A string of bits, packed eight to a byte starting from the low bit.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct BitSequence {
     // NOTE: in most cases, u64 will be equal to usize, so indexing with u64 will work.
     // The only time this wouldn't work is:
     // 1. you're on a 32-bit system
@@ -22,31 +53,66 @@ pub(crate) struct BitSequence {
 
 // ****** CONSTRUCTORS ****** //
 impl BitSequence {
-    // Create a new, empty BitSequence.
-    pub(crate) fn new() -> Self {
+    /// Create a new, empty BitSequence.
+    pub fn new() -> Self {
         Self {
             num_bits: 0,
             bytes: vec![],
         }
     }
 
-    // Create a BitSequence from a string of bits.
-    pub(crate) fn from_bits(bits: &[Bit]) -> Self {
+    /// Create a BitSequence from a string of bits.
+    pub fn from_bits(bits: &[Bit]) -> Self {
         let mut seq = Self::new();
         seq.append_bits(bits);
         seq
     }
 
-    // Create a BitSequence from a vector and length in bits.
-    pub(crate) fn from(num_bits: u64, bytes: &[u8]) -> Self {
-        Self { num_bits, bytes: bytes.to_vec() }
+    /// Create a BitSequence from bytes packed eight bits to a byte from the low bit, and a length in bits.
+    /// Panics if there are too few bytes for num_bits. Any bytes or bits past the end are dropped.
+    pub fn from(num_bits: u64, bytes: &[u8]) -> Self {
+        Self::from_raw(num_bits, bytes.to_vec())
+    }
+
+    /// Create a BitSequence from bytes packed eight bits to a byte from the low bit, taking ownership of them.
+    /// Unlike from_stream, there's no length in front: callers with their own framing pass it in.
+    /// Panics if there are too few bytes for num_bits. Any bytes or bits past the end are dropped.
+    pub fn from_raw(num_bits: u64, mut bytes: Vec<u8>) -> Self {
+        let len = num_bits.div_ceil(8) as usize;
+        assert!(bytes.len() >= len, "{} bytes can't hold {} bits!", bytes.len(), num_bits);
+        bytes.truncate(len);
+        let mut retval = Self { num_bits, bytes };
+        retval.clear_past_end();
+        retval
+    }
+
+    /// Create a BitSequence from bytes packed in the given order, as packed leaves them.
+    /// Panics if there are too few bytes for num_bits. Any bytes or bits past the end are dropped.
+    pub fn from_packed(num_bits: u64, bytes: &[u8], order: BitOrder) -> Self {
+        // Reversing a byte twice gives it back, so MSB bytes unpack just as they were packed.
+        let bytes = match order {
+            BitOrder::Lsb => { bytes.to_vec() }
+            BitOrder::Msb => { bytes.iter().map(|byte| byte.reverse_bits()).collect() }
+        };
+        Self::from_raw(num_bits, bytes)
+    }
+
+    /// Read a whole stream written by to_packed_stream, packed in the same order.
+    pub fn from_packed_stream(bytes: &[u8], order: BitOrder) -> Result<Self, WzError> {
+        let mut i = 0;
+        let (num_bits, len) = read_bit_count(bytes, &mut i)?;
+        let data = read_bytes(bytes, &mut i, len, "a bit sequence")?;
+        match i == bytes.len() {
+            true => { Ok(Self::from_packed(num_bits, data, order)) }
+            false => { Err(WzError::Malformed(format!("{} unexpected bytes at the end of the stream!", bytes.len() - i))) }
+        }
     }
 
-    // Translate a collection of bytes into a large bitsequence.
-    pub(crate) fn translate(bytes: &[u8], encoding: &HashMap<u8, BitSequence>) -> Self {
+    /// Translate a collection of bytes into a large bitsequence.
+    pub fn translate(bytes: &[u8], encoding: &Encoding) -> Self {
         let mut retval = BitSequence::new();
         for byte in bytes {
-            retval.append_seq(encoding.get(byte).unwrap());
+            retval.append_seq(encoding[*byte as usize].as_ref().unwrap());
         }
         retval
     }
@@ -55,8 +121,8 @@ impl BitSequence {
 
 // ****** MUTATORS ****** ///
 impl BitSequence {
-    // Append a single bit to the end of the sequence.
-    pub(crate) fn append_bit(&mut self, bit: Bit) {
+    /// Append a single bit to the end of the sequence.
+    pub fn append_bit(&mut self, bit: Bit) {
         assert!(bit == 0 || bit == 1);
 
         let byte_index = self.num_bits / 8;
@@ -73,17 +139,88 @@ impl BitSequence {
         self.num_bits += 1;
     }
 
-    // Append all bits from bit slice to self.
-    // Useful for adding all bits while maintaining ownership.
-    pub(crate) fn append_bits(&mut self, bits: &[Bit]) {
+    /// Append the low count bits of value, least significant first, as if by append_bit.
+    /// Works a word at a time, rather than a bit at a time. Count may be at most 64.
+    pub fn append_bits_u64(&mut self, value: u64, count: u32) {
+        assert!(count <= u64::BITS, "Can't append {} bits of a u64!", count);
+        let value = match count {
+            64 => { value }
+            _ => { value & ((1 << count) - 1) }
+        };
+
+        // Shifted into place, the low bits fill the end of our last byte.
+        // 64 bits shifted by up to 7 need more than a u64, so a u128 holds them.
+        let offset = self.num_bits % 8;
+        let shifted = ((value as u128) << offset).to_le_bytes();
+        let end = (offset + count as u64).div_ceil(8) as usize;
+        match offset {
+            0 => { self.bytes.extend_from_slice(&shifted[..end]) }
+            _ => {
+                *self.bytes.last_mut().unwrap() |= shifted[0];
+                self.bytes.extend_from_slice(&shifted[1..end.max(1)]);
+            }
+        }
+        self.num_bits += count as u64;
+    }
+
+    /// Append all bits from bit slice to self.
+    /// Useful for adding all bits while maintaining ownership.
+    pub fn append_bits(&mut self, bits: &[Bit]) {
         bits.iter().for_each(|bit| self.append_bit(*bit));
     }
 
-    // Assimilate a BitSequence into this sequence.
-    // Useful for removing temporary BitSequences from the equation
-    // if you want to keep your BitSequence, use append_bits
-    fn append_seq(&mut self, seq: &BitSequence) {
-        self.append_bits(&seq.get_bits());
+    /// Assimilate a BitSequence into this sequence.
+    /// Useful for removing temporary BitSequences from the equation
+    /// if you want to keep your BitSequence, use append_bits
+    /// Works a byte at a time, shifting seq's bytes into place rather than copying bit by bit.
+    pub fn append_seq(&mut self, seq: &BitSequence) {
+        let offset = self.num_bits % 8;
+        let seq_bytes = seq.num_bits.div_ceil(8) as usize;
+
+        for (i, byte) in seq.bytes[..seq_bytes].iter().enumerate() {
+            // Bits past the end of seq aren't always clear, i.e. in one read from a stream, so they're masked off.
+            let byte = match i + 1 == seq_bytes && !seq.num_bits.is_multiple_of(8) {
+                true => { byte & ((1 << (seq.num_bits % 8)) - 1) }
+                false => { *byte }
+            };
+            if offset == 0 {
+                self.bytes.push(byte);
+            } else {
+                // The low bits of byte fill the end of our last byte.
+                // The high bits spill over into a new one.
+                *self.bytes.last_mut().unwrap() |= byte << offset;
+                self.bytes.push(byte >> (8 - offset));
+            }
+        }
+
+        self.num_bits += seq.num_bits;
+        // Spilling may have left an empty byte past the end.
+        self.bytes.truncate(self.num_bits.div_ceil(8) as usize);
+    }
+
+    /// Shorten the sequence to its first num_bits bits. Longer lengths leave it as it is.
+    /// The bits cut off are cleared, so anything appended afterwards starts from a clean slate.
+    pub fn truncate(&mut self, num_bits: u64) {
+        if num_bits >= self.num_bits {
+            return
+        }
+        self.num_bits = num_bits;
+        self.bytes.truncate(num_bits.div_ceil(8) as usize);
+        self.clear_past_end();
+    }
+
+    /// Remove the last bit and return it, or none if there are no bits left.
+    pub fn pop_bit(&mut self) -> Option<Bit> {
+        let bit = self.get_bit(self.num_bits.checked_sub(1)?)?;
+        self.truncate(self.num_bits - 1);
+        Some(bit)
+    }
+
+    // Appending relies on any bits in the last byte past the end being zero.
+    fn clear_past_end(&mut self) {
+        if !self.num_bits.is_multiple_of(8) {
+            *self.bytes.last_mut().unwrap() &= (1 << (self.num_bits % 8)) - 1;
+        }
     }
 }
 
@@ -91,8 +228,8 @@ impl BitSequence {
 // ****** ACCESSORS ****** //
 
 impl BitSequence {
-    // Get the bit at index usize.
-    pub(crate) fn get_bit(&self, index: u64) -> Option<Bit> {
+    /// Get the bit at index, or none if the sequence isn't that long.
+    pub fn get_bit(&self, index: u64) -> Option<Bit> {
         if index >= self.num_bits {
             return None;
         }
@@ -105,24 +242,188 @@ impl BitSequence {
         }
     }
 
-    // Get all bits in bit sequence.
-    fn get_bits(&self) -> Vec<u8> {
-        let mut bits: Vec<Bit> = vec![];
-        for i in 0..self.num_bits {
-            bits.push(self.get_bit(i).unwrap());
+    /// Copy out the bits in range, as a sequence of their own.
+    /// Works a byte at a time, shifting the bytes down into place rather than copying bit by bit.
+    /// Panics if the range runs past the end of the sequence, just like slicing a Vec.
+    pub fn slice(&self, range: impl RangeBounds<u64>) -> BitSequence {
+        let start = match range.start_bound() {
+            Bound::Included(start) => { *start }
+            Bound::Excluded(start) => { start + 1 }
+            Bound::Unbounded => { 0 }
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => { end + 1 }
+            Bound::Excluded(end) => { *end }
+            Bound::Unbounded => { self.num_bits }
+        };
+        assert!(start <= end && end <= self.num_bits, "Slice {}..{} out of range for {} bits!", start, end, self.num_bits);
+
+        let num_bits = end - start;
+        let first = (start / 8) as usize;
+        let offset = start % 8;
+        let bytes: Vec<u8> = (first..first + num_bits.div_ceil(8) as usize).map(|i| {
+            // The high bits of this byte become the low bits of the result.
+            // The low bits of the next byte fill in above them.
+            let low = self.bytes[i] >> offset;
+            let high = match (offset, self.bytes.get(i + 1)) {
+                (0, _) | (_, None) => { 0 }
+                (_, Some(next)) => { next << (8 - offset) }
+            };
+            low | high
+        }).collect();
+
+        // The next byte's bits may have spilled in past the end of the slice.
+        let mut retval = BitSequence { num_bits, bytes };
+        retval.clear_past_end();
+        retval
+    }
+
+    /// The bits, packed eight to a byte starting from the low bit, without any length in front.
+    /// Any bits in the last byte past the end of the sequence are zero.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Like as_bytes, but gives up the bytes rather than borrowing them.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// The bits, packed eight to a byte in the given order.
+    /// Reversing each byte swaps one order for the other, so this also undoes itself.
+    pub fn packed(&self, order: BitOrder) -> Vec<u8> {
+        match order {
+            BitOrder::Lsb => { self.bytes.clone() }
+            BitOrder::Msb => { self.bytes.iter().map(|byte| byte.reverse_bits()).collect() }
         }
-        bits
     }
 
-    // Length attribute particularly useful when testing.
-    pub(crate) fn length(&self) -> u64 {
+    /// Like to_stream, but with the bits packed in the given order. Only from_packed_stream can read them back.
+    pub fn to_packed_stream(&self, order: BitOrder) -> Vec<u8> {
+        let mut retval = vec![];
+        write_bit_count(&mut retval, self.num_bits);
+        retval.append(&mut self.packed(order));
+        retval
+    }
+
+    /// How many bits are in the sequence.
+    pub fn len(&self) -> u64 {
         self.num_bits
     }
+
+    /// Whether the sequence has no bits at all.
+    pub fn is_empty(&self) -> bool {
+        self.num_bits == 0
+    }
+
+    /// Iterate over the bits, from first to last.
+    pub fn iter(&self) -> Bits<'_> {
+        Bits { seq: self, index: 0 }
+    }
+
+    /// How many bytes of memory the sequence takes, counting room reserved for bits not yet appended.
+    pub fn memory_size(&self) -> usize {
+        size_of::<Self>() + self.bytes.capacity()
+    }
+}
+
+/// An iterator over the bits of a BitSequence, made by BitSequence::iter.
+pub struct Bits<'a> {
+    seq: &'a BitSequence,
+    index: u64,
+}
+
+impl Iterator for Bits<'_> {
+    type Item = Bit;
+
+    fn next(&mut self) -> Option<Bit> {
+        let bit = self.seq.get_bit(self.index)?;
+        self.index += 1;
+        Some(bit)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = (self.seq.num_bits - self.index) as usize;
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for Bits<'_> {}
+
+impl<'a> IntoIterator for &'a BitSequence {
+    type Item = Bit;
+    type IntoIter = Bits<'a>;
+
+    fn into_iter(self) -> Bits<'a> {
+        self.iter()
+    }
+}
+
+/// An iterator over the bits of a BitSequence it owns, made by BitSequence::into_iter.
+pub struct IntoBits {
+    seq: BitSequence,
+    index: u64,
+}
+
+impl Iterator for IntoBits {
+    type Item = Bit;
+
+    fn next(&mut self) -> Option<Bit> {
+        let bit = self.seq.get_bit(self.index)?;
+        self.index += 1;
+        Some(bit)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = (self.seq.num_bits - self.index) as usize;
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for IntoBits {}
+
+impl IntoIterator for BitSequence {
+    type Item = Bit;
+    type IntoIter = IntoBits;
+
+    fn into_iter(self) -> IntoBits {
+        IntoBits { seq: self, index: 0 }
+    }
+}
+
+/// Get a bit by index, like get_bit. Panics if the sequence isn't that long.
+impl Index<u64> for BitSequence {
+    type Output = Bit;
+
+    fn index(&self, index: u64) -> &Bit {
+        // Bits are packed, so there's nothing to borrow but the constants.
+        match self.get_bit(index) {
+            Some(0) => { &0 }
+            Some(_) => { &1 }
+            None => { panic!("Bit {} is past the end of a sequence of {} bits!", index, self.num_bits) }
+        }
+    }
+}
+
+/// Collect bits into a sequence. Each bit must be 0 or 1.
+impl FromIterator<Bit> for BitSequence {
+    fn from_iter<I: IntoIterator<Item = Bit>>(iter: I) -> Self {
+        let mut seq = BitSequence::new();
+        seq.extend(iter);
+        seq
+    }
+}
+
+/// Append bits to the end of the sequence. Each bit must be 0 or 1.
+impl Extend<Bit> for BitSequence {
+    fn extend<I: IntoIterator<Item = Bit>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|bit| self.append_bit(bit));
+    }
 }
 
 impl Debug for BitSequence {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        for bit in self.get_bits() {
+        for bit in self {
             f.write_fmt(format_args!("{}", bit))?;
         }
         Ok(())
@@ -132,16 +433,18 @@ impl Debug for BitSequence {
 impl ByteStream for BitSequence {
     type Data = BitSequence;
 
-    fn from_stream(bytes: &[u8]) -> Self::Data {
-        let num_bits = slice_to_long(&bytes[..LONG_LEN]);
-        let data = &bytes[LONG_LEN..];
-        BitSequence::from(num_bits, data)
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError> {
+        let mut i = 0;
+        let (num_bits, len) = read_bit_count(bytes, &mut i)?;
+        let data = read_bytes(bytes, &mut i, len, "a bit sequence")?;
+        // Whatever was stored past the end is cleared, just as in any other sequence.
+        Ok((BitSequence::from_raw(num_bits, data.to_vec()), i))
     }
 
-    fn to_stream(mut self) -> Vec<u8> {
+    fn to_stream(self) -> Vec<u8> {
         let mut retval = vec![];
-        retval.append(&mut Vec::from(self.num_bits.to_le_bytes()));
-        retval.append(&mut self.bytes);
+        write_bit_count(&mut retval, self.num_bits);
+        retval.append(&mut self.into_bytes());
         retval
     }
 }
@@ -188,19 +491,226 @@ mod tests {
         seq1.append_seq(&seq2);
         assert_eq!(0, seq1.get_bit(127).unwrap());
     }
+
+    // Appending at every possible bit offset must match appending bit by bit.
+    #[test]
+    fn test_append_seq_unaligned() {
+        let tail_bits: Vec<u8> = (0..21).map(|i| (i * 7 % 3 == 0) as u8).collect();
+        let tail = BitSequence::from_bits(&tail_bits);
+
+        for prefix_len in 0..16 {
+            let prefix_bits: Vec<u8> = (0..prefix_len).map(|i| (i % 2) as u8).collect();
+            let mut expected = BitSequence::from_bits(&prefix_bits);
+            expected.append_bits(&tail_bits);
+
+            let mut actual = BitSequence::from_bits(&prefix_bits);
+            actual.append_seq(&tail);
+            assert_eq!(expected, actual);
+        }
+    }
+
+    // Only the bits in the sequence are appended, whatever is set past its end.
+    #[test]
+    fn test_append_seq_past_end() {
+        let mut seq = BitSequence::new();
+        seq.append_seq(&BitSequence::from(3, &[0b1111_0101]));
+        seq.append_bits(&[0, 0, 0]);
+        assert_eq!(vec![1, 0, 1, 0, 0, 0], seq.iter().collect::<Vec<Bit>>());
+
+        let mut stream = BitSequence::from_bits(&[1, 1]).to_stream();
+        *stream.last_mut().unwrap() = 0xff;
+        let mut seq = BitSequence::from_bits(&[0]);
+        seq.append_seq(&BitSequence::from_whole_stream(&stream).unwrap());
+        seq.append_bit(0);
+        assert_eq!(vec![0, 1, 1, 0], seq.iter().collect::<Vec<Bit>>());
+    }
+
+    #[test]
+    fn test_packed() {
+        let seq = BitSequence::from_bits(&[1, 1, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(vec![0b11, 0b10], seq.packed(BitOrder::Lsb));
+        // The first bit is the high one, and the unused bits at the end are low.
+        assert_eq!(vec![0b11000000, 0b01000000], seq.packed(BitOrder::Msb));
+        assert_eq!(seq, BitSequence::from_packed(10, &seq.packed(BitOrder::Msb), BitOrder::Msb));
+
+        // Packed, the bits survive a trip through a stream.
+        for order in [BitOrder::Lsb, BitOrder::Msb] {
+            assert_eq!(seq, BitSequence::from_packed_stream(&seq.to_packed_stream(order), order).unwrap());
+            let mut stream = seq.to_packed_stream(order);
+            stream.push(0);
+            assert!(BitSequence::from_packed_stream(&stream, order).is_err());
+        }
+        assert_eq!(seq.clone().to_stream(), seq.to_packed_stream(BitOrder::Lsb));
+    }
+
+    // Bits stored past the end of a sequence are dropped on the way in, so it's equal to any other with the same bits.
+    #[test]
+    fn test_stream_past_end() {
+        let mut stream = 1u64.to_le_bytes().to_vec();
+        stream.push(0xff);
+        let mut seq = BitSequence::from_whole_stream(&stream).unwrap();
+        assert_eq!(&[1], seq.as_bytes());
+        assert_eq!(BitSequence::from_bits(&[1]), seq);
+        seq.append_bits_u64(0, 3);
+        assert_eq!(&[1], seq.as_bytes());
+        assert_eq!(BitSequence::from_bits(&[1, 0, 0, 0]), seq);
+    }
+
+    #[test]
+    fn test_iter() {
+        let bits = [1, 0, 0, 1, 1, 0, 1, 0, 1];
+        let seq: BitSequence = bits.iter().copied().collect();
+        assert_eq!(BitSequence::from_bits(&bits), seq);
+        assert_eq!(9, seq.len());
+        assert_eq!(9, seq.iter().len());
+        assert_eq!(bits.to_vec(), seq.iter().collect::<Vec<Bit>>());
+
+        let mut extended = BitSequence::new();
+        assert!(extended.is_empty());
+        extended.extend(seq.iter());
+        extended.extend([1, 1]);
+        assert_eq!(11, extended.len());
+        assert_eq!(Some(1), extended.get_bit(10));
+    }
+
+    #[test]
+    fn test_index() {
+        let seq = BitSequence::from_bits(&[0, 1, 1]);
+        assert_eq!(0, seq[0]);
+        assert_eq!(1, seq[2]);
+        assert_eq!(None, seq.get_bit(3));
+
+        let mut bits = vec![];
+        for bit in &seq {
+            bits.push(bit);
+        }
+        assert_eq!(vec![0, 1, 1], bits);
+        assert_eq!(3, seq.clone().into_iter().len());
+        assert_eq!(bits, seq.into_iter().collect::<Vec<Bit>>());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_index_past_end() {
+        let seq = BitSequence::from_bits(&[0, 1, 1]);
+        let _ = seq[3];
+    }
+
+    // Appending words at every possible bit offset must match appending bit by bit.
+    #[test]
+    fn test_append_bits_u64() {
+        let value = 0xdead_beef_cafe_f00d_u64;
+        for prefix_len in 0..9 {
+            for count in [0, 1, 7, 8, 13, 57, 63, 64] {
+                let prefix: Vec<u8> = (0..prefix_len).map(|i| (i % 2) as u8).collect();
+                let mut expected = BitSequence::from_bits(&prefix);
+                (0..count).for_each(|i| expected.append_bit(((value >> i) & 1) as u8));
+
+                let mut actual = BitSequence::from_bits(&prefix);
+                actual.append_bits_u64(value, count);
+                assert_eq!(expected, actual);
+                assert_eq!(expected.as_bytes(), actual.as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn test_raw() {
+        let seq = BitSequence::from_raw(10, vec![0xff, 0xff, 0xff]);
+        assert_eq!(BitSequence::from_bits(&[1; 10]), seq);
+        // Only the bytes the bits need are kept, and nothing past the end.
+        assert_eq!(&[0xff, 0b11], seq.as_bytes());
+        assert_eq!(vec![0xff, 0b11], seq.clone().into_bytes());
+        assert_eq!(seq, BitSequence::from_raw(seq.len(), seq.clone().into_bytes()));
+        assert!(BitSequence::from_raw(0, vec![]).is_empty());
+        // from copies, but is otherwise the same.
+        assert_eq!(seq, BitSequence::from(10, &[0xff, 0xff, 0xff]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_from_too_short() {
+        BitSequence::from(17, &[0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_memory_size() {
+        let mut seq = BitSequence::new();
+        assert_eq!(size_of::<BitSequence>(), seq.memory_size());
+        seq.append_bits(&[1; 100]);
+        assert!(seq.memory_size() >= size_of::<BitSequence>() + 13);
+        // Trimmed to the bytes the bits need, there's nothing spare.
+        assert_eq!(size_of::<BitSequence>() + 2, BitSequence::from_raw(10, vec![0xff, 0xff]).memory_size());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_raw_too_short() {
+        BitSequence::from_raw(17, vec![0, 0]);
+    }
+
+    #[test]
+    fn test_truncate_pop() {
+        let mut seq = BitSequence::from_bits(&[1; 20]);
+        seq.truncate(30);
+        assert_eq!(20, seq.len());
+
+        // Cleared bits stay cleared when appended over.
+        seq.truncate(11);
+        assert_eq!(BitSequence::from_bits(&[1; 11]), seq);
+        seq.append_bits(&[0, 0, 0, 0, 0, 0]);
+        assert_eq!(vec![0xff, 0b111, 0], seq.as_bytes());
+
+        assert_eq!(Some(0), seq.pop_bit());
+        assert_eq!(16, seq.len());
+        seq.truncate(1);
+        assert_eq!(Some(1), seq.pop_bit());
+        assert_eq!(None, seq.pop_bit());
+        assert!(seq.is_empty());
+        assert!(seq.as_bytes().is_empty());
+    }
+
+    // Slicing at every possible start and end must match copying bit by bit.
+    #[test]
+    fn test_slice() {
+        let bits: Vec<u8> = (0..37).map(|i| (i * 5 % 3 == 0) as u8).collect();
+        let seq = BitSequence::from_bits(&bits);
+        for start in 0..=bits.len() {
+            for end in start..=bits.len() {
+                assert_eq!(BitSequence::from_bits(&bits[start..end]), seq.slice(start as u64..end as u64));
+            }
+        }
+        assert_eq!(seq, seq.slice(..));
+        assert_eq!(BitSequence::from_bits(&bits[3..=9]), seq.slice(3..=9));
+        assert_eq!(BitSequence::from_bits(&bits[30..]), seq.slice(30..));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_slice_past_end() {
+        BitSequence::from_bits(&[1, 0, 1]).slice(1..4);
+    }
+
+    #[test]
+    fn test_debug() {
+        let seq = BitSequence::from_bits(&[1, 0, 0, 1, 1]);
+        assert_eq!("10011", format!("{:?}", seq));
+    }
 }
 
 #[cfg(test)]
 mod serialize_tests {
     use crate::encoding::bitsequence::BitSequence;
     use crate::file::bytestream::ByteStream;
+    use crate::file::error::WzError;
 
     #[test]
     fn test_empty_bitseq() {
         let seq = BitSequence::new();
         let from = seq.to_stream();
-        let to = BitSequence::from_stream(&from);
+        let (to, len) = BitSequence::from_stream(&from).unwrap();
         assert_eq!(0, to.num_bits);
+        assert_eq!(8, len);
     }
 
     #[test]
@@ -211,10 +721,15 @@ mod serialize_tests {
         }
         seq.append_bit(1);
 
-        let bytes = seq.clone().to_stream();
-        let from = BitSequence::from_stream(&bytes);
-
+        let mut bytes = seq.clone().to_stream();
+        let from = BitSequence::from_whole_stream(&bytes).unwrap();
         assert_eq!(seq, from);
+
+        // Only the bytes the bits need are read.
+        bytes.push(0xff);
+        assert_eq!((seq, 10), BitSequence::from_stream(&bytes).unwrap());
+        assert!(BitSequence::from_whole_stream(&bytes).is_err());
+        assert_eq!(Err(WzError::Truncated("a bit sequence")), BitSequence::from_stream(&bytes[..9]));
     }
 }
 
@@ -0,0 +1,193 @@
+use std::io;
+use std::io::Write;
+use crate::encoding::bitsequence::{BitOrder, BitSequence};
+use crate::file::format::write_bit_count;
+
+// A BitWriter packs bits into bytes and writes them out, a buffer at a time.
+// Bits fill each byte starting with the low bit, just as a BitSequence packs them, unless they're packed from the high bit.
+// Output never needs to be in memory all at once, unlike a BitSequence.
+// Blocks are coded straight into their stored bytes this way, rather than gathering their bits into a BitSequence first.
+// Author: Will Morris
+
+const BUFFER_SIZE: usize = 8192;
+
+pub struct BitWriter<W: Write> {
+    inner: W,
+    // Whole bytes, waiting to be written.
+    buffer: Vec<u8>,
+    // Bits that don't yet make up a whole byte, the first of them the low one.
+    current: u128,
+    bits: u32,
+    // Which end of each byte the bits are packed from.
+    order: BitOrder,
+    // How many bits have been written, padding included.
+    position: u64,
+}
+
+impl<W: Write> BitWriter<W> {
+    // Start writing bits to inner.
+    // NOTE: nothing is written when a BitWriter is dropped. Call finish when done!
+    pub fn new(inner: W) -> Self {
+        Self::with_order(inner, BitOrder::Lsb)
+    }
+
+    // Start writing bits to inner, packing each byte from the given end.
+    pub fn with_order(inner: W, order: BitOrder) -> Self {
+        Self { inner, buffer: Vec::with_capacity(BUFFER_SIZE), current: 0, bits: 0, order, position: 0 }
+    }
+
+    // Write the count low bits of value, least significant bit first.
+    pub fn write_bits(&mut self, value: u64, count: u32) -> io::Result<()> {
+        assert!(count <= u64::BITS, "Can't write {} bits from a u64!", count);
+        let mask = u64::MAX.checked_shr(u64::BITS - count).unwrap_or(0);
+        self.current |= ((value & mask) as u128) << self.bits;
+        self.bits += count;
+        self.position += count as u64;
+        while self.bits >= 8 {
+            self.buffer.push(match self.order {
+                BitOrder::Lsb => { self.current as u8 }
+                BitOrder::Msb => { (self.current as u8).reverse_bits() }
+            });
+            self.current >>= 8;
+            self.bits -= 8;
+        }
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.write_buffer()?;
+        }
+        Ok(())
+    }
+
+    // Write every bit of seq.
+    pub fn write_seq(&mut self, seq: &BitSequence) -> io::Result<()> {
+        let bytes = seq.as_bytes();
+        let whole = (seq.len() / 8) as usize;
+        for byte in &bytes[..whole] {
+            self.write_bits(*byte as u64, 8)?;
+        }
+        match bytes.get(whole) {
+            Some(byte) => { self.write_bits(*byte as u64, (seq.len() % 8) as u32) }
+            None => { Ok(()) }
+        }
+    }
+
+    // How many bits have been written so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // Pad the current byte with zeros, so the next write starts on a byte boundary.
+    pub fn align_to_byte(&mut self) -> io::Result<()> {
+        match self.bits {
+            0 => { Ok(()) }
+            bits => { self.write_bits(0, 8 - bits) }
+        }
+    }
+
+    // Write out every whole byte so far, then flush inner.
+    // The bits of a partial byte are held back, as more may yet be added to it.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer()?;
+        self.inner.flush()
+    }
+
+    // Pad the last byte with zeros, write everything out, and give back inner.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.align_to_byte()?;
+        self.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl BitWriter<Vec<u8>> {
+    // Finish the bits written as a stored sequence: how many there are, then their bytes.
+    // This is just what BitSequence::to_packed_stream writes, so BitReader::from_packed_stream reads it back.
+    pub fn into_packed_stream(self) -> Vec<u8> {
+        let mut retval = vec![];
+        write_bit_count(&mut retval, self.position);
+        // Writing to a vector never fails.
+        retval.append(&mut self.finish().unwrap());
+        retval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::bitreader::BitReader;
+    use crate::encoding::bitsequence::{BitOrder, BitSequence};
+    use crate::encoding::bitwriter::{BitWriter, BUFFER_SIZE};
+
+    #[test]
+    fn test_write_bits() {
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0xb, 4).unwrap();
+        writer.write_bits(0xff, 0).unwrap();
+        // Bits above count are ignored.
+        writer.write_bits(0xfa, 4).unwrap();
+        writer.write_bits(0xff89_6745_2301_efcd, 64).unwrap();
+        writer.write_bits(1, 1).unwrap();
+        assert_eq!(vec![0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xff, 0x01], writer.finish().unwrap());
+    }
+
+    #[test]
+    fn test_padding() {
+        let mut writer = BitWriter::new(vec![]);
+        writer.align_to_byte().unwrap();
+        writer.write_bits(0b101, 3).unwrap();
+        writer.align_to_byte().unwrap();
+        writer.align_to_byte().unwrap();
+        writer.write_bits(0b11, 2).unwrap();
+        // Flushing holds back the partial byte. Finishing pads it out.
+        writer.flush().unwrap();
+        assert_eq!(vec![0b101], writer.inner);
+        assert_eq!(vec![0b101, 0b11], writer.finish().unwrap());
+    }
+
+    #[test]
+    fn test_write_seq() {
+        let seq = BitSequence::from_bits(&[1, 0, 1, 1, 0, 0, 0, 1, 1, 1, 0]);
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(1, 1).unwrap();
+        writer.write_seq(&seq).unwrap();
+        writer.write_seq(&BitSequence::new()).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let mut expected = BitSequence::from_bits(&[1]);
+        expected.append_seq(&seq);
+        assert_eq!(expected.as_bytes(), &bytes[..]);
+    }
+
+    // Output larger than the buffer comes out whole, and reads back the same.
+    #[test]
+    fn test_round_trip() {
+        let mut writer = BitWriter::new(vec![]);
+        for i in 0..BUFFER_SIZE as u64 * 2 {
+            writer.write_bits(i, (i % 13) as u32).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = BitReader::new(&bytes[..]);
+        for i in 0..BUFFER_SIZE as u64 * 2 {
+            let count = (i % 13) as u32;
+            assert_eq!(Some(i & ((1 << count) - 1)), reader.read_bits(count));
+        }
+    }
+
+    // Sequences written straight to a stream are stored just as BitSequences store them, in either order.
+    #[test]
+    fn test_packed_stream() {
+        let seq = BitSequence::from_bits(&[1, 1, 0, 1, 0, 0, 0, 1, 1, 0, 1]);
+        for order in [BitOrder::Lsb, BitOrder::Msb] {
+            let mut writer = BitWriter::with_order(vec![], order);
+            writer.write_seq(&seq).unwrap();
+            assert_eq!(seq.len(), writer.position());
+            assert_eq!(seq.to_packed_stream(order), writer.into_packed_stream());
+        }
+        assert_eq!(BitSequence::new().to_packed_stream(BitOrder::Lsb), BitWriter::new(vec![]).into_packed_stream());
+    }
+}
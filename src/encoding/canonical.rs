@@ -0,0 +1,179 @@
+// Canonical Huffman codes.
+// Codes are assigned from their lengths alone: shorter codes first, then in symbol order.
+// So anyone given the lengths can rebuild exactly the same codes, whatever Huffman tree they'd have built.
+// Author: Will Morris
+
+use std::collections::HashMap;
+use crate::encoding::bitsequence::{BitSequence, Encoding};
+use crate::encoding::decodingtable::DecodingTable;
+use crate::ordering::symbolfreq::Symbol;
+use crate::tree::node::huffman_symbols;
+
+// Canonical blocks keep their codes this short, so each length fits in half a byte.
+pub const MAX_CANONICAL_LEN: u32 = 15;
+// Half a byte for each possible byte's length.
+pub const LENGTHS_LEN: usize = 128;
+
+// Code lengths for each symbol, none longer than max_len. Unused symbols get length zero.
+// If the Huffman tree is too deep, rare symbols are made less rare until it fits.
+pub fn limited_lengths(freqs: &[u64], max_len: u32) -> Vec<u32> {
+    let mut freqs = freqs.to_vec();
+    loop {
+        let ordering: HashMap<Symbol, u64> = freqs.iter().enumerate()
+            .filter(|(_, freq)| **freq > 0)
+            .map(|(symbol, freq)| (symbol as Symbol, *freq))
+            .collect();
+        let encoding = huffman_symbols(&ordering).unwrap().gen_symbol_encoding();
+        let lens: Vec<u32> = (0..freqs.len())
+            .map(|symbol| encoding.get(symbol).and_then(|code| code.as_ref()).map_or(0, |code| code.len() as u32))
+            .collect();
+        if lens.iter().all(|len| *len <= max_len) {
+            return lens
+        }
+        // Halving flattens the frequencies. At worst, they all become one, and the tree is balanced.
+        freqs.iter_mut().filter(|freq| **freq > 0).for_each(|freq| *freq = (*freq / 2).max(1));
+    }
+}
+
+// Assign codes from lengths, as RFC 1951 describes.
+// Shorter codes come first, and codes of the same length are in symbol order.
+pub fn canonical_codes(lens: &[u32]) -> Vec<u32> {
+    let max_len = *lens.iter().max().unwrap_or(&0) as usize;
+    let mut counts = vec![0u32; max_len + 1];
+    lens.iter().filter(|len| **len > 0).for_each(|len| counts[*len as usize] += 1);
+
+    let mut next = vec![0u32; max_len + 1];
+    let mut code = 0;
+    for bits in 1..=max_len {
+        code = (code + counts[bits - 1]) << 1;
+        next[bits] = code;
+    }
+
+    lens.iter().map(|len| {
+        if *len == 0 {
+            return 0
+        }
+        let code = next[*len as usize];
+        next[*len as usize] += 1;
+        code
+    }).collect()
+}
+
+// Code lengths for every byte value, from how often each appears.
+pub fn byte_lengths(ordering: &HashMap<u8, u64>) -> Vec<u32> {
+    let freqs: Vec<u64> = (0..=255).map(|byte| *ordering.get(&byte).unwrap_or(&0)).collect();
+    limited_lengths(&freqs, MAX_CANONICAL_LEN)
+}
+
+// A code's bits, most significant first.
+pub fn code_bits(code: u32, len: u32) -> BitSequence {
+    let bits: Vec<u8> = (0..len).rev().map(|i| ((code >> i) & 1) as u8).collect();
+    BitSequence::from_bits(&bits)
+}
+
+// The code for each byte, given the length of every byte value's code.
+pub fn canonical_encoding(lens: &[u32]) -> Encoding {
+    let mut encoding: Encoding = std::array::from_fn(|_| None);
+    for (byte, (code, len)) in canonical_codes(lens).iter().zip(lens).enumerate() {
+        if *len > 0 {
+            encoding[byte] = Some(code_bits(*code, *len));
+        }
+    }
+    encoding
+}
+
+// Rebuild a code from its lengths.
+// Lengths which would give two symbols the same code are rejected, rather than trusted.
+pub fn canonical_decoding(lens: &[u32]) -> Result<DecodingTable, String> {
+    let max_len = *lens.iter().max().unwrap_or(&0);
+    if max_len > MAX_CANONICAL_LEN {
+        return Err(format!("Codes may be at most {} bits long!", MAX_CANONICAL_LEN))
+    }
+    let space: u64 = lens.iter().filter(|len| **len > 0).map(|len| 1 << (max_len - len)).sum();
+    if space > 1 << max_len {
+        return Err(String::from("Code lengths are oversubscribed!"))
+    }
+
+    let mut table = DecodingTable::new();
+    for (symbol, (code, len)) in canonical_codes(lens).iter().zip(lens).enumerate() {
+        if *len > 0 {
+            table.insert(&code_bits(*code, *len), symbol as Symbol);
+        }
+    }
+    Ok(table)
+}
+
+// Pack the length of each byte value's code two to a byte, low half first.
+pub fn lengths_to_stream(lens: &[u32]) -> Vec<u8> {
+    lens.chunks(2).map(|pair| (pair[0] | pair[1] << 4) as u8).collect()
+}
+
+pub fn lengths_from_stream(bytes: &[u8]) -> Vec<u32> {
+    bytes[..LENGTHS_LEN].iter().flat_map(|byte| [(byte & 0xf) as u32, (byte >> 4) as u32]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::encoding::bitreader::BitReader;
+    use crate::encoding::bitsequence::BitSequence;
+    use crate::encoding::canonical::{byte_lengths, canonical_codes, canonical_decoding, canonical_encoding, limited_lengths, lengths_from_stream, lengths_to_stream};
+
+    // The example from RFC 1951, section 3.2.2.
+    #[test]
+    fn test_canonical_codes() {
+        let lens = [3, 3, 3, 3, 3, 2, 4, 4];
+        assert_eq!(vec![0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111], canonical_codes(&lens));
+    }
+
+    #[test]
+    fn test_limited_lengths() {
+        // Fibonacci frequencies make the deepest possible tree.
+        let mut freqs = vec![1u64, 1];
+        for i in 2..30 {
+            freqs.push(freqs[i - 1] + freqs[i - 2]);
+        }
+        let lens = limited_lengths(&freqs, 15);
+        assert!(lens.iter().all(|len| *len > 0 && *len <= 15));
+        // The lengths must still describe a complete code.
+        let kraft: f64 = lens.iter().map(|len| 0.5f64.powi(*len as i32)).sum();
+        assert_eq!(1.0, kraft);
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let ordering = HashMap::from([(b'a', 20), (b'b', 6), (b'c', 3), (b'd', 1)]);
+        let lens = byte_lengths(&ordering);
+        assert_eq!(256, lens.len());
+        assert_eq!((1, 2), (lens[b'a' as usize], lens[b'b' as usize]));
+        assert_eq!(0, lens[b'e' as usize]);
+
+        // Shorter codes come first, and ties go in byte order.
+        let encoding = canonical_encoding(&lens);
+        assert_eq!(BitSequence::from_bits(&[0]), encoding[b'a' as usize].clone().unwrap());
+        assert_eq!(BitSequence::from_bits(&[1, 0]), encoding[b'b' as usize].clone().unwrap());
+        assert_eq!(BitSequence::from_bits(&[1, 1, 0]), encoding[b'c' as usize].clone().unwrap());
+        assert_eq!(BitSequence::from_bits(&[1, 1, 1]), encoding[b'd' as usize].clone().unwrap());
+
+        let seq = BitSequence::translate(b"dcba", &encoding);
+        let decoding = canonical_decoding(&lens).unwrap();
+        let mut reader = BitReader::from_seq(&seq);
+        let decoded: Vec<u8> = std::iter::from_fn(|| decoding.decode_next(&mut reader)).collect();
+        assert_eq!(b"dcba".to_vec(), decoded);
+    }
+
+    #[test]
+    fn test_bad_lengths() {
+        assert!(canonical_decoding(&[1, 1, 1]).is_err());
+        assert!(canonical_decoding(&[16, 1]).is_err());
+    }
+
+    #[test]
+    fn test_lengths_stream() {
+        let lens: Vec<u32> = (0..256).map(|byte| byte % 16).collect();
+        let bytes = lengths_to_stream(&lens);
+        assert_eq!(128, bytes.len());
+        assert_eq!(0x10, bytes[0]);
+        assert_eq!(lens, lengths_from_stream(&bytes));
+    }
+}
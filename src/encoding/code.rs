@@ -0,0 +1,223 @@
+use std::io;
+use std::io::Write;
+use crate::encoding::bitsequence::{BitSequence, Encoding};
+use crate::encoding::bitwriter::BitWriter;
+
+// Codes held by value, rather than as BitSequences.
+// A whole table of them is one flat array with no allocations, so encoders can look codes up cheaply.
+// Author: Will Morris
+
+/// A code of up to 128 bits, stored just as a BitSequence stores them: the first bit is the low one.
+/// Huffman codes aren't length limited, and a block's own counts can make codes of 90 bits or so, so 32 bits wouldn't do.
+/// Counts which aren't a block's own can make codes longer still, and Tree::gen_encoding has no table for those.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Code {
+    bits: u128,
+    len: u8,
+}
+
+impl Code {
+    /// The longest code that fits.
+    pub const MAX_LEN: u8 = u128::BITS as u8;
+
+    /// A code made of the low len bits of bits. Any higher bits are ignored.
+    pub fn new(bits: u128, len: u8) -> Self {
+        assert!(len <= Code::MAX_LEN, "Codes can't be {} bits long!", len);
+        let mask = u128::MAX.checked_shr(u128::BITS - len as u32).unwrap_or(0);
+        Code { bits: bits & mask, len }
+    }
+
+    /// The same bits as seq, or none if it's longer than a code can be.
+    pub fn from_seq(seq: &BitSequence) -> Option<Self> {
+        if seq.len() > Code::MAX_LEN as u64 {
+            return None
+        }
+        let bits = seq.as_bytes().iter().rev().fold(0u128, |bits, byte| bits << 8 | *byte as u128);
+        Some(Code::new(bits, seq.len() as u8))
+    }
+
+    pub fn bits(&self) -> u128 {
+        self.bits
+    }
+
+    pub fn len(&self) -> u8 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append this code to the end of seq.
+    pub fn append_to(&self, seq: &mut BitSequence) {
+        match self.len as u32 {
+            len if len <= u64::BITS => { seq.append_bits_u64(self.bits as u64, len) }
+            len => {
+                seq.append_bits_u64(self.bits as u64, u64::BITS);
+                seq.append_bits_u64((self.bits >> u64::BITS) as u64, len - u64::BITS);
+            }
+        }
+    }
+
+    /// Write this code to writer.
+    pub fn write_to<W: Write>(&self, writer: &mut BitWriter<W>) -> io::Result<()> {
+        match self.len as u32 {
+            len if len <= u64::BITS => { writer.write_bits(self.bits as u64, len) }
+            len => {
+                writer.write_bits(self.bits as u64, u64::BITS)?;
+                writer.write_bits((self.bits >> u64::BITS) as u64, len - u64::BITS)
+            }
+        }
+    }
+
+    pub fn to_seq(&self) -> BitSequence {
+        let mut seq = BitSequence::new();
+        self.append_to(&mut seq);
+        seq
+    }
+}
+
+/// Maps each byte to its code, if it has one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeTable {
+    codes: [Option<Code>; 256],
+}
+
+impl Default for CodeTable {
+    fn default() -> Self {
+        CodeTable::new()
+    }
+}
+
+impl CodeTable {
+    /// A table where no byte has a code yet.
+    pub fn new() -> Self {
+        CodeTable { codes: [None; 256] }
+    }
+
+    pub fn get(&self, byte: u8) -> Option<Code> {
+        self.codes[byte as usize]
+    }
+
+    pub fn insert(&mut self, byte: u8, code: Code) {
+        self.codes[byte as usize] = Some(code);
+    }
+
+    /// Every byte with a code, in byte order.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, Code)> + '_ {
+        self.codes.iter().enumerate().filter_map(|(byte, code)| code.map(|code| (byte as u8, code)))
+    }
+
+    /// Code every byte in turn.
+    /// NOTE: every byte must have a code!
+    pub fn translate(&self, bytes: &[u8]) -> BitSequence {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("translate", bytes = bytes.len()).entered();
+        // Bits are gathered in a word, and written out eight bytes at a time.
+        // At most 63 bits wait in it, so another 64 always fit.
+        let mut packed = Vec::with_capacity(bytes.len());
+        let mut current = 0u128;
+        let mut waiting = 0;
+        let mut num_bits = 0;
+        let mut write = |value: u64, count: u32| {
+            current |= (value as u128) << waiting;
+            waiting += count;
+            if waiting >= u64::BITS {
+                packed.extend_from_slice(&(current as u64).to_le_bytes());
+                current >>= u64::BITS;
+                waiting -= u64::BITS;
+            }
+        };
+
+        for byte in bytes {
+            let code = self.codes[*byte as usize].unwrap_or_else(|| panic!("No code for byte {}!", byte));
+            num_bits += code.len as u64;
+            match code.len as u32 {
+                len if len <= u64::BITS => { write(code.bits as u64, len) }
+                len => {
+                    write(code.bits as u64, u64::BITS);
+                    write((code.bits >> u64::BITS) as u64, len - u64::BITS);
+                }
+            }
+        }
+        packed.extend_from_slice(&current.to_le_bytes()[..waiting.div_ceil(8) as usize]);
+        BitSequence::from_raw(num_bits, packed)
+    }
+
+    /// Code every byte in turn, straight to writer, rather than into a BitSequence.
+    /// NOTE: every byte must have a code!
+    pub fn write<W: Write>(&self, bytes: &[u8], writer: &mut BitWriter<W>) -> io::Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("write codes", bytes = bytes.len()).entered();
+        for byte in bytes {
+            self.codes[*byte as usize].unwrap_or_else(|| panic!("No code for byte {}!", byte)).write_to(writer)?;
+        }
+        Ok(())
+    }
+
+    /// How many bytes of memory the table takes. It holds every byte's code in place, so this never changes.
+    pub fn memory_size(&self) -> usize {
+        size_of::<Self>()
+    }
+
+    /// The same codes, as BitSequences.
+    pub fn to_encoding(&self) -> Encoding {
+        std::array::from_fn(|byte| self.codes[byte].map(|code| code.to_seq()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::bitsequence::{BitOrder, BitSequence};
+    use crate::encoding::bitwriter::BitWriter;
+    use crate::encoding::code::{Code, CodeTable};
+
+    #[test]
+    fn test_code() {
+        let code = Code::new(0b1111_0110, 4);
+        assert_eq!(0b0110, code.bits());
+        assert_eq!(BitSequence::from_bits(&[0, 1, 1, 0]), code.to_seq());
+        assert_eq!(Some(code), Code::from_seq(&code.to_seq()));
+        assert!(Code::new(0, 0).is_empty());
+
+        // Codes longer than a word still come out in order.
+        let long = Code::new(u128::MAX - 1, 100);
+        let seq = long.to_seq();
+        assert_eq!(100, seq.len());
+        assert_eq!(0, seq[0]);
+        assert_eq!(1, seq[99]);
+        assert_eq!(Some(long), Code::from_seq(&seq));
+
+        let mut too_long = BitSequence::new();
+        too_long.append_bits(&[1; 129]);
+        assert_eq!(None, Code::from_seq(&too_long));
+    }
+
+    #[test]
+    fn test_translate() {
+        let mut table = CodeTable::new();
+        table.insert(b'a', Code::new(0b0, 1));
+        table.insert(b'b', Code::new(0b01, 2));
+        table.insert(b'c', Code::new(u128::MAX, 70));
+
+        let seq = table.translate(b"abac");
+        let encoding = table.to_encoding();
+        assert_eq!(BitSequence::translate(b"abac", &encoding), seq);
+        // Enough to fill many words, with codes straddling them.
+        let long = b"abcbbac".repeat(50);
+        assert_eq!(BitSequence::translate(&long, &encoding), table.translate(&long));
+        // Written straight to a stream, the bits are the same.
+        let mut writer = BitWriter::new(vec![]);
+        table.write(&long, &mut writer).unwrap();
+        assert_eq!(table.translate(&long).to_packed_stream(BitOrder::Lsb), writer.into_packed_stream());
+        assert_eq!(1 + 2 + 1 + 70, seq.len());
+        assert_eq!(None, encoding[b'd' as usize]);
+        assert_eq!(vec![b'a', b'b', b'c'], table.iter().map(|(byte, _)| byte).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_missing_code() {
+        CodeTable::new().translate(b"a");
+    }
+}
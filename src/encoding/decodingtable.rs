@@ -0,0 +1,158 @@
+use std::io::Read;
+use crate::encoding::bitreader::BitReader;
+use crate::encoding::bitsequence::BitSequence;
+use crate::ordering::symbolfreq::Symbol;
+
+// A DecodingTable turns a stream of bits back into the bytes (or other symbols) they encode.
+// Callers only ever ask for the next symbol -- how the lookup happens is our business.
+// Author: Will Morris
+
+// Currently, the table is a binary trie flattened into a vector.
+// Each bit read selects a child, until a symbol is reached.
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Branch { children: [Option<usize>; 2] },
+    Symbol(Symbol),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodingTable {
+    // The root is always entries[0].
+    entries: Vec<Entry>,
+}
+
+impl DecodingTable {
+    // Create a table which decodes nothing.
+    pub fn new() -> Self {
+        Self { entries: vec![Entry::Branch { children: [None, None] }] }
+    }
+
+    // Record that path decodes to symbol.
+    // NOTE: paths must be prefix free! This holds for any path generated from a Huffman tree.
+    pub fn insert(&mut self, path: &BitSequence, symbol: Symbol) {
+        assert!(self.try_insert(path, symbol), "Decoding paths must be prefix free!");
+    }
+
+    // Record that path decodes to symbol, unless path is empty, or a prefix of another path or the other way round.
+    // For paths which come from somewhere other than our own trees, i.e. a table stored in a block.
+    pub fn try_insert(&mut self, path: &BitSequence, symbol: Symbol) -> bool {
+        let mut current = 0;
+        for bit in path {
+            let bit = bit as usize;
+            let next = match &self.entries[current] {
+                Entry::Branch { children } => { children[bit] }
+                Entry::Symbol(_) => { return false }
+            };
+
+            current = match next {
+                Some(index) => { index }
+                None => {
+                    let index = self.entries.len();
+                    self.entries.push(Entry::Branch { children: [None, None] });
+                    if let Entry::Branch { children } = &mut self.entries[current] {
+                        children[bit] = Some(index);
+                    }
+                    index
+                }
+            };
+        }
+        // Only a fresh branch, with nothing below it, can become a symbol.
+        match self.entries[current] {
+            Entry::Branch { children: [None, None] } if current != 0 => {
+                self.entries[current] = Entry::Symbol(symbol);
+                true
+            }
+            _ => { false }
+        }
+    }
+
+    // How many bytes of memory the table takes: one entry for every node on a path, and room for more.
+    pub fn memory_size(&self) -> usize {
+        size_of::<Self>() + self.entries.capacity() * size_of::<Entry>()
+    }
+
+    // Read just enough bits from reader to decode the next byte.
+    // NOTE: the table must have been built over bytes!
+    pub fn decode_next<R: Read>(&self, reader: &mut BitReader<R>) -> Option<u8> {
+        self.decode_symbol(reader).map(|symbol| symbol as u8)
+    }
+
+    // Read just enough bits from reader to decode the next symbol.
+    // Returns none if the reader runs dry before a full code is read,
+    // Or if the bits read do not correspond to any symbol.
+    pub fn decode_symbol<R: Read>(&self, reader: &mut BitReader<R>) -> Option<Symbol> {
+        let mut current = 0;
+        loop {
+            match &self.entries[current] {
+                Entry::Symbol(symbol) => { return Some(*symbol) }
+                Entry::Branch { children } => {
+                    current = children[reader.read_bit()? as usize]?;
+                }
+            }
+        }
+    }
+}
+
+impl Default for DecodingTable {
+    fn default() -> Self {
+        DecodingTable::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::bitreader::BitReader;
+    use crate::encoding::bitsequence::BitSequence;
+    use crate::encoding::decodingtable::{DecodingTable, Entry};
+    use crate::ordering::symbolfreq::Symbol;
+
+    #[test]
+    fn test_decode() {
+        let mut table = DecodingTable::new();
+        table.insert(&BitSequence::from_bits(&[0]), b'a' as Symbol);
+        table.insert(&BitSequence::from_bits(&[1, 0]), b'b' as Symbol);
+        table.insert(&BitSequence::from_bits(&[1, 1]), b'c' as Symbol);
+
+        let seq = BitSequence::from_bits(&[1, 1, 0, 1, 0, 1]);
+        let mut reader = BitReader::from_seq(&seq);
+        assert_eq!(Some(b'c'), table.decode_next(&mut reader));
+        assert_eq!(Some(b'a'), table.decode_next(&mut reader));
+        assert_eq!(Some(b'b'), table.decode_next(&mut reader));
+        // Only half a code left!
+        assert_eq!(None, table.decode_next(&mut reader));
+    }
+
+    #[test]
+    fn test_memory_size() {
+        let mut table = DecodingTable::new();
+        let empty = table.memory_size();
+        table.insert(&BitSequence::from_bits(&[0]), b'a' as Symbol);
+        table.insert(&BitSequence::from_bits(&[1, 0]), b'b' as Symbol);
+        table.insert(&BitSequence::from_bits(&[1, 1]), b'c' as Symbol);
+        // A root and branch, and three symbols.
+        assert!(table.memory_size() > empty);
+        assert!(table.memory_size() >= size_of::<DecodingTable>() + 5 * size_of::<Entry>());
+    }
+
+    #[test]
+    fn test_unknown_path() {
+        let mut table = DecodingTable::new();
+        table.insert(&BitSequence::from_bits(&[0]), b'a' as Symbol);
+
+        let seq = BitSequence::from_bits(&[1]);
+        let mut reader = BitReader::from_seq(&seq);
+        assert_eq!(None, table.decode_next(&mut reader));
+    }
+
+    #[test]
+    fn test_try_insert() {
+        let mut table = DecodingTable::new();
+        assert!(table.try_insert(&BitSequence::from_bits(&[0, 1]), b'a' as Symbol));
+        // Prefixes of a path, paths through a symbol, the same path again, and the empty path.
+        assert!(!table.try_insert(&BitSequence::from_bits(&[0]), b'b' as Symbol));
+        assert!(!table.try_insert(&BitSequence::from_bits(&[0, 1, 1]), b'b' as Symbol));
+        assert!(!table.try_insert(&BitSequence::from_bits(&[0, 1]), b'b' as Symbol));
+        assert!(!table.try_insert(&BitSequence::new(), b'b' as Symbol));
+        assert!(table.try_insert(&BitSequence::from_bits(&[1]), b'b' as Symbol));
+    }
+}
@@ -0,0 +1,339 @@
+// Huffman coding with no heap at all, for targets that can't have one.
+// Everything lives in fixed size arrays, and output goes into buffers the caller provides.
+// Blocks are laid out just as wz lays out a canonical block, so either side can be a microcontroller.
+// Author: Will Morris
+
+/*
+  CONTENTS:
+  -- bit order (always low bit first when written here)
+  -- length of each byte value's code, two to a byte, low half first
+  -- number of bits, as 8 bytes
+  -- the bits, each code's highest bit first.
+ */
+
+use crate::encoding::code::{Code, CodeTable};
+use crate::file::bytestream::LONG_LEN;
+#[cfg(feature = "heapless")]
+use crate::file::bytestream::slice_to_long;
+use crate::file::error::WzError;
+
+/// The longest code a block may use, so that each length fits in half a byte.
+pub const MAX_LEN: usize = 15;
+/// How many bytes come before the bits: the bit order, every code length, and the number of bits.
+pub const HEADER_LEN: usize = 1 + 128 + LONG_LEN;
+
+// A tree over every byte value has at most this many nodes.
+const MAX_NODES: usize = 2 * 256 - 1;
+
+/// How often each byte value appears.
+pub fn count(bytes: &[u8]) -> [u64; 256] {
+    let mut counts = [0; 256];
+    for byte in bytes {
+        counts[*byte as usize] += 1;
+    }
+    counts
+}
+
+/// Huffman code lengths for each byte value, none longer than MAX_LEN. Bytes that never appear get length zero.
+/// If the tree is too deep, rare bytes are made less rare until it fits.
+pub fn code_lengths(counts: &[u64; 256]) -> [u8; 256] {
+    let mut counts = *counts;
+    loop {
+        let lens = huffman_lengths(&counts);
+        if lens.iter().all(|len| *len as usize <= MAX_LEN) {
+            return lens
+        }
+        // Halving flattens the counts. At worst, they all become one, and the tree is balanced.
+        counts.iter_mut().filter(|count| **count > 0).for_each(|count| *count = (*count / 2).max(1));
+    }
+}
+
+// Build a Huffman tree in arrays, by joining the two lightest nodes until one is left.
+// Each leaf's length is how many parents it has. A lone leaf is still coded with one bit.
+fn huffman_lengths(counts: &[u64; 256]) -> [u8; 256] {
+    // Leaves are nodes 0 to 255, and joined nodes come after them.
+    let mut weights = [0u64; MAX_NODES];
+    let mut parents = [usize::MAX; MAX_NODES];
+    let mut live = [false; MAX_NODES];
+    for (byte, count) in counts.iter().enumerate() {
+        weights[byte] = *count;
+        live[byte] = *count > 0;
+    }
+
+    let mut nodes = 256;
+    loop {
+        let lightest = lightest_live(&weights, &live, nodes, usize::MAX);
+        let next = lightest_live(&weights, &live, nodes, lightest);
+        if next == usize::MAX {
+            break
+        }
+        weights[nodes] = weights[lightest].saturating_add(weights[next]);
+        live[nodes] = true;
+        for joined in [lightest, next] {
+            live[joined] = false;
+            parents[joined] = nodes;
+        }
+        nodes += 1;
+    }
+
+    let mut lens = [0; 256];
+    for (byte, len) in lens.iter_mut().enumerate().filter(|(byte, _)| counts[*byte] > 0) {
+        let mut node = byte;
+        *len = 1;
+        while parents[node] != usize::MAX && parents[parents[node]] != usize::MAX {
+            node = parents[node];
+            *len += 1;
+        }
+    }
+    lens
+}
+
+// The live node with the smallest weight, other than skip. Ties go to the earlier node.
+// Returns usize::MAX if there's none.
+fn lightest_live(weights: &[u64; MAX_NODES], live: &[bool; MAX_NODES], nodes: usize, skip: usize) -> usize {
+    (0..nodes).filter(|node| live[*node] && *node != skip)
+        .min_by_key(|node| weights[*node])
+        .unwrap_or(usize::MAX)
+}
+
+/// The canonical code for every byte with a length: shorter codes first, then in byte order.
+/// The codes are stored just as they're written, highest bit first.
+pub fn code_table(lens: &[u8; 256]) -> CodeTable {
+    let mut counts = [0u32; MAX_LEN + 1];
+    lens.iter().filter(|len| **len > 0).for_each(|len| counts[*len as usize] += 1);
+    let mut next = [0u32; MAX_LEN + 1];
+    let mut code = 0;
+    for bits in 1..=MAX_LEN {
+        code = (code + counts[bits - 1]) << 1;
+        next[bits] = code;
+    }
+
+    let mut table = CodeTable::new();
+    for (byte, len) in lens.iter().enumerate().filter(|(_, len)| **len > 0) {
+        let len = *len as usize;
+        // Codes keep their first bit lowest, and canonical codes are written from the top.
+        let reversed = next[len].reverse_bits() >> (u32::BITS as usize - len);
+        table.insert(byte as u8, Code::new(reversed as u128, len as u8));
+        next[len] += 1;
+    }
+    table
+}
+
+/// Huffman code bytes into out, returning how much of out the block took.
+/// Fails, without writing anything past out, if out isn't big enough.
+pub fn encode_into(bytes: &[u8], out: &mut [u8]) -> Result<usize, WzError> {
+    encode_counted_into(bytes, &count(bytes), out)
+}
+
+/// Huffman code bytes into out, with codes built from counts made beforehand rather than from bytes themselves.
+/// Fails if any byte of bytes was never counted, since it has no code.
+pub fn encode_counted_into(bytes: &[u8], counts: &[u64; 256], out: &mut [u8]) -> Result<usize, WzError> {
+    let lens = code_lengths(counts);
+    if bytes.iter().any(|byte| lens[*byte as usize] == 0) {
+        return Err(WzError::Invalid("counts: a byte to code was never counted"))
+    }
+    // The bytes needn't be the ones counted, so the bits are added up from them.
+    let num_bits: u64 = bytes.iter().map(|byte| lens[*byte as usize] as u64).sum();
+    let block_len = HEADER_LEN + num_bits.div_ceil(8) as usize;
+    if out.len() < block_len {
+        return Err(WzError::Full("a Huffman coded block"))
+    }
+
+    out[0] = 0;
+    for (packed, pair) in out[1..129].iter_mut().zip(lens.chunks_exact(2)) {
+        *packed = pair[0] | pair[1] << 4;
+    }
+    out[129..HEADER_LEN].copy_from_slice(&num_bits.to_le_bytes());
+
+    let bits = &mut out[HEADER_LEN..block_len];
+    bits.fill(0);
+    let table = code_table(&lens);
+    let mut i = 0;
+    for byte in bytes {
+        let code = table.get(*byte).unwrap();
+        for bit in 0..code.len() as usize {
+            bits[i / 8] |= ((code.bits() >> bit) as u8 & 1) << (i % 8);
+            i += 1;
+        }
+    }
+    Ok(block_len)
+}
+
+/// Decode a block written by encode_into, or a canonical block from wz, into out.
+/// Returns how many bytes were decoded. Fails if out can't hold them all.
+// Anywhere with a heap decodes with codec::block instead, as wz does.
+#[cfg(feature = "heapless")]
+pub fn decode_into(block: &[u8], out: &mut [u8]) -> Result<usize, WzError> {
+    if block.len() < HEADER_LEN {
+        return Err(WzError::Truncated("a Huffman coded block"))
+    }
+    let msb_first = match block[0] {
+        0 => { false }
+        1 => { true }
+        _ => { return Err(WzError::Invalid("bit order")) }
+    };
+    let mut lens = [0u8; 256];
+    for (pair, packed) in lens.chunks_exact_mut(2).zip(&block[1..129]) {
+        pair[0] = packed & 0xf;
+        pair[1] = packed >> 4;
+    }
+    let num_bits = slice_to_long(&block[129..HEADER_LEN]);
+    let bits = &block[HEADER_LEN..];
+    if num_bits.div_ceil(8) > bits.len() as u64 {
+        return Err(WzError::Truncated("a Huffman coded block"))
+    }
+
+    // Canonical codes of each length are consecutive, so each length only needs a count,
+    // and the bytes with codes sorted by length then value.
+    let mut counts = [0u16; MAX_LEN + 1];
+    lens.iter().filter(|len| **len > 0).for_each(|len| counts[*len as usize] += 1);
+    let mut space = 1i32;
+    for count in &counts[1..] {
+        space = space * 2 - *count as i32;
+        if space < 0 {
+            return Err(WzError::Invalid("code lengths"))
+        }
+    }
+    let mut sorted = [0u8; 256];
+    let mut offsets = [0usize; MAX_LEN + 2];
+    for len in 1..=MAX_LEN {
+        offsets[len + 1] = offsets[len] + counts[len] as usize;
+    }
+    for (byte, len) in lens.iter().enumerate().filter(|(_, len)| **len > 0) {
+        sorted[offsets[*len as usize]] = byte as u8;
+        offsets[*len as usize] += 1;
+    }
+
+    let bit_at = |i: u64| {
+        let byte = bits[(i / 8) as usize];
+        match msb_first {
+            true => { byte >> (7 - i % 8) & 1 }
+            false => { byte >> (i % 8) & 1 }
+        }
+    };
+
+    let mut written = 0;
+    let mut i = 0;
+    'codes: while i < num_bits {
+        // Read one bit at a time, until the code read so far is one of this length.
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for count in &counts[1..] {
+            if i == num_bits {
+                // Only part of a code is left, which can only be padding.
+                break 'codes
+            }
+            code |= bit_at(i) as i32;
+            i += 1;
+            let count = *count as i32;
+            if code - first < count {
+                let byte = out.get_mut(written).ok_or(WzError::Full("the decoded bytes"))?;
+                *byte = sorted[(index + code - first) as usize];
+                written += 1;
+                continue 'codes
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        return Err(WzError::Invalid("code"))
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::code::Code;
+    use crate::encoding::fixed::{code_lengths, code_table, count, encode_into, HEADER_LEN, MAX_LEN};
+    #[cfg(feature = "heapless")]
+    use crate::encoding::fixed::decode_into;
+    use crate::file::error::WzError;
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn test_round_trip() {
+        let bytes = b"the rain in spain falls mainly on the plain".repeat(10);
+        let mut block = [0u8; 1024];
+        let len = encode_into(&bytes, &mut block).unwrap();
+        assert!(len < bytes.len());
+
+        let mut out = [0u8; 1024];
+        let decoded = decode_into(&block[..len], &mut out).unwrap();
+        assert_eq!(bytes, out[..decoded]);
+
+        // One byte over and over still takes a bit each.
+        let len = encode_into(&[7; 20], &mut block).unwrap();
+        assert_eq!(HEADER_LEN + 3, len);
+        assert_eq!(Ok(20), decode_into(&block[..len], &mut out));
+        assert_eq!([7; 20], out[..20]);
+
+        let len = encode_into(&[], &mut block).unwrap();
+        assert_eq!(Ok(0), decode_into(&block[..len], &mut out));
+    }
+
+    #[test]
+    fn test_canonical() {
+        let mut lens = [0; 256];
+        lens[b'a' as usize] = 1;
+        lens[b'b' as usize] = 2;
+        lens[b'c' as usize] = 3;
+        lens[b'd' as usize] = 3;
+        // 0, 10, 110, 111, each stored first bit lowest.
+        let table = code_table(&lens);
+        assert_eq!(Some(Code::new(0b0, 1)), table.get(b'a'));
+        assert_eq!(Some(Code::new(0b01, 2)), table.get(b'b'));
+        assert_eq!(Some(Code::new(0b011, 3)), table.get(b'c'));
+        assert_eq!(Some(Code::new(0b111, 3)), table.get(b'd'));
+        assert_eq!(None, table.get(b'e'));
+    }
+
+    #[test]
+    fn test_limited_lengths() {
+        // Fibonacci counts make the deepest possible tree.
+        let mut counts = [0u64; 256];
+        counts[0] = 1;
+        counts[1] = 1;
+        for i in 2..30 {
+            counts[i] = counts[i - 1] + counts[i - 2];
+        }
+        let lens = code_lengths(&counts);
+        assert!(lens[..30].iter().all(|len| *len > 0 && *len as usize <= MAX_LEN));
+        assert!(lens[30..].iter().all(|len| *len == 0));
+        // The lengths must still describe a complete code.
+        let kraft: f64 = lens[..30].iter().map(|len| 0.5f64.powi(*len as i32)).sum();
+        assert_eq!(1.0, kraft);
+        assert_eq!(1, code_lengths(&count(b"aaaa"))[b'a' as usize]);
+    }
+
+    #[test]
+    fn test_no_room() {
+        let bytes = b"abracadabra";
+        let mut block = [0u8; HEADER_LEN + 2];
+        assert_eq!(Err(WzError::Full("a Huffman coded block")), encode_into(bytes, &mut block));
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn test_bad_block() {
+        let mut block = [0u8; 256];
+        let len = encode_into(b"abracadabra", &mut block).unwrap();
+        let mut out = [0u8; 10];
+        assert_eq!(Err(WzError::Full("the decoded bytes")), decode_into(&block[..len], &mut out));
+
+        let mut out = [0u8; 16];
+        assert!(decode_into(&[0; HEADER_LEN - 1], &mut out).is_err());
+
+        let mut block = [0u8; HEADER_LEN + 1];
+        block[0] = 2;
+        assert_eq!(Err(WzError::Invalid("bit order")), decode_into(&block, &mut out));
+        // Three one bit codes can't all be told apart.
+        block[0] = 0;
+        block[1] = 0x11;
+        block[2] = 0x01;
+        assert_eq!(Err(WzError::Invalid("code lengths")), decode_into(&block, &mut out));
+        // Claims more bits than there are.
+        block[1] = 0x11;
+        block[2] = 0;
+        block[129] = 9;
+        assert_eq!(Err(WzError::Truncated("a Huffman coded block")), decode_into(&block, &mut out));
+    }
+}
@@ -0,0 +1,217 @@
+// LZW dictionary coding.
+// Strings are added to a dictionary as they're seen, and each later copy is sent as one code.
+// Nothing needs storing: the decoder rebuilds the same dictionary from the codes themselves.
+// Author: Will Morris
+
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Write};
+use crate::encoding::bitreader::BitReader;
+use crate::encoding::bitwriter::BitWriter;
+use crate::file::error::WzError;
+
+// Codes below 256 are single bytes.
+// Throw the dictionary away and start over.
+const CLEAR: u32 = 256;
+// No more codes follow.
+const STOP: u32 = 257;
+const FIRST_CODE: u32 = 258;
+// Codes grow one bit at a time up to 16 bits, after which the dictionary is cleared.
+const MAX_CODES: u32 = 1 << 16;
+// Marks a dictionary entry which has no prefix, i.e. a single byte.
+const NO_PREFIX: u32 = u32::MAX;
+
+// Code bytes into a sequence of variable width codes, written to writer as they're made.
+pub fn lzw_encode<W: Write>(bytes: &[u8], writer: &mut BitWriter<W>) -> io::Result<()> {
+    let mut dictionary: HashMap<(u32, u8), u32> = HashMap::new();
+    // Codes are as wide as they need to be for the current dictionary.
+    let mut size = FIRST_CODE;
+    let mut current: Option<u32> = None;
+
+    for byte in bytes {
+        let prefix = match current {
+            Some(prefix) => { prefix }
+            None => {
+                current = Some(*byte as u32);
+                continue
+            }
+        };
+        if let Some(code) = dictionary.get(&(prefix, *byte)) {
+            current = Some(*code);
+            continue
+        }
+
+        write_code(writer, prefix, size)?;
+        if size < MAX_CODES {
+            dictionary.insert((prefix, *byte), size);
+            size += 1;
+        } else {
+            write_code(writer, CLEAR, size)?;
+            dictionary.clear();
+            size = FIRST_CODE;
+        }
+        current = Some(*byte as u32);
+    }
+
+    if let Some(prefix) = current {
+        write_code(writer, prefix, size)?;
+        size = (size + 1).min(MAX_CODES);
+    }
+    write_code(writer, STOP, size)
+}
+
+// Decode a sequence of codes, rebuilding the dictionary as it goes.
+// Codes the dictionary doesn't have yet can only come from a corrupt block.
+pub fn lzw_decode<R: Read>(reader: &mut BitReader<R>, limit: usize) -> Result<Vec<u8>, WzError> {
+    let mut retval = vec![];
+    // The decoder learns each entry one code later than the encoder did,
+    // so the code width is tracked separately from the dictionary.
+    let mut size = FIRST_CODE;
+    // Each entry is a prefix code, followed by a byte.
+    let mut entries: Vec<(u32, u8)> = initial_entries();
+    let mut previous: Option<u32> = None;
+
+    loop {
+        let code = read_code(reader, size).ok_or(WzError::Truncated("an LZW block"))?;
+        match code {
+            CLEAR => {
+                entries.truncate(FIRST_CODE as usize);
+                size = FIRST_CODE;
+                previous = None;
+                continue
+            }
+            STOP => { break }
+            _ => {}
+        }
+
+        let start = retval.len();
+        if (code as usize) < entries.len() {
+            write_entry(&entries, code, &mut retval);
+        } else if code as usize == entries.len() && previous.is_some() {
+            // The encoder used an entry the moment it made it: the previous string, plus its own first byte.
+            write_entry(&entries, previous.unwrap(), &mut retval);
+            retval.push(retval[start]);
+        } else {
+            return Err(WzError::Malformed(format!("Unknown LZW code: {}", code)))
+        }
+
+        if let Some(prefix) = previous {
+            if entries.len() < MAX_CODES as usize {
+                entries.push((prefix, retval[start]));
+            }
+        }
+        previous = Some(code);
+        size = (size + 1).min(MAX_CODES);
+        if retval.len() > limit {
+            return Err(WzError::Full("decompressed output"))
+        }
+    }
+    Ok(retval)
+}
+
+// Every byte starts out with an entry of its own.
+// The control codes get placeholders, so that codes and indices line up.
+fn initial_entries() -> Vec<(u32, u8)> {
+    let mut entries: Vec<(u32, u8)> = (0..=255).map(|byte| (NO_PREFIX, byte)).collect();
+    entries.push((NO_PREFIX, 0));
+    entries.push((NO_PREFIX, 0));
+    entries
+}
+
+// Append the string for code to output.
+fn write_entry(entries: &[(u32, u8)], code: u32, output: &mut Vec<u8>) {
+    let start = output.len();
+    // Entries are linked from last byte to first, so walk back then flip.
+    let mut code = code;
+    while code != NO_PREFIX {
+        let (prefix, byte) = entries[code as usize];
+        output.push(byte);
+        code = prefix;
+    }
+    output[start..].reverse();
+}
+
+// How many bits are needed for codes less than size.
+fn width(size: u32) -> u32 {
+    u32::BITS - (size - 1).leading_zeros()
+}
+
+// Codes are written least significant bit first.
+fn write_code<W: Write>(writer: &mut BitWriter<W>, code: u32, size: u32) -> io::Result<()> {
+    writer.write_bits(code as u64, width(size))
+}
+
+fn read_code<R: Read>(reader: &mut BitReader<R>, size: u32) -> Option<u32> {
+    reader.read_bits(width(size)).map(|code| code as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::bitreader::BitReader;
+    use crate::encoding::bitsequence::BitSequence;
+    use crate::encoding::bitwriter::BitWriter;
+    use crate::encoding::lzw::{FIRST_CODE, lzw_decode, lzw_encode, MAX_CODES, STOP, width, write_code};
+    use crate::file::bytestream::ByteStream;
+
+    fn lzw_encoded(bytes: &[u8]) -> BitSequence {
+        let mut writer = BitWriter::new(vec![]);
+        lzw_encode(bytes, &mut writer).unwrap();
+        BitSequence::from_whole_stream(&writer.into_packed_stream()).unwrap()
+    }
+
+    #[test]
+    fn test_widths() {
+        assert_eq!(9, width(FIRST_CODE));
+        assert_eq!(9, width(512));
+        assert_eq!(10, width(513));
+        assert_eq!(16, width(MAX_CODES));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Includes the case where a code is used as soon as it's made.
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![7],
+            b"aaaaaaaaaaaaaaaaaaaaaa".to_vec(),
+            b"TOBEORNOTTOBEORTOBEORNOT".to_vec(),
+        ];
+        for input in inputs {
+            assert_eq!(input, lzw_decode(&mut BitReader::from_seq(&lzw_encoded(&input)), usize::MAX).unwrap());
+        }
+    }
+
+    // Enough distinct strings to fill the dictionary several times over.
+    #[test]
+    fn test_clear() {
+        let mut state: u32 = 1;
+        let bytes: Vec<u8> = (0..400_000).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        assert_eq!(bytes, lzw_decode(&mut BitReader::from_seq(&lzw_encoded(&bytes)), usize::MAX).unwrap());
+    }
+
+    #[test]
+    fn test_repetitive() {
+        let bytes = b"the same thing over and over ".repeat(1000);
+        let seq = lzw_encoded(&bytes);
+        assert!(seq.len() / 8 < bytes.len() as u64 / 10);
+        assert_eq!(bytes, lzw_decode(&mut BitReader::from_seq(&seq), usize::MAX).unwrap());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        // A code the dictionary hasn't made yet.
+        let mut writer = BitWriter::new(vec![]);
+        write_code(&mut writer, 300, FIRST_CODE).unwrap();
+        write_code(&mut writer, STOP, FIRST_CODE + 1).unwrap();
+        let seq = BitSequence::from_whole_stream(&writer.into_packed_stream()).unwrap();
+        assert!(lzw_decode(&mut BitReader::from_seq(&seq), usize::MAX).is_err());
+
+        // No stop code.
+        let mut seq = lzw_encoded(b"TOBEORNOT");
+        seq = seq.slice(0..seq.len() - 9);
+        assert!(lzw_decode(&mut BitReader::from_seq(&seq), usize::MAX).is_err());
+    }
+}
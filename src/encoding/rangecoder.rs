@@ -0,0 +1,210 @@
+// A range coder: an alternative to Huffman coding.
+// Huffman must spend a whole number of bits on every byte. A range coder narrows an interval
+// in proportion to each byte's probability, so very common bytes can cost a fraction of a bit.
+// Author: Will Morris
+
+use std::collections::HashMap;
+
+// Intervals are kept at least this wide, so there's always enough precision left to split them.
+const TOP: u32 = 1 << 24;
+// The frequencies of a model must sum to no more than this.
+// Since range >= TOP, every byte with a frequency still gets a nonempty slice of the range.
+pub const MAX_TOTAL: u64 = 1 << 16;
+
+// The cumulative frequencies of every byte, in a form a range coder can use.
+pub struct Model {
+    // starts[b] is the total frequency of every byte less than b.
+    // starts[256] is the total frequency of every byte.
+    starts: Vec<u32>,
+}
+
+impl Model {
+    // Frequencies must sum to no more than MAX_TOTAL.
+    pub fn new(freqs: &HashMap<u8, u64>) -> Self {
+        let mut starts = vec![0u32; 257];
+        for byte in 0..256 {
+            let freq = *freqs.get(&(byte as u8)).unwrap_or(&0) as u32;
+            starts[byte + 1] = starts[byte] + freq;
+        }
+        assert!(starts[256] as u64 <= MAX_TOTAL);
+        Model { starts }
+    }
+
+    fn total(&self) -> u32 {
+        self.starts[256]
+    }
+
+    fn start(&self, byte: u8) -> u32 {
+        self.starts[byte as usize]
+    }
+
+    fn size(&self, byte: u8) -> u32 {
+        self.starts[byte as usize + 1] - self.starts[byte as usize]
+    }
+
+    // Find the byte whose slice of the total contains target.
+    fn find(&self, target: u32) -> u8 {
+        // The last start which is <= target. Bytes with no frequency have empty slices, and are skipped.
+        (self.starts.partition_point(|start| *start <= target) - 1) as u8
+    }
+}
+
+pub struct RangeEncoder {
+    low: u64,
+    range: u32,
+    // The last byte produced is held back, in case a carry needs to ripple into it.
+    cache: u8,
+    // How many bytes are held back: the cache, plus any 0xFFs which a carry would also change.
+    pending: u64,
+    bytes: Vec<u8>,
+}
+
+impl RangeEncoder {
+    pub fn new() -> Self {
+        RangeEncoder { low: 0, range: u32::MAX, cache: 0, pending: 1, bytes: vec![] }
+    }
+
+    // Narrow the interval to byte's slice of it.
+    // byte must have a nonzero frequency in model.
+    pub fn encode(&mut self, byte: u8, model: &Model) {
+        let size = model.size(byte);
+        assert!(size > 0, "Cannot encode a byte with no frequency!");
+
+        let r = self.range / model.total();
+        self.low += r as u64 * model.start(byte) as u64;
+        self.range = r * size;
+
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    // Write out enough of the interval to identify it, and return every byte produced.
+    pub fn finish(mut self) -> Vec<u8> {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+        self.bytes
+    }
+
+    // Move the top byte of low into the output.
+    fn shift_low(&mut self) {
+        let carry = (self.low >> 32) as u8;
+        // Unless this byte is 0xFF (and there's no carry), it can no longer be changed by a carry.
+        if (self.low as u32) < 0xFF00_0000 || carry != 0 {
+            let mut held = self.cache;
+            while self.pending > 0 {
+                self.bytes.push(held.wrapping_add(carry));
+                held = 0xFF;
+                self.pending -= 1;
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.pending += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+}
+
+impl Default for RangeEncoder {
+    fn default() -> Self {
+        RangeEncoder::new()
+    }
+}
+
+pub struct RangeDecoder<'a> {
+    code: u32,
+    range: u32,
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> RangeDecoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        let mut decoder = RangeDecoder { code: 0, range: u32::MAX, bytes, position: 0 };
+        // The encoder always starts by writing the (empty) cache byte, so that's skipped.
+        decoder.next_byte();
+        for _ in 0..4 {
+            decoder.code = (decoder.code << 8) | decoder.next_byte() as u32;
+        }
+        decoder
+    }
+
+    // Decode the next byte, given the same model the encoder used.
+    pub fn decode(&mut self, model: &Model) -> u8 {
+        let r = self.range / model.total();
+        let target = (self.code / r).min(model.total() - 1);
+        let byte = model.find(target);
+
+        self.code -= r * model.start(byte);
+        self.range = r * model.size(byte);
+
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | self.next_byte() as u32;
+        }
+        byte
+    }
+
+    // Past the end of the input, pretend there are zeros.
+    fn next_byte(&mut self) -> u8 {
+        let byte = *self.bytes.get(self.position).unwrap_or(&0);
+        self.position += 1;
+        byte
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::encoding::rangecoder::{Model, RangeDecoder, RangeEncoder};
+    use crate::ordering::freq::{gen_frequency, scale_frequencies};
+
+    fn round_trip(bytes: &[u8]) -> Vec<u8> {
+        let freqs = scale_frequencies(&gen_frequency(bytes), super::MAX_TOTAL);
+        let model = Model::new(&freqs);
+
+        let mut encoder = RangeEncoder::new();
+        for byte in bytes {
+            encoder.encode(*byte, &model);
+        }
+        let encoded = encoder.finish();
+
+        let mut decoder = RangeDecoder::new(&encoded);
+        let decoded: Vec<u8> = (0..bytes.len()).map(|_| decoder.decode(&model)).collect();
+        assert_eq!(bytes, decoded);
+        encoded
+    }
+
+    #[test]
+    fn test_text() {
+        round_trip(b"the quick brown fox jumps over the lazy dog, again and again and again");
+    }
+
+    #[test]
+    fn test_every_byte() {
+        let bytes: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        round_trip(&bytes);
+    }
+
+    // Heavily skewed data should cost much less than a bit per byte, which Huffman can't do.
+    #[test]
+    fn test_skewed() {
+        let mut bytes = vec![b'a'; 10000];
+        bytes[5000] = b'b';
+        let encoded = round_trip(&bytes);
+        assert!(encoded.len() * 8 < bytes.len() / 4);
+    }
+
+    #[test]
+    fn test_find() {
+        let mut freqs = HashMap::new();
+        freqs.insert(3, 2);
+        freqs.insert(7, 5);
+        let model = Model::new(&freqs);
+        assert_eq!(3, model.find(0));
+        assert_eq!(3, model.find(1));
+        assert_eq!(7, model.find(2));
+        assert_eq!(7, model.find(6));
+    }
+}
@@ -0,0 +1,576 @@
+// Represents an archive: many files, compressed together.
+// Author: Will Morris
+
+/*
+  CONTENTS:
+  -- number of shared dictionaries
+  -- each shared dictionary
+  -- number of members
+  -- for each member:
+     -- name
+     -- number of chunks
+     -- hash of each chunk, in order
+  -- number of stored chunks
+  -- for each stored chunk:
+     -- hash
+     -- compressed chunk.
+  -- only if there are any links, sparse members, details or copies, the number of links
+  -- for each link:
+     -- kind of link
+     -- name
+     -- target
+  -- only if there are any sparse members, details or copies, the number of sparse members
+  -- for each sparse member:
+     -- which member it is, counting from 0
+     -- size of the whole file
+     -- number of runs of data
+     -- offset and length of each run
+  -- only if any member has details or there are any copies, one sized frame of details:
+     -- number of members with details
+     -- for each:
+        -- which member it is, counting from 0
+        -- number of records
+        -- for each record, what kind it is, then the sized record.
+  -- only if there are any copies, the number of copies
+  -- for each copy:
+     -- which member it is, counting from 0
+     -- which earlier member it has the same contents as.
+
+  Members are split into chunks by content, and each distinct chunk is stored only once.
+  Duplicated regions within files cost only a hash in the index.
+  Duplicate files cost less still: they're stored with no chunks, and a copy entry naming the first member like them.
+  Likewise, a table shared by many chunks is only stored once.
+  Chunks name the dictionary they were compressed with, so archives joined together can keep one each.
+  Links and sparse members come last, and only when there are some, so archives without them are just as they always were.
+  A sparse member's chunks hold only its runs of data, one after another. The holes between them are never stored.
+  Details are kept in a frame of their own, so readers which don't want them can skip it whole.
+  Within it, records of kinds a reader doesn't know are skipped too, so new kinds can be added.
+ */
+
+use std::collections::HashMap;
+use crate::file::bytestream::{append_sized, ByteStream, read_bytes, read_long, read_sized};
+use crate::file::error::WzError;
+use crate::ordering::dictionary::{Dictionary, DictionaryId};
+
+// Chunks are identified by a 128-bit hash of their uncompressed contents.
+pub type ChunkHash = u128;
+pub const HASH_LEN: usize = size_of::<ChunkHash>();
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    // Relative path of the member, with '/' separating directories.
+    pub name: String,
+    pub chunks: Vec<ChunkHash>,
+    // For sparse files, where their data lies. Other members are their chunks, and nothing more.
+    pub extents: Option<Extents>,
+    pub details: Details,
+}
+
+// What's recorded about a member besides its contents, when asked for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Details {
+    // Extended attributes, as names and values, in order of name. ACLs are kept among them.
+    pub xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+    pub owner: Option<Owner>,
+}
+
+// Who owned a member, by number, and by name where the names were known.
+// Names are more likely to mean the same person on another system, so they're preferred when restoring.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+    pub user: Option<String>,
+    pub group: Option<String>,
+}
+
+// The kinds of record details are made of.
+const XATTR_RECORD: u8 = 1;
+const OWNER_RECORD: u8 = 2;
+
+// A record's kind, and the record.
+type Record = (u8, Vec<u8>);
+
+impl Details {
+    pub fn is_empty(&self) -> bool {
+        self.xattrs.is_empty() && self.owner.is_none()
+    }
+
+    fn to_records(&self) -> Vec<Record> {
+        let mut records = vec![];
+        for (name, value) in &self.xattrs {
+            let mut record = vec![];
+            append_sized(&mut record, &mut name.clone());
+            append_sized(&mut record, &mut value.clone());
+            records.push((XATTR_RECORD, record));
+        }
+        // Unknown names are stored empty, which no user or group is called.
+        if let Some(owner) = &self.owner {
+            let mut record = vec![];
+            record.extend_from_slice(&(owner.uid as u64).to_le_bytes());
+            record.extend_from_slice(&(owner.gid as u64).to_le_bytes());
+            append_sized(&mut record, &mut owner.user.clone().unwrap_or_default().into_bytes());
+            append_sized(&mut record, &mut owner.group.clone().unwrap_or_default().into_bytes());
+            records.push((OWNER_RECORD, record));
+        }
+        records
+    }
+
+    // Add a record to these details. Records of unknown kinds are ignored.
+    fn add_record(&mut self, kind: u8, record: &[u8]) -> Result<(), WzError> {
+        let mut i = 0;
+        match kind {
+            XATTR_RECORD => {
+                let name = read_sized(record, &mut i)?.to_vec();
+                let value = read_sized(record, &mut i)?.to_vec();
+                self.xattrs.push((name, value));
+            }
+            OWNER_RECORD => {
+                let id = |i: &mut usize| u32::try_from(read_long(record, i)?)
+                    .map_err(|_| WzError::Invalid("an owner's id"));
+                let (uid, gid) = (id(&mut i)?, id(&mut i)?);
+                let user = Some(read_name(record, &mut i)?).filter(|name| !name.is_empty());
+                let group = Some(read_name(record, &mut i)?).filter(|name| !name.is_empty());
+                self.owner = Some(Owner { uid, gid, user, group });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// Where a sparse file's data lies. Everything else in it is a hole, read as zeros.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extents {
+    // Size of the whole file, holes and all.
+    pub size: u64,
+    // The offset and length of each run of data, in order.
+    pub data: Vec<(u64, u64)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkKind {
+    Symbolic = 0,
+    Hard = 1,
+}
+
+// A member which points at something else, rather than holding contents of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub name: String,
+    // For symbolic links, the path they hold, with '/' separating directories.
+    // For hard links, the name of the member whose contents they share.
+    pub target: String,
+    pub kind: LinkKind,
+}
+
+impl Extents {
+    // Could a member of len bytes be these runs? They must hold it exactly, and all lie within the file.
+    pub fn fits(&self, len: usize) -> bool {
+        let within = self.data.iter().all(|(offset, run)| offset.checked_add(*run).is_some_and(|end| end <= self.size));
+        within && self.data.iter().try_fold(0u64, |total, (_, run)| total.checked_add(*run)) == Some(len as u64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Archive {
+    members: Vec<Member>,
+    links: Vec<Link>,
+    // Each chunk appears here exactly once, however many members refer to it.
+    chunks: Vec<(ChunkHash, Vec<u8>)>,
+    // Where each chunk lives in chunks, for quick lookup by hash.
+    positions: HashMap<ChunkHash, usize>,
+    // Tables for chunks to share, instead of each storing their own.
+    dictionaries: Vec<Dictionary>,
+}
+
+impl Archive {
+    pub fn new() -> Self {
+        Archive { members: vec![], links: vec![], chunks: vec![], positions: HashMap::new(), dictionaries: vec![] }
+    }
+
+    // Store a shared dictionary. Dictionaries which are already stored are ignored.
+    pub fn add_dictionary(&mut self, dictionary: Dictionary) {
+        if self.dictionary(dictionary.id()).is_none() {
+            self.dictionaries.push(dictionary);
+        }
+    }
+
+    pub fn dictionaries(&self) -> &[Dictionary] {
+        &self.dictionaries
+    }
+
+    pub fn dictionary(&self, id: DictionaryId) -> Option<&Dictionary> {
+        self.dictionaries.iter().find(|dictionary| dictionary.id() == id)
+    }
+
+    // Has a chunk with this hash already been stored?
+    pub fn has_chunk(&self, hash: ChunkHash) -> bool {
+        self.positions.contains_key(&hash)
+    }
+
+    // Store a compressed chunk. Chunks which are already stored are ignored.
+    pub fn add_chunk(&mut self, hash: ChunkHash, compressed: Vec<u8>) {
+        if !self.has_chunk(hash) {
+            self.positions.insert(hash, self.chunks.len());
+            self.chunks.push((hash, compressed));
+        }
+    }
+
+    pub fn add_member(&mut self, member: Member) {
+        self.members.push(member);
+    }
+
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    pub fn add_link(&mut self, link: Link) {
+        self.links.push(link);
+    }
+
+    pub fn links(&self) -> &[Link] {
+        &self.links
+    }
+
+    // Find the compressed contents of a chunk.
+    pub fn chunk(&self, hash: ChunkHash) -> Option<&[u8]> {
+        self.positions.get(&hash).map(|position| self.chunks[*position].1.as_slice())
+    }
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Archive::new()
+    }
+}
+
+// Hash a chunk's contents with 128-bit FNV-1a.
+// Not cryptographic, but at 128 bits, accidental collisions are not a practical concern.
+pub fn hash_chunk(bytes: &[u8]) -> ChunkHash {
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013B;
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ *byte as u128).wrapping_mul(PRIME)
+    })
+}
+
+pub fn read_hash(bytes: &[u8], i: &mut usize) -> Result<ChunkHash, WzError> {
+    let mut buf = [0u8; HASH_LEN];
+    buf.copy_from_slice(read_bytes(bytes, i, HASH_LEN, "a chunk hash")?);
+    Ok(ChunkHash::from_le_bytes(buf))
+}
+
+// Names are stored as UTF-8, whatever the platform.
+pub fn read_name(bytes: &[u8], i: &mut usize) -> Result<String, WzError> {
+    String::from_utf8(read_sized(bytes, i)?.to_vec())
+        .map_err(|_| WzError::Malformed(String::from("Member names must be UTF-8!")))
+}
+
+impl ByteStream for Archive {
+    type Data = Archive;
+
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError> {
+        let mut archive = Archive::new();
+        let mut i = 0;
+
+        let num_dictionaries = read_long(bytes, &mut i)?;
+        for _ in 0..num_dictionaries {
+            archive.add_dictionary(Dictionary::from_whole_stream(read_sized(bytes, &mut i)?)?);
+        }
+
+        let num_members = read_long(bytes, &mut i)?;
+        for _ in 0..num_members {
+            let name = read_name(bytes, &mut i)?;
+            let num_chunks = read_long(bytes, &mut i)?;
+            let chunks = (0..num_chunks).map(|_| read_hash(bytes, &mut i)).collect::<Result<_, _>>()?;
+            archive.add_member(Member { name, chunks, extents: None, details: Details::default() });
+        }
+
+        let num_chunks = read_long(bytes, &mut i)?;
+        for _ in 0..num_chunks {
+            let hash = read_hash(bytes, &mut i)?;
+            let compressed = read_sized(bytes, &mut i)?.to_vec();
+            archive.add_chunk(hash, compressed);
+        }
+
+        if i < bytes.len() {
+            let num_links = read_long(bytes, &mut i)?;
+            for _ in 0..num_links {
+                let kind = match read_bytes(bytes, &mut i, 1, "a link kind")?[0] {
+                    0 => { LinkKind::Symbolic }
+                    1 => { LinkKind::Hard }
+                    kind => { return Err(WzError::Malformed(format!("Unknown kind of link: {}", kind))) }
+                };
+                let name = read_name(bytes, &mut i)?;
+                let target = read_name(bytes, &mut i)?;
+                archive.add_link(Link { name, target, kind });
+            }
+        }
+
+        if i < bytes.len() {
+            let num_sparse = read_long(bytes, &mut i)?;
+            for _ in 0..num_sparse {
+                let index = read_long(bytes, &mut i)? as usize;
+                let size = read_long(bytes, &mut i)?;
+                let num_runs = read_long(bytes, &mut i)?;
+                let data = (0..num_runs).map(|_| Ok((read_long(bytes, &mut i)?, read_long(bytes, &mut i)?)))
+                    .collect::<Result<_, WzError>>()?;
+                let member = archive.members.get_mut(index)
+                    .ok_or(WzError::Malformed(format!("No member {} to be sparse", index)))?;
+                member.extents = Some(Extents { size, data });
+            }
+        }
+
+        if i < bytes.len() {
+            let frame = read_sized(bytes, &mut i)?;
+            let mut j = 0;
+            let num_detailed = read_long(frame, &mut j)?;
+            for _ in 0..num_detailed {
+                let index = read_long(frame, &mut j)? as usize;
+                let member = archive.members.get_mut(index)
+                    .ok_or(WzError::Malformed(format!("No member {} to have details", index)))?;
+                let num_records = read_long(frame, &mut j)?;
+                for _ in 0..num_records {
+                    let kind = read_bytes(frame, &mut j, 1, "a kind of record")?[0];
+                    member.details.add_record(kind, read_sized(frame, &mut j)?)?;
+                }
+            }
+        }
+
+        if i < bytes.len() {
+            let num_copies = read_long(bytes, &mut i)?;
+            for _ in 0..num_copies {
+                let index = read_long(bytes, &mut i)? as usize;
+                let original = read_long(bytes, &mut i)? as usize;
+                let chunks = archive.members.get(original).filter(|_| original < index)
+                    .ok_or(WzError::Malformed(format!("Member {} can't be a copy of member {}", index, original)))?
+                    .chunks.clone();
+                let member = archive.members.get_mut(index)
+                    .ok_or(WzError::Malformed(format!("No member {} to be a copy", index)))?;
+                member.chunks = chunks;
+            }
+        }
+
+        Ok((archive, i))
+    }
+
+    fn to_stream(self) -> Vec<u8> {
+        let mut retval = vec![];
+
+        retval.extend_from_slice(&(self.dictionaries.len() as u64).to_le_bytes());
+        for dictionary in self.dictionaries {
+            append_sized(&mut retval, &mut dictionary.to_stream());
+        }
+
+        // Sparse members and details are listed after the links, by where they are among the members.
+        let sparse: Vec<(usize, Extents)> = self.members.iter().enumerate()
+            .filter_map(|(index, member)| member.extents.clone().map(|extents| (index, extents)))
+            .collect();
+        let detailed: Vec<(usize, Vec<Record>)> = self.members.iter().enumerate()
+            .filter(|(_, member)| !member.details.is_empty())
+            .map(|(index, member)| (index, member.details.to_records()))
+            .collect();
+        // Members with the same chunks as an earlier one are written as copies of it.
+        // A single chunk's hash is no bigger than a copy entry, so only members with more are worth it.
+        let mut firsts: HashMap<&[ChunkHash], usize> = HashMap::new();
+        let copies: Vec<(usize, usize)> = self.members.iter().enumerate()
+            .filter(|(_, member)| member.chunks.len() > 1)
+            .filter_map(|(index, member)| {
+                let original = *firsts.entry(member.chunks.as_slice()).or_insert(index);
+                (original != index).then_some((index, original))
+            })
+            .collect();
+        let mut copied = copies.iter().map(|(index, _)| *index).peekable();
+        retval.extend_from_slice(&(self.members.len() as u64).to_le_bytes());
+        for (index, member) in self.members.into_iter().enumerate() {
+            append_sized(&mut retval, &mut member.name.into_bytes());
+            let chunks = match copied.next_if_eq(&index) {
+                Some(_) => { vec![] }
+                None => { member.chunks }
+            };
+            retval.extend_from_slice(&(chunks.len() as u64).to_le_bytes());
+            for hash in chunks {
+                retval.extend_from_slice(&hash.to_le_bytes());
+            }
+        }
+
+        retval.extend_from_slice(&(self.chunks.len() as u64).to_le_bytes());
+        for (hash, mut compressed) in self.chunks {
+            retval.extend_from_slice(&hash.to_le_bytes());
+            append_sized(&mut retval, &mut compressed);
+        }
+
+        if !self.links.is_empty() || !sparse.is_empty() || !detailed.is_empty() || !copies.is_empty() {
+            retval.extend_from_slice(&(self.links.len() as u64).to_le_bytes());
+            for link in self.links {
+                retval.push(link.kind as u8);
+                append_sized(&mut retval, &mut link.name.into_bytes());
+                append_sized(&mut retval, &mut link.target.into_bytes());
+            }
+        }
+
+        if !sparse.is_empty() || !detailed.is_empty() || !copies.is_empty() {
+            retval.extend_from_slice(&(sparse.len() as u64).to_le_bytes());
+            for (index, extents) in sparse {
+                retval.extend_from_slice(&(index as u64).to_le_bytes());
+                retval.extend_from_slice(&extents.size.to_le_bytes());
+                retval.extend_from_slice(&(extents.data.len() as u64).to_le_bytes());
+                for (offset, len) in extents.data {
+                    retval.extend_from_slice(&offset.to_le_bytes());
+                    retval.extend_from_slice(&len.to_le_bytes());
+                }
+            }
+        }
+
+        if !detailed.is_empty() || !copies.is_empty() {
+            let mut frame = vec![];
+            frame.extend_from_slice(&(detailed.len() as u64).to_le_bytes());
+            for (index, records) in detailed {
+                frame.extend_from_slice(&(index as u64).to_le_bytes());
+                frame.extend_from_slice(&(records.len() as u64).to_le_bytes());
+                for (kind, mut record) in records {
+                    frame.push(kind);
+                    append_sized(&mut frame, &mut record);
+                }
+            }
+            append_sized(&mut retval, &mut frame);
+        }
+
+        if !copies.is_empty() {
+            retval.extend_from_slice(&(copies.len() as u64).to_le_bytes());
+            for (index, original) in copies {
+                retval.extend_from_slice(&(index as u64).to_le_bytes());
+                retval.extend_from_slice(&(original as u64).to_le_bytes());
+            }
+        }
+
+        retval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::archive::{Archive, Details, Extents, hash_chunk, Link, LinkKind, Member, Owner};
+    use crate::file::bytestream::{append_sized, ByteStream};
+    use crate::ordering::dictionary::Dictionary;
+
+    #[test]
+    fn test_empty() {
+        let expected = Archive::new();
+        let from = Archive::from_whole_stream(&expected.clone().to_stream()).unwrap();
+        assert_eq!(expected, from);
+    }
+
+    #[test]
+    fn test_to_from() {
+        let mut expected = Archive::new();
+        let first = hash_chunk(b"first");
+        let second = hash_chunk(b"second");
+        expected.add_chunk(first, vec![1, 2, 3]);
+        expected.add_chunk(second, vec![4, 5]);
+        expected.add_member(Member { name: String::from("a.txt"), chunks: vec![first, second], extents: None, details: Details::default() });
+        expected.add_member(Member { name: String::from("dir/b.txt"), chunks: vec![first], extents: None, details: Details::default() });
+        expected.add_member(Member { name: String::from("empty"), chunks: vec![], extents: None, details: Details::default() });
+
+        let from = Archive::from_whole_stream(&expected.clone().to_stream()).unwrap();
+        assert_eq!(expected, from);
+        assert_eq!(Some(&[4u8, 5][..]), from.chunk(second));
+        assert!(from.dictionaries().is_empty());
+
+        let dictionary = Dictionary::train(&[b"first second"]);
+        expected.add_dictionary(dictionary.clone());
+        expected.add_dictionary(dictionary.clone());
+        expected.add_dictionary(Dictionary::train(&[b"third"]));
+        let from = Archive::from_whole_stream(&expected.clone().to_stream()).unwrap();
+        assert_eq!(expected, from);
+        assert_eq!(2, from.dictionaries().len());
+        assert_eq!(Some(&dictionary), from.dictionary(dictionary.id()));
+    }
+
+    #[test]
+    fn test_links() {
+        let mut expected = Archive::new();
+        expected.add_member(Member { name: String::from("a.txt"), chunks: vec![], extents: None, details: Details::default() });
+        // Archives without links are written as they were before there were links.
+        let without = expected.clone().to_stream();
+
+        expected.add_link(Link { name: String::from("latest"), target: String::from("logs/a.txt"), kind: LinkKind::Symbolic });
+        expected.add_link(Link { name: String::from("b.txt"), target: String::from("a.txt"), kind: LinkKind::Hard });
+        let with = expected.clone().to_stream();
+        assert!(with.starts_with(&without));
+        assert_eq!(expected, Archive::from_whole_stream(&with).unwrap());
+
+        // Sparse members need the links' count before them, even when there are no links.
+        let mut sparse = Archive::new();
+        let hole = Extents { size: 1 << 30, data: vec![(0, 5), (1 << 29, 5)] };
+        sparse.add_member(Member { name: String::from("a.txt"), chunks: vec![], extents: None, details: Details::default() });
+        sparse.add_member(Member { name: String::from("disk.img"), chunks: vec![hash_chunk(b"startstart")], extents: Some(hole), details: Details::default() });
+        assert_eq!(sparse, Archive::from_whole_stream(&sparse.clone().to_stream()).unwrap());
+        expected.add_member(Member { name: String::from("disk.img"), chunks: vec![], extents: Some(Extents { size: 1, data: vec![] }), details: Details::default() });
+        assert_eq!(expected, Archive::from_whole_stream(&expected.clone().to_stream()).unwrap());
+
+        let mut unknown = with.clone();
+        unknown[without.len() + 8] = 9;
+        assert!(Archive::from_whole_stream(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_details() {
+        let mut expected = Archive::new();
+        let xattrs = vec![(b"user.origin".to_vec(), b"camera".to_vec()), (b"user.rating".to_vec(), vec![5, 0])];
+        expected.add_member(Member { name: String::from("plain"), chunks: vec![], extents: None, details: Details::default() });
+        expected.add_member(Member { name: String::from("photo.jpg"), chunks: vec![], extents: None,
+                                     details: Details { xattrs, owner: None } });
+        let owner = Some(Owner { uid: 1000, gid: 100, user: Some(String::from("will")), group: None });
+        expected.add_member(Member { name: String::from("mine"), chunks: vec![], extents: None,
+                                     details: Details { xattrs: vec![], owner } });
+        let bytes = expected.clone().to_stream();
+        assert_eq!(expected, Archive::from_whole_stream(&bytes).unwrap());
+
+        // A record of a kind added later is skipped by readers which don't know it.
+        let mut frame = vec![];
+        frame.extend_from_slice(&1u64.to_le_bytes());
+        frame.extend_from_slice(&0u64.to_le_bytes());
+        frame.extend_from_slice(&1u64.to_le_bytes());
+        frame.push(200);
+        append_sized(&mut frame, &mut b"from the future".to_vec());
+        let mut plain = Archive::new();
+        plain.add_member(Member { name: String::from("plain"), chunks: vec![], extents: None, details: Details::default() });
+        let mut bytes = plain.clone().to_stream();
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        append_sized(&mut bytes, &mut frame);
+        assert_eq!(plain, Archive::from_whole_stream(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_copies() {
+        let mut expected = Archive::new();
+        let chunks = vec![hash_chunk(b"first"), hash_chunk(b"second"), hash_chunk(b"third")];
+        expected.add_member(Member { name: String::from("logo.png"), chunks: chunks.clone(), extents: None, details: Details::default() });
+        expected.add_member(Member { name: String::from("b/logo.png"), chunks: chunks.clone(), extents: None, details: Details::default() });
+        expected.add_member(Member { name: String::from("c/logo.png"), chunks: chunks.clone(), extents: None, details: Details::default() });
+        let bytes = expected.clone().to_stream();
+        assert_eq!(expected, Archive::from_whole_stream(&bytes).unwrap());
+
+        // Each copy costs two numbers, instead of a hash for every chunk.
+        let mut distinct = expected.clone();
+        distinct.members[2].chunks.reverse();
+        let longer = distinct.clone().to_stream();
+        assert_eq!(distinct, Archive::from_whole_stream(&longer).unwrap());
+        assert!(bytes.len() < longer.len());
+
+        // Copies must be of a member before them.
+        let mut bad = bytes.clone();
+        let len = bad.len();
+        bad[len - 8..].copy_from_slice(&2u64.to_le_bytes());
+        assert!(Archive::from_whole_stream(&bad).is_err());
+    }
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(hash_chunk(b"same"), hash_chunk(b"same"));
+        assert_ne!(hash_chunk(b"same"), hash_chunk(b"different"));
+    }
+}
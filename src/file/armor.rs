@@ -0,0 +1,212 @@
+// ASCII armor: compressed bytes as base64 text, so they survive being pasted into tickets, emails, or YAML.
+// Armored text is recognized by its header line, so it can be extracted like anything else.
+// Author: Will Morris
+
+/*
+  CONTENTS:
+  -- HEADER, on a line of its own
+  -- the bytes in base64, LINE_LEN characters to a line, the last line padded with =
+  -- FOOTER, on a line of its own.
+
+  Whitespace anywhere is ignored when reading, since pasted text is often indented or given other line endings.
+ */
+
+use std::io::{self, Write};
+
+pub(crate) const HEADER: &str = "-----BEGIN WZ DATA-----";
+pub(crate) const FOOTER: &str = "-----END WZ DATA-----";
+// Characters on each full line, short enough for any email.
+const LINE_LEN: usize = 64;
+// Three bytes make four characters.
+const LINE_BYTES: usize = LINE_LEN / 4 * 3;
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Writes to inner armored, a line at a time. Without armor, writes are left alone.
+// Nothing is armored until the first write, and the last line and footer only go out with finish.
+pub(crate) struct Armored<W> {
+    inner: W,
+    armor: bool,
+    started: bool,
+    // Bytes short of a full line, waiting for more.
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Armored<W> {
+    pub(crate) fn new(inner: W, armor: bool) -> Self {
+        Armored { inner, armor, started: false, pending: vec![] }
+    }
+
+    pub(crate) fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    // Write whatever's left, and the footer. Armored output is incomplete without this.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        if self.armor {
+            self.start()?;
+            if !self.pending.is_empty() {
+                let line = encode(&self.pending);
+                self.pending.clear();
+                self.inner.write_all(line.as_bytes())?;
+                self.inner.write_all(b"\n")?;
+            }
+            self.inner.write_all(FOOTER.as_bytes())?;
+            self.inner.write_all(b"\n")?;
+        }
+        self.inner.flush()
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            self.inner.write_all(HEADER.as_bytes())?;
+            self.inner.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Armored<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.armor {
+            return self.inner.write(buf)
+        }
+        self.start()?;
+        self.pending.extend_from_slice(buf);
+        let full = self.pending.len() / LINE_BYTES * LINE_BYTES;
+        for line in self.pending[..full].chunks(LINE_BYTES) {
+            self.inner.write_all(encode(line).as_bytes())?;
+            self.inner.write_all(b"\n")?;
+        }
+        self.pending.drain(..full);
+        Ok(buf.len())
+    }
+
+    // Bytes short of a line stay pending, since any more would change how they're encoded.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Does bytes start with an armor header? Whitespace before it is skipped.
+pub(crate) fn is_armored(bytes: &[u8]) -> bool {
+    bytes.trim_ascii_start().starts_with(HEADER.as_bytes())
+}
+
+// The bytes between an armor's header and footer.
+pub(crate) fn dearmor(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let text = bytes.trim_ascii_start().strip_prefix(HEADER.as_bytes()).ok_or("Armored data has no header!")?;
+    let end = text.windows(FOOTER.len()).position(|window| window == FOOTER.as_bytes())
+        .ok_or("Armored data has no footer, so it may have been cut short!")?;
+    let text: Vec<u8> = text[..end].iter().copied().filter(|c| !c.is_ascii_whitespace()).collect();
+    decode(&text)
+}
+
+// Base64, with padding.
+fn encode(bytes: &[u8]) -> String {
+    let mut retval = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let bits = group.iter().enumerate().fold(0u32, |bits, (i, byte)| bits | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= group.len() {
+                true => { retval.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char) }
+                false => { retval.push('=') }
+            }
+        }
+    }
+    retval
+}
+
+fn decode(text: &[u8]) -> Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(4) {
+        return Err(String::from("Armored data isn't a whole number of base64 groups!"))
+    }
+    let mut retval = Vec::with_capacity(text.len() / 4 * 3);
+    let groups = text.len() / 4;
+    for (n, group) in text.chunks(4).enumerate() {
+        // Only the last group may be padded, and only at its end.
+        let padding = group.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && n + 1 != groups) {
+            return Err(String::from("Armored data has padding in the wrong place!"))
+        }
+        let mut bits = 0u32;
+        for (i, c) in group[..4 - padding].iter().enumerate() {
+            let value = ALPHABET.iter().position(|letter| letter == c)
+                .ok_or(format!("Armored data can't hold {:?}!", *c as char))?;
+            bits |= (value as u32) << (18 - 6 * i);
+        }
+        retval.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Ok(retval)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use crate::file::armor::{Armored, dearmor, decode, encode, FOOTER, HEADER, is_armored, LINE_LEN};
+
+    fn armor(bytes: &[u8]) -> Vec<u8> {
+        let mut armored = Armored::new(vec![], true);
+        armored.write_all(bytes).unwrap();
+        armored.finish().unwrap();
+        armored.inner
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!("", encode(b""));
+        assert_eq!("Zg==", encode(b"f"));
+        assert_eq!("Zm8=", encode(b"fo"));
+        assert_eq!("Zm9v", encode(b"foo"));
+        assert_eq!("Zm9vYmFy", encode(b"foobar"));
+        assert_eq!(Ok(b"foob".to_vec()), decode(b"Zm9vYg=="));
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(Ok(all.clone()), decode(encode(&all).as_bytes()));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes: Vec<u8> = (0..1000).map(|i| (i * 7 % 256) as u8).collect();
+        for len in [0, 1, 2, 3, 47, 48, 49, 96, 1000] {
+            let armored = armor(&bytes[..len]);
+            assert!(is_armored(&armored));
+            let text = String::from_utf8(armored.clone()).unwrap();
+            assert!(text.starts_with(HEADER) && text.ends_with(&format!("{}\n", FOOTER)));
+            assert!(text.lines().all(|line| line.len() <= LINE_LEN));
+            assert_eq!(Ok(bytes[..len].to_vec()), dearmor(&armored));
+        }
+
+        // Written a byte at a time, it comes out the same.
+        let mut armored = Armored::new(vec![], true);
+        bytes.iter().for_each(|byte| armored.write_all(&[*byte]).unwrap());
+        armored.finish().unwrap();
+        assert_eq!(armor(&bytes), armored.inner);
+    }
+
+    #[test]
+    fn test_pasted() {
+        // Indented, as in YAML, with Windows line endings.
+        let armored = String::from_utf8(armor(b"pasted into a ticket")).unwrap();
+        let pasted = format!("\n  {}", armored.replace('\n', "\r\n  "));
+        assert!(is_armored(pasted.as_bytes()));
+        assert_eq!(Ok(b"pasted into a ticket".to_vec()), dearmor(pasted.as_bytes()));
+    }
+
+    #[test]
+    fn test_unarmored() {
+        let mut plain = Armored::new(vec![], false);
+        plain.write_all(b"left alone").unwrap();
+        plain.finish().unwrap();
+        assert_eq!(b"left alone".to_vec(), plain.inner);
+        assert!(!is_armored(b"left alone"));
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let armored = armor(b"cut short");
+        assert!(dearmor(&armored[..armored.len() - 5]).is_err());
+        assert!(dearmor(format!("{}\nZm9v!\n{}", HEADER, FOOTER).as_bytes()).is_err());
+        assert!(dearmor(format!("{}\nZm9\n{}", HEADER, FOOTER).as_bytes()).is_err());
+        assert!(dearmor(format!("{}\nZg==Zm9v\n{}", HEADER, FOOTER).as_bytes()).is_err());
+    }
+}
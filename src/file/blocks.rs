@@ -0,0 +1,377 @@
+// A compressed file is a series of blocks, each compressed independently.
+// This keeps the work for any one block bounded, no matter how large the input gets.
+// Author: Will Morris
+
+/*
+  CONTENTS (repeated for each block):
+  -- block length, as a varint
+  -- the block itself.
+ */
+
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use crate::file::bytestream::{append_sized, read_sized, read_varint_from};
+use crate::file::error::WzError;
+use crate::file::stream::{CHECKSUM, decode, index_lens};
+
+/// How many bytes of input go into each block, unless the user says otherwise.
+/// Large enough that the per-block frequency table is a rounding error.
+pub const DEFAULT_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Frame a compressed block, so that it can be found again in a stream of blocks.
+pub fn frame_block(mut block: Vec<u8>) -> Vec<u8> {
+    let mut retval = vec![];
+    append_sized(&mut retval, &mut block);
+    retval
+}
+
+/// A block, and where it was found in the stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FramedBlock<'a> {
+    /// Where the block's frame starts.
+    pub offset: usize,
+    /// How many bytes the whole frame takes, length field included.
+    pub framed_len: usize,
+    /// The block itself, kind first, still compressed.
+    pub contents: &'a [u8],
+}
+
+impl FramedBlock<'_> {
+    /// The bytes the block holds, decompressed as read::WzDecoder would.
+    /// Indexes, checksums, and empty blocks hold none.
+    /// Fails on corrupt blocks, and on blocks coded with a dictionary, which only `wz -x --dict` can extract.
+    pub fn decode(&self) -> Result<Vec<u8>, WzError> {
+        decode(self.contents)
+    }
+}
+
+/// Walks a stream of framed blocks, reading each frame only when it's asked for.
+/// Nothing is decompressed: that's up to whoever is walking, with FramedBlock::decode or otherwise.
+pub struct Blocks<'a> {
+    bytes: &'a [u8],
+    i: usize,
+    // A bad frame means nothing after it can be found, so the walk ends there.
+    failed: bool,
+}
+
+impl<'a> Iterator for Blocks<'a> {
+    type Item = Result<FramedBlock<'a>, WzError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.i >= self.bytes.len() {
+            return None
+        }
+        let offset = self.i;
+        match read_sized(self.bytes, &mut self.i) {
+            Ok(contents) => { Some(Ok(FramedBlock { offset, framed_len: self.i - offset, contents })) }
+            Err(error) => {
+                self.failed = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Walk the framed blocks of a whole compressed stream.
+pub fn blocks(bytes: &[u8]) -> Blocks<'_> {
+    Blocks { bytes, i: 0, failed: false }
+}
+
+/// Where a block is in a stream that's read a piece at a time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    /// Where the block starts, past its length field.
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Find every block in a seekable stream, reading nothing but their length fields.
+/// Any block can then be read on its own, without holding the whole stream in memory.
+pub fn frames<R: Read + Seek>(reader: &mut R) -> Result<Vec<Frame>, WzError> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    let mut i = reader.seek(SeekFrom::Start(0))?;
+    let mut retval = vec![];
+    while i < end {
+        let len = read_varint_from(reader, "a field length")?.ok_or(WzError::Truncated("a field length"))?;
+        let offset = reader.stream_position()?;
+        if len > end - offset {
+            return Err(WzError::Truncated("a sized field"))
+        }
+        retval.push(Frame { offset, len });
+        i = reader.seek(SeekFrom::Start(offset + len))?;
+    }
+    Ok(retval)
+}
+
+/// Read a single block found by frames.
+pub fn read_frame<R: Read + Seek>(reader: &mut R, frame: Frame) -> Result<Vec<u8>, WzError> {
+    reader.seek(SeekFrom::Start(frame.offset))?;
+    let len = usize::try_from(frame.len).map_err(|_| WzError::Truncated("a sized field"))?;
+    read_exact(reader, len, "a sized field")
+}
+
+/// Read the next block from a stream that can only be read in order, such as a socket.
+/// Returns none if the stream ends cleanly, between blocks.
+pub fn read_block<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>, WzError> {
+    let len = match read_varint_from(reader, "a field length")? {
+        Some(len) => { len }
+        None => { return Ok(None) }
+    };
+
+    // The length can't be trusted until the block's really there, so the buffer only grows as it arrives.
+    let mut retval = vec![];
+    reader.by_ref().take(len).read_to_end(&mut retval)?;
+    match retval.len() as u64 == len {
+        true => { Ok(Some(retval)) }
+        false => { Err(WzError::Truncated("a sized field")) }
+    }
+}
+
+/// Which blocks, of these uncompressed lengths, hold len bytes from offset on, and where in the first of them those bytes start.
+/// Every stream's index lists the lengths of its blocks of data, so any part of it can be found without decoding the rest.
+pub fn locate_range(block_lens: &[u64], offset: u64, len: u64) -> (Vec<usize>, usize) {
+    let mut blocks = vec![];
+    let mut skip = 0;
+    let mut start = 0;
+    for (i, block_len) in block_lens.iter().enumerate() {
+        let end = start + block_len;
+        if end > offset && start < offset.saturating_add(len) {
+            if blocks.is_empty() {
+                skip = (offset - start) as usize;
+            }
+            blocks.push(i);
+        }
+        start = end;
+    }
+    (blocks, skip)
+}
+
+/// Read len bytes of a wz stream's contents, starting at start. Fewer come back if the stream ends first.
+/// The stream's index says which blocks hold them, so no other block is read, let alone decoded.
+/// Streams without an index are decoded from the start, up to the end of the range.
+/// Blocks are decoded as read::WzDecoder decodes them, so a range lying in blocks coded with a dictionary fails the same way.
+pub fn read_range<R: Read + Seek>(reader: &mut R, start: u64, len: u64) -> Result<Vec<u8>, WzError> {
+    read_range_with(reader, start, len, decode)
+}
+
+/// Like read_range, with each block the range lies in decoded by decode, given the whole block, kind and all.
+/// Checksums cover the whole stream, so they aren't checked.
+pub fn read_range_with<R: Read + Seek>(reader: &mut R, start: u64, len: u64,
+                                       mut decode: impl FnMut(&[u8]) -> Result<Vec<u8>, WzError>) -> Result<Vec<u8>, WzError> {
+    // The index counts only blocks of data, not checksums. The first byte of a block says which it is.
+    let mut data = vec![];
+    for frame in frames(reader)? {
+        let kind = read_frame(reader, Frame { len: frame.len.min(1), ..frame })?;
+        if kind.first() != Some(&CHECKSUM) {
+            data.push(frame);
+        }
+    }
+    let block_lens = match data.last() {
+        Some(frame) => { index_lens(&read_frame(reader, *frame)?) }
+        None => { None }
+    };
+
+    let mut contents = vec![];
+    let skip = match block_lens {
+        Some(block_lens) => {
+            let (needed, skip) = locate_range(&block_lens, start, len);
+            for block in needed {
+                let frame = data.get(block)
+                    .ok_or_else(|| WzError::Malformed(String::from("The index lists more blocks than the stream has!")))?;
+                contents.append(&mut decode(&read_frame(reader, *frame)?)?);
+            }
+            skip
+        }
+        None => {
+            for frame in data {
+                if contents.len() as u64 >= start.saturating_add(len) {
+                    break
+                }
+                contents.append(&mut decode(&read_frame(reader, frame)?)?);
+            }
+            start.min(contents.len() as u64) as usize
+        }
+    };
+    Ok(contents.into_iter().skip(skip).take(usize::try_from(len).unwrap_or(usize::MAX)).collect())
+}
+
+// Read len bytes. If the stream ends first, field says what was being read.
+fn read_exact<R: Read>(reader: &mut R, len: usize, field: &'static str) -> Result<Vec<u8>, WzError> {
+    let mut retval = vec![0; len];
+    reader.read_exact(&mut retval).map_err(|error| match error.kind() {
+        ErrorKind::UnexpectedEof => { WzError::Truncated(field) }
+        _ => { WzError::from(error) }
+    })?;
+    Ok(retval)
+}
+
+/// Split a stream of framed blocks into the blocks themselves. Fails at the first bad frame.
+pub fn split_blocks(bytes: &[u8]) -> Result<Vec<&[u8]>, WzError> {
+    blocks(bytes).map(|block| block.map(|block| block.contents)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::codec::block::{BlockKind, compress_block, Method};
+    use crate::diagnostics::report::Report;
+    use crate::file::blocks::{blocks, Frame, frame_block, frames, locate_range, read_block, read_frame, read_range, read_range_with, split_blocks};
+    use crate::file::stream::{CANONICAL, CHECKSUM, index, RAW};
+
+    #[test]
+    fn test_no_blocks() {
+        assert!(split_blocks(&[]).unwrap().is_empty());
+        assert!(split_blocks(&[2, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        let mut bytes = frame_block(vec![1, 2, 3]);
+        bytes.append(&mut frame_block(vec![4; 1000]));
+
+        let blocks = split_blocks(&bytes).unwrap();
+        assert_eq!(2, blocks.len());
+        assert_eq!(&[1, 2, 3], blocks[0]);
+        assert_eq!(&[4; 1000], blocks[1]);
+    }
+
+    #[test]
+    fn test_walk() {
+        let mut bytes = frame_block(vec![1, 2, 3]);
+        bytes.append(&mut frame_block(vec![4; 1000]));
+        let first_len = bytes.len() - frame_block(vec![4; 1000]).len();
+
+        let walked: Vec<_> = blocks(&bytes).map(|block| block.unwrap()).collect();
+        assert_eq!((0, first_len), (walked[0].offset, walked[0].framed_len));
+        assert_eq!((first_len, bytes.len() - first_len), (walked[1].offset, walked[1].framed_len));
+        assert_eq!(&[4; 1000], walked[1].contents);
+
+        // Blocks before a bad frame are still found, and the walk stops at it.
+        bytes.extend_from_slice(&[2, 0xff]);
+        let mut walk = blocks(&bytes);
+        assert!(walk.next().unwrap().is_ok());
+        assert!(walk.next().unwrap().is_ok());
+        assert!(walk.next().unwrap().is_err());
+        assert!(walk.next().is_none());
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut bytes = frame_block(vec![RAW, b'h', b'i']);
+        bytes.append(&mut frame_block(index(&[2])));
+        bytes.append(&mut frame_block(vec![0, 0, 0]));
+        bytes.append(&mut frame_block(vec![CANONICAL, 0]));
+        bytes.append(&mut frame_block(vec![BlockKind::Dictionary as u8, 0, 0, 0, 0]));
+
+        let decoded: Vec<_> = blocks(&bytes).map(|block| block.unwrap().decode()).collect();
+        assert_eq!(Ok(b"hi".to_vec()), decoded[0]);
+        assert_eq!(Ok(vec![]), decoded[1]);
+        // Truncated blocks are no blocks at all, and dictionary blocks need the dictionary.
+        assert!(decoded[2].is_err());
+        assert!(decoded[3].is_err());
+        assert!(decoded[4].is_err());
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut bytes = frame_block(vec![1, 2, 3]);
+        bytes.append(&mut frame_block(vec![4; 1000]));
+        let mut reader = Cursor::new(bytes.clone());
+
+        let found = frames(&mut reader).unwrap();
+        assert_eq!(vec![Frame { offset: 1, len: 3 }, Frame { offset: 6, len: 1000 }], found);
+        assert_eq!(vec![4; 1000], read_frame(&mut reader, found[1]).unwrap());
+        assert_eq!(vec![1, 2, 3], read_frame(&mut reader, found[0]).unwrap());
+        assert!(frames(&mut Cursor::new(vec![])).unwrap().is_empty());
+
+        // A block that runs past the end of the stream is noticed before anything is read from it.
+        bytes.pop();
+        assert!(frames(&mut Cursor::new(bytes)).is_err());
+        assert!(frames(&mut Cursor::new(vec![9])).is_err());
+    }
+
+    #[test]
+    fn test_locate_range() {
+        let lens = [1024, 512, 1000];
+        assert_eq!((vec![0, 1], 512), locate_range(&lens, 512, 1000));
+        assert_eq!((vec![1, 2], 10), locate_range(&lens, 1034, 600));
+        assert_eq!((vec![2], 0), locate_range(&lens, 1536, u64::MAX));
+        assert_eq!((vec![], 0), locate_range(&lens, 2536, 1));
+        assert_eq!((vec![], 0), locate_range(&lens, 0, 0));
+    }
+
+    #[test]
+    fn test_read_block() {
+        let mut bytes = frame_block(vec![1, 2, 3]);
+        bytes.append(&mut frame_block(vec![]));
+        let mut reader = Cursor::new(bytes.clone());
+        assert_eq!(Some(vec![1, 2, 3]), read_block(&mut reader).unwrap());
+        assert_eq!(Some(vec![]), read_block(&mut reader).unwrap());
+        assert_eq!(None, read_block(&mut reader).unwrap());
+
+        // Ending partway through a block isn't ending cleanly.
+        assert!(read_block(&mut Cursor::new(&bytes[..3])).is_err());
+        assert!(read_block(&mut Cursor::new(&bytes[..1])).is_err());
+        // Nor is claiming to be huge, without allocating anything like it.
+        assert!(read_block(&mut Cursor::new(vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0])).is_err());
+    }
+
+    // Contents compressed in blocks of the given lengths, with a checksum, and an index if asked for.
+    fn stream(contents: &[u8], lens: &[usize], indexed: bool) -> Vec<u8> {
+        let mut report = Report::new(false);
+        let mut retval = vec![];
+        let mut start = 0;
+        for len in lens {
+            retval.append(&mut frame_block(compress_block(&contents[start..start + len], &Method::new(), &mut report)));
+            start += len;
+        }
+        retval.append(&mut frame_block(vec![CHECKSUM, 0]));
+        if indexed {
+            retval.append(&mut frame_block(index(&lens.iter().map(|len| *len as u64).collect::<Vec<_>>())));
+        }
+        retval
+    }
+
+    // Skewed enough that every block is Huffman coded.
+    fn contents() -> Vec<u8> {
+        b"the rain in spain ".iter().cycle().take(3000).copied().collect()
+    }
+
+    #[test]
+    fn test_read_range() {
+        let contents = contents();
+        for indexed in [true, false] {
+            let bytes = stream(&contents, &[1000, 1000, 1000], indexed);
+            assert_eq!(BlockKind::Huffman as u8, split_blocks(&bytes).unwrap()[0][0]);
+            let mut reader = Cursor::new(bytes);
+            assert_eq!(contents[1500..2500], read_range(&mut reader, 1500, 1000).unwrap());
+            assert_eq!(contents[..10], read_range(&mut reader, 0, 10).unwrap());
+            // Past the end, there's only what there is.
+            assert_eq!(contents[2990..], read_range(&mut reader, 2990, 100).unwrap());
+            assert!(read_range(&mut reader, 5000, 10).unwrap().is_empty());
+            assert!(read_range(&mut reader, 10, 0).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_range_errors() {
+        let contents = contents();
+        let mut bytes = stream(&contents, &[1000, 1000, 1000], true);
+        // Make the first block one the library can't decode, without its dictionary. With the index, it's never read.
+        bytes[3] = BlockKind::Dictionary as u8;
+        let mut reader = Cursor::new(bytes);
+        assert_eq!(contents[2000..2100], read_range(&mut reader, 2000, 100).unwrap());
+        assert!(read_range(&mut reader, 0, 100).is_err());
+        // Whoever decodes instead gets every block the range lies in.
+        let mut decoded = 0;
+        read_range_with(&mut reader, 0, 1500, |block| {
+            decoded += 1;
+            Ok(block[1..].to_vec())
+        }).unwrap();
+        assert_eq!(2, decoded);
+
+        // An index listing blocks that aren't there.
+        let mut bytes = stream(&contents, &[1000], false);
+        bytes.append(&mut frame_block(index(&[1000, 1000, 1000])));
+        assert!(read_range(&mut Cursor::new(bytes), 2500, 10).is_err());
+    }
+}
@@ -2,48 +2,121 @@
 // This represents any data that can be constructed from a stream of bytes.
 // This will be used for efficient serialization.
 
+use std::io;
+use std::io::{Read, Write};
 use std::mem::size_of;
+use crate::file::error::WzError;
+
+// Structs whose fields are all ByteStreams can derive it, writing each field in order with append_sized.
+pub use will_zip_derive::ByteStream;
 
 pub trait ByteStream {
     type Data;
 
-    // Given a slice of bytes of a fixed size
-    // an object of this type can be constructed.
-    fn from_stream(bytes: &[u8]) -> Self::Data;
+    // Given a slice of bytes, an object of this type can be constructed from the start of it.
+    // Returns the object, and how many bytes it took up.
+    // Bytes that don't make an object give an error, rather than a panic.
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError>;
+
+    // Like from_stream, but the object must take up every byte.
+    fn from_whole_stream(bytes: &[u8]) -> Result<Self::Data, WzError> {
+        let (data, len) = Self::from_stream(bytes)?;
+        match len == bytes.len() {
+            true => { Ok(data) }
+            false => { Err(WzError::Malformed(format!("{} unexpected bytes at the end of the stream!", bytes.len() - len))) }
+        }
+    }
 
     // This function converts self to a byte vector, taking ownership.
     // Typically, converting into a stream is the last step before file serialization.
     // However, if you need self back, from_stream will work on a proper implementation.
     fn to_stream(self) -> Vec<u8>;
+
+    // Construct an object from everything left in reader.
+    // Objects don't all know their own length, so this reads until the reader runs dry.
+    fn read_from(reader: &mut impl Read) -> Result<Self::Data, WzError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("read stream", bytes = bytes.len()).entered();
+        Self::from_whole_stream(&bytes)
+    }
+
+    // Write self to writer, taking ownership just like to_stream.
+    fn write_to(self, writer: &mut impl Write) -> io::Result<()> where Self: Sized {
+        let bytes = self.to_stream();
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("write stream", bytes = bytes.len()).entered();
+        writer.write_all(&bytes)
+    }
+}
+
+pub const LONG_LEN: usize = size_of::<u64>();
+
+// Which end of a number its bytes start from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
 }
 
-pub(crate) const LONG_LEN: usize = size_of::<u64>();
+// Every number in a wz file starts from its low byte.
+// slice_to_long and long_to_bytes use this. Anything else must ask for its byte order by name.
+pub const FORMAT_ENDIAN: Endian = Endian::Little;
 
-// Given a slice of bytes, convert them into u64.
-pub(crate) fn slice_to_long(bytes: &[u8]) -> u64 {
+// Given a slice of bytes, convert them into u64, in the format's byte order.
+pub fn slice_to_long(bytes: &[u8]) -> u64 {
+    match FORMAT_ENDIAN {
+        Endian::Little => { slice_to_long_le(bytes) }
+        Endian::Big => { slice_to_long_be(bytes) }
+    }
+}
+
+// Given up to eight bytes, low byte first, convert them into u64.
+pub fn slice_to_long_le(bytes: &[u8]) -> u64 {
     let mut buf = [0u8; LONG_LEN];
     buf[..bytes.len()].copy_from_slice(bytes);
     u64::from_le_bytes(buf)
 }
 
-// Given a long, convert it to a byte array of size size.
+// Given up to eight bytes, high byte first, convert them into u64.
+pub fn slice_to_long_be(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; LONG_LEN];
+    buf[LONG_LEN - bytes.len()..].copy_from_slice(bytes);
+    u64::from_be_bytes(buf)
+}
+
+// Given a long, convert it to a byte array of size size, in the format's byte order.
 // NOTE: size must be >= minimum bytes to represent this data!
 // Also, size must be at least one. Not representing 0 with zero bytes!
-pub(crate) fn long_to_bytes(value: u64, size: u8) -> Vec<u8> {
-    let min_size = min_byte_size(value);
-    assert!(size > 0 && size >= min_size);
-    // Requiring size be sent as u8 to establish upper bound on max size.
-    let size = size as usize;
+pub fn long_to_bytes(value: u64, size: u8) -> Vec<u8> {
+    match FORMAT_ENDIAN {
+        Endian::Little => { long_to_bytes_le(value, size) }
+        Endian::Big => { long_to_bytes_be(value, size) }
+    }
+}
 
-    let mut retval = vec![0u8; size];
-    let data_bytes = value.to_le_bytes();
-    retval[..size].copy_from_slice(&data_bytes[..size]);
+// Like long_to_bytes, but always low byte first.
+pub fn long_to_bytes_le(value: u64, size: u8) -> Vec<u8> {
+    let size = checked_size(value, size);
+    value.to_le_bytes()[..size].to_vec()
+}
+
+// Like long_to_bytes, but always high byte first.
+pub fn long_to_bytes_be(value: u64, size: u8) -> Vec<u8> {
+    let size = checked_size(value, size);
+    value.to_be_bytes()[LONG_LEN - size..].to_vec()
+}
 
-    retval
+// Requiring size be sent as u8 to establish upper bound on max size.
+fn checked_size(value: u64, size: u8) -> usize {
+    let min_size = min_byte_size(value);
+    assert!(size > 0 && size >= min_size);
+    size as usize
 }
 
 // Get the minimum number of bytes needed to represent a 64-bit value.
-pub(crate) fn min_byte_size(value: u64) -> u8 {
+pub fn min_byte_size(value: u64) -> u8 {
     let data_bytes = value.to_be_bytes();
 
     // How many leading zeros do we have?
@@ -65,9 +138,93 @@ pub(crate) fn min_byte_size(value: u64) -> u8 {
     (LONG_LEN - leading_zeros) as u8
 }
 
+// Append a field to retval, prefixed by its length as a varint.
+pub fn append_sized(retval: &mut Vec<u8>, field: &mut Vec<u8>) {
+    write_varint(retval, field.len() as u64);
+    retval.append(field);
+}
+
+// Append value as a varint (LEB128): seven bits per byte, low bits first.
+// Every byte but the last has its high bit set, so small values take a single byte.
+pub fn write_varint(retval: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        retval.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    retval.push(value as u8);
+}
+
+// Read a varint written by write_varint, starting at bytes[*i].
+// Afterwards, i points just past it.
+pub fn read_varint(bytes: &[u8], i: &mut usize) -> Result<u64, WzError> {
+    read_varint_at(bytes, i, "a varint")
+}
+
+// Like read_varint, but field says what the varint is, should it be cut short.
+fn read_varint_at(bytes: &[u8], i: &mut usize, field: &'static str) -> Result<u64, WzError> {
+    let mut stream = bytes.get(*i..).unwrap_or_default();
+    let value = read_varint_from(&mut stream, field)?.ok_or(WzError::Truncated(field))?;
+    *i = bytes.len() - stream.len();
+    Ok(value)
+}
+
+// Read a varint from reader a byte at a time, so nothing past it is consumed.
+// Returns none if reader has already ended, before the varint begins. If it ends partway, field says what was being read.
+pub fn read_varint_from<R: Read>(reader: &mut R, field: &'static str) -> Result<Option<u64>, WzError> {
+    let mut value = 0;
+    for shift in (0..u64::BITS).step_by(7) {
+        let mut byte = [0u8];
+        match reader.read_exact(&mut byte) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                return match shift {
+                    0 => { Ok(None) }
+                    _ => { Err(WzError::Truncated(field)) }
+                }
+            }
+            Err(error) => { return Err(WzError::from(error)) }
+        }
+        let bits = (byte[0] & 0x7f) as u64;
+        // The tenth byte only has room for the top bit.
+        if bits << shift >> shift != bits {
+            return Err(WzError::Malformed(String::from("A varint can't be more than 64 bits!")))
+        }
+        value |= bits << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value))
+        }
+    }
+    Err(WzError::Malformed(String::from("A varint can't be more than 64 bits!")))
+}
+
+// Read a field written by append_sized, starting at bytes[*i].
+// Afterwards, i points just past the field.
+pub fn read_sized<'a>(bytes: &'a [u8], i: &mut usize) -> Result<&'a [u8], WzError> {
+    let size = read_varint_at(bytes, i, "a field length")?;
+    let size = usize::try_from(size).map_err(|_| WzError::Truncated("a sized field"))?;
+    read_bytes(bytes, i, size, "a sized field")
+}
+
+// Read a full width u64, starting at bytes[*i].
+// Afterwards, i points just past it.
+pub fn read_long(bytes: &[u8], i: &mut usize) -> Result<u64, WzError> {
+    read_bytes(bytes, i, LONG_LEN, "a number").map(slice_to_long)
+}
+
+// Read len bytes, starting at bytes[*i]. If there aren't that many, field says what was being read.
+// Afterwards, i points just past them.
+pub fn read_bytes<'a>(bytes: &'a [u8], i: &mut usize, len: usize, field: &'static str) -> Result<&'a [u8], WzError> {
+    let retval = i.checked_add(len).and_then(|end| bytes.get(*i..end)).ok_or(WzError::Truncated(field))?;
+    *i += len;
+    Ok(retval)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::file::bytestream::{long_to_bytes, min_byte_size, slice_to_long};
+    use std::io::Cursor;
+    use crate::encoding::bitsequence::BitSequence;
+    use crate::file::bytestream::{append_sized, ByteStream, Endian, FORMAT_ENDIAN, long_to_bytes, long_to_bytes_be, long_to_bytes_le, min_byte_size, read_long, read_sized, read_varint, slice_to_long, slice_to_long_be, slice_to_long_le, write_varint};
+    use crate::file::error::WzError;
 
     #[test]
     fn test_slice_to_long() {
@@ -82,10 +239,117 @@ mod tests {
         assert_eq!(vec![0], long_to_bytes(0, 1));
     }
 
+    #[test]
+    fn test_endian() {
+        assert_eq!(Endian::Little, FORMAT_ENDIAN);
+        assert_eq!(0x0102, slice_to_long_le(&[2, 1]));
+        assert_eq!(0x0102, slice_to_long_be(&[1, 2]));
+        assert_eq!(0x0102_0304_0506_0708, slice_to_long_be(&[1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(0, slice_to_long_be(&[]));
+
+        assert_eq!(vec![2, 1, 0], long_to_bytes_le(0x0102, 3));
+        assert_eq!(vec![0, 1, 2], long_to_bytes_be(0x0102, 3));
+        for value in [0, 1, 0x0102, u64::MAX] {
+            let size = min_byte_size(value);
+            assert_eq!(value, slice_to_long_le(&long_to_bytes_le(value, size)));
+            assert_eq!(value, slice_to_long_be(&long_to_bytes_be(value, size)));
+            assert_eq!(long_to_bytes_le(value, size), long_to_bytes(value, size));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_too_small() {
+        long_to_bytes_be(0x0102, 1);
+    }
+
     #[test]
     fn test_min_byte_size() {
         assert_eq!(8, min_byte_size(18446744073709551615));
         assert_eq!(1, min_byte_size(1));
         assert_eq!(1, min_byte_size(0));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_sized() {
+        let mut bytes = vec![];
+        append_sized(&mut bytes, &mut vec![7; 300]);
+        append_sized(&mut bytes, &mut vec![]);
+        // 300 needs two bytes to represent, and nothing says how wide they are.
+        assert_eq!(vec![0xac, 2, 7], bytes[..3]);
+
+        let mut i = 0;
+        assert_eq!(vec![7; 300], read_sized(&bytes, &mut i).unwrap());
+        assert_eq!(Vec::<u8>::new(), read_sized(&bytes, &mut i).unwrap());
+        assert_eq!(bytes.len(), i);
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut bytes = vec![];
+        for value in [0, 127, 128, 300, u64::MAX] {
+            write_varint(&mut bytes, value);
+        }
+        assert_eq!(vec![0, 127, 0x80, 1, 0xac, 2], bytes[..6]);
+        // u64::MAX needs all ten bytes, the last holding just the top bit.
+        assert_eq!(16, bytes.len());
+
+        let mut i = 0;
+        for value in [0, 127, 128, 300, u64::MAX] {
+            assert_eq!(Ok(value), read_varint(&bytes, &mut i));
+        }
+        assert_eq!(bytes.len(), i);
+
+        assert_eq!(Err(WzError::Truncated("a varint")), read_varint(&[0x80], &mut 0));
+        assert!(matches!(read_varint(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 2], &mut 0), Err(WzError::Malformed(_))));
+        assert!(matches!(read_varint(&[0x80; 11], &mut 0), Err(WzError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut bytes = vec![];
+        append_sized(&mut bytes, &mut vec![7; 300]);
+        assert_eq!(Err(WzError::Truncated("a sized field")), read_sized(&bytes[..bytes.len() - 1], &mut 0));
+        assert_eq!(Err(WzError::Truncated("a sized field")), read_sized(&bytes[..2], &mut 0));
+        assert_eq!(Err(WzError::Truncated("a field length")), read_sized(&bytes[..1], &mut 0));
+        assert_eq!(Err(WzError::Truncated("a field length")), read_sized(&[], &mut 0));
+        assert!(matches!(read_sized(&[0xff; 11], &mut 0), Err(WzError::Malformed(_))));
+        assert_eq!(Err(WzError::Truncated("a number")), read_long(&[0; 7], &mut 0));
+        assert_eq!(Ok(1), read_long(&[1, 0, 0, 0, 0, 0, 0, 0], &mut 0));
+    }
+
+    #[test]
+    fn test_read_write() {
+        let seq = BitSequence::from_bits(&[1, 0, 1, 1]);
+        let mut writer = Cursor::new(vec![]);
+        seq.clone().write_to(&mut writer).unwrap();
+        assert_eq!(seq.clone().to_stream(), writer.get_ref()[..]);
+
+        writer.set_position(0);
+        assert_eq!(Ok(seq), BitSequence::read_from(&mut writer));
+        // Everything left is read, so anything after the object is an error.
+        let mut reader = Cursor::new(vec![1, 0, 0, 0, 0, 0, 0, 0, 1, 7]);
+        assert!(matches!(BitSequence::read_from(&mut reader), Err(WzError::Malformed(_))));
+    }
+
+    #[derive(ByteStream, Debug, PartialEq)]
+    struct Header {
+        /// Doc comments and visibility don't get in the way.
+        pub(crate) first: BitSequence,
+        second: BitSequence,
+    }
+
+    #[test]
+    fn test_derive() {
+        let header = Header { first: BitSequence::from_bits(&[1, 0, 1]), second: BitSequence::new() };
+        let bytes = Header { first: header.first.clone(), second: header.second.clone() }.to_stream();
+
+        let mut expected = vec![];
+        append_sized(&mut expected, &mut header.first.clone().to_stream());
+        append_sized(&mut expected, &mut header.second.clone().to_stream());
+        assert_eq!(expected, bytes);
+        assert_eq!(Ok((header, bytes.len())), Header::from_stream(&bytes));
+
+        assert_eq!(Err(WzError::Truncated("a sized field")), Header::from_stream(&bytes[..bytes.len() - 1]));
+    }
+}
@@ -0,0 +1,164 @@
+// A checkpoint records how far a compression job has gotten.
+// If the job is interrupted, it can pick up from the last completed block instead of starting over.
+// Author: Will Morris
+
+/*
+  CONTENTS:
+  -- input length
+  -- input modification time
+  -- block size
+  -- whether blocks were split by content (rsyncable) or by size
+  -- number of completed blocks
+  -- number of output bytes written for those blocks.
+ */
+
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use crate::file::bytestream::{ByteStream, read_long};
+use crate::file::error::WzError;
+
+// Checkpoints are kept in a sidecar file next to the output.
+const SIDECAR_EXTENSION: &str = ".wzresume";
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Checkpoint {
+    // Used to make sure the input hasn't changed since the checkpoint was taken.
+    input_len: u64,
+    input_modified: u64,
+    block_size: u64,
+    rsyncable: u64,
+    blocks_done: u64,
+    output_len: u64,
+}
+
+impl Checkpoint {
+    // Start a fresh checkpoint for compressing the input file.
+    pub(crate) fn new(input_file: &str, block_size: usize, rsyncable: bool) -> Result<Self, String> {
+        let (input_len, input_modified) = fingerprint(input_file)?;
+        Ok(Checkpoint { input_len, input_modified, block_size: block_size as u64,
+            rsyncable: rsyncable as u64, blocks_done: 0, output_len: 0 })
+    }
+
+    // Load the checkpoint stored alongside output_file.
+    // The input must be the same file, unchanged, as when the checkpoint was taken.
+    pub(crate) fn load(input_file: &str, output_file: &str) -> Result<Self, String> {
+        let mut file = File::open(sidecar_path(output_file))
+            .map_err(|_| format!("No checkpoint found for {}", output_file))?;
+        let checkpoint = Checkpoint::read_from(&mut file)
+            .map_err(|_| format!("Corrupt checkpoint for {}", output_file))?;
+        if (checkpoint.input_len, checkpoint.input_modified) != fingerprint(input_file)? {
+            return Err(format!("{} has changed since the checkpoint was taken!", input_file))
+        }
+        Ok(checkpoint)
+    }
+
+    // Write this checkpoint to the sidecar of output_file.
+    // Written to a temporary file and renamed into place, so a crash never leaves half a checkpoint.
+    pub(crate) fn save(&self, output_file: &str) -> Result<(), String> {
+        let path = sidecar_path(output_file);
+        let temp_path = format!("{}.tmp", path);
+        File::create(&temp_path)
+            .and_then(|mut file| self.clone().write_to(&mut file))
+            .and_then(|_| fs::rename(&temp_path, &path))
+            .map_err(|e| format!("Could not save checkpoint: {}", e))
+    }
+
+    // Once the job is complete, the checkpoint is no longer needed.
+    pub(crate) fn remove(output_file: &str) {
+        let path = sidecar_path(output_file);
+        if Path::new(&path).exists() {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    // Note that another block, of block_len output bytes, has been written.
+    pub(crate) fn record_block(&mut self, block_len: usize) {
+        self.blocks_done += 1;
+        self.output_len += block_len as u64;
+    }
+
+    pub(crate) fn block_size(&self) -> usize {
+        self.block_size as usize
+    }
+
+    pub(crate) fn rsyncable(&self) -> bool {
+        self.rsyncable != 0
+    }
+
+    pub(crate) fn blocks_done(&self) -> usize {
+        self.blocks_done as usize
+    }
+
+    pub(crate) fn output_len(&self) -> u64 {
+        self.output_len
+    }
+}
+
+// Every field of a checkpoint is a long.
+impl ByteStream for Checkpoint {
+    type Data = Checkpoint;
+
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError> {
+        let mut i = 0;
+        let checkpoint = Checkpoint {
+            input_len: read_long(bytes, &mut i)?,
+            input_modified: read_long(bytes, &mut i)?,
+            block_size: read_long(bytes, &mut i)?,
+            rsyncable: read_long(bytes, &mut i)?,
+            blocks_done: read_long(bytes, &mut i)?,
+            output_len: read_long(bytes, &mut i)?,
+        };
+        Ok((checkpoint, i))
+    }
+
+    fn to_stream(self) -> Vec<u8> {
+        let mut retval = vec![];
+        for field in [self.input_len, self.input_modified, self.block_size,
+            self.rsyncable, self.blocks_done, self.output_len] {
+            retval.extend_from_slice(&field.to_le_bytes());
+        }
+        retval
+    }
+}
+
+fn sidecar_path(output_file: &str) -> String {
+    format!("{}{}", output_file, SIDECAR_EXTENSION)
+}
+
+// Get the length and modification time of a file.
+// Cheap, and good enough to notice that an input has been replaced or edited.
+pub(crate) fn fingerprint(input_file: &str) -> Result<(u64, u64), String> {
+    let metadata = fs::metadata(input_file)
+        .map_err(|_| format!("File not found: {}", input_file))?;
+    let modified = metadata.modified().ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or(0);
+    Ok((metadata.len(), modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::bytestream::{ByteStream, LONG_LEN};
+    use crate::file::checkpoint::Checkpoint;
+
+    #[test]
+    fn test_to_from() {
+        let mut expected = Checkpoint { input_len: 1 << 40, input_modified: 12345,
+            block_size: 1024, rsyncable: 1, blocks_done: 0, output_len: 0 };
+        expected.record_block(700);
+        expected.record_block(800);
+
+        let to = expected.clone().to_stream();
+        // Six full width fields.
+        assert_eq!(LONG_LEN * 6, to.len());
+        let from = Checkpoint::from_whole_stream(&to).unwrap();
+
+        assert_eq!(expected, from);
+        assert_eq!(2, from.blocks_done());
+        assert!(from.rsyncable());
+        assert_eq!(1500, from.output_len());
+    }
+}
@@ -0,0 +1,107 @@
+// Checksums, to check that data came out as it went in.
+// Each is stored with its id, so the reader knows which one to check with.
+// Every checksum is found through ALL, so adding one means implementing Checksum and listing it there.
+// Author: Will Morris
+
+use crate::file::crc32::crc32;
+use crate::file::sha256::sha256;
+use crate::file::xxhash::xxhash64;
+
+pub trait Checksum: Sync {
+    // Stored with each digest, to find the checksum again.
+    fn id(&self) -> u8;
+
+    // Its name on the command line.
+    fn name(&self) -> &'static str;
+
+    fn digest(&self, bytes: &[u8]) -> Vec<u8>;
+}
+
+// CRC-32, as gzip and zip use. Cheap, and needs no more than a small table.
+pub struct Crc32;
+
+impl Checksum for Crc32 {
+    fn id(&self) -> u8 {
+        0
+    }
+
+    fn name(&self) -> &'static str {
+        "crc32"
+    }
+
+    fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        crc32(bytes).to_le_bytes().to_vec()
+    }
+}
+
+// XXH64, which is faster still on long inputs.
+pub struct XxHash64;
+
+impl Checksum for XxHash64 {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn name(&self) -> &'static str {
+        "xxhash"
+    }
+
+    fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        xxhash64(bytes, 0).to_le_bytes().to_vec()
+    }
+}
+
+// SHA-256, for when data mustn't be tampered with, not just damaged.
+pub struct Sha256;
+
+impl Checksum for Sha256 {
+    fn id(&self) -> u8 {
+        2
+    }
+
+    fn name(&self) -> &'static str {
+        "sha256"
+    }
+
+    fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        sha256(bytes).to_vec()
+    }
+}
+
+// Every checksum there is.
+const ALL: [&dyn Checksum; 3] = [&Crc32, &XxHash64, &Sha256];
+
+// Find a checksum from its name on the command line.
+pub fn parse_checksum(name: &str) -> Option<&'static dyn Checksum> {
+    ALL.into_iter().find(|checksum| checksum.name() == name)
+}
+
+// Find the checksum a digest was stored with.
+pub fn checksum_from_id(id: u8) -> Option<&'static dyn Checksum> {
+    ALL.into_iter().find(|checksum| checksum.id() == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::checksum::{ALL, checksum_from_id, parse_checksum};
+
+    #[test]
+    fn test_find() {
+        for checksum in ALL {
+            assert_eq!(Some(checksum.id()), parse_checksum(checksum.name()).map(|found| found.id()));
+            assert_eq!(Some(checksum.name()), checksum_from_id(checksum.id()).map(|found| found.name()));
+        }
+        assert!(parse_checksum("md5").is_none());
+        assert!(checksum_from_id(ALL.len() as u8).is_none());
+    }
+
+    #[test]
+    fn test_digest() {
+        // Each notices a single changed byte, and has its own length.
+        let lens: Vec<usize> = ALL.iter().map(|checksum| {
+            assert_ne!(checksum.digest(b"hello world"), checksum.digest(b"hello worle"));
+            checksum.digest(b"hello world").len()
+        }).collect();
+        assert_eq!(vec![4, 8, 32], lens);
+    }
+}
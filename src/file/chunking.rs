@@ -0,0 +1,125 @@
+// Content-defined chunking: splitting a stream wherever its contents say to,
+// Rather than every N bytes.
+// Because boundaries depend only on the nearby bytes, an edit early in a file
+// doesn't shift every boundary after it. Only the chunks around the edit change.
+// Author: Will Morris
+
+// How many bytes the rolling hash looks at.
+const WINDOW: usize = 64;
+
+// A boundary is declared wherever the low bits of the hash are all ones.
+// With n bits in the mask, that happens about once every 2^n bytes.
+pub(crate) const RSYNCABLE_AVERAGE: usize = 1 << 19;
+
+// A buzhash over the last WINDOW bytes.
+// Each byte is mapped to a random-looking word, and the words are combined with rotations,
+// So that the oldest byte can be removed as cheaply as the newest is added.
+struct RollingHash {
+    hash: u32,
+}
+
+impl RollingHash {
+    fn new() -> Self {
+        RollingHash { hash: 0 }
+    }
+
+    // Slide the window forward by one byte.
+    // If the window is full, outgoing is the byte falling out of it.
+    fn roll(&mut self, incoming: u8, outgoing: Option<u8>) {
+        self.hash = self.hash.rotate_left(1) ^ BUZ_TABLE[incoming as usize];
+        if let Some(outgoing) = outgoing {
+            // The outgoing byte has been rotated once for every byte in the window.
+            self.hash ^= BUZ_TABLE[outgoing as usize].rotate_left(WINDOW as u32);
+        }
+    }
+}
+
+// Split bytes into chunks averaging about average bytes, each no larger than max_size.
+// average must be a power of two.
+pub(crate) fn content_defined_chunks(bytes: &[u8], average: usize, max_size: usize) -> Vec<&[u8]> {
+    assert!(average.is_power_of_two() && max_size > 0);
+    let mask = (average - 1) as u32;
+    // Tiny chunks are never worth their overhead.
+    let min_size = (average / 4).min(max_size);
+
+    let mut chunks = vec![];
+    let mut start = 0;
+    let mut hash = RollingHash::new();
+
+    for i in 0..bytes.len() {
+        let outgoing = if i >= WINDOW { Some(bytes[i - WINDOW]) } else { None };
+        hash.roll(bytes[i], outgoing);
+
+        let len = i + 1 - start;
+        if (len >= min_size && hash.hash & mask == mask) || len == max_size {
+            chunks.push(&bytes[start..=i]);
+            start = i + 1;
+        }
+    }
+
+    if start < bytes.len() {
+        chunks.push(&bytes[start..]);
+    }
+    chunks
+}
+
+// The buzhash table must be identical everywhere, so it's generated from a fixed seed.
+// Uses the splitmix64 generator, which is plenty random for hashing.
+const BUZ_TABLE: [u32; 256] = gen_buz_table();
+
+const fn gen_buz_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut state: u64 = 0x5745_5a49_505f_5a57;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        table[i] = (z >> 32) as u32;
+        i += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::chunking::content_defined_chunks;
+
+    // Deterministic, incompressible-looking test data.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn test_covers_input() {
+        let bytes = noise(100_000, 1);
+        let chunks = content_defined_chunks(&bytes, 1 << 10, 4096);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 4096));
+        assert_eq!(bytes, chunks.concat());
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(content_defined_chunks(&[], 1 << 10, 4096).is_empty());
+    }
+
+    // Inserting a byte near the start should leave most later chunks untouched.
+    #[test]
+    fn test_resynchronizes() {
+        let original = noise(200_000, 2);
+        let mut edited = original.clone();
+        edited.insert(100, 42);
+
+        let before = content_defined_chunks(&original, 1 << 10, 1 << 14);
+        let after = content_defined_chunks(&edited, 1 << 10, 1 << 14);
+        let shared = after.iter().filter(|chunk| before.contains(chunk)).count();
+        assert!(shared + 3 >= after.len());
+    }
+}
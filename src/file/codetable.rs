@@ -0,0 +1,142 @@
+// Tables of codes, written as JSON so that other tools can read them.
+// Author: Will Morris
+
+/*
+  CONTENTS:
+  -- an array, with an object for each byte that has a code:
+     -- "byte": the byte's value
+     -- "length": how many bits its code takes
+     -- "bits": the bits of its code, in the order they're written.
+
+  Tables are read back in the same form. Lengths may be left out, since the bits say as much.
+  A block printed by wz inspect, with its codes in a "codes" field, may be read too.
+ */
+
+use std::fs;
+use crate::encoding::bitsequence::{BitSequence, Encoding};
+use crate::file::json::{Json, parse_json};
+
+// Codes are stored with a byte for their length.
+pub(crate) const MAX_TABLE_CODE_LEN: usize = u8::MAX as usize;
+
+// Write each byte's code as JSON, one byte per line.
+pub(crate) fn codes_to_json(encoding: &Encoding) -> String {
+    let entries: Vec<String> = encoding.iter().enumerate()
+        .filter_map(|(byte, code)| code.as_ref().map(|code| (byte, code)))
+        .map(|(byte, code)| format!("{{\"byte\": {}, \"length\": {}, \"bits\": \"{:?}\"}}", byte, code.len(), code))
+        .collect();
+    match entries.is_empty() {
+        true => { String::from("[]") }
+        false => { format!("[\n  {}\n]", entries.join(",\n  ")) }
+    }
+}
+
+// Read each byte's code back from JSON.
+// The codes must be prefix free, or they couldn't be decoded.
+pub(crate) fn codes_from_json(text: &str) -> Result<Encoding, String> {
+    let json = parse_json(text).map_err(|message| format!("Code table isn't JSON: {}", message))?;
+
+    let entries = match json {
+        Json::Array(entries) => { entries }
+        Json::Object(fields) => {
+            match fields.into_iter().find(|(key, _)| key == "codes") {
+                Some((_, Json::Array(entries))) => { entries }
+                _ => { return Err(String::from("Code table has no codes!")) }
+            }
+        }
+        _ => { return Err(String::from("Code table must be an array of codes!")) }
+    };
+
+    let mut encoding: Encoding = std::array::from_fn(|_| None);
+    for entry in entries {
+        let Json::Object(fields) = entry else {
+            return Err(String::from("Each code must be an object!"))
+        };
+        let field = |name: &str| fields.iter().find(|(key, _)| key == name).map(|(_, value)| value);
+
+        let byte = match field("byte") {
+            Some(Json::Number(byte)) if byte.fract() == 0.0 && (0.0..=255.0).contains(byte) => { *byte as usize }
+            _ => { return Err(String::from("Each code needs a byte from 0 to 255!")) }
+        };
+        let bits = match field("bits") {
+            Some(Json::String(bits)) if !bits.is_empty() && bits.chars().all(|bit| bit == '0' || bit == '1') => { bits }
+            _ => { return Err(format!("The code for byte {} must be a string of 0s and 1s!", byte)) }
+        };
+        if bits.len() > MAX_TABLE_CODE_LEN {
+            return Err(format!("Codes may be at most {} bits long!", MAX_TABLE_CODE_LEN))
+        }
+        match field("length") {
+            None => {}
+            Some(Json::Number(length)) if *length == bits.len() as f64 => {}
+            Some(_) => { return Err(format!("The code for byte {} isn't as long as its length says!", byte)) }
+        }
+        if encoding[byte].is_some() {
+            return Err(format!("Byte {} has more than one code!", byte))
+        }
+        let bits: Vec<u8> = bits.chars().map(|bit| (bit == '1') as u8).collect();
+        encoding[byte] = Some(BitSequence::from_bits(&bits));
+    }
+
+    // Sorted, any code which prefixes another is followed by one it prefixes.
+    let mut codes: Vec<String> = encoding.iter().flatten().map(|code| format!("{:?}", code)).collect();
+    codes.sort();
+    if let Some(pair) = codes.windows(2).find(|pair| pair[1].starts_with(&pair[0])) {
+        return Err(format!("Code {} is a prefix of code {}, so they can't be told apart!", pair[0], pair[1]))
+    }
+    Ok(encoding)
+}
+
+// Load a code table from a JSON file.
+pub(crate) fn load_codes(path: &str) -> Result<Encoding, String> {
+    let text = fs::read_to_string(path).map_err(|_| format!("Could not read code table: {}", path))?;
+    codes_from_json(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::bitsequence::{BitSequence, Encoding};
+    use crate::file::codetable::{codes_from_json, codes_to_json};
+
+    #[test]
+    fn test_to_json() {
+        let mut encoding: Encoding = std::array::from_fn(|_| None);
+        assert_eq!("[]", codes_to_json(&encoding));
+
+        encoding[b'a' as usize] = Some(BitSequence::from_bits(&[0]));
+        encoding[b'b' as usize] = Some(BitSequence::from_bits(&[1, 0]));
+        let expected = "[\n  {\"byte\": 97, \"length\": 1, \"bits\": \"0\"},\n  {\"byte\": 98, \"length\": 2, \"bits\": \"10\"}\n]";
+        assert_eq!(expected, codes_to_json(&encoding));
+    }
+
+    #[test]
+    fn test_from_json() {
+        let mut encoding: Encoding = std::array::from_fn(|_| None);
+        encoding[0] = Some(BitSequence::from_bits(&[0]));
+        encoding[b'a' as usize] = Some(BitSequence::from_bits(&[1, 0]));
+        encoding[255] = Some(BitSequence::from_bits(&[1, 1]));
+        assert_eq!(encoding, codes_from_json(&codes_to_json(&encoding)).unwrap());
+
+        // Lengths are optional, and a block from wz inspect will do.
+        let json = r#"{"block": 0, "kind": "Huffman", "codes": [{"byte": 0, "bits": "0"}, {"byte": 97, "bits": "10"},
+            {"byte": 255, "length": 2, "bits": "11"}], "extra": [null, true, false, -1.5e3, "\"quoted\""]}"#;
+        assert_eq!(encoding, codes_from_json(json).unwrap());
+        assert!(codes_from_json("[]").unwrap().iter().all(|code| code.is_none()));
+    }
+
+    #[test]
+    fn test_bad_json() {
+        // Not prefix free.
+        assert!(codes_from_json(r#"[{"byte": 1, "bits": "0"}, {"byte": 2, "bits": "01"}]"#).is_err());
+        assert!(codes_from_json(r#"[{"byte": 1, "bits": "0"}, {"byte": 2, "bits": "0"}]"#).is_err());
+        // Not a byte, not bits, or the wrong length.
+        assert!(codes_from_json(r#"[{"byte": 256, "bits": "0"}]"#).is_err());
+        assert!(codes_from_json(r#"[{"byte": 1, "bits": "012"}]"#).is_err());
+        assert!(codes_from_json(r#"[{"byte": 1, "bits": ""}]"#).is_err());
+        assert!(codes_from_json(r#"[{"byte": 1, "length": 2, "bits": "0"}]"#).is_err());
+        assert!(codes_from_json(r#"[{"byte": 1, "bits": "0"}, {"byte": 1, "bits": "1"}]"#).is_err());
+        // Not JSON.
+        assert!(codes_from_json(r#"[{"byte": 1, "bits": "0"}"#).is_err());
+        assert!(codes_from_json(r#"[] []"#).is_err());
+        assert!(codes_from_json("").is_err());
+    }
+}
@@ -0,0 +1,45 @@
+// CRC-32, as used by gzip and zip to check that data came out as it went in.
+// Author: Will Morris
+
+// The reversed form of the standard polynomial.
+const POLYNOMIAL: u32 = 0xedb88320;
+
+// The CRC of every byte, so bytes can be folded in one at a time.
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => { (crc >> 1) ^ POLYNOMIAL }
+                _ => { crc >> 1 }
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::crc32::crc32;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crc32(b""));
+        // The standard check value.
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+    }
+}
@@ -0,0 +1,48 @@
+// What goes wrong when reading bytes that aren't what they should be.
+// Author: Will Morris
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+/// Why a stream of bytes couldn't be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WzError {
+    /// The bytes ran out partway through the named field.
+    Truncated(&'static str),
+    /// The bytes were all there, but didn't make sense.
+    Malformed(String),
+    /// The bytes couldn't be read at all.
+    Io(String),
+    /// The named field didn't make sense. Unlike Malformed, saying so needs no allocation.
+    Invalid(&'static str),
+    /// There was no room left to write the named field.
+    Full(&'static str),
+}
+
+impl Display for WzError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WzError::Truncated(field) => { write!(f, "Stream ended partway through {}!", field) }
+            WzError::Malformed(message) => { write!(f, "{}", message) }
+            WzError::Io(message) => { write!(f, "Couldn't read stream: {}", message) }
+            WzError::Invalid(field) => { write!(f, "Invalid {}!", field) }
+            WzError::Full(field) => { write!(f, "No room left for {}!", field) }
+        }
+    }
+}
+
+impl Error for WzError {}
+
+impl From<io::Error> for WzError {
+    fn from(error: io::Error) -> Self {
+        WzError::Io(error.to_string())
+    }
+}
+
+// Most of the compressor reports errors as strings.
+impl From<WzError> for String {
+    fn from(error: WzError) -> Self {
+        error.to_string()
+    }
+}
@@ -0,0 +1,177 @@
+// The layout of a wzfile on disk: how wide each field is, and how to read and write it.
+// Wzfile, Freqmap and BitSequence say what they hold. Where each piece goes is decided here, and only here.
+// It's part of the library, so other crates reading wz files lay them out the same way.
+// Author: Will Morris
+
+/*
+  CONTENTS of a wzfile:
+  -- length of the frequency map, MAP_SIZE_FIELD_LEN bytes
+  -- frequency map:
+     -- width of each count, ENTRY_WIDTH_LEN byte
+     -- a byte, and its count, for each byte counted.
+  -- length of the sequence, as a varint
+  -- sequence:
+     -- number of bits, BIT_COUNT_LEN bytes
+     -- the bits, eight to a byte.
+
+  Symbol frequency maps give the width of each symbol before the width of each count, then symbol-count pairs.
+  Every number is stored in FORMAT_ENDIAN order.
+ */
+
+use crate::file::bytestream::{LONG_LEN, long_to_bytes, read_bytes, read_long, read_varint, slice_to_long, write_varint};
+use crate::file::error::WzError;
+
+// How many bytes will it take to represent the size field for a map?
+// MAX_MAP_SIZE can be represented as u16 (with lots of spare space!).
+pub const MAP_SIZE_FIELD_LEN: usize = 2;
+// What's the maximum number of bytes needed to represent the contents of a freqmap?
+// 9 bytes per 256 entries, plus one byte for the per-entry width field.
+pub const MAX_MAP_SIZE: usize = (LONG_LEN + 1) * 256 + ENTRY_WIDTH_LEN;
+// Keys and counts in a map are trimmed to a width of their own, stored in this many bytes.
+pub const ENTRY_WIDTH_LEN: usize = 1;
+// Sequences count their bits in full, since a file can be any length.
+pub const BIT_COUNT_LEN: usize = LONG_LEN;
+
+// Read the length of a frequency map. Since there are only 256 bytes, maps have a tight upper bound on their size.
+pub fn read_map_len(bytes: &[u8], i: &mut usize) -> Result<usize, WzError> {
+    let len = slice_to_long(read_bytes(bytes, i, MAP_SIZE_FIELD_LEN, "a frequency map length")?) as usize;
+    match len <= MAX_MAP_SIZE {
+        true => { Ok(len) }
+        false => { Err(WzError::Malformed(format!("A frequency map can't be {} bytes long!", len))) }
+    }
+}
+
+// NOTE: len must be no more than MAX_MAP_SIZE, which any real map's is.
+pub fn write_map_len(retval: &mut Vec<u8>, len: usize) {
+    retval.append(&mut long_to_bytes(len as u64, MAP_SIZE_FIELD_LEN as u8));
+}
+
+// Read the width of each key or count in a map. Every width fits in a u64, and none is zero.
+pub fn read_entry_width(bytes: &[u8], i: &mut usize) -> Result<usize, WzError> {
+    let width = read_bytes(bytes, i, ENTRY_WIDTH_LEN, "a frequency map")?[0] as usize;
+    match (1..=LONG_LEN).contains(&width) {
+        true => { Ok(width) }
+        false => { Err(WzError::Malformed(format!("Frequency map entries can't be {} bytes wide!", width))) }
+    }
+}
+
+pub fn write_entry_width(retval: &mut Vec<u8>, width: u8) {
+    retval.push(width);
+}
+
+// Read a number width bytes wide from a map, i.e. a count or a symbol.
+pub fn read_entry(bytes: &[u8], i: &mut usize, width: usize) -> Result<u64, WzError> {
+    read_bytes(bytes, i, width, "a frequency map").map(slice_to_long)
+}
+
+// NOTE: width must be enough to hold value, as trimmed widths always are.
+pub fn write_entry(retval: &mut Vec<u8>, value: u64, width: u8) {
+    retval.append(&mut long_to_bytes(value, width));
+}
+
+// Read how many bytes the sequence after it takes.
+// There can be arbitrarily many characters in a file. A varint covers any length, while keeping short ones short.
+pub fn read_sequence_len(bytes: &[u8], i: &mut usize) -> Result<usize, WzError> {
+    usize::try_from(read_varint(bytes, i)?).map_err(|_| WzError::Truncated("a sequence"))
+}
+
+pub fn write_sequence_len(retval: &mut Vec<u8>, len: usize) {
+    write_varint(retval, len as u64);
+}
+
+// Read how many bits a sequence holds. Returns the bits, and how many bytes they're packed into.
+pub fn read_bit_count(bytes: &[u8], i: &mut usize) -> Result<(u64, usize), WzError> {
+    let num_bits = read_long(bytes, i)?;
+    let len = usize::try_from(packed_len(num_bits)).map_err(|_| WzError::Truncated("a bit sequence"))?;
+    Ok((num_bits, len))
+}
+
+pub fn write_bit_count(retval: &mut Vec<u8>, num_bits: u64) {
+    retval.append(&mut long_to_bytes(num_bits, BIT_COUNT_LEN as u8));
+}
+
+// How many bytes num_bits bits are packed into, eight to a byte.
+pub fn packed_len(num_bits: u64) -> u64 {
+    num_bits.div_ceil(8)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::error::WzError;
+    use crate::file::format::{BIT_COUNT_LEN, MAP_SIZE_FIELD_LEN, MAX_MAP_SIZE, packed_len, read_bit_count, read_entry,
+                              read_entry_width, read_map_len, read_sequence_len, write_bit_count, write_entry,
+                              write_entry_width, write_map_len, write_sequence_len};
+
+    #[test]
+    fn test_map_len() {
+        let mut bytes = vec![];
+        write_map_len(&mut bytes, MAX_MAP_SIZE);
+        write_map_len(&mut bytes, 0);
+        assert_eq!(MAP_SIZE_FIELD_LEN * 2, bytes.len());
+        // Low byte first.
+        assert_eq!(vec![(MAX_MAP_SIZE & 0xff) as u8, (MAX_MAP_SIZE >> 8) as u8, 0, 0], bytes);
+
+        let mut i = 0;
+        assert_eq!(Ok(MAX_MAP_SIZE), read_map_len(&bytes, &mut i));
+        assert_eq!(Ok(0), read_map_len(&bytes, &mut i));
+        assert_eq!(bytes.len(), i);
+        assert_eq!(Err(WzError::Truncated("a frequency map length")), read_map_len(&bytes, &mut i));
+
+        let too_long = (MAX_MAP_SIZE as u16 + 1).to_le_bytes();
+        assert!(matches!(read_map_len(&too_long, &mut 0), Err(WzError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_entries() {
+        let mut bytes = vec![];
+        write_entry_width(&mut bytes, 3);
+        write_entry(&mut bytes, 0x010203, 3);
+        write_entry(&mut bytes, 7, 3);
+        assert_eq!(vec![3, 3, 2, 1, 7, 0, 0], bytes);
+
+        let mut i = 0;
+        let width = read_entry_width(&bytes, &mut i).unwrap();
+        assert_eq!(3, width);
+        assert_eq!(Ok(0x010203), read_entry(&bytes, &mut i, width));
+        assert_eq!(Ok(7), read_entry(&bytes, &mut i, width));
+        assert_eq!(Err(WzError::Truncated("a frequency map")), read_entry(&bytes, &mut i, width));
+
+        // Widths run from one byte to a whole u64.
+        for width in [0, 9, 255] {
+            assert!(matches!(read_entry_width(&[width], &mut 0), Err(WzError::Malformed(_))));
+        }
+        assert_eq!(Ok(8), read_entry_width(&[8], &mut 0));
+        assert_eq!(Err(WzError::Truncated("a frequency map")), read_entry_width(&[], &mut 0));
+    }
+
+    #[test]
+    fn test_sequence_len() {
+        for len in [0, 1, 127, 128, 1 << 20] {
+            let mut bytes = vec![];
+            write_sequence_len(&mut bytes, len);
+            let mut i = 0;
+            assert_eq!(Ok(len), read_sequence_len(&bytes, &mut i));
+            assert_eq!(bytes.len(), i);
+        }
+        // Short lengths take a single byte.
+        let mut bytes = vec![];
+        write_sequence_len(&mut bytes, 127);
+        assert_eq!(vec![127], bytes);
+    }
+
+    #[test]
+    fn test_bit_count() {
+        let mut bytes = vec![];
+        write_bit_count(&mut bytes, 17);
+        assert_eq!(BIT_COUNT_LEN, bytes.len());
+        assert_eq!(vec![17, 0, 0, 0, 0, 0, 0, 0], bytes);
+        assert_eq!(Ok((17, 3)), read_bit_count(&bytes, &mut 0));
+        assert!(read_bit_count(&bytes[..BIT_COUNT_LEN - 1], &mut 0).is_err());
+
+        assert_eq!(0, packed_len(0));
+        assert_eq!(1, packed_len(1));
+        assert_eq!(1, packed_len(8));
+        assert_eq!(2, packed_len(9));
+        assert_eq!(u64::MAX / 8 + 1, packed_len(u64::MAX));
+    }
+}
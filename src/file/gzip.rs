@@ -0,0 +1,152 @@
+// The gzip file format (RFC 1952): a deflate stream, with a header and a checksum.
+// Author: Will Morris
+
+/*
+  CONTENTS:
+  -- magic number, 1f 8b
+  -- compression method, 8 for deflate
+  -- flags, none set
+  -- modification time, zero for unknown
+  -- extra flags, then the operating system, 255 for unknown
+  -- deflate stream
+  -- CRC-32 of the uncompressed data
+  -- uncompressed length, modulo 2^32.
+ */
+
+use crate::codec::deflate::deflate;
+use crate::codec::inflate::inflate;
+use crate::file::crc32::crc32;
+use crate::file::error::WzError;
+
+pub(crate) const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+pub(crate) const DEFLATE_METHOD: u8 = 8;
+const UNKNOWN_OS: u8 = 255;
+const HEADER_LEN: usize = 10;
+const TRAILER_LEN: usize = 8;
+
+// Flags for optional header fields. Other writers may set these, even though we never do.
+const HEADER_CRC: u8 = 1 << 1;
+const EXTRA: u8 = 1 << 2;
+const NAME: u8 = 1 << 3;
+const COMMENT: u8 = 1 << 4;
+
+// Does this look like a gzip file?
+// wz files start with the width of a block length, which is never 0x1f, so there's no mixing them up.
+pub(crate) fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&GZIP_MAGIC)
+}
+
+// Compress bytes into a complete gzip file.
+pub(crate) fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut retval = GZIP_MAGIC.to_vec();
+    retval.extend_from_slice(&[DEFLATE_METHOD, 0, 0, 0, 0, 0, 0, UNKNOWN_OS]);
+    // Writing to a vector never fails.
+    let mut retval = deflate(bytes, retval).unwrap();
+    retval.extend_from_slice(&crc32(bytes).to_le_bytes());
+    retval.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    retval
+}
+
+// Decompress a gzip file, so long as it decompresses to no more than limit bytes.
+// A file may hold several gzip members back to back, as cat makes. They're decompressed in order,
+// each only given the room the ones before it left.
+pub(crate) fn gunzip(bytes: &[u8], limit: usize) -> Result<Vec<u8>, WzError> {
+    let mut retval = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        i += skip_header(&bytes[i..])?;
+        let (mut member, used) = inflate(&bytes[i..], limit - retval.len())?;
+        i += used;
+
+        let trailer = bytes.get(i..i + TRAILER_LEN).ok_or(WzError::Truncated("a gzip trailer"))?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let len = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+        if crc != crc32(&member) || len != member.len() as u32 {
+            return Err(WzError::Malformed(String::from("gzip file failed its checksum!")))
+        }
+        i += TRAILER_LEN;
+        retval.append(&mut member);
+    }
+    Ok(retval)
+}
+
+// Returns how long a member's header is, after checking that it's one we can read.
+fn skip_header(bytes: &[u8]) -> Result<usize, WzError> {
+    if !is_gzip(bytes) || bytes.len() < HEADER_LEN {
+        return Err(WzError::Malformed(String::from("Not a gzip file!")))
+    }
+    if bytes[2] != DEFLATE_METHOD {
+        return Err(WzError::Malformed(String::from("gzip file uses an unknown compression method!")))
+    }
+
+    let flags = bytes[3];
+    let mut i = HEADER_LEN;
+    if flags & EXTRA != 0 {
+        let len = bytes.get(i..i + 2).ok_or(WzError::Truncated("a gzip header"))?;
+        i += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    // The name and comment are zero terminated.
+    for field in [NAME, COMMENT] {
+        if flags & field != 0 {
+            let len = bytes.get(i..).and_then(|rest| rest.iter().position(|byte| *byte == 0))
+                .ok_or(WzError::Truncated("a gzip header"))?;
+            i += len + 1;
+        }
+    }
+    if flags & HEADER_CRC != 0 {
+        i += 2;
+    }
+    match i <= bytes.len() {
+        true => { Ok(i) }
+        false => { Err(WzError::Truncated("a gzip header")) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::error::WzError;
+    use crate::file::gzip::{GZIP_MAGIC, gunzip, gzip, is_gzip};
+
+    #[test]
+    fn test_header() {
+        let bytes = gzip(b"");
+        assert_eq!(GZIP_MAGIC, bytes[..2]);
+        // Header, an empty fixed block, the CRC of nothing, and a length of zero.
+        assert_eq!(10 + 2 + 8, bytes.len());
+        assert_eq!([0; 8], bytes[12..]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = b"one fish two fish red fish blue fish".repeat(20);
+        let compressed = gzip(&bytes);
+        assert!(is_gzip(&compressed));
+        assert_eq!(bytes, gunzip(&compressed, usize::MAX).unwrap());
+
+        // Concatenated members decompress to concatenated contents.
+        let mut twice = compressed.clone();
+        twice.extend_from_slice(&gzip(b"!"));
+        assert_eq!([&bytes[..], b"!"].concat(), gunzip(&twice, usize::MAX).unwrap());
+
+        // The limit covers every member together.
+        assert_eq!(bytes.len() + 1, gunzip(&twice, bytes.len() + 1).unwrap().len());
+        assert_eq!(Err(WzError::Full("decompressed output")), gunzip(&twice, bytes.len()));
+    }
+
+    // A file made by gzip itself, with the original name in its header.
+    #[test]
+    fn test_name() {
+        let bytes = [0x1f, 0x8b, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, b'h', b'i', 0x00,
+            0xcb, 0xc8, 0xe4, 0x02, 0x00, 0x7a, 0x7a, 0x6f, 0xed, 0x03, 0x00, 0x00, 0x00];
+        assert_eq!(b"hi\n".to_vec(), gunzip(&bytes, usize::MAX).unwrap());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut compressed = gzip(b"some bytes");
+        let last = compressed.len() - 5;
+        compressed[last] ^= 1;
+        assert!(gunzip(&compressed, usize::MAX).is_err());
+        assert!(gunzip(&compressed[..12], usize::MAX).is_err());
+    }
+}
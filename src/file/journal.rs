@@ -0,0 +1,249 @@
+// A journal records that an archive is about to be replaced by an updated copy, staged beside it.
+// It's written once the copy is whole on disk, and removed once the copy has taken the archive's place.
+// A crash in between leaves the journal behind, so the next update can finish the job, or see that there's none to finish.
+// Either way, the archive is only ever the old one or the new one.
+// Author: Will Morris
+
+/*
+  CONTENTS:
+  -- length of the archive before the update, or u64::MAX if there wasn't one
+  -- hash of the archive before the update
+  -- length of the updated archive
+  -- hash of the updated archive
+  -- hash of everything above, so a journal cut short or garbled is never taken for a whole one.
+ */
+
+use std::fs;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::Path;
+use crate::file::bytestream::{ByteStream, read_long};
+use crate::file::error::WzError;
+use crate::file::xxhash::xxhash64;
+use crate::system::endpoint::sync_directory;
+
+// Journals are kept in a sidecar file next to the archive.
+const SIDECAR_EXTENSION: &str = ".wzjournal";
+
+// Stands in for the length of an archive that didn't exist.
+const NO_ARCHIVE: u64 = u64::MAX;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Journal {
+    old_len: u64,
+    old_hash: u64,
+    new_len: u64,
+    new_hash: u64,
+}
+
+// What was found of an interrupted update.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Recovery {
+    // There was none.
+    Clean,
+    // It had been committed, and has now taken effect.
+    Finished,
+    // It hadn't been committed, so the archive is as it was before.
+    Undone,
+}
+
+impl Journal {
+    // Replacing old, if there was an archive, with new.
+    pub(crate) fn new(old: Option<&[u8]>, new: &[u8]) -> Self {
+        let (old_len, old_hash) = old.map_or((NO_ARCHIVE, 0), fingerprint);
+        let (new_len, new_hash) = fingerprint(new);
+        Journal { old_len, old_hash, new_len, new_hash }
+    }
+
+    // Record that archive is to be replaced. The staged copy must already be on disk.
+    // Once this returns, an interrupted update is finished by recover, rather than undone.
+    // Written to a temporary file and renamed into place, so a crash never leaves half a journal.
+    pub(crate) fn commit(&self, archive: &str, durable: bool) -> Result<(), String> {
+        let path = sidecar_path(archive);
+        let temp_path = format!("{}.tmp", path);
+        File::create(&temp_path)
+            .and_then(|mut file| self.clone().write_to(&mut file).and_then(|_| file.sync_data()))
+            .and_then(|_| fs::rename(&temp_path, &path))
+            .and_then(|_| if durable { sync_directory(&path) } else { Ok(()) })
+            .map_err(|e| format!("Could not write journal for {}: {}", archive, e))
+    }
+
+    // Once the staged copy has taken the archive's place, the journal is no longer needed.
+    // Another update may have found it first, and finished the job itself.
+    pub(crate) fn remove(archive: &str) -> Result<(), String> {
+        match fs::remove_file(sidecar_path(archive)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => { Err(format!("Could not remove journal for {}: {}", archive, e)) }
+            _ => { Ok(()) }
+        }
+    }
+
+    // Whether archive has an update committed and not yet finished.
+    pub(crate) fn pending(archive: &str) -> bool {
+        Path::new(&sidecar_path(archive)).exists()
+    }
+
+    fn is_new(&self, bytes: &[u8]) -> bool {
+        fingerprint(bytes) == (self.new_len, self.new_hash)
+    }
+
+    fn is_old(&self, bytes: Option<&[u8]>) -> bool {
+        bytes.map_or((NO_ARCHIVE, 0), fingerprint) == (self.old_len, self.old_hash)
+    }
+}
+
+// Finish or undo any update to archive that was interrupted, its copy staged at staged.
+// Whoever calls this must be the only one updating archive, or one might undo what the other is doing.
+pub(crate) fn recover(archive: &str, staged: &str) -> Result<Recovery, String> {
+    let path = sidecar_path(archive);
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => { bytes }
+        Err(e) if e.kind() == ErrorKind::NotFound => { return Ok(Recovery::Clean) }
+        Err(e) => { return Err(format!("Could not read journal for {}: {}", archive, e)) }
+    };
+    // A journal that isn't whole was never committed, so whatever was staged was never going to be used.
+    let Ok(journal) = Journal::from_whole_stream(&bytes) else {
+        Journal::remove(archive)?;
+        return Ok(Recovery::Undone)
+    };
+
+    let read = |path: &str| match fs::read(path) {
+        Ok(bytes) => { Ok(Some(bytes)) }
+        Err(e) if e.kind() == ErrorKind::NotFound => { Ok(None) }
+        Err(e) => { Err(format!("Could not read {}: {}", path, e)) }
+    };
+    let current = read(archive)?;
+    if current.as_deref().is_some_and(|bytes| journal.is_new(bytes)) {
+        Journal::remove(archive)?;
+        return Ok(Recovery::Finished)
+    }
+    if read(staged)?.is_some_and(|bytes| journal.is_new(&bytes)) {
+        fs::rename(staged, archive).map_err(|e| format!("Could not write {}: {}", archive, e))?;
+        Journal::remove(archive)?;
+        return Ok(Recovery::Finished)
+    }
+    // Only something other than wz could have done this. Whatever's there is left alone, for someone to look at.
+    match journal.is_old(current.as_deref()) {
+        true => {
+            Journal::remove(archive)?;
+            Ok(Recovery::Undone)
+        }
+        false => {
+            Err(format!("{} was changed partway through an update, and its updated copy is gone! Remove {} to carry on with it as it is.",
+                        archive, path))
+        }
+    }
+}
+
+// Cheap enough for a whole archive, and good enough to tell one from another.
+fn fingerprint(bytes: &[u8]) -> (u64, u64) {
+    (bytes.len() as u64, xxhash64(bytes, 0))
+}
+
+fn sidecar_path(archive: &str) -> String {
+    format!("{}{}", archive, SIDECAR_EXTENSION)
+}
+
+// Every field of a journal is a long.
+impl ByteStream for Journal {
+    type Data = Journal;
+
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError> {
+        let mut i = 0;
+        let journal = Journal {
+            old_len: read_long(bytes, &mut i)?,
+            old_hash: read_long(bytes, &mut i)?,
+            new_len: read_long(bytes, &mut i)?,
+            new_hash: read_long(bytes, &mut i)?,
+        };
+        let hash = xxhash64(&bytes[..i], 0);
+        match read_long(bytes, &mut i)? == hash {
+            true => { Ok((journal, i)) }
+            false => { Err(WzError::Malformed(String::from("Journal doesn't match its hash!"))) }
+        }
+    }
+
+    fn to_stream(self) -> Vec<u8> {
+        let mut retval = vec![];
+        for field in [self.old_len, self.old_hash, self.new_len, self.new_hash] {
+            retval.extend_from_slice(&field.to_le_bytes());
+        }
+        let hash = xxhash64(&retval, 0);
+        retval.extend_from_slice(&hash.to_le_bytes());
+        retval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+    use std::path::Path;
+    use crate::file::bytestream::ByteStream;
+    use crate::file::journal::{Journal, recover, Recovery, sidecar_path};
+
+    #[test]
+    fn test_to_from() {
+        let journal = Journal::new(Some(b"old"), b"new");
+        let bytes = journal.clone().to_stream();
+        assert_eq!(journal, Journal::from_whole_stream(&bytes).unwrap());
+        assert_eq!(Journal::new(None, b"new"), Journal::from_whole_stream(&Journal::new(None, b"new").to_stream()).unwrap());
+
+        // Cut short, or garbled.
+        assert!(Journal::from_whole_stream(&bytes[..bytes.len() - 1]).is_err());
+        let mut garbled = bytes.clone();
+        garbled[20] ^= 1;
+        assert!(Journal::from_whole_stream(&garbled).is_err());
+    }
+
+    #[test]
+    fn test_recover() {
+        let dir = temp_dir().join(format!("wz_journal_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("a.wz").to_string_lossy().to_string();
+        let staged = format!("{}.tmp", archive);
+        let journal = Journal::new(Some(b"old"), b"new");
+        let setup = |current: Option<&[u8]>, copy: Option<&[u8]>| {
+            for (path, contents) in [(&archive, current), (&staged, copy)] {
+                match contents {
+                    Some(contents) => { fs::write(path, contents).unwrap() }
+                    None => { let _ = fs::remove_file(path); }
+                }
+            }
+            journal.commit(&archive, false).unwrap();
+        };
+
+        // Nothing to do.
+        Journal::remove(&archive).unwrap();
+        assert_eq!(Recovery::Clean, recover(&archive, &staged).unwrap());
+
+        // Committed, but the staged copy never took the archive's place.
+        setup(Some(b"old"), Some(b"new"));
+        assert_eq!(Recovery::Finished, recover(&archive, &staged).unwrap());
+        assert_eq!(b"new".to_vec(), fs::read(&archive).unwrap());
+        assert!(!Path::new(&staged).exists());
+        assert!(!Path::new(&sidecar_path(&archive)).exists());
+
+        // It did, but the journal wasn't removed.
+        setup(Some(b"new"), None);
+        assert_eq!(Recovery::Finished, recover(&archive, &staged).unwrap());
+        assert_eq!(b"new".to_vec(), fs::read(&archive).unwrap());
+
+        // A journal cut short was never committed.
+        setup(Some(b"old"), Some(b"ne"));
+        fs::write(sidecar_path(&archive), &journal.clone().to_stream()[..10]).unwrap();
+        assert_eq!(Recovery::Undone, recover(&archive, &staged).unwrap());
+        assert_eq!(b"old".to_vec(), fs::read(&archive).unwrap());
+
+        // The staged copy has gone, but the archive is as it was.
+        setup(Some(b"old"), None);
+        assert_eq!(Recovery::Undone, recover(&archive, &staged).unwrap());
+
+        // Neither copy is one the journal knows: left for someone to look at.
+        setup(Some(b"other"), Some(b"newer"));
+        assert!(recover(&archive, &staged).is_err());
+        assert!(Path::new(&sidecar_path(&archive)).exists());
+        assert_eq!(b"other".to_vec(), fs::read(&archive).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
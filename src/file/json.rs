@@ -0,0 +1,234 @@
+// Just enough JSON for wz: reading code tables and server requests, and quoting strings in what it writes.
+// Author: Will Morris
+
+// A parsed JSON value. Objects keep their fields in order.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    // The field of an object with this name. Anything else has none.
+    pub(crate) fn field(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => { fields.iter().find(|(key, _)| key == name).map(|(_, value)| value) }
+            _ => { None }
+        }
+    }
+}
+
+// Parse text holding a single JSON value, and nothing after it but whitespace.
+pub(crate) fn parse_json(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { chars: text.chars().collect(), i: 0 };
+    let json = parser.value()?;
+    parser.skip_whitespace();
+    match parser.i < parser.chars.len() {
+        true => { Err(format!("Unexpected text after the JSON, at character {}!", parser.i)) }
+        false => { Ok(json) }
+    }
+}
+
+// A JSON string holding text, with quotes, backslashes and control characters escaped.
+pub(crate) fn json_string(text: &str) -> String {
+    let mut retval = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => { retval += "\\\"" }
+            '\\' => { retval += "\\\\" }
+            '\n' => { retval += "\\n" }
+            c if (c as u32) < 0x20 => { retval += &format!("\\u{:04x}", c as u32) }
+            c => { retval.push(c) }
+        }
+    }
+    retval.push('"');
+    retval
+}
+
+struct Parser {
+    chars: Vec<char>,
+    i: usize,
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.i).is_some_and(|c| c.is_whitespace()) {
+            self.i += 1;
+        }
+    }
+
+    // Consume c, after any whitespace.
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.get(self.i) {
+            Some(found) if *found == c => {
+                self.i += 1;
+                Ok(())
+            }
+            _ => { Err(format!("Expected '{}' in JSON, at character {}!", c, self.i)) }
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.get(self.i) {
+            Some('{') => { self.object() }
+            Some('[') => { self.array() }
+            Some('"') => { self.string().map(Json::String) }
+            Some(c) if *c == '-' || c.is_ascii_digit() => { self.number() }
+            Some(_) => { self.literal() }
+            None => { Err(String::from("JSON ended early!")) }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut fields = vec![];
+        self.skip_whitespace();
+        if self.chars.get(self.i) == Some(&'}') {
+            self.i += 1;
+            return Ok(Json::Object(fields))
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.chars.get(self.i) {
+                Some(',') => { self.i += 1 }
+                _ => { break }
+            }
+        }
+        self.expect('}')?;
+        Ok(Json::Object(fields))
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut values = vec![];
+        self.skip_whitespace();
+        if self.chars.get(self.i) == Some(&']') {
+            self.i += 1;
+            return Ok(Json::Array(values))
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.get(self.i) {
+                Some(',') => { self.i += 1 }
+                _ => { break }
+            }
+        }
+        self.expect(']')?;
+        Ok(Json::Array(values))
+    }
+
+    // Paths may hold anything, so every escape is understood, surrogate pairs included.
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut retval = String::new();
+        loop {
+            match self.chars.get(self.i) {
+                Some('"') => {
+                    self.i += 1;
+                    return Ok(retval)
+                }
+                Some('\\') => {
+                    let escaped = *self.chars.get(self.i + 1).ok_or("JSON ended early!")?;
+                    self.i += 2;
+                    match escaped {
+                        '"' | '\\' | '/' => { retval.push(escaped) }
+                        'b' => { retval.push('\u{8}') }
+                        'f' => { retval.push('\u{c}') }
+                        'n' => { retval.push('\n') }
+                        'r' => { retval.push('\r') }
+                        't' => { retval.push('\t') }
+                        'u' => { retval.push(self.unicode()?) }
+                        _ => { return Err(format!("Unsupported escape in JSON: \\{}", escaped)) }
+                    }
+                }
+                Some(c) => {
+                    retval.push(*c);
+                    self.i += 1;
+                }
+                None => { return Err(String::from("JSON ended early!")) }
+            }
+        }
+    }
+
+    // The character a \u escape stands for, its \u already consumed.
+    // Characters beyond the first plane are escaped as two, high surrogate first.
+    fn unicode(&mut self) -> Result<char, String> {
+        let high = self.hex()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or(format!("Unpaired surrogate in JSON, at character {}!", self.i))
+        }
+        match (self.chars.get(self.i), self.chars.get(self.i + 1)) {
+            (Some('\\'), Some('u')) => { self.i += 2 }
+            _ => { return Err(format!("Unpaired surrogate in JSON, at character {}!", self.i)) }
+        }
+        let low = self.hex()?;
+        match (0xdc00..0xe000).contains(&low) {
+            true => { char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)).ok_or(String::from("Bad surrogate pair in JSON!")) }
+            false => { Err(format!("Unpaired surrogate in JSON, at character {}!", self.i)) }
+        }
+    }
+
+    // Four hex digits.
+    fn hex(&mut self) -> Result<u32, String> {
+        let digits: String = self.chars.get(self.i..self.i + 4).ok_or("JSON ended early!")?.iter().collect();
+        self.i += 4;
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("Invalid \\u escape in JSON: {}", digits))
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.i;
+        while self.chars.get(self.i).is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+            self.i += 1;
+        }
+        let text: String = self.chars[start..self.i].iter().collect();
+        text.parse().map(Json::Number).map_err(|_| format!("Invalid number in JSON: {}", text))
+    }
+
+    fn literal(&mut self) -> Result<Json, String> {
+        for (word, value) in [("null", Json::Null), ("true", Json::Bool(true)), ("false", Json::Bool(false))] {
+            let end = self.i + word.len();
+            if self.chars.get(self.i..end).is_some_and(|chars| chars.iter().copied().eq(word.chars())) {
+                self.i = end;
+                return Ok(value)
+            }
+        }
+        Err(format!("Unexpected character in JSON, at character {}!", self.i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::json::{Json, json_string, parse_json};
+
+    #[test]
+    fn test_parse() {
+        let json = parse_json(r#" {"a": [1, -2.5e1, true, null], "b": {}, "c": "x\"\\\/\n\u00e9\ud83d\ude00"} "#).unwrap();
+        assert_eq!(Some(&Json::Array(vec![Json::Number(1.0), Json::Number(-25.0), Json::Bool(true), Json::Null])), json.field("a"));
+        assert_eq!(Some(&Json::Object(vec![])), json.field("b"));
+        assert_eq!(Some(&Json::String(String::from("x\"\\/\né😀"))), json.field("c"));
+        assert_eq!(None, json.field("d"));
+        assert_eq!(None, Json::Null.field("a"));
+
+        for bad in ["", "[1,", "{\"a\" 1}", "\"open", "[] []", "nul", "\"\\q\"", "\"\\ud83d\"", "\"\\u12\"", "1e+e"] {
+            assert!(parse_json(bad).is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!("\"Bad \\\"block\\\"\\n\\u0009at the end\\\\\"", json_string("Bad \"block\"\n\tat the end\\"));
+        let text = "tab\there, \"quoted\", and é";
+        assert_eq!(Json::String(String::from(text)), parse_json(&json_string(text)).unwrap());
+    }
+}
@@ -0,0 +1,203 @@
+// Member names: how paths are stored in archives, and turned back into paths on extraction.
+// Names are UTF-8, with '/' between directories, whatever platform wrote them.
+// Not every name can be a path everywhere, so names are checked against the platform they're extracted on.
+// Author: Will Morris
+
+use std::path::{Component, MAIN_SEPARATOR, Path, PathBuf};
+
+// Names Windows keeps for devices, with or without an extension.
+#[cfg(any(windows, test))]
+const WINDOWS_DEVICES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// Members are named by their relative path, with '/' between directories.
+// Like tar, any leading root is dropped, so that archives always extract below the destination.
+pub(crate) fn member_name(path: &Path) -> Result<String, String> {
+    let mut parts = vec![];
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => {
+                let part = part.to_str()
+                    .ok_or(format!("Member names must be UTF-8: {}", path.display()))?;
+                parts.push(part);
+            }
+            Component::ParentDir => {
+                return Err(format!("Member names may not contain '..': {}", path.display()))
+            }
+            Component::RootDir | Component::Prefix(_) | Component::CurDir => {}
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+// Turn a member name back into a relative path on this platform.
+// Archives may come from anywhere, so names that could escape the destination are refused,
+// as are names this platform can't give a file, rather than extracting them somewhere unexpected.
+pub(crate) fn member_path(name: &str) -> Result<PathBuf, String> {
+    let mut path = PathBuf::new();
+    for part in name.split('/') {
+        if part.is_empty() || part == "." || part == ".." || part.contains('\\') {
+            return Err(format!("Refusing to extract unsafe member name: {}", name))
+        }
+        if let Some(problem) = platform_problem(part) {
+            return Err(format!("Member name {} can't be extracted here: {}", name, problem))
+        }
+        path.push(part);
+    }
+    Ok(path)
+}
+
+// A symbolic link's target, stored like a member name, with '/' between directories.
+pub(crate) fn link_target(path: &Path, target: &Path) -> Result<String, String> {
+    let target = target.to_str().ok_or(format!("Link targets must be UTF-8: {}", path.display()))?;
+    Ok(target.replace(MAIN_SEPARATOR, "/"))
+}
+
+// Turn a symbolic link's target back into a path on this platform.
+// Targets are followed from the directory holding the link, named name, and may not lead above the destination.
+pub(crate) fn target_path(name: &str, target: &str) -> Result<PathBuf, String> {
+    let error = |why| format!("Refusing to extract link {} to {}: {}", name, target, why);
+    // How many directories below the destination the target has got to.
+    let mut depth = name.matches('/').count();
+    if target.is_empty() || target.starts_with('/') {
+        return Err(error("only relative targets can be extracted"))
+    }
+    let mut path = PathBuf::new();
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if depth == 0 => { return Err(error("it points outside the destination")) }
+            ".." => {
+                depth -= 1;
+                path.push(part);
+            }
+            _ => {
+                if part.contains('\\') || Path::new(part).has_root() {
+                    return Err(error("only relative targets can be extracted"))
+                }
+                if let Some(problem) = platform_problem(part) {
+                    return Err(error(problem))
+                }
+                depth += 1;
+                path.push(part);
+            }
+        }
+    }
+    Ok(path)
+}
+
+// The directories a link's target passes through before its last part, named like members.
+// Only lexically, from the directory holding the link, so it's only meaningful for targets target_path accepts.
+pub(crate) fn target_dirs(name: &str, target: &str) -> Vec<String> {
+    let mut dir: Vec<&str> = name.split('/').collect();
+    dir.pop();
+    let parts: Vec<&str> = target.split('/').filter(|part| !part.is_empty() && *part != ".").collect();
+    let mut retval = vec![];
+    for (i, part) in parts.iter().enumerate() {
+        // Going up from a directory still has to go through it.
+        if i > 0 {
+            retval.push(dir.join("/"));
+        }
+        match *part {
+            ".." => { dir.pop(); }
+            _ => { dir.push(part); }
+        }
+    }
+    retval
+}
+
+#[cfg(windows)]
+fn platform_problem(part: &str) -> Option<&'static str> {
+    windows_problem(part)
+}
+
+#[cfg(not(windows))]
+fn platform_problem(part: &str) -> Option<&'static str> {
+    unix_problem(part)
+}
+
+// Why Windows can't name a file part, if it can't.
+#[cfg(any(windows, test))]
+fn windows_problem(part: &str) -> Option<&'static str> {
+    if part.chars().any(|c| c < ' ' || "<>:\"|?*".contains(c)) {
+        return Some("Windows file names can't hold control characters, or any of <>:\"|?*")
+    }
+    if part.ends_with('.') || part.ends_with(' ') {
+        return Some("Windows file names can't end with '.' or ' '")
+    }
+    let stem = part.split('.').next().unwrap_or(part).trim_end().to_ascii_uppercase();
+    if WINDOWS_DEVICES.contains(&stem.as_str()) {
+        return Some("Windows keeps that name for a device")
+    }
+    None
+}
+
+// Unix allows anything in a name but '/', which never gets this far, and NUL.
+#[cfg(any(not(windows), test))]
+fn unix_problem(part: &str) -> Option<&'static str> {
+    match part.contains('\0') {
+        true => { Some("file names can't hold NUL characters") }
+        false => { None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use crate::file::names::{link_target, member_name, member_path, target_dirs, target_path, unix_problem, windows_problem};
+
+    #[test]
+    fn test_member_name() {
+        assert_eq!("dir/a.txt", member_name(Path::new("./dir/a.txt")).unwrap());
+        assert_eq!("tmp/a.txt", member_name(Path::new("/tmp/a.txt")).unwrap());
+        assert_eq!("d\u{e9}j\u{e0}/vu.txt", member_name(Path::new("d\u{e9}j\u{e0}/vu.txt")).unwrap());
+        assert!(member_name(Path::new("../a.txt")).is_err());
+    }
+
+    #[test]
+    fn test_member_path() {
+        assert_eq!(PathBuf::from("dir").join("a.txt"), member_path("dir/a.txt").unwrap());
+        assert_eq!(PathBuf::from("d\u{e9}j\u{e0}").join("vu.txt"), member_path("d\u{e9}j\u{e0}/vu.txt").unwrap());
+        assert!(member_path("../a.txt").is_err());
+        assert!(member_path("/a.txt").is_err());
+        assert!(member_path("dir//a.txt").is_err());
+        assert!(member_path("dir\\..\\a.txt").is_err());
+        assert!(member_path("a\0b").is_err());
+    }
+
+    #[test]
+    fn test_target_path() {
+        assert_eq!("logs/a.txt", link_target(Path::new("latest"), &PathBuf::from("logs").join("a.txt")).unwrap());
+        assert_eq!(PathBuf::from("logs").join("a.txt"), target_path("latest", "logs/a.txt").unwrap());
+        assert_eq!(PathBuf::from("..").join("b.txt"), target_path("dir/a.txt", "../b.txt").unwrap());
+        assert_eq!(PathBuf::from("c").join("..").join("..").join("b.txt"), target_path("dir/a.txt", "./c/../../b.txt").unwrap());
+        // Links may point anywhere below the destination, but no further.
+        assert!(target_path("a.txt", "../b.txt").is_err());
+        assert!(target_path("dir/a.txt", "c/../../../b.txt").is_err());
+        assert!(target_path("a.txt", "/etc/passwd").is_err());
+        assert!(target_path("a.txt", "").is_err());
+        assert_eq!(PathBuf::from("dir"), target_path("a.txt", "dir/").unwrap());
+    }
+
+    #[test]
+    fn test_target_dirs() {
+        assert_eq!(vec!["a/up", "a"], target_dirs("a/up2", "up/../.."));
+        assert_eq!(vec!["logs"], target_dirs("latest", "./logs/today.log"));
+        assert_eq!(vec!["", "b"], target_dirs("dir/a.txt", "../b/c.txt"));
+        assert!(target_dirs("a/up", "..").is_empty());
+    }
+
+    #[test]
+    fn test_platforms() {
+        for fine in ["a.txt", "console.txt", "my file", "\u{e9}t\u{e9}"] {
+            assert_eq!(None, windows_problem(fine), "{}", fine);
+            assert_eq!(None, unix_problem(fine), "{}", fine);
+        }
+        for bad in ["a:b", "what?", "tab\there", "trailing.", "trailing ", "CON", "nul.txt", "Com1"] {
+            assert!(windows_problem(bad).is_some(), "{}", bad);
+            assert_eq!(None, unix_problem(bad), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// A patch: what it takes to turn one version of a file into another.
+// Author: Will Morris
+
+/*
+  CONTENTS:
+  -- magic number, "WZDIFF"
+  -- length of the old file
+  -- hash of the old file
+  -- length of the new file
+  -- hash of the new file
+  -- compressed block of delta instructions
+  -- compressed block of bytes inserted by the instructions.
+
+  The hashes make sure a patch is only applied to the file it was made from,
+  and that what comes out is what went in.
+ */
+
+use crate::file::archive::{ChunkHash, read_hash};
+use crate::file::bytestream::{append_sized, ByteStream, read_long, read_sized};
+use crate::file::error::WzError;
+
+const MAGIC: &[u8] = b"WZDIFF";
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Patch {
+    pub(crate) old_len: u64,
+    pub(crate) old_hash: ChunkHash,
+    pub(crate) new_len: u64,
+    pub(crate) new_hash: ChunkHash,
+    pub(crate) control: Vec<u8>,
+    pub(crate) data: Vec<u8>,
+}
+
+// Does this look like a patch?
+pub(crate) fn is_patch(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+impl ByteStream for Patch {
+    type Data = Patch;
+
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError> {
+        if !is_patch(bytes) {
+            return Err(WzError::Malformed(String::from("Not a patch!")))
+        }
+        let mut i = MAGIC.len();
+        let old_len = read_long(bytes, &mut i)?;
+        let old_hash = read_hash(bytes, &mut i)?;
+        let new_len = read_long(bytes, &mut i)?;
+        let new_hash = read_hash(bytes, &mut i)?;
+        let control = read_sized(bytes, &mut i)?.to_vec();
+        let data = read_sized(bytes, &mut i)?.to_vec();
+
+        Ok((Patch { old_len, old_hash, new_len, new_hash, control, data }, i))
+    }
+
+    fn to_stream(mut self) -> Vec<u8> {
+        let mut retval = MAGIC.to_vec();
+        retval.extend_from_slice(&self.old_len.to_le_bytes());
+        retval.extend_from_slice(&self.old_hash.to_le_bytes());
+        retval.extend_from_slice(&self.new_len.to_le_bytes());
+        retval.extend_from_slice(&self.new_hash.to_le_bytes());
+        append_sized(&mut retval, &mut self.control);
+        append_sized(&mut retval, &mut self.data);
+        retval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::archive::hash_chunk;
+    use crate::file::bytestream::ByteStream;
+    use crate::file::patch::{is_patch, Patch};
+
+    #[test]
+    fn test_to_from() {
+        let patch = Patch {
+            old_len: 3,
+            old_hash: hash_chunk(b"old"),
+            new_len: 3,
+            new_hash: hash_chunk(b"new"),
+            control: vec![1, 2, 3],
+            data: vec![],
+        };
+        let bytes = patch.clone().to_stream();
+        assert!(is_patch(&bytes));
+        assert_eq!(patch, Patch::from_whole_stream(&bytes).unwrap());
+        assert!(Patch::from_stream(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Patch::from_stream(b"not a patch").is_err());
+    }
+}
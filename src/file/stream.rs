@@ -0,0 +1,120 @@
+// The parts of a wz stream around its blocks: frames, and the index that ends them.
+// Blocks themselves are coded by codec::block, for the library just as for wz, so either side reads what the other writes.
+// Author: Will Morris
+
+/*
+  CONTENTS (repeated for each block):
+  -- block length, as a varint
+  -- the block: its kind, then whatever that kind holds.
+
+  The last block is an index: the number of blocks, the uncompressed length of each, then no tar members.
+ */
+
+use std::io::{self, ErrorKind, Read};
+use crate::codec::block::{BlockKind, decompress_block};
+use crate::diagnostics::report::Report;
+use crate::file::bytestream::{append_sized, read_long, read_varint_from};
+use crate::file::error::WzError;
+
+// The kinds of block the encoder writes, and those the readers step over.
+pub(crate) const RAW: u8 = BlockKind::Raw as u8;
+pub(crate) const INDEX: u8 = BlockKind::Index as u8;
+pub(crate) const CANONICAL: u8 = BlockKind::Canonical as u8;
+pub(crate) const CHECKSUM: u8 = BlockKind::Checksum as u8;
+
+// Frame a block, so that it can be found again in a stream of blocks.
+pub(crate) fn frame(mut block: Vec<u8>) -> Vec<u8> {
+    let mut retval = vec![];
+    append_sized(&mut retval, &mut block);
+    retval
+}
+
+// An index of blocks of these lengths, ready to be framed.
+pub(crate) fn index(block_lens: &[u64]) -> Vec<u8> {
+    let mut retval = vec![INDEX];
+    retval.extend_from_slice(&(block_lens.len() as u64).to_le_bytes());
+    block_lens.iter().for_each(|len| retval.extend_from_slice(&len.to_le_bytes()));
+    retval.extend_from_slice(&0u64.to_le_bytes());
+    retval
+}
+
+// The uncompressed lengths an index lists, if block is one. The library has no use for its members.
+pub(crate) fn index_lens(block: &[u8]) -> Option<Vec<u64>> {
+    if block.first() != Some(&INDEX) {
+        return None
+    }
+    let mut i = 1;
+    let count = read_long(block, &mut i).ok()?;
+    (0..count).map(|_| read_long(block, &mut i).ok()).collect()
+}
+
+// Read the next framed block from reader. None once the stream has ended between blocks.
+pub(crate) fn read_frame(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let len = match read_varint_from(reader, "a block length") {
+        Ok(Some(len)) => { len }
+        Ok(None) => { return Ok(None) }
+        Err(error) => { return Err(io::Error::new(ErrorKind::InvalidData, error)) }
+    };
+
+    // Only as much is allocated as is really there, however long the frame claims to be.
+    let mut block = vec![];
+    reader.take(len).read_to_end(&mut block)?;
+    match block.len() as u64 == len {
+        true => { Ok(Some(block)) }
+        false => { Err(io::Error::new(ErrorKind::UnexpectedEof, "Stream ended partway through a block!")) }
+    }
+}
+
+// The bytes a block holds, kind and all, decompressed just as wz -x decompresses them.
+// Indexes, checksums, and empty blocks hold none. Nothing here has a dictionary, so blocks coded with one are refused.
+pub(crate) fn decode(block: &[u8]) -> Result<Vec<u8>, WzError> {
+    decompress_block(block, None, &mut Report::new(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::block::{BlockKind, compress_block, Method};
+    use crate::diagnostics::report::Report;
+    use crate::file::stream::{decode, frame, index, INDEX, index_lens, RAW, read_frame};
+
+    #[test]
+    fn test_frames() {
+        let mut stream = frame(vec![1, 2, 3]);
+        stream.append(&mut frame(vec![]));
+        stream.append(&mut frame(vec![7; 300]));
+        let mut reader = &stream[..];
+        assert_eq!(Some(vec![1, 2, 3]), read_frame(&mut reader).unwrap());
+        assert_eq!(Some(vec![]), read_frame(&mut reader).unwrap());
+        assert_eq!(Some(vec![7; 300]), read_frame(&mut reader).unwrap());
+        assert_eq!(None, read_frame(&mut reader).unwrap());
+
+        // Cut short, or claiming more than could be there.
+        assert!(read_frame(&mut &frame(vec![7; 300])[..301]).is_err());
+        assert!(read_frame(&mut &[1u8][..]).is_err());
+        assert!(read_frame(&mut &[0xffu8; 11][..]).is_err());
+        assert!(read_frame(&mut &[0xffu8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0][..]).is_err());
+    }
+
+    #[test]
+    fn test_index() {
+        let index = index(&[10, 20]);
+        assert_eq!(INDEX, index[0]);
+        assert_eq!(1 + 8 * 4, index.len());
+        assert_eq!(2, index[1]);
+        assert_eq!(Some(vec![10, 20]), index_lens(&index));
+        assert_eq!(None, index_lens(&index[..10]));
+        assert_eq!(None, index_lens(&[RAW]));
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Ok(b"hi".to_vec()), decode(&[RAW, b'h', b'i']));
+        assert_eq!(Ok(vec![]), decode(&[]));
+        assert_eq!(Ok(vec![]), decode(&index(&[2])));
+        // Every kind wz writes, Huffman coded just as it would be.
+        let bytes = b"the rain in spain".repeat(20);
+        assert_eq!(Ok(bytes.clone()), decode(&compress_block(&bytes, &Method::new(), &mut Report::new(false))));
+        assert!(decode(&[0, 0, 0]).is_err());
+        assert!(decode(&[BlockKind::Dictionary as u8, 0, 0, 0, 0]).is_err());
+    }
+}
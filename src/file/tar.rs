@@ -0,0 +1,329 @@
+// Finding the members of a tar stream, so a .tar.wz can be split and indexed along them.
+// We never write tar ourselves -- only read enough of its headers to know where each member lies.
+// Author: Will Morris
+
+/*
+  CONTENTS (of the index):
+  -- number of blocks
+  -- uncompressed length of each block, in order
+  -- number of members
+  -- for each member:
+     -- name
+     -- offset of its contents in the uncompressed stream
+     -- length of its contents.
+ */
+
+use std::iter::once;
+use crate::file::archive::read_name;
+use crate::file::blocks::locate_range;
+use crate::file::bytestream::{append_sized, ByteStream, read_long};
+use crate::file::error::WzError;
+
+// Tar works in records of this many bytes. Headers are one record, and contents are padded to a whole record.
+const RECORD_LEN: usize = 512;
+const MAGIC: &[u8] = b"ustar";
+const MAGIC_OFFSET: usize = 257;
+const SIZE_FIELD: (usize, usize) = (124, 136);
+const CHECKSUM_FIELD: (usize, usize) = (148, 156);
+const TYPE_OFFSET: usize = 156;
+const NAME_FIELD: (usize, usize) = (0, 100);
+const PREFIX_FIELD: (usize, usize) = (345, 500);
+
+// Regular files. Old tars leave the type empty.
+const REGULAR: [u8; 3] = [b'0', b'\0', b'7'];
+// Headers which describe the next header, rather than a member of their own.
+const GNU_LONG_NAME: u8 = b'L';
+const PAX_HEADER: u8 = b'x';
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TarMember {
+    pub name: String,
+    // Where the member's contents start in the tar stream, past its header.
+    pub offset: u64,
+    pub size: u64,
+}
+
+// Every stream is indexed, so any range of it can be found. Only tar streams have members.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TarIndex {
+    // How many bytes each block decompresses to.
+    block_lens: Vec<u64>,
+    members: Vec<TarMember>,
+}
+
+impl TarIndex {
+    pub fn new(block_lens: Vec<u64>, members: Vec<TarMember>) -> Self {
+        TarIndex { block_lens, members }
+    }
+
+    pub fn members(&self) -> &[TarMember] {
+        &self.members
+    }
+
+    // Add the members of another stream, which follows this one.
+    pub fn append(&mut self, other: TarIndex) {
+        let shift: u64 = self.block_lens.iter().sum();
+        self.block_lens.extend(other.block_lens);
+        self.members.extend(other.members.into_iter().map(|member| {
+            TarMember { offset: member.offset + shift, ..member }
+        }));
+    }
+
+    // Which blocks hold a member's contents, and where in the first of them the contents start.
+    pub fn locate(&self, member: &TarMember) -> (Vec<usize>, usize) {
+        self.locate_range(member.offset, member.size)
+    }
+
+    // Which blocks hold len bytes from offset on, and where in the first of them those bytes start.
+    pub fn locate_range(&self, offset: u64, len: u64) -> (Vec<usize>, usize) {
+        locate_range(&self.block_lens, offset, len)
+    }
+}
+
+// Does this look like a tar stream? The first header must be intact.
+pub fn is_tar(bytes: &[u8]) -> bool {
+    bytes.len() >= RECORD_LEN && valid_header(&bytes[..RECORD_LEN])
+}
+
+// Walk the headers of a tar stream.
+// Returns where each entry starts, including any headers describing it, and every regular file in it.
+// If the stream isn't a tar, or is damaged, returns none.
+pub fn read_tar(bytes: &[u8]) -> Option<(Vec<usize>, Vec<TarMember>)> {
+    if !is_tar(bytes) {
+        return None
+    }
+
+    let mut starts = vec![];
+    let mut members = vec![];
+    let mut start = 0;
+    let mut long_name = None;
+    let mut i = 0;
+    while i + RECORD_LEN <= bytes.len() {
+        let header = &bytes[i..i + RECORD_LEN];
+        // The stream ends with records of zeros.
+        if header.iter().all(|byte| *byte == 0) {
+            break
+        }
+        if !valid_header(header) {
+            return None
+        }
+
+        let size = parse_octal(field(header, SIZE_FIELD))? as usize;
+        let offset = i + RECORD_LEN;
+        let contents = bytes.get(offset..offset + size)?;
+        i = offset + size.div_ceil(RECORD_LEN) * RECORD_LEN;
+
+        match header[TYPE_OFFSET] {
+            GNU_LONG_NAME => { long_name = Some(String::from_utf8_lossy(trim_nul(contents)).into_owned()) }
+            PAX_HEADER => { long_name = pax_path(contents).or(long_name) }
+            kind => {
+                let name = long_name.take().unwrap_or_else(|| header_name(header));
+                if REGULAR.contains(&kind) {
+                    members.push(TarMember { name, offset: offset as u64, size: size as u64 });
+                }
+                starts.push(start);
+                start = i;
+            }
+        }
+    }
+    Some((starts, members))
+}
+
+// Split a tar stream into blocks, starting a new block only between entries.
+// Entries are gathered into blocks of up to block_size bytes. Entries bigger than that get blocks of their own.
+pub fn tar_blocks<'a>(bytes: &'a [u8], starts: &[usize], block_size: usize) -> Vec<&'a [u8]> {
+    let mut blocks = vec![];
+    let mut block_start = 0;
+    let mut previous = 0;
+    for boundary in starts.iter().copied().filter(|start| *start > 0).chain(once(bytes.len())) {
+        if boundary - block_start > block_size && previous > block_start {
+            blocks.push(&bytes[block_start..previous]);
+            block_start = previous;
+        }
+        while boundary - block_start > block_size {
+            blocks.push(&bytes[block_start..block_start + block_size]);
+            block_start += block_size;
+        }
+        previous = boundary;
+    }
+    if block_start < bytes.len() {
+        blocks.push(&bytes[block_start..]);
+    }
+    blocks
+}
+
+// The checksum is the sum of every header byte, counting the checksum itself as spaces.
+fn valid_header(header: &[u8]) -> bool {
+    if &header[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC.len()] != MAGIC {
+        return false
+    }
+    let (from, to) = CHECKSUM_FIELD;
+    let sum: u64 = header.iter().enumerate()
+        .map(|(i, byte)| if (from..to).contains(&i) { b' ' as u64 } else { *byte as u64 })
+        .sum();
+    parse_octal(&header[from..to]) == Some(sum)
+}
+
+// Names longer than 100 bytes are split, with the start in the prefix.
+fn header_name(header: &[u8]) -> String {
+    let name = String::from_utf8_lossy(trim_nul(field(header, NAME_FIELD)));
+    let prefix = String::from_utf8_lossy(trim_nul(field(header, PREFIX_FIELD)));
+    match prefix.is_empty() {
+        true => { name.into_owned() }
+        false => { format!("{}/{}", prefix, name) }
+    }
+}
+
+// pax headers are records of the form "length key=value\n".
+fn pax_path(contents: &[u8]) -> Option<String> {
+    let mut i = 0;
+    let mut path = None;
+    while i < contents.len() {
+        let space = i + contents[i..].iter().position(|byte| *byte == b' ')?;
+        let len: usize = std::str::from_utf8(&contents[i..space]).ok()?.parse().ok()?;
+        let record = contents.get(space + 1..i + len)?;
+        if let Some(value) = record.strip_prefix(b"path=") {
+            path = Some(String::from_utf8_lossy(value.strip_suffix(b"\n")?).into_owned());
+        }
+        i += len.max(1);
+    }
+    path
+}
+
+fn field(header: &[u8], (from, to): (usize, usize)) -> &[u8] {
+    &header[from..to]
+}
+
+fn trim_nul(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+// Numbers are octal text, padded with spaces or NULs.
+// Very large sizes are instead big endian binary, flagged by the top bit.
+fn parse_octal(bytes: &[u8]) -> Option<u64> {
+    if bytes.first().is_some_and(|byte| byte & 0x80 != 0) {
+        return Some(bytes[1..].iter().fold(0, |value, byte| (value << 8) | *byte as u64))
+    }
+    let text = std::str::from_utf8(trim_nul(bytes)).ok()?.trim();
+    match text.is_empty() {
+        true => { Some(0) }
+        false => { u64::from_str_radix(text, 8).ok() }
+    }
+}
+
+impl ByteStream for TarIndex {
+    type Data = TarIndex;
+
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError> {
+        let mut i = 0;
+        let num_blocks = read_long(bytes, &mut i)?;
+        let block_lens = (0..num_blocks).map(|_| read_long(bytes, &mut i)).collect::<Result<_, _>>()?;
+
+        let num_members = read_long(bytes, &mut i)?;
+        let mut members = vec![];
+        for _ in 0..num_members {
+            let name = read_name(bytes, &mut i)?;
+            let offset = read_long(bytes, &mut i)?;
+            let size = read_long(bytes, &mut i)?;
+            members.push(TarMember { name, offset, size });
+        }
+
+        Ok((TarIndex::new(block_lens, members), i))
+    }
+
+    fn to_stream(self) -> Vec<u8> {
+        let mut retval = vec![];
+        retval.extend_from_slice(&(self.block_lens.len() as u64).to_le_bytes());
+        for len in self.block_lens {
+            retval.extend_from_slice(&len.to_le_bytes());
+        }
+
+        retval.extend_from_slice(&(self.members.len() as u64).to_le_bytes());
+        for member in self.members {
+            append_sized(&mut retval, &mut member.name.into_bytes());
+            retval.extend_from_slice(&member.offset.to_le_bytes());
+            retval.extend_from_slice(&member.size.to_le_bytes());
+        }
+        retval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::bytestream::ByteStream;
+    use crate::file::tar::{CHECKSUM_FIELD, is_tar, RECORD_LEN, read_tar, tar_blocks, TarIndex, TarMember};
+
+    // A header, with contents padded out to whole records.
+    fn entry(name: &str, kind: u8, contents: &[u8]) -> Vec<u8> {
+        let mut header = vec![0u8; RECORD_LEN];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", contents.len()).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        let (from, to) = CHECKSUM_FIELD;
+        header[from..to].fill(b' ');
+        let sum: u64 = header.iter().map(|byte| *byte as u64).sum();
+        header[from..from + 7].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+
+        let mut padded = contents.to_vec();
+        padded.resize(contents.len().div_ceil(RECORD_LEN) * RECORD_LEN, 0);
+        header.append(&mut padded);
+        header
+    }
+
+    fn sample() -> Vec<u8> {
+        let long_name = "long/".repeat(30) + "name.txt";
+        let mut bytes = entry("a.txt", b'0', b"first");
+        bytes.append(&mut entry("dir/", b'5', b""));
+        bytes.append(&mut entry("././@LongLink", b'L', long_name.as_bytes()));
+        bytes.append(&mut entry("long", b'0', &[7; 1000]));
+        bytes.append(&mut vec![0; RECORD_LEN * 2]);
+        bytes
+    }
+
+    #[test]
+    fn test_read_tar() {
+        let bytes = sample();
+        assert!(is_tar(&bytes));
+        let (starts, members) = read_tar(&bytes).unwrap();
+        assert_eq!(vec![0, 1024, 1536], starts);
+        assert_eq!(2, members.len());
+        assert_eq!(TarMember { name: String::from("a.txt"), offset: 512, size: 5 }, members[0]);
+        assert_eq!("long/".repeat(30) + "name.txt", members[1].name);
+        assert_eq!(vec![7; 1000], bytes[members[1].offset as usize..][..1000]);
+
+        assert!(!is_tar(b"not a tar"));
+        // A damaged header fails its checksum.
+        let mut corrupt = bytes.clone();
+        corrupt[1536 + 124] = b'9';
+        assert!(read_tar(&corrupt).is_none());
+    }
+
+    #[test]
+    fn test_tar_blocks() {
+        let bytes = sample();
+        let (starts, _) = read_tar(&bytes).unwrap();
+        // Small entries are gathered together, and big ones split.
+        let lens: Vec<usize> = tar_blocks(&bytes, &starts, 1100).iter().map(|block| block.len()).collect();
+        assert_eq!(vec![1024, 512, 1100, 1100, 1100, 284], lens);
+        assert_eq!(vec![bytes.len()], tar_blocks(&bytes, &starts, 1 << 20).iter().map(|block| block.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_index() {
+        let member = TarMember { name: String::from("a.txt"), offset: 512, size: 1000 };
+        let index = TarIndex::new(vec![1024, 512, 1000], vec![member.clone()]);
+        assert_eq!((vec![0, 1], 512), index.locate(&member));
+        assert_eq!((vec![1, 2], 10), index.locate_range(1034, 600));
+        assert_eq!((vec![2], 0), index.locate_range(1536, u64::MAX));
+        assert_eq!((vec![], 0), index.locate_range(2536, 1));
+        assert_eq!(index, TarIndex::from_whole_stream(&index.clone().to_stream()).unwrap());
+
+        // Members of a later stream come after everything in the earlier one.
+        let mut joined = index.clone();
+        joined.append(index);
+        assert_eq!(2536 + 512, joined.members()[1].offset);
+        assert_eq!((vec![3, 4], 512), joined.locate(&joined.members()[1]));
+    }
+}
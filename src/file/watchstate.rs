@@ -0,0 +1,113 @@
+// Remembers which files a watched directory held when they were last compressed.
+// Kept in a sidecar file in the destination, so a restarted watch only compresses what has changed since.
+// Author: Will Morris
+
+/*
+  CONTENTS:
+  -- number of files
+  -- for each file:
+     -- name, relative to the watched directory
+     -- length when compressed
+     -- modification time when compressed.
+ */
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use crate::file::bytestream::{append_sized, ByteStream, read_long, read_sized};
+use crate::file::error::WzError;
+
+// The state lives in the destination directory, under a name no compressed file can have.
+pub(crate) const STATE_NAME: &str = ".wzwatch";
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct WatchState {
+    // Each file's length and modification time, as checkpoints fingerprint their input.
+    files: BTreeMap<String, (u64, u64)>,
+}
+
+impl WatchState {
+    pub(crate) fn new() -> Self {
+        WatchState { files: BTreeMap::new() }
+    }
+
+    // Load the state kept in dest. A destination which has never been watched into has none yet.
+    pub(crate) fn load(dest: &Path) -> Result<Self, String> {
+        let path = dest.join(STATE_NAME);
+        match File::open(&path) {
+            Ok(mut file) => {
+                WatchState::read_from(&mut file).map_err(|_| format!("Corrupt watch state: {}", path.display()))
+            }
+            Err(_) => { Ok(WatchState::new()) }
+        }
+    }
+
+    // Written to a temporary file and renamed into place, so a crash never leaves half a state.
+    pub(crate) fn save(&self, dest: &Path) -> Result<(), String> {
+        let path = dest.join(STATE_NAME);
+        let temp_path = dest.join(format!("{}.tmp", STATE_NAME));
+        File::create(&temp_path)
+            .and_then(|mut file| self.clone().write_to(&mut file))
+            .and_then(|_| fs::rename(&temp_path, &path))
+            .map_err(|e| format!("Could not save watch state: {}", e))
+    }
+
+    // Was name compressed when it looked just like this?
+    pub(crate) fn is_current(&self, name: &str, fingerprint: (u64, u64)) -> bool {
+        self.files.get(name) == Some(&fingerprint)
+    }
+
+    pub(crate) fn record(&mut self, name: &str, fingerprint: (u64, u64)) {
+        self.files.insert(name.to_string(), fingerprint);
+    }
+}
+
+impl ByteStream for WatchState {
+    type Data = WatchState;
+
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError> {
+        let mut state = WatchState::new();
+        let mut i = 0;
+        let num_files = read_long(bytes, &mut i)?;
+        for _ in 0..num_files {
+            let name = String::from_utf8(read_sized(bytes, &mut i)?.to_vec())
+                .map_err(|_| WzError::Malformed(String::from("Watched file names must be UTF-8!")))?;
+            let len = read_long(bytes, &mut i)?;
+            let modified = read_long(bytes, &mut i)?;
+            state.files.insert(name, (len, modified));
+        }
+        Ok((state, i))
+    }
+
+    fn to_stream(self) -> Vec<u8> {
+        let mut retval = vec![];
+        retval.extend_from_slice(&(self.files.len() as u64).to_le_bytes());
+        for (name, (len, modified)) in self.files {
+            append_sized(&mut retval, &mut name.into_bytes());
+            retval.extend_from_slice(&len.to_le_bytes());
+            retval.extend_from_slice(&modified.to_le_bytes());
+        }
+        retval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::bytestream::ByteStream;
+    use crate::file::watchstate::WatchState;
+
+    #[test]
+    fn test_to_from() {
+        let mut expected = WatchState::new();
+        expected.record("app.log", (100, 12345));
+        expected.record("nested/db.log", (1 << 40, 0));
+        expected.record("app.log", (200, 12346));
+
+        let from = WatchState::from_whole_stream(&expected.clone().to_stream()).unwrap();
+        assert_eq!(expected, from);
+        assert!(from.is_current("app.log", (200, 12346)));
+        assert!(!from.is_current("app.log", (100, 12345)));
+        assert!(!from.is_current("other.log", (200, 12346)));
+    }
+}
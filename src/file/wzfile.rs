@@ -5,32 +5,74 @@
   CONTENTS:
   -- length of frequency map
   -- actual frequency map
-  -- num bytes
-  -- bytestream.
+  -- length of the sequence, as a varint
+  -- sequence.
  */
 
 use std::collections::HashMap;
-use crate::encoding::bitsequence::BitSequence;
-use crate::file::bytestream::{ByteStream, long_to_bytes, min_byte_size, slice_to_long};
-use crate::ordering::freqmap::{Freqmap, MAP_SIZE_FIELD_LEN, MAX_MAP_SIZE};
+use crate::encoding::bitreader::BitReader;
+use crate::encoding::bitsequence::{BitOrder, BitSequence};
+use crate::file::bytestream::{ByteStream, read_bytes};
+use crate::file::error::WzError;
+use crate::file::format::{read_map_len, read_sequence_len, write_map_len, write_sequence_len};
+use crate::ordering::freqmap::Freqmap;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Wzfile {
     map: Freqmap,
-    seq: BitSequence
+    seq: BitSequence,
+    // How the sequence's bits are packed once serialized. The block's kind records which.
+    order: BitOrder,
 }
 
 impl Wzfile {
-    // Given a map and seq, Wzfile prepares compression.
-    pub fn new(map: HashMap<u8, u64>, seq: BitSequence) -> Self {
-        Wzfile { map: Freqmap::new(map), seq }
+    // Given a map and seq, Wzfile prepares compression, with the seq's bits packed in the given order.
+    pub fn new(map: HashMap<u8, u64>, seq: BitSequence, order: BitOrder) -> Self {
+        Wzfile { map: map.into(), seq, order }
+    }
+
+    // Deserialize a whole wzfile whose sequence was packed in the given order.
+    pub fn from_packed_stream(bytes: &[u8], order: BitOrder) -> Result<Self, WzError> {
+        let mut i = 0;
+
+        let map_len = read_map_len(bytes, &mut i)?;
+        let map = Freqmap::from_whole_stream(read_bytes(bytes, &mut i, map_len, "a frequency map")?)?;
+
+        let seq_len = read_sequence_len(bytes, &mut i)?;
+        let seq = BitSequence::from_packed_stream(read_bytes(bytes, &mut i, seq_len, "a sequence")?, order)?;
+
+        match i == bytes.len() {
+            true => { Ok(Wzfile { map, seq, order }) }
+            false => { Err(WzError::Malformed(format!("{} unexpected bytes at the end of the stream!", bytes.len() - i))) }
+        }
+    }
+
+    // Read a whole wzfile's frequencies, and a reader over its bits where they lie, packed in the given order.
+    // Decoders read wzfiles this way, so their bits are never copied into a BitSequence.
+    pub fn read_packed(bytes: &[u8], order: BitOrder) -> Result<(Freqmap, BitReader<&[u8]>), WzError> {
+        let mut i = 0;
+
+        let map_len = read_map_len(bytes, &mut i)?;
+        let map = Freqmap::from_whole_stream(read_bytes(bytes, &mut i, map_len, "a frequency map")?)?;
+
+        let seq_len = read_sequence_len(bytes, &mut i)?;
+        let reader = BitReader::from_packed_stream(read_bytes(bytes, &mut i, seq_len, "a sequence")?, order)?;
+
+        match i == bytes.len() {
+            true => { Ok((map, reader)) }
+            false => { Err(WzError::Malformed(format!("{} unexpected bytes at the end of the stream!", bytes.len() - i))) }
+        }
+    }
+
+    // Serialize a wzfile whose bits were written straight to a stored sequence, as BitWriter::into_packed_stream gives.
+    // Encoders write wzfiles this way, so their bits are never gathered into a BitSequence.
+    pub fn packed_to_stream(map: HashMap<u8, u64>, seq: Vec<u8>) -> Vec<u8> {
+        write_wzfile(map.into(), seq)
     }
 
     // Once a wzfile has been deserialized, deconstruct it for access to its fields.
     pub fn deconstruct(self) -> (HashMap<u8, u64>, BitSequence) {
-        let map = self.map.take();
-        let seq = self.seq;
-        (map, seq)
+        (self.map.into(), self.seq)
     }
 }
 
@@ -39,61 +81,42 @@ impl ByteStream for Wzfile {
 
     // Given a byte array, deconstruct it into its component byte fields.
     // Which will then deserialize themselves.
-    fn from_stream(bytes: &[u8]) -> Self::Data {
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError> {
         let mut i = 0;
 
-        // Since there are only 256 bytes, maps have a tight upper bound on their size.
-        let map_len = slice_to_long(&bytes[..MAP_SIZE_FIELD_LEN]) as usize;
-        assert!(map_len <= MAX_MAP_SIZE);
-
-        i += MAP_SIZE_FIELD_LEN;
-        let map = Freqmap::from_stream(&bytes[i..i + map_len]);
-        i += map_len;
+        let map_len = read_map_len(bytes, &mut i)?;
+        let map = Freqmap::from_whole_stream(read_bytes(bytes, &mut i, map_len, "a frequency map")?)?;
 
-        // However, there can be arbitrarily many characters in a file, so this length will
-        // be encoded as a long.
+        let seq_len = read_sequence_len(bytes, &mut i)?;
+        let seq = BitSequence::from_whole_stream(read_bytes(bytes, &mut i, seq_len, "a sequence")?)?;
 
-        // In order to reduce the size of the bit len field, having a field for its length.
-        let seq_len_len = bytes[i] as usize;
-        i += 1;
-
-        let seq_len = slice_to_long(&bytes[i..i + seq_len_len]) as usize;
-        i += seq_len_len;
-        let seq = BitSequence::from_stream(&bytes[i.. i + seq_len]);
-        i += seq_len;
-
-        assert_eq!(i, bytes.len());
-        Wzfile::new(map.take(), seq)
+        Ok((Wzfile { map, seq, order: BitOrder::Lsb }, i))
     }
 
     fn to_stream(self) -> Vec<u8> {
-        let mut retval = vec![];
-
-        let mut map_bytes = self.map.to_stream();
-        // Add length of frequency mapping
-        retval.append(&mut long_to_bytes(map_bytes.len() as u64, MAP_SIZE_FIELD_LEN as u8));
-        retval.append(&mut map_bytes);
-
-        // Add length of sequence
-        let mut seq_bytes = self.seq.to_stream();
-
-        let size = seq_bytes.len() as u64;
-        // Need to know the width of the seq size field for deserialization!
-        let size_width = min_byte_size(size);
-        retval.push(size_width);
-        // Now, append that many bytes representing the size of the seq.
-        retval.append(&mut long_to_bytes(size, size_width));
-        // And finally, append the actual sequence
-        retval.append(&mut seq_bytes);
-
-        retval
+        write_wzfile(self.map, self.seq.to_packed_stream(self.order))
     }
 }
 
+fn write_wzfile(map: Freqmap, mut seq_bytes: Vec<u8>) -> Vec<u8> {
+    let mut retval = vec![];
+
+    let mut map_bytes = map.to_stream();
+    // Add length of frequency mapping
+    write_map_len(&mut retval, map_bytes.len());
+    retval.append(&mut map_bytes);
+
+    // Add length of sequence, and then the actual sequence.
+    write_sequence_len(&mut retval, seq_bytes.len());
+    retval.append(&mut seq_bytes);
+
+    retval
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
-    use crate::encoding::bitsequence::BitSequence;
+    use crate::encoding::bitsequence::{BitOrder, BitSequence};
     use crate::file::bytestream::ByteStream;
     use crate::file::wzfile::Wzfile;
 
@@ -101,10 +124,10 @@ mod tests {
     fn test_no_len() {
         let empty_map = HashMap::new();
         let empty_seq = BitSequence::new();
-        let expected = Wzfile::new(empty_map, empty_seq);
+        let expected = Wzfile::new(empty_map, empty_seq, BitOrder::Lsb);
 
         let to = expected.clone().to_stream();
-        let from = Wzfile::from_stream(&to);
+        let from = Wzfile::from_whole_stream(&to).unwrap();
 
         assert_eq!(expected, from);
     }
@@ -121,11 +144,16 @@ mod tests {
             seq.append_bit(i % 2);
         }
 
-        let expected = Wzfile::new(map, seq);
+        let expected = Wzfile::new(map, seq, BitOrder::Lsb);
 
         let to = expected.clone().to_stream();
-        let from = Wzfile::from_stream(&to);
+        let from = Wzfile::from_whole_stream(&to).unwrap();
 
         assert_eq!(expected, from);
+
+        // Any shorter, and something's missing.
+        for len in 0..to.len() {
+            assert!(Wzfile::from_stream(&to[..len]).is_err());
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,94 @@
+// XXH64, a fast non-cryptographic hash.
+// Much quicker than CRC-32 byte by byte, since it reads whole words at a time.
+// Author: Will Morris
+
+const PRIME_1: u64 = 0x9e3779b185ebca87;
+const PRIME_2: u64 = 0xc2b2ae3d27d4eb4f;
+const PRIME_3: u64 = 0x165667b19e3779f9;
+const PRIME_4: u64 = 0x85ebca77c2b2ae63;
+const PRIME_5: u64 = 0x27d4eb2f165667c5;
+
+// Input is read 32 bytes at a time, into four lanes.
+const STRIPE_LEN: usize = 32;
+
+fn round(lane: u64, input: u64) -> u64 {
+    lane.wrapping_add(input.wrapping_mul(PRIME_2)).rotate_left(31).wrapping_mul(PRIME_1)
+}
+
+fn merge_round(hash: u64, lane: u64) -> u64 {
+    (hash ^ round(0, lane)).wrapping_mul(PRIME_1).wrapping_add(PRIME_4)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8]) -> u64 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap()) as u64
+}
+
+pub fn xxhash64(bytes: &[u8], seed: u64) -> u64 {
+    let stripes = bytes.chunks_exact(STRIPE_LEN);
+    let tail = stripes.remainder();
+
+    let mut hash = match bytes.len() >= STRIPE_LEN {
+        true => {
+            let mut lanes = [
+                seed.wrapping_add(PRIME_1).wrapping_add(PRIME_2),
+                seed.wrapping_add(PRIME_2),
+                seed,
+                seed.wrapping_sub(PRIME_1),
+            ];
+            for stripe in stripes {
+                for (lane, word) in lanes.iter_mut().zip(stripe.chunks_exact(8)) {
+                    *lane = round(*lane, read_u64(word));
+                }
+            }
+            let hash = lanes[0].rotate_left(1).wrapping_add(lanes[1].rotate_left(7))
+                .wrapping_add(lanes[2].rotate_left(12)).wrapping_add(lanes[3].rotate_left(18));
+            lanes.iter().fold(hash, |hash, lane| merge_round(hash, *lane))
+        }
+        false => { seed.wrapping_add(PRIME_5) }
+    };
+    hash = hash.wrapping_add(bytes.len() as u64);
+
+    // Whatever's left over is mixed in a word, then half a word, then a byte at a time.
+    let words = tail.chunks_exact(8);
+    let rest = words.remainder();
+    for word in words {
+        hash = (hash ^ round(0, read_u64(word))).rotate_left(27).wrapping_mul(PRIME_1).wrapping_add(PRIME_4);
+    }
+    let bytes = match rest.len() >= 4 {
+        true => {
+            hash = (hash ^ read_u32(rest).wrapping_mul(PRIME_1)).rotate_left(23).wrapping_mul(PRIME_2).wrapping_add(PRIME_3);
+            &rest[4..]
+        }
+        false => { rest }
+    };
+    for byte in bytes {
+        hash = (hash ^ (*byte as u64).wrapping_mul(PRIME_5)).rotate_left(11).wrapping_mul(PRIME_1);
+    }
+
+    // Avalanche, so every bit of input affects every bit of output.
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME_3);
+    hash ^ (hash >> 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::xxhash::xxhash64;
+
+    #[test]
+    fn test_xxhash64() {
+        // Reference values, from too short for the lanes to long enough for every kind of leftover.
+        assert_eq!(0xef46db3751d8e999, xxhash64(b"", 0));
+        assert_eq!(0x44bc2cf5ad770999, xxhash64(b"abc", 0));
+        assert_eq!(0xfbcea83c8a378bf1, xxhash64(b"Nobody inspects the spammish repetition", 0));
+        let bytes: Vec<u8> = (0..45).collect();
+        assert_eq!(0x10fdd84d6409abdf, xxhash64(&bytes, 0));
+        assert_ne!(xxhash64(b"abc", 0), xxhash64(b"abc", 1));
+    }
+}
@@ -0,0 +1,251 @@
+// Reading .zip files, so their members can be listed and extracted.
+// Only what's needed to read the common cases: stored and deflated members, without encryption or zip64.
+// Author: Will Morris
+
+/*
+  CONTENTS (of the parts we read):
+  -- for each member, a local header and the member's compressed data
+  -- the central directory, with an entry for each member
+  -- end of central directory record, pointing back to the central directory.
+
+  The central directory is the authority on what's in the file, so it's read first.
+ */
+
+use crate::codec::inflate::inflate;
+use crate::file::crc32::crc32;
+
+const LOCAL_MAGIC: [u8; 4] = *b"PK\x03\x04";
+const CENTRAL_MAGIC: [u8; 4] = *b"PK\x01\x02";
+const END_MAGIC: [u8; 4] = *b"PK\x05\x06";
+const LOCAL_HEADER_LEN: usize = 30;
+const CENTRAL_HEADER_LEN: usize = 46;
+const END_LEN: usize = 22;
+// The end record may be followed by a comment, up to this long.
+const MAX_COMMENT_LEN: usize = u16::MAX as usize;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+const ENCRYPTED: u16 = 1;
+// Set when names are UTF-8. Otherwise they're in code page 437, as DOS had them.
+const UTF8_NAMES: u16 = 1 << 11;
+
+// The top half of code page 437. The bottom half is ASCII.
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ZipEntry {
+    // Relative path of the member, with '/' separating directories.
+    // Directories are members too, with a trailing '/'.
+    pub(crate) name: String,
+    method: u16,
+    flags: u16,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+    // Where the member's local header starts.
+    offset: usize,
+}
+
+impl ZipEntry {
+    pub(crate) fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+// Does this look like a zip file? Empty zip files are nothing but an end record.
+// wz files and archives start with the width of a length, which is never 'P'.
+pub(crate) fn is_zip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&LOCAL_MAGIC) || bytes.starts_with(&END_MAGIC)
+}
+
+// Read the central directory, describing every member.
+pub(crate) fn read_entries(bytes: &[u8]) -> Result<Vec<ZipEntry>, String> {
+    let end = find_end(bytes)?;
+    let count = read_short(bytes, end + 10)? as usize;
+    let mut i = read_int(bytes, end + 16)? as usize;
+
+    let mut entries = vec![];
+    for _ in 0..count {
+        if bytes.get(i..i + 4) != Some(&CENTRAL_MAGIC[..]) {
+            return Err(String::from("Corrupt zip central directory!"))
+        }
+        let name_len = read_short(bytes, i + 28)? as usize;
+        let extra_len = read_short(bytes, i + 30)? as usize;
+        let comment_len = read_short(bytes, i + 32)? as usize;
+        let name = bytes.get(i + CENTRAL_HEADER_LEN..i + CENTRAL_HEADER_LEN + name_len)
+            .ok_or("Corrupt zip central directory!")?;
+        let flags = read_short(bytes, i + 8)?;
+
+        entries.push(ZipEntry {
+            name: decode_name(name, flags),
+            flags,
+            method: read_short(bytes, i + 10)?,
+            crc: read_int(bytes, i + 16)?,
+            compressed_size: read_int(bytes, i + 20)? as usize,
+            size: read_int(bytes, i + 24)? as usize,
+            offset: read_int(bytes, i + 42)? as usize,
+        });
+        i += CENTRAL_HEADER_LEN + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+// Decompress one member, checking it against its CRC.
+pub(crate) fn read_contents(bytes: &[u8], entry: &ZipEntry) -> Result<Vec<u8>, String> {
+    if entry.flags & ENCRYPTED != 0 {
+        return Err(format!("{} is encrypted!", entry.name))
+    }
+    let i = entry.offset;
+    if bytes.get(i..i + 4) != Some(&LOCAL_MAGIC[..]) {
+        return Err(format!("Corrupt zip header for {}!", entry.name))
+    }
+    // The local header's lengths can differ from the central directory's, so they're read again.
+    let start = i + LOCAL_HEADER_LEN + read_short(bytes, i + 26)? as usize + read_short(bytes, i + 28)? as usize;
+    let data = bytes.get(start..start + entry.compressed_size)
+        .ok_or(format!("{} runs past the end of the zip file!", entry.name))?;
+
+    let contents = match entry.method {
+        STORED => { data.to_vec() }
+        // Anything more than the size recorded would fail the check below anyway.
+        DEFLATED => { inflate(data, entry.size)?.0 }
+        method => { return Err(format!("{} uses unsupported compression method {}!", entry.name, method)) }
+    };
+    if contents.len() != entry.size || crc32(&contents) != entry.crc {
+        return Err(format!("{} failed its checksum!", entry.name))
+    }
+    Ok(contents)
+}
+
+// Names are UTF-8 when flagged so, and code page 437 otherwise. The two agree on ASCII.
+// Some Windows tools separate directories with '\', though zip says '/', so either is taken as a separator.
+fn decode_name(name: &[u8], flags: u16) -> String {
+    let decoded = match flags & UTF8_NAMES != 0 {
+        true => { String::from_utf8_lossy(name).into_owned() }
+        false => {
+            name.iter().map(|byte| match byte.checked_sub(0x80) {
+                Some(high) => { CP437_HIGH.chars().nth(high as usize).unwrap() }
+                None => { *byte as char }
+            }).collect()
+        }
+    };
+    decoded.replace('\\', "/")
+}
+
+// Search backwards for the end record, skipping over any comment.
+fn find_end(bytes: &[u8]) -> Result<usize, String> {
+    let last = bytes.len().checked_sub(END_LEN).ok_or("Not a zip file!")?;
+    let first = last.saturating_sub(MAX_COMMENT_LEN);
+    let end = (first..=last).rev().find(|i| bytes[*i..].starts_with(&END_MAGIC))
+        .ok_or("Not a zip file!")?;
+    // zip64 marks its records as full, and keeps the real values elsewhere.
+    if read_short(bytes, end + 10)? == u16::MAX || read_int(bytes, end + 16)? == u32::MAX {
+        return Err(String::from("zip64 files are not supported!"))
+    }
+    Ok(end)
+}
+
+fn read_short(bytes: &[u8], i: usize) -> Result<u16, String> {
+    let field = bytes.get(i..i + 2).ok_or("zip file ended early!")?;
+    Ok(u16::from_le_bytes([field[0], field[1]]))
+}
+
+fn read_int(bytes: &[u8], i: usize) -> Result<u32, String> {
+    let field = bytes.get(i..i + 4).ok_or("zip file ended early!")?;
+    Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::deflate::deflate;
+    use crate::file::crc32::crc32;
+    use crate::file::zip::{CENTRAL_MAGIC, decode_name, DEFLATED, END_MAGIC, is_zip, LOCAL_MAGIC, read_contents, read_entries,
+                           STORED, UTF8_NAMES};
+
+    // Lay out a zip file the way other tools do, minus anything we don't read.
+    fn make_zip(members: &[(&str, u16, &[u8])]) -> Vec<u8> {
+        let mut retval = vec![];
+        let mut central = vec![];
+        for (name, method, contents) in members {
+            let data = match *method {
+                DEFLATED => { deflate(contents, vec![]).unwrap() }
+                _ => { contents.to_vec() }
+            };
+            let mut fields = vec![];
+            fields.extend_from_slice(&[20, 0, 0, 0]);
+            fields.extend_from_slice(&method.to_le_bytes());
+            fields.extend_from_slice(&[0; 4]);
+            fields.extend_from_slice(&crc32(contents).to_le_bytes());
+            fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&[0; 2]);
+
+            central.extend_from_slice(&CENTRAL_MAGIC);
+            central.extend_from_slice(&[20, 0]);
+            central.extend_from_slice(&fields);
+            central.extend_from_slice(&[0; 10]);
+            central.extend_from_slice(&(retval.len() as u32).to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+
+            retval.extend_from_slice(&LOCAL_MAGIC);
+            retval.extend_from_slice(&fields);
+            retval.extend_from_slice(name.as_bytes());
+            retval.extend_from_slice(&data);
+        }
+        let offset = retval.len() as u32;
+        retval.extend_from_slice(&central);
+        retval.extend_from_slice(&END_MAGIC);
+        retval.extend_from_slice(&[0; 4]);
+        retval.extend_from_slice(&(members.len() as u16).to_le_bytes());
+        retval.extend_from_slice(&(members.len() as u16).to_le_bytes());
+        retval.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        retval.extend_from_slice(&offset.to_le_bytes());
+        retval.extend_from_slice(&[0; 2]);
+        retval
+    }
+
+    #[test]
+    fn test_entries() {
+        let zip = make_zip(&[("dir/", STORED, b""), ("dir/a.txt", STORED, b"a"), ("b.txt", DEFLATED, b"b")]);
+        assert!(is_zip(&zip));
+        let entries = read_entries(&zip).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(vec!["dir/", "dir/a.txt", "b.txt"], names);
+        assert!(entries[0].is_dir());
+        assert!(!entries[1].is_dir());
+
+        let empty = make_zip(&[]);
+        assert!(is_zip(&empty));
+        assert!(read_entries(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decode_name() {
+        assert_eq!("dir/a.txt", decode_name(b"dir/a.txt", 0));
+        assert_eq!("dir/a.txt", decode_name(b"dir\\a.txt", 0));
+        assert_eq!("caf\u{e9}.txt", decode_name("caf\u{e9}.txt".as_bytes(), UTF8_NAMES));
+        // Without the flag, the same bytes are read as DOS wrote them.
+        assert_eq!("\u{fc}ber.txt", decode_name(b"\x81ber.txt", 0));
+        assert_eq!("caf\u{251c}\u{2310}.txt", decode_name("caf\u{e9}.txt".as_bytes(), 0));
+    }
+
+    #[test]
+    fn test_contents() {
+        let text = b"deflated, deflated, deflated\n".repeat(10);
+        let zip = make_zip(&[("stored.txt", STORED, b"as it is"), ("deflated.txt", DEFLATED, &text)]);
+        let entries = read_entries(&zip).unwrap();
+        assert_eq!(b"as it is".to_vec(), read_contents(&zip, &entries[0]).unwrap());
+        assert_eq!(text, read_contents(&zip, &entries[1]).unwrap());
+
+        let mut corrupt = zip.clone();
+        let offset = corrupt.windows(8).position(|window| window == b"as it is").unwrap();
+        corrupt[offset] ^= 1;
+        assert!(read_contents(&corrupt, &entries[0]).is_err());
+    }
+
+    #[test]
+    fn test_not_zip() {
+        assert!(!is_zip(b"hello"));
+        assert!(read_entries(b"hello").is_err());
+    }
+}
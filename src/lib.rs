@@ -0,0 +1,126 @@
+// The parts of will_zip that other crates can build on.
+// The compressor itself is in main.rs, which uses these same modules.
+// Author: Will Morris
+
+// Derived ByteStreams name their helpers by crate, which needs to work in here too.
+extern crate self as will_zip;
+
+// Huffman trees, built from frequencies, or learned as a block is read.
+pub mod tree {
+    pub mod node;
+    // A Huffman tree which learns its code as it goes.
+    pub mod adaptive;
+}
+
+// The core of the program revolves around ordering bytes by their precedence.
+pub mod ordering {
+    // Generates an ordering of bytes-frequency of appearance.
+    pub mod freq;
+    pub mod symbolfreq;
+    pub mod freqmap;
+    // Built in frequency tables, for when storing one would cost too much.
+    pub mod presets;
+    // Frequency tables trained on the user's own data.
+    pub mod dictionary;
+}
+
+// Encodings are used when serializing the file to save space.
+pub mod encoding {
+    // Represents a list of bits, compressed using bitwise ops into a vec<u8>
+    pub mod bitsequence;
+    // Codes and tables of them, for coding bytes without a BitSequence per code.
+    pub mod code;
+    // Huffman coding into fixed size buffers, with no heap at all.
+    #[cfg(feature = "heapless")]
+    pub mod fixed;
+    // The encoder below codes blocks with it, whether or not it's offered on its own.
+    #[cfg(not(feature = "heapless"))]
+    pub(crate) mod fixed;
+    // An alternative to Huffman, which can spend fractions of a bit per byte.
+    pub mod rangecoder;
+    // LZW builds a dictionary of strings as it goes, and sends each string as a single code.
+    pub mod lzw;
+    // Reads bits in order, from a sequence or any other stream.
+    pub mod bitreader;
+    // Writes bits out as they're made, to any stream.
+    pub mod bitwriter;
+    // Turns bits back into bytes.
+    pub mod decodingtable;
+    // Huffman codes anyone can rebuild from their lengths.
+    pub mod canonical;
+}
+
+// Relevant to the actual act of saving the file.
+pub mod file {
+    // Anything which can be represented as a stream of bytes uses this trait.
+    pub mod bytestream;
+    // Walking the framed blocks of a compressed stream, finding them in one too large to hold, and reading ranges from them.
+    pub mod blocks;
+    // What goes wrong when a stream can't be read.
+    pub mod error;
+    // Where each field of a wzfile goes, and how wide it is.
+    pub mod format;
+    // Frames, indexes, and the kinds of block the encoder below writes.
+    pub(crate) mod stream;
+    pub mod wzfile;
+    // Many files, compressed together. Dictionaries are identified by its hash.
+    pub mod archive;
+    // Checksums data, as gzip expects.
+    pub mod crc32;
+    // A faster checksum, a word at a time.
+    pub mod xxhash;
+    // A cryptographic hash, for checking against tampering.
+    pub mod sha256;
+    // Lets files choose how their output is checked.
+    pub mod checksum;
+    // Finding the members of a tar stream, and indexing them.
+    pub mod tar;
+}
+
+// Reversible rearrangements of bytes, which make them easier to compress.
+pub mod transform {
+    // The Burrows-Wheeler transform groups bytes by their context.
+    pub mod bwt;
+    // Move-to-front coding turns recently seen bytes into small numbers.
+    pub mod mtf;
+    // LZ77 replaces repeated strings with references to earlier copies.
+    pub mod lz77;
+    // Run-length encoding squashes runs of one byte.
+    pub mod rle;
+    // Splits text into words, for coding whole words at once.
+    pub mod words;
+    // Splits UTF-8 text into characters, for coding whole characters at once.
+    pub mod chars;
+    // Byte pair encoding swaps common pairs of bytes for spare ones.
+    pub mod bpe;
+    // Transposition stores a table of records column by column.
+    pub mod transpose;
+    // Byte deltas store how much each byte differs from the last.
+    pub mod bytedelta;
+    // Filters which run over a block before it's coded.
+    pub mod filter;
+}
+
+// Information about how the program itself is running.
+pub mod diagnostics {
+    // Tracks time and memory spent in each phase of the program.
+    pub mod report;
+}
+
+// Turning raw bytes into compressed bytes, and back again.
+// Every kind of block is coded here, so wz and WzDecoder read blocks the same way.
+pub mod codec {
+    // Compresses and decompresses a single block.
+    pub mod block;
+    // The coders a block can be coded with.
+    pub mod coder;
+}
+
+// How hard the encoder works, as flate2::Compression.
+mod compression;
+pub use compression::Compression;
+
+// Decompressing a wz stream through Read, as flate2::read.
+pub mod read;
+// Compressing into a wz stream through Write, as flate2::write.
+pub mod write;
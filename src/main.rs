@@ -1,149 +1,1857 @@
 use std::env;
 use std::fs;
 use std::fs::File;
-use std::io::{Read, stdin, stdout, Write};
+use std::fs::{OpenOptions, TryLockError};
+use std::io::{IsTerminal, Read, Seek, SeekFrom, stdin, stdout, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::path::Path;
 use std::process::exit;
-use getopts::Options;
-use crate::encoding::bitsequence::BitSequence;
-use crate::file::bytestream::ByteStream;
-use crate::file::wzfile::Wzfile;
-use crate::ordering::freq::gen_frequency;
-use crate::tree::node::huffman;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use getopts::{Matches, Options};
+use crate::codec::archiver::{ArchiveOptions, create_archive, extract_archive, extract_tar_member, extract_zip, list_archive, Symlinks,
+                             sync_archive};
+use crate::codec::block::{block_header, block_kind, BlockKind, checksum_block, compress_block, compress_smallest, decompress_block, decompress_block_with_limit, index_block, Method, verify_checksum};
+use crate::codec::coder::{CANONICAL, CODERS, CONTEXT, DICTIONARY, EntropyCoder, LZ77, PRESET, RANGE, TABLE};
+use crate::codec::comparer::{Comparer, Difference};
+use crate::codec::concat::{concat_archives, concat_streams};
+use crate::codec::control::{Action, Progress, serve_requests, Task};
+use crate::codec::differ::{apply_patch, make_patch};
+use crate::codec::dumper::dump_stream;
+use crate::codec::follow::{Flush, follow};
+use crate::codec::inspector::{inspect_blocks, inspect_codes, inspect_trees};
+use crate::codec::pipe::{receive, send};
+use crate::codec::selfcheck::self_check;
+use crate::codec::trainer::train_dictionary;
+use crate::codec::vectors::write_vectors;
+use crate::diagnostics::failure::{Failure, FailureKind};
+use crate::diagnostics::report::Report;
+use crate::encoding::bitsequence::BitOrder;
+use crate::file::blocks::{blocks, DEFAULT_BLOCK_SIZE, frame_block, read_range_with};
+use crate::file::checkpoint::Checkpoint;
+use crate::file::checksum::{Checksum, parse_checksum};
+use crate::file::codetable::load_codes;
+use crate::file::error::WzError;
+use crate::file::journal::{Journal, recover, Recovery};
+use crate::file::chunking::{content_defined_chunks, RSYNCABLE_AVERAGE};
+use crate::file::armor::{Armored, dearmor, is_armored};
+use crate::file::gzip::{gunzip, gzip, is_gzip};
+use crate::file::tar::{read_tar, tar_blocks, TarIndex};
+use crate::file::zip::is_zip;
+use crate::system::deadline::{expired, parse_duration, TIMED_OUT, watchdog};
+use crate::system::endpoint::{Endpoint, is_regular, is_url, sync_directory};
+use crate::system::glob::expand;
+use crate::system::owners::is_root;
+use crate::system::pipeline::{DEPTH_PER_THREAD, run_bounded};
+use crate::system::priority::lower_priority;
+use crate::system::throttle::Throttled;
+use crate::system::watcher::watch;
+use crate::ordering::dictionary::Dictionary;
+use crate::ordering::presets::Preset;
+use crate::transform::bwt::BWT_BLOCK_SIZE;
+use crate::transform::filter::{Bwt, parse_filters, Rle};
 
 // Given a file F, this program converts F into a HuffmanEncoding and saves a copy of it
 // Or given an already-encoded file F', this program converts it into a decoded file F.
 // Author: Will Morris
 
-mod tree {
-    pub(crate) mod node;
-}
-
 // The core of the program revolves around ordering bytes by their precedence.
 mod ordering {
-    // Generates an ordering of bytes-frequency of appearance.
-    pub(crate) mod freq;
-    pub(crate) mod bytefreq;
-    pub(crate) mod freqmap;
+    pub(crate) use will_zip::ordering::symbolfreq;
+    // Built in frequency tables, for when storing one would cost too much.
+    pub(crate) use will_zip::ordering::presets;
+    // Frequency tables trained on the user's own data.
+    pub(crate) use will_zip::ordering::dictionary;
 }
 
 // Encodings are used when serializing the file to save space.
 mod encoding {
     // Represents a list of bits, compressed using bitwise ops into a vec<u8>
-    pub(crate) mod bitsequence;
+    // Shared with other crates, so it lives in the library.
+    pub(crate) use will_zip::encoding::bitsequence;
+    // Reads bits in order, from a sequence or any other stream.
+    pub(crate) use will_zip::encoding::bitreader;
+    // Writes bits out as they're made, to any stream.
+    pub(crate) use will_zip::encoding::bitwriter;
+    // Turns bits back into bytes.
+    pub(crate) use will_zip::encoding::decodingtable;
+    // Huffman codes anyone can rebuild from their lengths.
+    pub(crate) use will_zip::encoding::canonical;
+}
+
+// Turning raw bytes into compressed bytes, and back again.
+mod codec {
+    // Compresses and decompresses a single block.
+    pub(crate) use will_zip::codec::block;
+    // The coders a block can be coded with.
+    pub(crate) use will_zip::codec::coder;
+    // Compresses and extracts many files at once.
+    pub(crate) mod archiver;
+    // Builds dictionaries from sample files.
+    pub(crate) mod trainer;
+    // Joins compressed files without recompressing them.
+    pub(crate) mod concat;
+    // Makes patches between versions of a file.
+    pub(crate) mod differ;
+    // Shows how a compressed file was coded.
+    pub(crate) mod inspector;
+    // Shows every byte of a compressed file, and which field it belongs to.
+    pub(crate) mod dumper;
+    // Checks decompressed output against the original, without writing it.
+    pub(crate) mod comparer;
+    // Sends compressed blocks from one machine to another.
+    pub(crate) mod pipe;
+    // Compresses a file as it grows.
+    pub(crate) mod follow;
+    // Writes deflate streams, for gzip.
+    pub(crate) mod deflate;
+    // Reads deflate streams back.
+    pub(crate) mod inflate;
+    // Checks that everything round trips on this build.
+    pub(crate) mod selfcheck;
+    // Writes inputs and the streams they should make, for other implementations to check against.
+    pub(crate) mod vectors;
+    // Takes jobs from a frontend, as JSON-RPC on stdio.
+    pub(crate) mod control;
 }
 
 // Relevant to the actual act of saving the file.
 mod file {
     // Anything which can be represented as a stream of bytes uses this trait.
     // This allows for easier deserialization... given a byte array, an object will come out!
-    pub(crate) mod bytestream;
-    pub(crate) mod wzfile;
+    // BitSequences are serialized with it, so it lives in the library too.
+    pub(crate) use will_zip::file::bytestream;
+    // Why a stream couldn't be read, for ByteStreams that fail.
+    pub(crate) use will_zip::file::error;
+    // Where each field of a wzfile goes. Shared with the library, so both lay files out the same way.
+    pub(crate) use will_zip::file::format;
+    // A compressed file is a series of independently compressed blocks. Shared with the library, so other crates can walk them too.
+    pub(crate) use will_zip::file::blocks;
+    // Records the progress of a compression, so that it can be resumed.
+    pub(crate) mod checkpoint;
+    // Splits a stream into chunks wherever its contents say to.
+    pub(crate) mod chunking;
+    // Many files, compressed together.
+    pub(crate) use will_zip::file::archive;
+    // The names members are stored under, and the paths they're extracted to.
+    pub(crate) mod names;
+    // Checksums data, as gzip expects.
+    pub(crate) use will_zip::file::crc32;
+    // A faster checksum, a word at a time.
+    pub(crate) use will_zip::file::xxhash;
+    // Lets files choose how their output is checked.
+    pub(crate) use will_zip::file::checksum;
+    // Output that gunzip and friends can read, and reading theirs.
+    pub(crate) mod gzip;
+    // Output as base64 text, for pasting where bytes can't go.
+    pub(crate) mod armor;
+    // Tables of codes, in a form other tools can read.
+    pub(crate) mod codetable;
+    // Reading and writing JSON, for code tables and frontends.
+    pub(crate) mod json;
+    // Reading other people's zip files.
+    pub(crate) mod zip;
+    // Finding the members of a tar stream, and indexing them.
+    pub(crate) use will_zip::file::tar;
+    // The differences between two versions of a file.
+    pub(crate) mod patch;
+    // Which files a watched directory held when they were last compressed.
+    pub(crate) mod watchstate;
+    // Records an archive's update until it's done, so a crash can't leave it half updated.
+    pub(crate) mod journal;
 }
 
-fn main() {
+// Reversible rearrangements of bytes, which make them easier to compress.
+mod transform {
+    // The Burrows-Wheeler transform groups bytes by their context.
+    pub(crate) use will_zip::transform::bwt;
+    // LZ77 replaces repeated strings with references to earlier copies.
+    pub(crate) use will_zip::transform::lz77;
+    // Filters which run over a block before it's coded.
+    pub(crate) use will_zip::transform::filter;
+    // Describes one version of a file as copies from another.
+    pub(crate) mod delta;
+}
+
+// Information about how the program itself is running.
+mod diagnostics {
+    // Tracks time and memory spent in each phase of the program.
+    pub(crate) use will_zip::diagnostics::report;
+    // Says why a run failed, to people or to other tools.
+    pub(crate) mod failure;
+}
+
+// Interactions with the operating system.
+mod system {
+    // Lets background jobs get out of the way.
+    pub(crate) mod priority;
+    // Lets input and output be pipes and sockets, as well as files.
+    pub(crate) mod endpoint;
+    // Compresses files as they appear in a directory.
+    pub(crate) mod watcher;
+    // Expands wildcards in file arguments, which Windows shells leave alone.
+    pub(crate) mod glob;
+    // Makes the symbolic links archives hold, and finds the hard ones.
+    pub(crate) mod links;
+    // Finds the holes in sparse files, so they're never read.
+    pub(crate) mod sparse;
+    // Reads and writes the extended attributes archives can keep.
+    pub(crate) mod xattrs;
+    // Finds and changes who owns files, for archives which keep it.
+    pub(crate) mod owners;
+    // Gives up on jobs which run out of time.
+    pub(crate) mod deadline;
+    // Keeps reading and writing under a rate, for jobs sharing a link.
+    pub(crate) mod throttle;
+    // Runs independent jobs on several threads at once.
+    pub(crate) mod workers;
+    // Runs jobs on several threads at once, handing results on in order without getting too far ahead.
+    pub(crate) mod pipeline;
+}
+
+// Everything the user asked for on the command line.
+struct Settings {
     // If not specified, use stdin/out
-    let mut input_file: Option<String> = None;
-    let mut output_file: Option<String> = None;
-    let mut zip = false;
+    input_file: Option<String>,
+    output_file: Option<String>,
+    zip: bool,
     // Unzip isn't strictly necessary, but I'm keeping it around for potential future use.
-    let mut unzip = false;
+    unzip: bool,
+    report_resources: bool,
+    nice: bool,
+    // How many bytes of input are compressed together.
+    block_size: usize,
+    // Whether block boundaries are chosen by content, rather than every block_size bytes.
+    rsyncable: bool,
+    // How blocks are compressed.
+    method: Method,
+    // Whether to compress many files together, rather than a single stream.
+    archive: bool,
+    // When creating an archive, the files and directories to put in it.
+    members: Vec<String>,
+    // What archives keep besides contents: whether symbolic links are followed, and whether attributes are kept.
+    archive_options: ArchiveOptions,
+    // Whether to record progress so that an interrupted compression can be resumed.
+    checkpoint: bool,
+    resume: bool,
+    // Whether to write a gzip file instead of a wz file.
+    gzip: bool,
+    // Whether compressed output is written as base64 text.
+    armor: bool,
+    // When extracting a compressed tar stream, the one member to extract.
+    member: Option<String>,
+    // If not empty, each block is compressed with every one of these, and the smallest kept.
+    // This takes the place of method.
+    candidates: Vec<Method>,
+    // When extracting a single stream, the most bytes it may decompress to.
+    max_output_size: Option<u64>,
+    // When extracting a single stream, the most times larger than itself it may decompress to.
+    max_expansion: Option<u64>,
+    // When extracting a single stream, the most entries any block's frequency map or code table may have.
+    max_map_entries: Option<usize>,
+    // When extracting a single stream, the most blocks it may have.
+    max_blocks: Option<usize>,
+    // When extracting a single stream, the most bytes any block may have before its coded data.
+    max_header_size: Option<usize>,
+    // When compressing a single stream, how many blocks are compressed at once.
+    threads: usize,
+    // When compressing a single stream, what to check it with on the way out.
+    checksum: Option<&'static dyn Checksum>,
+    // When extracting from a file, the start and length of the only bytes wanted from it.
+    byte_range: Option<(u64, u64)>,
+    // When compressing a file as it grows, the longest any of it may wait to be written.
+    follow: Option<Duration>,
+    // Whether the output file, and its entry in its directory, must be on disk before success is reported.
+    durable: bool,
+    // When the job must be done by.
+    deadline: Option<Instant>,
+    // The most bytes a second input may be read, and output written, at.
+    rate_limit: Option<u64>,
+    // Whether failures are printed as JSON records on stderr, rather than sentences.
+    json_errors: bool,
+}
+
+impl Settings {
+    fn new() -> Self {
+        Settings {
+            input_file: None,
+            output_file: None,
+            zip: false,
+            unzip: false,
+            report_resources: false,
+            nice: false,
+            block_size: DEFAULT_BLOCK_SIZE,
+            rsyncable: false,
+            method: Method::new(),
+            archive: false,
+            members: vec![],
+            archive_options: ArchiveOptions::new(),
+            checkpoint: false,
+            resume: false,
+            gzip: false,
+            armor: false,
+            member: None,
+            candidates: vec![],
+            max_output_size: None,
+            max_expansion: None,
+            max_map_entries: None,
+            max_blocks: None,
+            max_header_size: None,
+            threads: 1,
+            checksum: None,
+            byte_range: None,
+            follow: None,
+            durable: false,
+            deadline: None,
+            rate_limit: None,
+            json_errors: false,
+        }
+    }
+
+    // How big a single extracted stream may get.
+    fn limits(&self) -> Limits {
+        Limits {
+            max_out: self.max_output_size,
+            max_expansion: self.max_expansion,
+            max_map_entries: self.max_map_entries,
+            max_blocks: self.max_blocks,
+            max_header_size: self.max_header_size,
+        }
+    }
+}
+
+fn main() {
+    // Subcommands come before any options.
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).is_some_and(|arg| arg == "train") {
+        exit(train(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "list") {
+        exit(list(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "recompress") {
+        exit(recompress(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "selfcheck") {
+        exit(selfcheck(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "gen-vectors") {
+        exit(gen_vectors(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "cat") {
+        exit(cat(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "diff") {
+        exit(diff(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "cmp") {
+        exit(cmp(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "patch") {
+        exit(patch(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "inspect") {
+        exit(inspect(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "dump") {
+        exit(dump(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "send") {
+        exit(send_file(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "serve") {
+        exit(serve(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "sync") {
+        exit(sync(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "watch") {
+        exit(watch_dir(&args[2..]))
+    }
+    if args.get(1).is_some_and(|arg| arg == "--server") {
+        exit(server(&args[2..]))
+    }
+
+    let mut settings = Settings::new();
 
-    if let Some(exit_code) =
-        parse_args(&mut input_file, &mut output_file, &mut zip, &mut unzip) {
+    if let Some(exit_code) = parse_args(&mut settings) {
         println!("Terminating.");
         exit(exit_code)
     };
 
-    // Now, prepare input and output data for compression.
-    let bytes: Vec<u8>;
+    // Lowering priority is only a courtesy, so failing to do so isn't fatal.
+    if settings.nice {
+        if let Err(message) = lower_priority() {
+            eprintln!("{}", message);
+        }
+    }
+
+    let mut report = Report::new(settings.report_resources);
 
-    // Use stdin or the specified input file.
-    if let Some(filename) = input_file {
-        bytes = match fs::read(&filename) {
-            Ok(val) => { val }
-            Err(_) => {
-                println!("File not found: {}", &filename);
-                exit(1)
+    // Archives gather their own input from the member paths.
+    if settings.archive && settings.zip {
+        let result = create_archive(&settings.members, settings.block_size, &settings.method,
+                                    &settings.archive_options, &mut report);
+        match result {
+            Ok((archive, 0)) => { write_output(&settings, &archive, &mut report) }
+            Ok((archive, left_out)) => {
+                write_output(&settings, &archive, &mut report);
+                let message = format!("Timed out, so {} files and links were left out of the archive.", left_out);
+                Failure::new("archiving", FailureKind::Timeout, message).print(settings.json_errors);
+                report.print();
+                exit(TIMED_OUT)
             }
+            Err(message) => { fail(&settings, Failure::new("archiving", FailureKind::Failed, message)) }
+        }
+        report.print();
+        exit(0)
+    }
+
+    // Anything else is stopped where it is when time is up, except followed files, which finish their last block.
+    // Half written output goes too, unless a checkpoint can resume it.
+    if let (Some(deadline), None) = (settings.deadline, settings.follow) {
+        let partial = settings.output_file.clone()
+            .filter(|output| !settings.archive && !settings.checkpoint && !settings.resume && is_regular(output));
+        let timed_out = Failure::new(phase(&settings), FailureKind::Timeout, "Timed out!").in_file(settings.input_file.as_deref());
+        let json = settings.json_errors;
+        watchdog(deadline, move || {
+            timed_out.print(json);
+            if let Some(output) = partial {
+                let _ = fs::remove_file(output);
+            }
+        });
+    }
+
+    // Ranges are read straight from the file, so that only the blocks they lie in are ever read.
+    if let Some((start, len)) = settings.byte_range {
+        let filename = settings.input_file.as_ref().unwrap();
+        let result = File::open(filename)
+            .map_err(|_| Failure::new("reading", FailureKind::NotFound, format!("File not found: {}", filename)))
+            .and_then(|mut file| {
+                let dictionary = settings.method.dictionary.as_deref();
+                read_range_with(&mut file, start, len, |block| decompress_block(block, dictionary, &mut report))
+                    .map_err(|error| Failure::from_stream("decompressing", error))
+            });
+        match result {
+            Ok(to_write) => { write_output(&settings, &to_write, &mut report) }
+            Err(failure) => { fail(&settings, failure.in_file(Some(filename))) }
+        }
+        report.print();
+        exit(0)
+    }
+
+    // Followed files never end, so they're compressed as they're read, until the program is killed or time is up.
+    if let Some(interval) = settings.follow {
+        let filename = settings.input_file.as_ref().unwrap();
+        let result = File::open(filename)
+            .map_err(|_| Failure::new("reading", FailureKind::NotFound, format!("File not found: {}", filename)))
+            .and_then(|input| {
+                let mut input = Throttled::new(input, settings.rate_limit);
+                let output: Box<dyn Write> = match &settings.output_file {
+                    Some(output) => {
+                        Box::new(Endpoint::create(output).map_err(|message| Failure::new("writing", FailureKind::Io, message))?)
+                    }
+                    None => { Box::new(stdout()) }
+                };
+                let mut output = Throttled::new(output, settings.rate_limit);
+                let flush = Flush { block_size: settings.block_size, interval };
+                follow(&mut input, &mut output, &flush, &settings.method, settings.checksum, &mut report,
+                       || expired(settings.deadline))
+                    .map_err(|message| Failure::new("compressing", FailureKind::Io, message))
+            });
+        if let Err(failure) = result {
+            fail(&settings, failure.in_file(Some(filename)))
+        }
+        report.print();
+        match expired(settings.deadline) {
+            true => {
+                Failure::new("compressing", FailureKind::Timeout, "Timed out!").in_file(Some(filename)).print(settings.json_errors);
+                exit(TIMED_OUT)
+            }
+            false => { exit(0) }
         }
-    } else {
-        // I have to unwrap all the potential errors... on each byte.
-        bytes = stdin().bytes().map(| item | item.unwrap()).collect();
     }
 
+    // Now, prepare input and output data for compression.
+    let bytes = read_input(&settings, &mut report);
+    // Armored input is recognized by its header, and extracted like anything else.
+    let bytes = match !settings.zip && is_armored(&bytes) {
+        true => {
+            report.time("dearmor", || dearmor(&bytes)).unwrap_or_else(|message| {
+                let failure = Failure::new(phase(&settings), FailureKind::Corrupt, message);
+                fail(&settings, failure.in_file(settings.input_file.as_deref()))
+            })
+        }
+        false => { bytes }
+    };
+
     // We've validated that zip or unzip must be true.
     // So no need to check unzip here -- if not zip, then go!
-    let to_write = match zip {
-        true => { compress(&bytes) }
-        false => { decompress(&bytes) }
+    let result = match (settings.zip, settings.archive) {
+        // gzip files are a single stream, with no blocks of ours.
+        (true, _) if settings.gzip => {
+            let to_write = report.time("deflate", || gzip(&bytes));
+            write_output(&settings, &to_write, &mut report);
+            Ok(())
+        }
+        // Blocks are written out as they're compressed.
+        (true, _) => {
+            compress(&settings, &bytes, &mut report).map_err(|message| Failure::new("compressing", FailureKind::Failed, message))
+        }
+        // Archive output has been validated to be a directory name.
+        // zip files are recognized by their magic number, and extracted the same way.
+        (false, true) if is_zip(&bytes) => {
+            extract_zip(&bytes, settings.output_file.as_ref().unwrap(), &mut report)
+                .map_err(|message| Failure::new("extracting", FailureKind::Failed, message))
+        }
+        (false, true) => {
+            extract_archive(&bytes, settings.output_file.as_ref().unwrap(),
+                            settings.method.dictionary.as_deref(), &settings.archive_options, &mut report)
+                .map_err(|message| Failure::new("extracting", FailureKind::Failed, message))
+        }
+        (false, false) if is_zip(&bytes) => {
+            Err(Failure::new("extracting", FailureKind::Usage, "zip files hold many files, so they must be extracted with -a!"))
+        }
+        (false, false) if settings.member.is_some() => {
+            extract_tar_member(&bytes, settings.member.as_ref().unwrap(),
+                               settings.method.dictionary.as_deref(), &mut report)
+                .map(|to_write| write_output(&settings, &to_write, &mut report))
+                .map_err(|message| Failure::new("extracting", FailureKind::Failed, message))
+        }
+        // gzip files are recognized by their magic number, so they can be extracted just like ours.
+        // Members are only given the room left under the limits, so they stop inflating as soon as they pass them.
+        (false, false) if is_gzip(&bytes) => {
+            let limits = settings.limits();
+            report.time("inflate", || gunzip(&bytes, limits.room(0, bytes.len())))
+                .map_err(|error| limits.decode_failure(error, bytes.len()))
+                .map(|to_write| write_output(&settings, &to_write, &mut report))
+        }
+        // Pipes, sockets and stdout are given each block as soon as it's decoded, since whoever reads them may be waiting.
+        // Files are only written once everything has been checked, so a bad stream leaves nothing behind.
+        (false, false) => {
+            let dictionary = settings.method.dictionary.as_deref();
+            let limits = settings.limits();
+            match settings.output_file.as_deref() {
+                Some(filename) if is_regular(filename) => {
+                    decompress_with_limit(&bytes, dictionary, limits, &mut report)
+                        .map(|to_write| write_output(&settings, &to_write, &mut report))
+                }
+                Some(filename) => {
+                    Endpoint::create(filename).map(|output| Throttled::new(output, settings.rate_limit))
+                        .map_err(|message| Failure::new("writing", FailureKind::Io, message).in_file(Some(filename)))
+                        .and_then(|mut output| decompress_to(&bytes, dictionary, limits, &mut output, &mut report))
+                }
+                None => { decompress_to(&bytes, dictionary, limits, &mut Throttled::new(stdout(), settings.rate_limit), &mut report) }
+            }
+        }
+    };
+    if let Err(failure) = result {
+        // Failures in writing name their own file. Everything else went wrong with the input.
+        let file = failure.file.clone().or(settings.input_file.clone());
+        fail(&settings, Failure { file, ..failure })
+    }
+
+    report.print();
+    exit(0)
+}
+
+// Say why the run failed, and stop.
+fn fail(settings: &Settings, failure: Failure) -> ! {
+    failure.print(settings.json_errors);
+    exit(1)
+}
+
+// What a run which fails partway was doing.
+fn phase(settings: &Settings) -> &'static str {
+    match (settings.zip, settings.archive) {
+        (true, true) => { "archiving" }
+        (true, false) => { "compressing" }
+        (false, true) => { "extracting" }
+        (false, false) => { "decompressing" }
+    }
+}
+
+
+// Use stdin or the specified input file, pipe, or socket.
+fn read_input(settings: &Settings, report: &mut Report) -> Vec<u8> {
+    #[cfg(feature = "tracing")]
+    let span = tracing::info_span!("read input", bytes = tracing::field::Empty).entered();
+    let bytes = if let Some(filename) = &settings.input_file {
+        let mut buf = vec![];
+        let result = Endpoint::open(filename)
+            .map(|input| Throttled::new(input, settings.rate_limit))
+            .and_then(|mut input| report.time("i/o", || input.read_to_end(&mut buf))
+                .map_err(|e| format!("Could not read input {}: {}", filename, e)));
+        match result {
+            Ok(_) => { buf }
+            Err(_) if !is_url(filename) && !fs::exists(filename).unwrap_or(false) => {
+                let message = format!("File not found: {}", filename);
+                fail(settings, Failure::new("reading", FailureKind::NotFound, message).in_file(Some(filename)))
+            }
+            Err(message) => { fail(settings, Failure::new("reading", FailureKind::Io, message).in_file(Some(filename))) }
+        }
+    } else {
+        let mut buf = vec![];
+        report.time("i/o", || Throttled::new(stdin(), settings.rate_limit).read_to_end(&mut buf)).unwrap();
+        buf
+    };
+    #[cfg(feature = "tracing")]
+    span.record("bytes", bytes.len());
+    bytes
+}
+
+// Use stdout or the specified output file, pipe, or socket.
+fn write_output(settings: &Settings, bytes: &[u8], report: &mut Report) {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("write output", bytes = bytes.len()).entered();
+    let result = report.time("i/o", || {
+        if let Some(filename) = &settings.output_file {
+            let mut output = Endpoint::create(filename)?;
+            let mut armored = Armored::new(Throttled::new(&mut output, settings.rate_limit), settings.armor);
+            armored.write_all(bytes).and_then(|_| armored.finish())
+                .map_err(|e| format!("Could not write output: {}", e))?;
+            match settings.durable {
+                true => { make_durable(&output, filename) }
+                false => { Ok(()) }
+            }
+        } else {
+            let mut armored = Armored::new(Throttled::new(stdout(), settings.rate_limit), settings.armor);
+            armored.write_all(bytes).and_then(|_| armored.finish()).map_err(|e| format!("Could not write output: {}", e))
+        }
+    });
+    if let Err(message) = result {
+        fail(settings, Failure::new("writing", FailureKind::Io, message).in_file(settings.output_file.as_deref()))
+    }
+}
+
+
+// Sync output, and the directory it's in, so that a crash after this can't lose it.
+fn make_durable(output: &Endpoint, filename: &str) -> Result<(), String> {
+    output.sync().and_then(|_| sync_directory(filename)).map_err(|e| format!("Could not sync output {}: {}", filename, e))
+}
+
+// ****** COMPRESSOR ****** //
+
+// Compress bytes one block at a time, writing each block as soon as it is ready.
+// When checkpointing, progress is saved after every block, so that an interrupted run can resume.
+fn compress(settings: &Settings, bytes: &[u8], report: &mut Report) -> Result<(), String> {
+    let mut block_size = settings.block_size;
+    let mut rsyncable = settings.rsyncable;
+    let mut first_block = 0;
+    let mut checkpoint = None;
+
+    // Checkpointing has been validated to have both an input and output file.
+    if settings.checkpoint || settings.resume {
+        let input_file = settings.input_file.as_ref().unwrap();
+        let output_file = settings.output_file.as_ref().unwrap();
+        let current = match settings.resume {
+            true => { Checkpoint::load(input_file, output_file)? }
+            false => { Checkpoint::new(input_file, block_size, rsyncable)? }
+        };
+        // Resuming with different block boundaries would produce a different file!
+        block_size = current.block_size();
+        rsyncable = current.rsyncable();
+        first_block = current.blocks_done();
+        current.save(output_file)?;
+        checkpoint = Some(current);
+    }
+
+    let mut output_file = match &settings.output_file {
+        Some(filename) => {
+            Some(Armored::new(Throttled::new(open_output(filename, checkpoint.as_ref())?, settings.rate_limit), settings.armor))
+        }
+        None => { None }
+    };
+    let mut stdout = Armored::new(Throttled::new(stdout(), settings.rate_limit), settings.armor);
+
+    // A table given by the user must have a code for every byte it's asked to code.
+    if let Some(table) = &settings.method.table {
+        if let Some(byte) = bytes.iter().find(|byte| table[**byte as usize].is_none()) {
+            return Err(format!("The code table has no code for byte {}!", byte))
+        }
+    }
+
+    let options = StreamOptions {
+        block_size, rsyncable, method: &settings.method, candidates: &settings.candidates, checksum: settings.checksum,
+        threads: settings.threads,
+    };
+    let tail = write_stream(bytes, &options, first_block, report, |framed, _, report| {
+        report.time("i/o", || match &mut output_file {
+            Some(file) => { file.write_all(&framed) }
+            None => { stdout.write_all(&framed) }
+        }).map_err(|e| format!("Could not write output: {}", e))?;
+
+        if let Some(checkpoint) = &mut checkpoint {
+            // The block must really be on disk before the checkpoint claims it's done.
+            output_file.as_ref().unwrap().get_ref().get_ref().sync()
+                .map_err(|e| format!("Could not write output: {}", e))?;
+            checkpoint.record_block(framed.len());
+            checkpoint.save(settings.output_file.as_ref().unwrap())?;
+        }
+        Ok(())
+    })?;
+    report.time("i/o", || match &mut output_file {
+        Some(file) => { file.write_all(&tail).and_then(|_| file.finish()) }
+        None => { stdout.write_all(&tail).and_then(|_| stdout.finish()) }
+    }).map_err(|e| format!("Could not write output: {}", e))?;
+
+    if let (true, Some(file)) = (settings.durable, &output_file) {
+        report.time("i/o", || make_durable(file.get_ref().get_ref(), settings.output_file.as_ref().unwrap()))?;
+    }
+    if checkpoint.is_some() {
+        Checkpoint::remove(settings.output_file.as_ref().unwrap());
+    }
+    Ok(())
+}
+
+// How a stream's input is cut into blocks, and how each block is coded.
+// wz -z and the server's tasks both write their streams with these.
+struct StreamOptions<'a> {
+    block_size: usize,
+    // Cut blocks where the content says to, rather than between tar members or every block_size bytes.
+    rsyncable: bool,
+    method: &'a Method,
+    // If any are given, each block is coded with whichever of these makes it smallest, rather than with method.
+    candidates: &'a [Method],
+    checksum: Option<&'static dyn Checksum>,
+    threads: usize,
+}
+
+// Compress bytes as a stream, handing each framed block to write in order, along with how much input it holds.
+// Blocks before first_block were written by an earlier run, so they're only counted in the index.
+// Returns what goes after the blocks, framed and ready to write: the checksum, if there is one, then the index.
+fn write_stream(bytes: &[u8], options: &StreamOptions, first_block: usize, report: &mut Report,
+                mut write: impl FnMut(Vec<u8>, usize, &mut Report) -> Result<(), String>) -> Result<Vec<u8>, String> {
+    // Tar streams are split between members, so that each member can be found again on its own.
+    let tar = match options.rsyncable {
+        true => { None }
+        false => { report.time("chunking", || read_tar(bytes)) }
+    };
+    let blocks = report.time("chunking", || match (options.rsyncable, &tar) {
+        (true, _) => { content_defined_chunks(bytes, RSYNCABLE_AVERAGE, options.block_size) }
+        (false, Some((starts, _))) => { tar_blocks(bytes, starts, options.block_size) }
+        (false, None) => { bytes.chunks(options.block_size).collect() }
+    });
+    let block_lens = blocks.iter().map(|block| block.len() as u64).collect();
+
+    // With several threads, blocks are compressed at once but written in order.
+    // Only a few blocks a thread may be compressed ahead of what's written, so a slow socket or disk holds compression back.
+    let (method, candidates, threads) = (options.method, options.candidates, options.threads);
+    let todo: Vec<&[u8]> = blocks.into_iter().skip(first_block).collect();
+    run_bounded(&todo, threads, threads * DEPTH_PER_THREAD, report, |block, report| {
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!("compress block", input = block.len(), output = tracing::field::Empty).entered();
+        let compressed = match candidates.is_empty() {
+            true => { compress_block(block, method, report) }
+            false => { compress_smallest(block, candidates, report) }
+        };
+        #[cfg(feature = "tracing")]
+        span.record("output", compressed.len());
+        (frame_block(compressed), block.len())
+    }, |(framed, len), report| write(framed, len, report))?;
+
+    // The checksum covers every block before it.
+    let mut tail = vec![];
+    if let Some(checksum) = options.checksum {
+        tail.append(&mut frame_block(report.time("checksumming", || checksum_block(checksum, bytes))));
+    }
+    // The index goes last, where it can be found without reading anything before it.
+    // Every stream has one, so that any range of it can be found. Only tar streams have members.
+    let members = tar.map_or(vec![], |(_, members)| members);
+    tail.append(&mut frame_block(index_block(TarIndex::new(block_lens, members))));
+    Ok(tail)
+}
+
+// Open the output for writing.
+// When resuming, anything written after the last checkpoint is thrown away. Checkpoints are only allowed for regular files.
+fn open_output(filename: &str, checkpoint: Option<&Checkpoint>) -> Result<Endpoint, String> {
+    let error = |e| format!("Could not open output {}: {}", filename, e);
+    match checkpoint {
+        Some(checkpoint) if checkpoint.blocks_done() > 0 => {
+            let mut file = OpenOptions::new().write(true).open(filename).map_err(error)?;
+            file.set_len(checkpoint.output_len()).map_err(error)?;
+            file.seek(SeekFrom::End(0)).map_err(error)?;
+            Ok(Endpoint::File(file))
+        }
+        _ => { Endpoint::create(filename) }
+    }
+}
+
+// ****** DECOMPRESSOR ****** //
+
+// Decompress every block in the file, in order.
+fn decompress(bytes: &[u8], dictionary: Option<&Dictionary>, report: &mut Report) -> Result<Vec<u8>, String> {
+    decompress_with_limit(bytes, dictionary, Limits::none(), report).map_err(|failure| failure.message)
+}
+
+// Decompress every block in the file, in order, so long as the output stays within limits.
+// Each block is only given the room left under the limit, and stops decoding once it's used up, so untrusted files can't grow without bound.
+fn decompress_with_limit(bytes: &[u8], dictionary: Option<&Dictionary>, limits: Limits,
+                         report: &mut Report) -> Result<Vec<u8>, Failure> {
+    let mut walk = blocks(bytes);
+    let mut retval = vec![];
+    // Where the output the next checksum covers starts. Joined files have a checksum for each.
+    let mut checked = 0;
+    // Where the next block starts, which is where a block that doesn't parse went wrong.
+    let mut offset = 0;
+    let mut count = 0;
+    while let Some(block) = report.time("parsing", || walk.next()) {
+        let block = block.map_err(|error| Failure::from_stream("decompressing", error).at(offset))?;
+        let at = block.offset as u64;
+        offset = (block.offset + block.framed_len) as u64;
+        let block = block.contents;
+        count += 1;
+        limits.check_block(block, count).map_err(|failure| failure.at(at))?;
+        if block_kind(block) == Some(BlockKind::Checksum) {
+            report.time("checksumming", || verify_checksum(block, &retval[checked..]))
+                .map_err(|message| Failure::new("decompressing", FailureKind::Corrupt, message).at(at))?;
+            checked = retval.len();
+            continue
+        }
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!("decompress block", input = block.len(), output = tracing::field::Empty).entered();
+        let room = limits.room(retval.len() as u64, bytes.len());
+        let mut decompressed = decompress_block_with_limit(block, dictionary, room, report)
+            .map_err(|error| limits.decode_failure(error, bytes.len()).at(at))?;
+        #[cfg(feature = "tracing")]
+        span.record("output", decompressed.len());
+        retval.append(&mut decompressed);
+    }
+    Ok(retval)
+}
+
+// Decompress every block in the file, in order, writing each to writer as soon as it's decoded.
+// Checksums come after what they cover, so a bad stream is only noticed once some of it has been written.
+// That suits pipes and sockets, whose readers would rather not wait for the whole stream.
+fn decompress_to<W: Write>(bytes: &[u8], dictionary: Option<&Dictionary>, limits: Limits, writer: &mut W,
+                           report: &mut Report) -> Result<(), Failure> {
+    // Output is only kept for as long as a checksum still has to be checked against it.
+    let checksummed = blocks(bytes).any(|block| block.is_ok_and(|block| block_kind(block.contents) == Some(BlockKind::Checksum)));
+    let mut walk = blocks(bytes);
+    let mut unchecked = vec![];
+    let mut total = 0;
+    let mut offset = 0;
+    let mut count = 0;
+    let write_failure = |e: std::io::Error| Failure::new("writing", FailureKind::Io, format!("Could not write output: {}", e));
+    while let Some(block) = report.time("parsing", || walk.next()) {
+        let block = block.map_err(|error| Failure::from_stream("decompressing", error).at(offset))?;
+        let at = block.offset as u64;
+        offset = (block.offset + block.framed_len) as u64;
+        let block = block.contents;
+        count += 1;
+        limits.check_block(block, count).map_err(|failure| failure.at(at))?;
+        if block_kind(block) == Some(BlockKind::Checksum) {
+            report.time("checksumming", || verify_checksum(block, &unchecked))
+                .map_err(|message| Failure::new("decompressing", FailureKind::Corrupt, message).at(at))?;
+            unchecked.clear();
+            continue
+        }
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!("decompress block", input = block.len(), output = tracing::field::Empty).entered();
+        let decompressed = decompress_block_with_limit(block, dictionary, limits.room(total, bytes.len()), report)
+            .map_err(|error| limits.decode_failure(error, bytes.len()).at(at))?;
+        #[cfg(feature = "tracing")]
+        span.record("output", decompressed.len());
+        total += decompressed.len() as u64;
+        report.time("i/o", || writer.write_all(&decompressed)).map_err(write_failure)?;
+        if checksummed {
+            unchecked.extend_from_slice(&decompressed);
+        }
+    }
+    writer.flush().map_err(write_failure)
+}
+
+// How big extracted output may get: in bytes, and as a multiple of the compressed stream it came from.
+// A size cap alone has to allow for the biggest file expected, while crafted bombs are far more compressed than any real file.
+// Streams can also be held to less than the format allows in how many blocks they have, and how big their tables are.
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    max_out: Option<u64>,
+    max_expansion: Option<u64>,
+    max_map_entries: Option<usize>,
+    max_blocks: Option<usize>,
+    max_header_size: Option<usize>,
+}
+
+impl Limits {
+    fn none() -> Self {
+        Limits { max_out: None, max_expansion: None, max_map_entries: None, max_blocks: None, max_header_size: None }
+    }
+
+    // Is the count'th block of a stream allowed to be decoded? Checked before it is, so nothing is spent on blocks that aren't.
+    fn check_block(&self, block: &[u8], count: usize) -> Result<(), Failure> {
+        let limit = |message: String| Failure::new("decompressing", FailureKind::Limit, message);
+        if let Some(max_blocks) = self.max_blocks.filter(|max_blocks| count > *max_blocks) {
+            return Err(limit(format!("Stream has more than the maximum of {} blocks!", max_blocks)))
+        }
+        if self.max_map_entries.is_none() && self.max_header_size.is_none() {
+            return Ok(())
+        }
+        let header = block_header(block).map_err(|error| Failure::from_stream("decompressing", error))?;
+        match (self.max_map_entries, self.max_header_size) {
+            (Some(max_entries), _) if header.map_entries > max_entries => {
+                Err(limit(format!("Block has a table of {} entries, more than the maximum of {}!", header.map_entries, max_entries)))
+            }
+            (_, Some(max_size)) if header.len > max_size => {
+                Err(limit(format!("Block has a header of {} bytes, more than the maximum of {}!", header.len, max_size)))
+            }
+            _ => { Ok(()) }
+        }
+    }
+
+    // How many bytes a compressed stream of compressed_len bytes may decompress to, whichever limit is tighter.
+    fn allowed(&self, compressed_len: usize) -> Option<u64> {
+        let by_ratio = self.max_expansion.map(|ratio| ratio.saturating_mul(compressed_len as u64));
+        match (self.max_out, by_ratio) {
+            (Some(max_out), Some(by_ratio)) => { Some(max_out.min(by_ratio)) }
+            (max_out, by_ratio) => { max_out.or(by_ratio) }
+        }
+    }
+
+    // Why output past what's allowed is refused, naming whichever limit is tighter.
+    fn exceeded(&self, compressed_len: usize) -> String {
+        match (self.max_out, self.max_expansion) {
+            (max_out, Some(ratio)) if max_out.is_none_or(|max_out| ratio.saturating_mul(compressed_len as u64) < max_out) => {
+                format!("Decompressed output is more than the maximum of {} times the size of its {} compressed bytes!",
+                        ratio, compressed_len)
+            }
+            (max_out, _) => {
+                format!("Decompressed output is larger than the maximum of {} bytes!", max_out.unwrap_or(u64::MAX))
+            }
+        }
+    }
+
+    // How many more bytes a block may decode to, after written bytes of output.
+    fn room(&self, written: u64, compressed_len: usize) -> usize {
+        let allowed = self.allowed(compressed_len).unwrap_or(u64::MAX).saturating_sub(written);
+        usize::try_from(allowed).unwrap_or(usize::MAX)
+    }
+
+    // A block which ran out of room went past the limit. Anything else wrong with it is corruption.
+    fn decode_failure(&self, error: WzError, compressed_len: usize) -> Failure {
+        match error {
+            WzError::Full(_) => { Failure::new("decompressing", FailureKind::Limit, self.exceeded(compressed_len)) }
+            error => { Failure::from_stream("decompressing", error) }
+        }
+    }
+}
+
+// ****** SELF CHECK ****** //
+
+// wz selfcheck
+// Round trip built in patterns through every coder, printing how each did. Exits 1 if any failed.
+fn selfcheck(args: &[String]) -> i32 {
+    if !args.is_empty() {
+        println!("Self checking takes no arguments!");
+        usage();
+        return 1
+    }
+
+    let checks = self_check();
+    for check in &checks {
+        let status = if check.passed { "pass" } else { "FAIL" };
+        println!("{} {}: {}", status, check.pattern, check.method);
+    }
+    let failed = checks.iter().filter(|check| !check.passed).count();
+    println!("{} of {} checks passed", checks.len() - failed, checks.len());
+    match failed {
+        0 => { 0 }
+        _ => { 1 }
+    }
+}
+
+// ****** TEST VECTORS ****** //
+
+// wz gen-vectors dir
+// Write inputs, the streams they compress to, and their codes, for checking other decoders against.
+fn gen_vectors(args: &[String]) -> i32 {
+    let dir = match args {
+        [dir] => { dir }
+        _ => {
+            println!("Generating test vectors requires exactly one directory!");
+            usage();
+            return 1
+        }
+    };
+
+    match write_vectors(Path::new(dir)) {
+        Ok(count) => {
+            println!("Wrote {} test vectors to {}", count, dir);
+            0
+        }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
+    }
+}
+
+// ****** RECOMPRESSOR ****** //
+
+// wz recompress in.wz -o out.wz
+// Decompress a file, then compress it again as well as we now can.
+// Files written by older versions, or with a poor choice of coder, come out smaller.
+fn recompress(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "output file name", "out.wz");
+    opts.optopt("", "dict", "dictionary the input was compressed with", "file.dict");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 1
+        }
+    };
+
+    let (input, output) = match (matches.free.as_slice(), matches.opt_str("o")) {
+        ([input], Some(output)) => { (input.clone(), output) }
+        _ => {
+            println!("Recompressing requires exactly one input file and -o!");
+            usage();
+            return 1
+        }
+    };
+
+    let mut report = Report::new(false);
+    let result = recompress_file(&input, &output, matches.opt_str("dict"), &mut report);
+    match result {
+        Ok((before, after)) => {
+            let change = (after as f64 - before as f64) / before.max(1) as f64 * 100.0;
+            println!("{}: {} -> {} bytes ({:+.1}%)", input, before, after, change);
+            0
+        }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
+    }
+}
+
+// Returns the sizes of the file before and after.
+fn recompress_file(input: &str, output: &str, dictionary: Option<String>,
+                   report: &mut Report) -> Result<(u64, u64), String> {
+    let dictionary = dictionary.map(|path| Dictionary::load(&path)).transpose()?;
+    let bytes = fs::read(input).map_err(|_| format!("File not found: {}", input))?;
+    let decompressed = match is_gzip(&bytes) {
+        true => { gunzip(&bytes, usize::MAX)? }
+        false => { decompress(&bytes, dictionary.as_ref(), report)? }
+    };
+
+    // Every block gets whichever of these suits it best.
+    let mut settings = Settings::new();
+    settings.zip = true;
+    settings.output_file = Some(output.to_string());
+    settings.candidates = vec![
+        Method::new(),
+        Method { coder: LZ77, ..Method::new() },
+        Method { coder: CONTEXT, ..Method::new() },
+        Method { coder: RANGE, ..Method::new() },
+        Method { filters: vec![Arc::new(Rle)], ..Method::new() },
+    ];
+    compress(&settings, &decompressed, report)?;
+
+    let after = fs::metadata(output).map_err(|e| format!("Could not read {}: {}", output, e))?.len();
+    Ok((bytes.len() as u64, after))
+}
+
+// ****** CONCATENATOR ****** //
+
+// wz cat a.wz b.wz -o combined.wz
+// Join compressed files, or with -a, archives, without recompressing them.
+fn cat(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "output file name", "combined.wz");
+    opts.optflag("a", "archive", "join archives, rather than streams");
+    opts.optflag("", "no-glob", "take file arguments as they are, without expanding wildcards");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 1
+        }
+    };
+
+    let output = match matches.opt_str("o") {
+        Some(output) if !matches.free.is_empty() => { output }
+        _ => {
+            println!("Joining requires -o and at least one input file!");
+            usage();
+            return 1
+        }
+    };
+
+    let result = file_arguments(&matches, &matches.free)
+        .and_then(|paths| paths.iter()
+            .map(|path| fs::read(path).map_err(|_| format!("File not found: {}", path)))
+            .collect::<Result<Vec<_>, _>>())
+        .and_then(|inputs| match matches.opt_present("a") {
+            true => { concat_archives(&inputs) }
+            false => { concat_streams(&inputs) }
+        })
+        .and_then(|joined| fs::write(&output, joined).map_err(|e| format!("Could not write output: {}", e)));
+    match result {
+        Ok(()) => { 0 }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
+    }
+}
+
+// ****** NETWORK ****** //
+
+// wz send host:port file
+// Compress a file and send it to wz serve, a block at a time, so neither end needs a compressed copy on disk.
+fn send_file(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optopt("", "block-size", "bytes of input per compressed block", "64K");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 1
+        }
+    };
+
+    let (addr, path) = match matches.free.as_slice() {
+        [addr, path] => { (addr, path) }
+        _ => {
+            println!("Sending requires an address to send to, and exactly one file!");
+            usage();
+            return 1
+        }
+    };
+    let block_size = match matches.opt_str("block-size").map(|size| parse_size(&size)) {
+        None => { DEFAULT_BLOCK_SIZE }
+        Some(Some(size)) if size > 0 => { size as usize }
+        Some(_) => {
+            println!("Invalid block size!");
+            usage();
+            return 1
+        }
+    };
+
+    let mut report = Report::new(false);
+    let result = File::open(path).map_err(|_| format!("File not found: {}", path))
+        .and_then(|mut file| {
+            let mut stream = TcpStream::connect(addr).map_err(|e| format!("Could not connect to {}: {}", addr, e))?;
+            send(&mut file, &mut stream, block_size, &Method::new(), &mut report)
+        });
+    match result {
+        Ok(sent) => {
+            println!("{}: {} bytes sent to {}", path, sent, addr);
+            0
+        }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
+    }
+}
+
+// wz serve --listen addr [-o file] [--dict file.dict]
+// Wait for a single wz send, and write out what it sends as it arrives.
+fn serve(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optopt("", "listen", "address to wait for a sender on", "0.0.0.0:7777");
+    opts.optopt("o", "output", "output file name, rather than stdout", "file");
+    opts.optopt("", "dict", "dictionary the sender compressed with", "file.dict");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 1
+        }
+    };
+
+    let addr = match matches.opt_str("listen") {
+        Some(addr) if matches.free.is_empty() => { addr }
+        _ => {
+            println!("Serving requires --listen, and no other arguments!");
+            usage();
+            return 1
+        }
+    };
+
+    let mut report = Report::new(false);
+    let result = matches.opt_str("dict").map(|path| Dictionary::load(&path)).transpose()
+        .and_then(|dictionary| {
+            let listener = TcpListener::bind(&addr).map_err(|e| format!("Could not listen on {}: {}", addr, e))?;
+            let (mut stream, _) = listener.accept().map_err(|e| format!("Could not accept a sender: {}", e))?;
+            match matches.opt_str("o") {
+                Some(path) => {
+                    let mut file = File::create(&path).map_err(|e| format!("Could not open output {}: {}", path, e))?;
+                    receive(&mut stream, &mut file, dictionary.as_ref(), &mut report)
+                }
+                None => { receive(&mut stream, &mut stdout(), dictionary.as_ref(), &mut report) }
+            }
+        });
+    match result {
+        Ok(_) => { 0 }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
+    }
+}
+
+// ****** WATCHER ****** //
+
+// wz watch dir --dest out [--block-size 64K] [--debounce 1000]
+// Compress every file in dir into out, then keep doing so as files appear or change, until killed.
+fn watch_dir(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optopt("", "dest", "directory to compress into", "out");
+    opts.optopt("", "block-size", "bytes of input per compressed block", "64K");
+    opts.optopt("", "debounce", "milliseconds a file must go unchanged before it's compressed", "1000");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 1
+        }
+    };
+
+    let (dir, dest) = match (matches.free.as_slice(), matches.opt_str("dest")) {
+        ([dir], Some(dest)) => { (dir.clone(), dest) }
+        _ => {
+            println!("Watching requires exactly one directory, and --dest!");
+            usage();
+            return 1
+        }
+    };
+    let block_size = match matches.opt_str("block-size").map(|size| parse_size(&size)) {
+        None => { DEFAULT_BLOCK_SIZE }
+        Some(Some(size)) if size > 0 => { size as usize }
+        Some(_) => {
+            println!("Invalid block size!");
+            usage();
+            return 1
+        }
+    };
+    let delay = match matches.opt_str("debounce").map(|delay| delay.parse::<u64>()) {
+        None => { Duration::from_millis(1000) }
+        Some(Ok(delay)) => { Duration::from_millis(delay) }
+        Some(Err(_)) => {
+            println!("Invalid debounce delay!");
+            usage();
+            return 1
+        }
+    };
+
+    let result = watch(Path::new(&dir), Path::new(&dest), delay, |input, output| {
+        let result = compress_into(input, output, block_size);
+        if result.is_ok() {
+            println!("{} -> {}", input.display(), output.display());
+        }
+        result
+    });
+    match result {
+        Ok(_) => { 0 }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
+    }
+}
+
+// Compress one file into output, creating any directories it needs.
+// The output is written beside its final name, then renamed, so whoever reads it never sees half a file.
+fn compress_into(input: &Path, output: &Path, block_size: usize) -> Result<(), String> {
+    let bytes = fs::read(input).map_err(|_| format!("File not found: {}", input.display()))?;
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Could not create {}: {}", parent.display(), e))?;
+    }
+    let temp_path = format!("{}.tmp", output.display());
+    let mut settings = Settings::new();
+    settings.zip = true;
+    settings.block_size = block_size;
+    settings.output_file = Some(temp_path.clone());
+    compress(&settings, &bytes, &mut Report::new(false))?;
+    fs::rename(&temp_path, output).map_err(|e| format!("Could not write output {}: {}", output.display(), e))
+}
+
+// ****** SERVER ****** //
+
+// wz --server
+// Take jobs from a frontend as JSON-RPC on stdin, answering on stdout, until stdin ends and every job has finished.
+fn server(args: &[String]) -> i32 {
+    if !args.is_empty() {
+        println!("--server takes no other arguments!");
+        usage();
+        return 1
+    }
+    // Stdout belongs to the frontend now, so anything else goes to stderr.
+    match serve_requests(stdin().lock(), stdout(), run_task) {
+        Ok(_) => { 0 }
+        Err(message) => {
+            eprintln!("{}", message);
+            1
+        }
+    }
+}
+
+// Run a job for wz --server, reporting progress after each block, and stopping between blocks if it's cancelled.
+// The output is written beside its final name, then renamed, so a job which fails or is cancelled leaves nothing behind.
+fn run_task(task: &Task, progress: &Progress) -> Result<Option<u64>, Failure> {
+    let bytes = fs::read(&task.input)
+        .map_err(|_| Failure::new("reading", FailureKind::NotFound, format!("File not found: {}", task.input)).in_file(Some(&task.input)))?;
+    let temp_path = format!("{}.tmp", task.output);
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let mut output = File::create(&temp_path)
+            .map_err(|e| Failure::new("writing", FailureKind::Io, format!("Could not open output {}: {}", temp_path, e)))?;
+        match task.action {
+            Action::Compress => { compress_task(task, &bytes, &mut output, progress) }
+            Action::Decompress => { decompress_task(&bytes, &mut output, progress) }
+        }
+    }));
+    // Failures in writing name their own file. Everything else went wrong with the input.
+    let in_file = |failure: Failure| match failure.phase {
+        "writing" => { failure.in_file(Some(&task.output)) }
+        _ => { failure.in_file(Some(&task.input)) }
+    };
+    match result {
+        Ok(Ok(Some(size))) => {
+            fs::rename(&temp_path, &task.output).map(|_| Some(size)).map_err(|e| {
+                let _ = fs::remove_file(&temp_path);
+                in_file(Failure::new("writing", FailureKind::Io, format!("Could not write output: {}", e)))
+            })
+        }
+        Ok(result) => {
+            let _ = fs::remove_file(&temp_path);
+            result.map_err(in_file)
+        }
+        // A bug hit by one task fails that task, rather than taking the server and every other task down with it.
+        Err(panic) => {
+            let _ = fs::remove_file(&temp_path);
+            let message = panic.downcast_ref::<&str>().map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| String::from("The task panicked!"));
+            let phase = match task.action {
+                Action::Compress => { "compressing" }
+                Action::Decompress => { "decompressing" }
+            };
+            Err(in_file(Failure::new(phase, FailureKind::Failed, message)))
+        }
+    }
+}
+
+// Compress bytes into output as wz -z would, a block at a time. None if cancelled.
+fn compress_task(task: &Task, bytes: &[u8], output: &mut File, progress: &Progress) -> Result<Option<u64>, Failure> {
+    let mut report = Report::new(false);
+    let method = Method::new();
+    let options = StreamOptions {
+        block_size: task.block_size, rsyncable: false, method: &method, candidates: &[], checksum: task.checksum, threads: 1,
+    };
+    // Cancelling stops the stream partway, as a failed write would.
+    let (mut done, mut written) = (0, 0);
+    let result = write_stream(bytes, &options, 0, &mut report, |framed, len, _| {
+        if progress.cancelled() {
+            return Err(String::from("Cancelled!"))
+        }
+        output.write_all(&framed).map_err(|e| format!("Could not write output: {}", e))?;
+        written += framed.len() as u64;
+        done += len as u64;
+        progress.report(done, bytes.len() as u64);
+        Ok(())
+    }).and_then(|tail| output.write_all(&tail).map(|_| tail.len() as u64).map_err(|e| format!("Could not write output: {}", e)));
+    match result {
+        Ok(tail_len) => { Ok(Some(written + tail_len)) }
+        Err(_) if progress.cancelled() => { Ok(None) }
+        Err(message) => { Err(Failure::new("writing", FailureKind::Io, message)) }
+    }
+}
+
+// Decompress bytes into output as wz -x would, a block at a time, checking any checksums. None if cancelled.
+fn decompress_task(bytes: &[u8], output: &mut File, progress: &Progress) -> Result<Option<u64>, Failure> {
+    let write_failure = |e: std::io::Error| Failure::new("writing", FailureKind::Io, format!("Could not write output: {}", e));
+    let mut report = Report::new(false);
+    // Output is only kept for as long as a checksum still has to be checked against it.
+    let checksummed = blocks(bytes).any(|block| block.is_ok_and(|block| block_kind(block.contents) == Some(BlockKind::Checksum)));
+    let mut unchecked = vec![];
+    let (mut offset, mut written) = (0, 0);
+    for block in blocks(bytes) {
+        if progress.cancelled() {
+            return Ok(None)
+        }
+        let block = block.map_err(|error| Failure::from_stream("decompressing", error).at(offset))?;
+        let at = block.offset as u64;
+        offset = (block.offset + block.framed_len) as u64;
+        if block_kind(block.contents) == Some(BlockKind::Checksum) {
+            verify_checksum(block.contents, &unchecked)
+                .map_err(|message| Failure::new("decompressing", FailureKind::Corrupt, message).at(at))?;
+            unchecked.clear();
+        } else {
+            let decompressed = decompress_block(block.contents, None, &mut report)
+                .map_err(|error| Failure::from_stream("decompressing", error).at(at))?;
+            output.write_all(&decompressed).map_err(write_failure)?;
+            written += decompressed.len() as u64;
+            if checksummed {
+                unchecked.extend_from_slice(&decompressed);
+            }
+        }
+        progress.report(offset, bytes.len() as u64);
+    }
+    Ok(Some(written))
+}
+
+// ****** DIFFER ****** //
+
+// wz diff old new -o patch.wzd
+// Make a patch which turns old into new.
+fn diff(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "output file name", "patch.wzd");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 1
+        }
+    };
+
+    let (old, new, output) = match (matches.free.as_slice(), matches.opt_str("o")) {
+        ([old, new], Some(output)) => { (old, new, output) }
+        _ => {
+            println!("Diffing requires an old file, a new file, and -o!");
+            usage();
+            return 1
+        }
+    };
+
+    let mut report = Report::new(false);
+    let read = |path: &String| fs::read(path).map_err(|_| format!("File not found: {}", path));
+    let result = read(old).and_then(|old| read(new).map(|new| (old, new)))
+        .map(|(old, new)| make_patch(&old, &new, &mut report))
+        .and_then(|patch| fs::write(&output, patch).map_err(|e| format!("Could not write output: {}", e)));
+    match result {
+        Ok(()) => { 0 }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
+    }
+}
+
+// wz patch old patch.wzd -o new
+// Apply a patch made by wz diff, turning old back into new.
+fn patch(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "output file name", "new");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 1
+        }
+    };
+
+    let (old, patch, output) = match (matches.free.as_slice(), matches.opt_str("o")) {
+        ([old, patch], Some(output)) => { (old, patch, output) }
+        _ => {
+            println!("Patching requires an old file, a patch, and -o!");
+            usage();
+            return 1
+        }
+    };
+
+    let mut report = Report::new(false);
+    let read = |path: &String| fs::read(path).map_err(|_| format!("File not found: {}", path));
+    let result = read(old).and_then(|old| read(patch).map(|patch| (old, patch)))
+        .and_then(|(old, patch)| apply_patch(&old, &patch, &mut report))
+        .and_then(|new| fs::write(&output, new).map_err(|e| format!("Could not write output: {}", e)));
+    match result {
+        Ok(()) => { 0 }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
+    }
+}
+
+// ****** TRAINER ****** //
+
+// wz train --dict out.dict files...
+// Train a dictionary on the given files and directories, and save it.
+fn train(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optopt("", "dict", "file to save the dictionary to", "out.dict");
+    opts.optflag("", "no-glob", "take file arguments as they are, without expanding wildcards");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 1
+        }
+    };
+
+    let path = match matches.opt_str("dict") {
+        Some(path) if !matches.free.is_empty() => { path }
+        _ => {
+            println!("Training requires --dict and at least one sample file!");
+            usage();
+            return 1
+        }
+    };
+
+    let mut report = Report::new(false);
+    let result = file_arguments(&matches, &matches.free)
+        .and_then(|samples| train_dictionary(&samples, &mut report))
+        .and_then(|dictionary| dictionary.save(&path));
+    match result {
+        Ok(()) => { 0 }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
+    }
+}
+
+// ****** LISTER ****** //
+
+// wz list archive
+// Print the name of every member of a wz archive or zip file.
+fn list(args: &[String]) -> i32 {
+    let [path] = args else {
+        println!("Listing requires exactly one archive!");
+        usage();
+        return 1
+    };
+    let result = fs::read(path).map_err(|_| format!("File not found: {}", path))
+        .and_then(|bytes| list_archive(&bytes));
+    match result {
+        Ok(names) => {
+            names.iter().for_each(|name| println!("{}", name));
+            0
+        }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
+    }
+}
+
+// ****** SYNCER ****** //
+
+// wz sync [--block-size 64K] [--preserve-symlinks] [--xattrs] [--preserve] [--sync] [--limit-rate 10M] dir... archive.wz
+// Bring an archive up to date with the files it was made from, compressing only what has changed.
+// An archive that isn't there yet is created.
+fn sync(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optopt("", "block-size", "largest chunk of a new or changed member", "64K");
+    opts.optflag("", "no-glob", "take file arguments as they are, without expanding wildcards");
+    opts.optflag("", "follow-symlinks", "archive what symbolic links point at, as if it were there instead");
+    opts.optflag("", "preserve-symlinks", "archive symbolic links as links, made again on extraction");
+    opts.optflag("", "xattrs", "keep extended attributes and ACLs in archives, and restore them on extraction");
+    opts.optflag("", "preserve", "keep who owns each file in archives, and as root, restore it on extraction");
+    opts.optflag("", "sync", "make sure the archive and its directory are on disk before reporting success");
+    opts.optopt("", "limit-rate", "read files and write the archive at no more than this many bytes a second", "10M");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 1
+        }
+    };
+
+    let Some((archive, paths)) = matches.free.split_last().filter(|(_, paths)| !paths.is_empty()) else {
+        println!("Syncing requires at least one file or directory, then the archive!");
+        usage();
+        return 1
+    };
+    let max_chunk = match matches.opt_str("block-size").map(|size| parse_size(&size)) {
+        None => { DEFAULT_BLOCK_SIZE }
+        Some(Some(size)) if size > 0 => { size as usize }
+        Some(_) => {
+            println!("Invalid block size!");
+            usage();
+            return 1
+        }
+    };
+
+    let options = match archive_options(&matches) {
+        Ok(options) => { options }
+        Err(message) => {
+            println!("{}", message);
+            usage();
+            return 1
+        }
+    };
+
+    // Written beside the archive, then renamed over it, so a failed sync leaves the old archive whole.
+    // That file is locked first, so two syncs of one archive can't both update it.
+    let mut report = Report::new(false);
+    let temp_path = format!("{}.tmp", archive);
+    let mut temp = match lock_staged(&temp_path, archive) {
+        Ok(temp) => { temp }
+        Err(message) => {
+            println!("{}", message);
+            return 1
+        }
+    };
+    // A sync that was interrupted is finished, if it got as far as committing, or else undone, before this one starts.
+    match recover(archive, &temp_path) {
+        Ok(Recovery::Clean) => {}
+        Ok(Recovery::Finished) => {
+            println!("Finished an interrupted update to {}.", archive);
+            // What was locked is now the archive, so the new staging file must be locked instead.
+            temp = match lock_staged(&temp_path, archive) {
+                Ok(temp) => { temp }
+                Err(message) => {
+                    println!("{}", message);
+                    return 1
+                }
+            };
+        }
+        Ok(Recovery::Undone) => { println!("Undid an interrupted update to {}.", archive) }
+        Err(message) => {
+            println!("{}", message);
+            return 1
+        }
+    }
+    let existing = match fs::exists(archive).unwrap_or(false) {
+        true => { fs::read(archive).map(Some).map_err(|e| format!("Could not read {}: {}", archive, e)) }
+        false => { Ok(None) }
+    };
+    let result = existing
+        .and_then(|bytes| Ok((bytes, file_arguments(&matches, paths)?)))
+        .and_then(|(bytes, paths)| {
+            let (updated, summary) = sync_archive(bytes.as_deref(), &paths, max_chunk, &options, &mut report)?;
+            Ok((Journal::new(bytes.as_deref(), &updated), updated, summary))
+        })
+        .and_then(|(journal, bytes, summary)| {
+            // The new archive is on disk before the journal says to use it, so it's never used half written.
+            // With --sync, the journal and the renaming are on disk too, before success is reported.
+            let durable = matches.opt_present("sync");
+            temp.set_len(0).and_then(|_| Throttled::new(&mut temp, options.rate_limit).write_all(&bytes))
+                .and_then(|_| temp.sync_data())
+                .map_err(|e| format!("Could not write {}: {}", archive, e))?;
+            journal.commit(archive, durable)?;
+            fs::rename(&temp_path, archive)
+                .and_then(|_| if durable { sync_directory(archive) } else { Ok(()) })
+                .map_err(|e| format!("Could not write {}: {}", archive, e))?;
+            Journal::remove(archive)?;
+            Ok(summary)
+        });
+    // Once the journal is committed, the staged archive is needed to finish the update, so it's kept.
+    if result.is_err() && !Journal::pending(archive) {
+        let _ = fs::remove_file(&temp_path);
+    }
+    match result {
+        Ok(summary) => {
+            println!("{}: {} unchanged, {} changed, {} added, {} removed", archive,
+                     summary.unchanged, summary.changed, summary.added, summary.removed);
+            0
+        }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
+    }
+}
+
+// Open the file an archive's update is staged in, and lock it, so no other sync can update the archive meanwhile.
+fn lock_staged(temp_path: &str, archive: &str) -> Result<File, String> {
+    let temp = OpenOptions::new().write(true).create(true).truncate(false).open(temp_path)
+        .map_err(|e| format!("Could not write {}: {}", temp_path, e))?;
+    match temp.try_lock() {
+        Ok(()) => { Ok(temp) }
+        Err(TryLockError::WouldBlock) => { Err(format!("{} is already being synced by another process!", archive)) }
+        Err(TryLockError::Error(e)) => { Err(format!("Could not lock {}: {}", temp_path, e)) }
+    }
+}
+
+// ****** INSPECTOR ****** //
+
+// wz inspect --codes json [--dict file.dict] file.wz
+// wz inspect --dump-tree text|dot file.wz
+// wz inspect --blocks file.wz
+// Print the codes each block of a compressed file was given, draw the trees they came from, or say where each block is.
+fn inspect(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optopt("", "codes", "print each block's codes", "json");
+    opts.optopt("", "dict", "dictionary the file was compressed with", "file.dict");
+    opts.optopt("", "dump-tree", "draw each block's Huffman tree", "text|dot");
+    opts.optflag("", "blocks", "print where each block is, its size, kind and checksum");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 1
+        }
+    };
+
+    let blocks = matches.opt_present("blocks");
+    let path = match (matches.free.as_slice(), matches.opt_str("codes"), matches.opt_str("dump-tree"), blocks) {
+        ([path], Some(format), None, false) if format == "json" => { path }
+        ([path], None, Some(_), false) => { path }
+        ([path], None, None, true) => { path }
+        _ => {
+            println!("Inspecting requires one of --codes json, --dump-tree or --blocks, and exactly one compressed file!");
+            usage();
+            return 1
+        }
     };
 
-    // Use stdout or the specified output file.
-    if let Some(filename) = output_file {
-        let mut output_file = File::create(filename).unwrap();
-        output_file.write_all(&to_write).unwrap();
-    } else {
-        stdout().write_all(&to_write).unwrap();
+    let dictionary = match matches.opt_str("dict").map(|path| Dictionary::load(&path)).transpose() {
+        Ok(dictionary) => { dictionary }
+        Err(message) => {
+            println!("{}", message);
+            return 1
+        }
+    };
+    let result = fs::read(path).map_err(|_| format!("File not found: {}", path))
+        .and_then(|bytes| match matches.opt_str("dump-tree") {
+            Some(format) => { inspect_trees(&bytes, &format) }
+            None if blocks => { inspect_blocks(&bytes).map(|json| json + "\n") }
+            None => { inspect_codes(&bytes, dictionary.as_ref()).map(|json| json + "\n") }
+        });
+    match result {
+        Ok(output) => {
+            print!("{}", output);
+            0
+        }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
     }
-
-    exit(0)
 }
 
+// ****** DUMPER ****** //
 
-// ****** COMPRESSOR ****** //
+// wz dump [--full] file.wz
+// Print a hex dump of a compressed file, naming the field each byte belongs to.
+fn dump(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optflag("", "full", "show every byte of long fields, rather than their first and last lines");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 1
+        }
+    };
 
-// Returns exit status of program
-fn compress(bytes: &[u8]) -> Vec<u8>{
-    let ordering = gen_frequency(bytes);
-    let heap = huffman(&ordering);
+    let path = match matches.free.as_slice() {
+        [path] => { path }
+        _ => {
+            println!("Dumping requires exactly one compressed file!");
+            usage();
+            return 1
+        }
+    };
 
-    // Create an empty file, do not do any additional work.
-    // This allows future encoding to rely on no "nones" being present.
-    if heap.is_none() {
-        return vec![]
+    // Armored files are dumped as the stream inside them.
+    let result = fs::read(path).map_err(|_| format!("File not found: {}", path))
+        .and_then(|bytes| match is_armored(&bytes) {
+            true => { dearmor(&bytes) }
+            false => { Ok(bytes) }
+        })
+        .and_then(|bytes| dump_stream(&bytes, matches.opt_present("full")));
+    match result {
+        Ok(output) => {
+            println!("{}", output);
+            0
+        }
+        Err(message) => {
+            println!("{}", message);
+            1
+        }
     }
-
-    let heap = heap.unwrap();
-    let encoding = heap.gen_encoding();
-    let seq = BitSequence::translate(bytes, &encoding);
-
-    Wzfile::new(ordering, seq).to_stream()
 }
 
+// ****** COMPARER ****** //
 
-// ****** DECOMPRESSOR ****** //
+// wz cmp [--dict file.dict] original file.wz
+// Check that a compressed file decompresses to the original, without writing anything. Like cmp(1), exits 1 if they differ.
+fn cmp(args: &[String]) -> i32 {
+    let mut opts = Options::new();
+    opts.optopt("", "dict", "dictionary the file was compressed with", "file.dict");
+    let matches = match opts.parse(args) {
+        Ok( m) => { m }
+        Err( f) => {
+            println!("{}", f);
+            usage();
+            return 2
+        }
+    };
 
-// Returns exit status of program
-fn decompress(bytes: &[u8]) -> Vec<u8> {
-    let (ordering, seq) = Wzfile::from_stream(bytes).deconstruct();
-    let heap = huffman(&ordering);
+    let (original, compressed) = match matches.free.as_slice() {
+        [original, compressed] => { (original, compressed) }
+        _ => {
+            println!("Comparing requires exactly one original file and one compressed file!");
+            usage();
+            return 2
+        }
+    };
 
-    if heap.is_none() {
-        return vec![]
+    match compare_file(original, compressed, matches.opt_str("dict")) {
+        Ok((None, len)) => {
+            println!("{} matches {}: {} bytes", compressed, original, len);
+            0
+        }
+        Ok((Some(difference), _)) => {
+            match difference {
+                Difference::Byte { offset, original: expected, decompressed } => {
+                    println!("{} and {} differ at offset {}: {:#04x} in the original, {:#04x} decompressed",
+                             original, compressed, offset, expected, decompressed)
+                }
+                Difference::OriginalShorter(offset) => {
+                    println!("{} ends at offset {}, but {} decompresses to more", original, offset, compressed)
+                }
+                Difference::OriginalLonger(offset) => {
+                    println!("{} decompresses to only {} bytes, but {} goes on", compressed, offset, original)
+                }
+            }
+            1
+        }
+        Err(message) => {
+            println!("{}", message);
+            2
+        }
     }
+}
 
-    let heap = heap.unwrap();
-    // Need to gen decoding.
-    let decoding = heap.gen_decoding();
-    // Now, need to turn each bit in bitsequence into a regular byte in output file.
-
-    let mut bytes = vec![];
-    let mut current_seq = BitSequence::new();
+// Decompress compressed, block by block, checking each against original as it's decoded.
+// Returns the first difference, if any, and how many bytes matched before it.
+fn compare_file(original: &str, compressed: &str, dictionary: Option<String>) -> Result<(Option<Difference>, u64), String> {
+    let dictionary = dictionary.map(|path| Dictionary::load(&path)).transpose()?;
+    let original_file = File::open(original).map_err(|_| format!("File not found: {}", original))?;
+    let bytes = fs::read(compressed).map_err(|_| format!("File not found: {}", compressed))?;
+    let bytes = match is_armored(&bytes) {
+        true => { dearmor(&bytes)? }
+        false => { bytes }
+    };
 
-    for i in 0..seq.length() {
-        let current = seq.get_bit(i).unwrap();
-        current_seq.append_bit(current);
-        if let Some(byte) = decoding.get(&current_seq) {
-            bytes.push(*byte);
-            // Start searching from the next bit again.
-            current_seq = BitSequence::new();
-        }
+    let mut report = Report::new(false);
+    let mut comparer = Comparer::new(original_file);
+    let result = match is_gzip(&bytes) {
+        true => { gunzip(&bytes, usize::MAX).map_err(String::from).and_then(|decompressed| comparer.write_all(&decompressed).map_err(|e| e.to_string())) }
+        false => { decompress_to(&bytes, dictionary.as_ref(), Limits::none(), &mut comparer, &mut report).map_err(|failure| failure.message) }
+    };
+    // Writes only fail once the output differs, or the original can't be read.
+    let difference = comparer.finish().map_err(|e| format!("Could not read {}: {}", original, e))?;
+    match (result, difference) {
+        (_, Some(difference)) => { Ok((Some(difference), comparer.matched())) }
+        (Ok(()), None) => { Ok((None, comparer.matched())) }
+        (Err(message), None) => { Err(message) }
     }
-
-    bytes
 }
 
-
 // ****** ARGUMENT CHECKERS ****** //
 
 // Parses args.
@@ -151,10 +1859,7 @@ fn decompress(bytes: &[u8]) -> Vec<u8> {
 // Grabs whether the input file is being zipped or unzipped.
 // Validates that the combination is correct.
 // Return either the exit code the program should give, or none.
-fn parse_args(input_filename: &mut Option<String>,
-              output_filename: &mut Option<String>,
-              zip: &mut bool,
-              unzip: &mut bool) -> Option<i32> {
+fn parse_args(settings: &mut Settings) -> Option<i32> {
 
     let args: Vec<String> = env::args().collect();
     // length one if no user args specified.
@@ -169,10 +1874,50 @@ fn parse_args(input_filename: &mut Option<String>,
     opts.optopt("o", "output", "output file name", "out.wz");
     opts.optopt("i", "input", "input file name", "in.txt");
     opts.optflag("r", "stdin", "read from stdin as input");
+    opts.optflag("", "no-glob", "take file arguments as they are, without expanding wildcards");
     opts.optflag("p", "stdout", "print to stdout");
     opts.optflag("u", "usage", "print this usage menu");
     opts.optflag("z", "zip", "compress input file");
     opts.optflag("x", "extract", "extract input file");
+    opts.optflag("a", "archive", "compress the listed files and directories together");
+    opts.optflag("", "follow-symlinks", "archive what symbolic links point at, as if it were there instead");
+    opts.optflag("", "preserve-symlinks", "archive symbolic links as links, made again on extraction");
+    opts.optflag("", "xattrs", "keep extended attributes and ACLs in archives, and restore them on extraction");
+    opts.optflag("", "preserve", "keep who owns each file in archives, and as root, restore it on extraction");
+    opts.optflag("", "report-resources", "print time per phase, peak memory and table sizes to stderr");
+    opts.optflag("", "nice", "run with low CPU and I/O priority");
+    opts.optopt("", "block-size", "bytes of input per compressed block", "16M");
+    for coder in optional_coders() {
+        opts.optflag("", coder.name(), coder.description());
+    }
+    opts.optflag("", "bwt", "apply the Burrows-Wheeler and move-to-front transforms before coding");
+    opts.optopt("", "preset", "Huffman code with a built in table, for small inputs", "json");
+    opts.optopt("", "dict", "Huffman code with a trained dictionary", "file.dict");
+    opts.optopt("", "filter", "filter each block before coding, several joined with commas", "rle|bpe|delta|bwt|transpose:N");
+    opts.optflag("", "rsyncable", "choose block boundaries by content, so small edits change few blocks");
+    opts.optflag("", "checkpoint", "record progress so an interrupted compression can resume");
+    opts.optflag("", "resume", "resume an interrupted compression from its checkpoint");
+    opts.optflag("", "gzip", "write a gzip file, which gunzip and other tools can read");
+    opts.optflag("", "armor", "write compressed output as base64 text, between header and footer lines");
+    opts.optopt("", "member", "extract one member of a compressed tar stream", "dir/file.txt");
+    opts.optflag("", "msb-first", "pack Huffman coded bits from the high end of each byte");
+    opts.optflag("", "canonical", "assign Huffman codes canonically, storing only their lengths");
+    opts.optopt("", "codes", "Huffman code with the codes in a JSON table, rather than counting", "table.json");
+    opts.optopt("", "freqs", "Huffman code with the frequencies in a dictionary, stored in each block", "file.dict");
+    opts.optopt("", "checksum", "store a checksum of the input, checked when extracting", "crc32|xxhash|sha256");
+    opts.optopt("", "max-output-size", "stop extracting once the output would be bigger than this", "1G");
+    opts.optopt("", "max-expansion", "stop extracting once the output would be more than this many times the input", "1000");
+    opts.optopt("", "max-map-entries", "refuse to extract blocks whose tables have more entries than this", "256");
+    opts.optopt("", "max-blocks", "refuse to extract streams of more blocks than this", "1024");
+    opts.optopt("", "max-header-size", "refuse to extract blocks with more than this many bytes before their coded data", "4K");
+    opts.optopt("", "byte-range", "extract only this many bytes, from this offset on", "START:LEN");
+    opts.optflag("", "follow", "keep compressing the input as it grows, like tail -f");
+    opts.optopt("", "flush-interval", "with --follow, milliseconds before appended data is written, even short of a block", "1000");
+    opts.optflag("", "sync", "make sure the output file and its directory are on disk before reporting success");
+    opts.optopt("", "timeout", "give up once this long has passed, finishing archives with what was done", "30s");
+    opts.optopt("", "limit-rate", "read input and write output at no more than this many bytes a second", "10M");
+    opts.optopt("", "threads", "when creating an archive, how many files to work on at once", "4");
+    opts.optopt("", "errors", "how to describe failures: as text, or as json records on stderr", "json");
 
     let matches = match opts.parse(&args[1..]) {
         Ok( m) => { m }
@@ -188,13 +1933,30 @@ fn parse_args(input_filename: &mut Option<String>,
         return Some(0)
     }
 
+    // Failures are sentences for people, unless the tools reading them asked for records.
+    match matches.opt_str("errors").as_deref() {
+        None | Some("text") => {}
+        Some("json") => { settings.json_errors = true }
+        Some(format) => {
+            println!("Unknown error format: {}", format);
+            usage();
+            return Some(1)
+        }
+    }
+
     if matches.opt_present("x") {
-        *unzip = true
+        settings.unzip = true
     }
     if matches.opt_present("z") {
-        *zip = true
+        settings.zip = true
+    }
+    if matches.opt_present("report-resources") {
+        settings.report_resources = true
+    }
+    if matches.opt_present("nice") {
+        settings.nice = true
     }
-    if *zip == *unzip {
+    if settings.zip == settings.unzip {
         println!("Must either zip or unzip a file!");
         usage();
         return Some(1)
@@ -203,11 +1965,89 @@ fn parse_args(input_filename: &mut Option<String>,
     let use_stdin = matches.opt_present("r");
     let use_stdout = matches.opt_present("p");
 
+    // When archiving, the input is every file listed after the options.
+    // When extracting an archive, the output is a directory, so there's no printing to stdout.
+    settings.archive = matches.opt_present("a");
+    settings.members = match file_arguments(&matches, &matches.free) {
+        Ok(members) => { members }
+        Err(message) => {
+            println!("{}", message);
+            return Some(1)
+        }
+    };
+    let creating_archive = settings.archive && settings.zip;
+    if creating_archive {
+        if settings.members.is_empty() || use_stdin || matches.opt_present("i") {
+            println!("Archives are created from the files listed after the options!");
+            usage();
+            return Some(1)
+        }
+    } else if !settings.members.is_empty() {
+        println!("Unexpected arguments: {}", settings.members.join(" "));
+        usage();
+        return Some(1)
+    }
+    settings.archive_options = match archive_options(&matches) {
+        Ok(options) => { options }
+        Err(message) => {
+            println!("{}", message);
+            usage();
+            return Some(1)
+        }
+    };
+    settings.rate_limit = settings.archive_options.rate_limit;
+    // Archives are made of many separate files, so they're worked on several at once, one thread to a core unless told otherwise.
+    // Single streams are compressed a block at a time unless asked, since their output goes out in order anyway.
+    let compressing_stream = settings.zip && !settings.archive && !matches.opt_present("follow");
+    match matches.opt_str("threads").map(|threads| threads.parse::<usize>()) {
+        None if creating_archive => {
+            settings.archive_options.threads = thread::available_parallelism().map_or(1, |threads| threads.get())
+        }
+        None => {}
+        Some(Ok(threads)) if threads > 0 && creating_archive => { settings.archive_options.threads = threads }
+        Some(Ok(threads)) if threads > 0 && compressing_stream => { settings.threads = threads }
+        Some(Ok(_)) if !creating_archive && !compressing_stream => {
+            println!("--threads can only be used when compressing, with -z, and not with --follow!");
+            usage();
+            return Some(1)
+        }
+        Some(_) => {
+            println!("Invalid thread count!");
+            usage();
+            return Some(1)
+        }
+    }
+    if !creating_archive && (matches.opt_present("follow-symlinks") || matches.opt_present("preserve-symlinks")) {
+        println!("--follow-symlinks and --preserve-symlinks can only be used when creating an archive, with -a -z!");
+        usage();
+        return Some(1)
+    }
+    if settings.archive_options.xattrs && !settings.archive {
+        println!("--xattrs can only be used with -a, when creating or extracting an archive!");
+        usage();
+        return Some(1)
+    }
+    if settings.archive_options.owners && !settings.archive {
+        println!("--preserve can only be used with -a, when creating or extracting an archive!");
+        usage();
+        return Some(1)
+    }
+    // Anyone else can only give files to themselves, which is who extracted files belong to anyway.
+    if settings.archive_options.owners && settings.unzip && !is_root() {
+        println!("Only root can restore owners, so extracted files will belong to you.");
+        settings.archive_options.owners = false;
+    }
+    if settings.archive && settings.unzip && use_stdout {
+        println!("Archives must be extracted to a directory!");
+        usage();
+        return Some(1)
+    }
+
     // if standard in is defined, we expect no input file.
     // But if it is, we expect an input file!
     match matches.opt_str("i") {
         None => {
-            if !use_stdin {
+            if !use_stdin && !creating_archive {
                 println!("No input specified!");
                 usage();
                 return Some(1);
@@ -219,7 +2059,7 @@ fn parse_args(input_filename: &mut Option<String>,
                 usage();
                 return Some(1);
             }
-            *input_filename = Some(filename)
+            settings.input_file = Some(filename)
         }
     }
 
@@ -238,7 +2078,336 @@ fn parse_args(input_filename: &mut Option<String>,
                 usage();
                 return Some(1)
             }
-            *output_filename = Some(filename)
+            settings.output_file = Some(filename)
+        }
+    }
+
+    // Compressed data is binary. A terminal would garble it, and a Windows console refuses bytes that aren't UTF-8.
+    // Pipes and redirects are byte exact everywhere: stdin and stdout are read and written raw, never in text mode.
+    if settings.zip && use_stdout && stdout().is_terminal() {
+        println!("Compressed data won't be written to a terminal! Redirect stdout, or use -o.");
+        return Some(1)
+    }
+
+    if let Some(size) = matches.opt_str("block-size") {
+        match parse_size(&size) {
+            Some(size) if size > 0 => { settings.block_size = size as usize }
+            _ => {
+                println!("Invalid block size: {}", size);
+                usage();
+                return Some(1)
+            }
+        }
+    }
+
+    settings.rsyncable = matches.opt_present("rsyncable");
+    // Only one kind of coding can be used at once.
+    let chosen: Vec<_> = optional_coders().filter(|coder| matches.opt_present(coder.name())).collect();
+    if chosen.len() > 1 {
+        let names: Vec<String> = optional_coders().map(|coder| format!("--{}", coder.name())).collect();
+        println!("Only one of {} may be used!", names.join(", "));
+        usage();
+        return Some(1)
+    }
+    if let Some(coder) = chosen.first() {
+        settings.method.coder = *coder
+    }
+
+    // The transform needs a lot of memory per byte, so it gets smaller blocks by default.
+    let bwt = matches.opt_present("bwt");
+    if bwt && !matches.opt_present("block-size") {
+        settings.block_size = BWT_BLOCK_SIZE
+    }
+
+    // Presets are Huffman tables, so they only work with plain Huffman coding.
+    if let Some(name) = matches.opt_str("preset") {
+        if !chosen.is_empty() || bwt {
+            println!("--preset can only be used with plain Huffman coding!");
+            usage();
+            return Some(1)
+        }
+        match Preset::parse(&name) {
+            Some(preset) => {
+                settings.method.coder = PRESET;
+                settings.method.preset = Some(preset)
+            }
+            None => {
+                println!("Unknown preset: {}", name);
+                usage();
+                return Some(1)
+            }
+        }
+    }
+
+    // Dictionaries are Huffman tables too. Extracting needs the same dictionary, whatever the coder.
+    if let Some(path) = matches.opt_str("dict") {
+        if settings.zip && (!chosen.is_empty() || bwt || settings.method.preset.is_some()) {
+            println!("--dict can only be used with plain Huffman coding!");
+            usage();
+            return Some(1)
+        }
+        match Dictionary::load(&path) {
+            Ok(dictionary) => {
+                if settings.zip {
+                    settings.method.coder = DICTIONARY
+                }
+                settings.method.dictionary = Some(Arc::new(dictionary))
+            }
+            Err(message) => {
+                println!("{}", message);
+                return Some(1)
+            }
+        }
+    }
+
+    // Only plain Huffman blocks can have their bits packed the other way.
+    if matches.opt_present("msb-first") {
+        if !chosen.is_empty() || settings.method.preset.is_some() || settings.method.dictionary.is_some() {
+            println!("--msb-first can only be used with plain Huffman coding!");
+            usage();
+            return Some(1)
+        }
+        settings.method.bit_order = BitOrder::Msb
+    }
+
+    // Canonical codes are plain Huffman codes too, assigned in an order anyone can reproduce.
+    if matches.opt_present("canonical") {
+        if !chosen.is_empty() || settings.method.preset.is_some() || settings.method.dictionary.is_some() {
+            println!("--canonical can only be used with plain Huffman coding!");
+            usage();
+            return Some(1)
+        }
+        settings.method.coder = CANONICAL
+    }
+
+    if let Some(names) = matches.opt_str("filter") {
+        match parse_filters(&names) {
+            Some(filters) => { settings.method.filters = filters }
+            None => {
+                println!("Unknown filter: {}", names);
+                usage();
+                return Some(1)
+            }
+        }
+    }
+    // The transform is just another filter, run after any others.
+    if bwt {
+        settings.method.filters.push(Arc::new(Bwt))
+    }
+
+    // A table of codes stands in for counting, so it codes the bytes exactly as they come.
+    // Nothing can transform them first, and each block stores the table for extraction.
+    if let Some(path) = matches.opt_str("codes") {
+        if !settings.zip || settings.archive || !chosen.is_empty() ||
+            !settings.method.filters.is_empty() || settings.method.preset.is_some() ||
+            settings.method.dictionary.is_some() || matches.opt_present("canonical") {
+            println!("--codes can only be used to compress a single stream with plain Huffman coding!");
+            usage();
+            return Some(1)
+        }
+        match load_codes(&path) {
+            Ok(table) => {
+                settings.method.coder = TABLE;
+                settings.method.table = Some(Arc::new(table))
+            }
+            Err(message) => {
+                println!("{}", message);
+                return Some(1)
+            }
+        }
+    }
+
+    // Frequencies counted elsewhere stand in for counting each block, but are stored just as counted ones are.
+    // Extracting doesn't need them, unlike a dictionary.
+    if let Some(path) = matches.opt_str("freqs") {
+        if !settings.zip || !chosen.is_empty() || !settings.method.filters.is_empty() ||
+            settings.method.preset.is_some() || settings.method.dictionary.is_some() ||
+            matches.opt_present("canonical") || settings.method.table.is_some() {
+            println!("--freqs can only be used to compress with plain Huffman coding!");
+            usage();
+            return Some(1)
+        }
+        match Dictionary::load(&path) {
+            Ok(dictionary) => { settings.method.frequencies = Some(Arc::new(dictionary.frequencies().clone())) }
+            Err(message) => {
+                println!("{}", message);
+                return Some(1)
+            }
+        }
+    }
+
+    // Checkpoints describe a compression from one file to another.
+    // Resuming only makes sense if there's a file to resume from, and a file to resume into!
+    settings.checkpoint = matches.opt_present("checkpoint");
+    settings.resume = matches.opt_present("resume");
+    // Pipes and sockets can't be truncated back to a checkpoint, or read again from the start, so both must be regular files.
+    if (settings.checkpoint || settings.resume) && (!settings.zip || settings.archive ||
+        !settings.input_file.as_deref().is_some_and(is_regular) || !settings.output_file.as_deref().is_some_and(is_regular)) {
+        println!("Checkpoints require compressing from a regular input file to a regular output file!");
+        usage();
+        return Some(1)
+    }
+
+    // gzip has its own format, so none of ours can be used with it.
+    settings.gzip = matches.opt_present("gzip");
+    if settings.gzip && (!settings.zip || settings.archive || settings.checkpoint || settings.resume ||
+        settings.rsyncable || settings.method != Method::new()) {
+        println!("--gzip can only be used to compress a single stream, with no other options!");
+        usage();
+        return Some(1)
+    }
+
+    // Members are found through the index written when compressing a tar stream.
+    settings.member = matches.opt_str("member");
+    if settings.member.is_some() && (settings.zip || settings.archive) {
+        println!("--member can only be used to extract from a compressed tar stream!");
+        usage();
+        return Some(1)
+    }
+
+    // Archives hash each chunk already, and gzip has its own checksum.
+    if let Some(name) = matches.opt_str("checksum") {
+        if !settings.zip || settings.archive || settings.gzip {
+            println!("--checksum can only be used to compress a single stream!");
+            usage();
+            return Some(1)
+        }
+        match parse_checksum(&name) {
+            Some(checksum) => { settings.checksum = Some(checksum) }
+            None => {
+                println!("Unknown checksum: {}", name);
+                usage();
+                return Some(1)
+            }
+        }
+    }
+
+    // Guards against small files that decompress to huge ones.
+    if let Some(size) = matches.opt_str("max-output-size") {
+        if settings.zip || settings.archive || settings.member.is_some() {
+            println!("--max-output-size can only be used to extract a single stream!");
+            usage();
+            return Some(1)
+        }
+        match parse_size(&size) {
+            Some(size) => { settings.max_output_size = Some(size) }
+            None => {
+                println!("Invalid maximum output size: {}", size);
+                usage();
+                return Some(1)
+            }
+        }
+    }
+
+    // Guards against files far more compressed than any real one, whatever their size.
+    if let Some(ratio) = matches.opt_str("max-expansion") {
+        if settings.zip || settings.archive || settings.member.is_some() {
+            println!("--max-expansion can only be used to extract a single stream!");
+            usage();
+            return Some(1)
+        }
+        match ratio.parse::<u64>() {
+            Ok(ratio) if ratio > 0 => { settings.max_expansion = Some(ratio) }
+            _ => {
+                println!("Invalid maximum expansion: {}", ratio);
+                usage();
+                return Some(1)
+            }
+        }
+    }
+
+    // Untrusted streams can be held to less than the format allows, before any of their tables are built.
+    for name in ["max-map-entries", "max-blocks", "max-header-size"] {
+        let Some(value) = matches.opt_str(name) else { continue };
+        if settings.zip || settings.archive || settings.member.is_some() {
+            println!("--{} can only be used to extract a single stream!", name);
+            usage();
+            return Some(1)
+        }
+        let limit = match parse_size(&value).and_then(|limit| usize::try_from(limit).ok()) {
+            Some(limit) => { limit }
+            None => {
+                println!("Invalid --{}: {}", name, value);
+                usage();
+                return Some(1)
+            }
+        };
+        match name {
+            "max-map-entries" => { settings.max_map_entries = Some(limit) }
+            "max-blocks" => { settings.max_blocks = Some(limit) }
+            _ => { settings.max_header_size = Some(limit) }
+        }
+    }
+
+    // The input file is read a block at a time, skipping around, so it must be a regular file, not stdin or a pipe.
+    if let Some(range) = matches.opt_str("byte-range") {
+        if settings.zip || settings.archive || settings.member.is_some() ||
+            !settings.input_file.as_deref().is_some_and(is_regular) {
+            println!("--byte-range can only be used to extract from a single stream, read with -i from a regular file!");
+            usage();
+            return Some(1)
+        }
+        match range.split_once(':').and_then(|(start, len)| Some((parse_size(start)?, parse_size(len)?))) {
+            Some(range) => { settings.byte_range = Some(range) }
+            None => {
+                println!("Invalid byte range: {}", range);
+                usage();
+                return Some(1)
+            }
+        }
+    }
+
+    // Followed files are read as they grow, a block at a time, so there's nothing to go back over or chunk by content.
+    if matches.opt_present("follow") {
+        if !settings.zip || settings.archive || settings.gzip || settings.checkpoint || settings.resume ||
+            settings.rsyncable || !settings.input_file.as_deref().is_some_and(is_regular) {
+            println!("--follow can only be used to compress a single stream, read with -i from a regular file!");
+            usage();
+            return Some(1)
+        }
+        match matches.opt_str("flush-interval").map(|interval| interval.parse::<u64>()) {
+            None => { settings.follow = Some(Duration::from_millis(1000)) }
+            Some(Ok(interval)) => { settings.follow = Some(Duration::from_millis(interval)) }
+            Some(Err(_)) => {
+                println!("Invalid flush interval!");
+                usage();
+                return Some(1)
+            }
+        }
+    } else if matches.opt_present("flush-interval") {
+        println!("--flush-interval can only be used with --follow!");
+        usage();
+        return Some(1)
+    }
+
+    // Only a single output file can be synced. Extracted archives are many files, and followed files are never finished.
+    // Armor is written as it goes, but never partly: checkpoints and followed files would leave it without a footer.
+    settings.armor = matches.opt_present("armor");
+    if settings.armor && (!settings.zip || settings.checkpoint || settings.resume || settings.follow.is_some()) {
+        println!("--armor can only be used when compressing, without --checkpoint, --resume or --follow!");
+        usage();
+        return Some(1)
+    }
+
+    settings.durable = matches.opt_present("sync");
+    if settings.durable && (settings.output_file.is_none() || (settings.archive && settings.unzip) || settings.follow.is_some()) {
+        println!("--sync can only be used when writing a single output file, with -o!");
+        usage();
+        return Some(1)
+    }
+
+    // The clock starts now. A timeout too long for the clock to reach is no more valid than none at all.
+    if let Some(limit) = matches.opt_str("timeout") {
+        match parse_duration(&limit).filter(|limit| !limit.is_zero()).and_then(|limit| Instant::now().checked_add(limit)) {
+            Some(deadline) => {
+                settings.deadline = Some(deadline);
+                settings.archive_options.deadline = settings.deadline;
+            }
+            _ => {
+                println!("Invalid timeout: {}", limit);
+                usage();
+                return Some(1)
+            }
         }
     }
 
@@ -246,13 +2415,119 @@ fn parse_args(input_filename: &mut Option<String>,
     None
 }
 
+// Windows shells leave wildcards in file arguments for the program to expand, so they're expanded here.
+// --no-glob takes them as they are, for files whose names really have wildcards in them.
+fn file_arguments(matches: &Matches, args: &[String]) -> Result<Vec<String>, String> {
+    match matches.opt_present("no-glob") {
+        true => { Ok(args.to_vec()) }
+        false => { expand(args) }
+    }
+}
+
+// Symbolic links are followed, unless --preserve-symlinks says to archive them as links.
+// Attributes are only kept with --xattrs, and owners with --preserve. Files are read as fast as they can be, unless --limit-rate says otherwise.
+fn archive_options(matches: &Matches) -> Result<ArchiveOptions, String> {
+    let symlinks = match (matches.opt_present("follow-symlinks"), matches.opt_present("preserve-symlinks")) {
+        (true, true) => { return Err(String::from("Symbolic links can either be followed or preserved, not both!")) }
+        (_, true) => { Symlinks::Preserve }
+        (_, false) => { Symlinks::Follow }
+    };
+    let rate_limit = match matches.opt_str("limit-rate").map(|rate| (parse_size(&rate), rate)) {
+        None => { None }
+        Some((Some(rate), _)) if rate > 0 => { Some(rate) }
+        Some((_, rate)) => { return Err(format!("Invalid rate: {}", rate)) }
+    };
+    Ok(ArchiveOptions {
+        symlinks,
+        xattrs: matches.opt_present("xattrs"),
+        owners: matches.opt_present("preserve"),
+        deadline: None,
+        rate_limit,
+        threads: 1,
+    })
+}
+
+// Every coder with a flag of its own. Plain Huffman is used when none of them is asked for.
+fn optional_coders() -> impl Iterator<Item = &'static dyn EntropyCoder> {
+    CODERS.into_iter().filter(|coder| coder.flag())
+}
+
+// Parse a size, such as 4096, 64K, 16M, or 1G.
+fn parse_size(size: &str) -> Option<u64> {
+    let (digits, multiplier) = match size.chars().last()?.to_ascii_uppercase() {
+        'K' => { (&size[..size.len() - 1], 1 << 10) }
+        'M' => { (&size[..size.len() - 1], 1 << 20) }
+        'G' => { (&size[..size.len() - 1], 1 << 30) }
+        _ => { (size, 1) }
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
 fn usage() {
     println!("Usage: wz");
     println!("-u (usage)");
     println!("-r (read from stdin, mutually exclusive with -i");
-    println!("-i (input file)");
+    println!("-i (input file, named pipe, or unix socket, or an http:// or https:// URL when built with the remote feature)");
     println!("-p (print to stdout, mutually exclusive with -so");
-    println!("-o (output file)");
+    println!("-o (output file, named pipe, or unix socket; blocks are written as soon as they are ready)");
     println!("-z (compress input file, mutually exclusive with -x)");
-    println!("-x (extract input file, mutually exclusive with -z)")
+    println!("-x (extract input file, gzip files too, mutually exclusive with -z)");
+    println!("-a (archive mode: -z compresses the files listed after the options, keeping hard links on Unix as links and holes in sparse files as holes, -x extracts into -o, zip files too)");
+    println!("--follow-symlinks (with -a -z and sync, archive what symbolic links point at, as if it were there instead; the default)");
+    println!("--preserve-symlinks (with -a -z and sync, archive symbolic links as links, made again on extraction if they stay inside it)");
+    println!("--xattrs (with -a and sync, keep extended attributes and ACLs, and restore them on extraction where the platform allows)");
+    println!("--preserve (with -a and sync, keep who owns each file, by id and name, and when extracting as root, give files back to them)");
+    println!("--report-resources (print time per phase, peak memory, and the largest table and buffer sizes to stderr)");
+    println!("--nice (run with low CPU and I/O priority)");
+    println!("--block-size (bytes of input per compressed block, i.e. 64K, 16M)");
+    for coder in optional_coders() {
+        println!("--{} ({})", coder.name(), coder.description());
+    }
+    println!("--bwt (apply the Burrows-Wheeler and move-to-front transforms before coding)");
+    println!("--preset (Huffman code with a built in table, for small inputs: text, json, html, x86)");
+    println!("--dict (Huffman code with a dictionary from wz train, needed again to extract)");
+    println!("--filter (filter each block before coding: rle, bpe, delta, bwt, or transpose:N for N byte records, several joined with commas)");
+    println!("--rsyncable (choose block boundaries by content, so small edits change few blocks)");
+    println!("--checkpoint (record progress, so an interrupted compression can resume)");
+    println!("--resume (resume an interrupted compression from its checkpoint)");
+    println!("--gzip (write a gzip file, which gunzip and other tools can read)");
+    println!("--armor (with -z, write compressed output as base64 text between header and footer lines, for pasting into tickets, emails or YAML; -x recognizes it)");
+    println!("--member (extract one member of a compressed tar stream, without decompressing the rest)");
+    println!("--msb-first (pack Huffman coded bits from the high end of each byte, as many other decoders expect)");
+    println!("--canonical (assign Huffman codes by length then byte, so other tools can rebuild them from lengths alone)");
+    println!("--codes (Huffman code with a JSON table of codes, like wz inspect prints, rather than counting)");
+    println!("--freqs (Huffman code with the frequencies of a dictionary from wz train, stored in each block rather than counted)");
+    println!("--checksum (store a checksum of the input, checked when extracting: crc32, xxhash, or sha256)");
+    println!("--max-output-size (with -x, fail rather than decompress a stream to more than this many bytes, i.e. 64K, 1G)");
+    println!("--max-expansion (with -x, fail rather than decompress a stream to more than this many times its compressed size, i.e. 1000)");
+    println!("--max-map-entries (with -x, fail rather than decode a block whose frequency map or code table has more entries than this, i.e. 256)");
+    println!("--max-blocks (with -x, fail rather than decode a stream of more blocks than this, i.e. 1024)");
+    println!("--max-header-size (with -x, fail rather than decode a block with more bytes than this before its coded data, i.e. 4K)");
+    println!("--byte-range (with -x and -i from a regular file, extract only LEN bytes from START on, decoding only the blocks they lie in, i.e. 1M:64K)");
+    println!("--follow (with -z and -i, keep compressing the input as it grows, like tail -f, until killed; --checksum checks each block)");
+    println!("--flush-interval (with --follow, milliseconds before appended data is written, even short of a block; 1000 unless given)");
+    println!("--timeout (give up after this long, i.e. 30s, 5m, exiting with 124; archives being created keep what was done, and followed files finish their last block)");
+    println!("--errors (how a failed run says why: text, the default, or json, a record on stderr with the file, phase, kind, offset and message; mistaken arguments are always text)");
+    println!("--threads (with -a -z, how many files to read and compress at once; one per core unless given. With -z alone, how many blocks to compress at once, written in order; one unless given)");
+    println!("--limit-rate (read input and write output, files being archived too, at no more than this many bytes a second, i.e. 512K, 10M)");
+    println!("--sync (with -o, make sure the output file and its directory are on disk before reporting success, so a crash can't lose it)");
+    println!("--no-glob (with -a, cat, train and sync, take file arguments as they are, rather than expanding * and ? in them)");
+    println!("wz train --dict out.dict files... (train a dictionary on sample files)");
+    println!("wz list archive (list the members of a wz archive or zip file)");
+    println!("wz sync [--block-size 64K] [--preserve-symlinks] [--xattrs] [--preserve] [--sync] [--limit-rate 10M] dir... archive.wz (update an archive from its files, compressing only new and changed chunks, and giving up if a file changes meanwhile; a sync cut short by a crash is finished or undone by the next, so the archive is only ever the old one or the new one)");
+    println!("wz recompress in.wz -o out.wz (compress again, trying every coder on each block)");
+    println!("wz selfcheck (round trip empty, single byte, every byte, random and skewed data through every coder, printing pass or FAIL for each; exits 1 if any failed)");
+    println!("wz gen-vectors dir (write test vectors for other decoders: inputs from fixed seeds, the .wz stream of each, canonically Huffman coded in 4K blocks, and its codes, listed in dir/vectors.json)");
+    println!("wz cat [-a] a.wz b.wz -o combined.wz (join compressed files or archives, without recompressing)");
+    println!("wz diff old new -o patch.wzd (make a compressed patch which turns old into new)");
+    println!("wz patch old patch.wzd -o new (apply a patch, checking both the old file and the result)");
+    println!("wz cmp [--dict file.dict] original file.wz (decompress a file block by block, checking it against the original without writing anything; prints the first differing offset, and like cmp exits 1 if they differ, 2 on trouble)");
+    println!("wz inspect --codes json [--dict file.dict] file.wz (print the codes each block gives each byte, and the shape of its Huffman tree, as JSON)");
+    println!("wz inspect --dump-tree text|dot file.wz (draw each block's Huffman tree, as indented text or Graphviz DOT)");
+    println!("wz inspect --blocks file.wz (print each block's offset, size, kind and checksum as JSON, without decompressing anything)");
+    println!("wz dump [--full] file.wz (print a hex dump of a compressed stream, naming each field: frame lengths, block kinds, frequency map lengths and entries, bit counts and payloads; long fields show their first and last lines unless --full)");
+    println!("wz send [--block-size 64K] host:port file (compress a file and send it to wz serve, a block at a time)");
+    println!("wz serve --listen addr [-o file] [--dict file.dict] (receive one file from wz send, checking each block as it arrives)");
+    println!("wz watch dir --dest out [--block-size 64K] [--debounce 1000] (compress files into out as they appear or change in dir, once unchanged for that many ms)");
+    println!("wz --server (take jobs from a frontend as JSON-RPC on stdin, one request a line: submit, cancel and result; answers, progress and results go to stdout the same way)")
 }
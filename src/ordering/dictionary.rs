@@ -0,0 +1,132 @@
+// A dictionary is a frequency table trained on sample data and kept in a file of its own.
+// Many small, similar files can then share one table instead of each storing their own.
+// Author: Will Morris
+
+/*
+  CONTENTS:
+  -- "WZDICT"
+  -- freqmap.
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use crate::file::archive::hash_chunk;
+use crate::file::bytestream::{ByteStream, read_bytes};
+use crate::file::error::WzError;
+use crate::ordering::freq::gen_frequency;
+use crate::ordering::freqmap::Freqmap;
+
+const MAGIC: &[u8] = b"WZDICT";
+
+pub type DictionaryId = u32;
+pub const ID_LEN: usize = size_of::<DictionaryId>();
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dictionary {
+    frequencies: HashMap<u8, u64>,
+}
+
+impl Dictionary {
+    // Count every byte of every sample.
+    // Bytes which never appeared still get a frequency, so that any input can be coded.
+    pub fn train(samples: &[&[u8]]) -> Self {
+        let mut frequencies: Freqmap = (0..=255).map(|byte| (byte, 1)).collect();
+        for sample in samples {
+            frequencies.merge(&gen_frequency(sample).into());
+        }
+        Dictionary { frequencies: frequencies.into() }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|_| format!("File not found: {}", path))?;
+        if !bytes.starts_with(MAGIC) || bytes.len() == MAGIC.len() {
+            return Err(format!("Not a dictionary: {}", path))
+        }
+        Dictionary::from_whole_stream(&bytes).map_err(|error| format!("Corrupt dictionary {}: {}", path, error))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        File::create(path)
+            .and_then(|mut file| self.clone().write_to(&mut file))
+            .map_err(|e| format!("Could not write dictionary {}: {}", path, e))
+    }
+
+    pub fn frequencies(&self) -> &HashMap<u8, u64> {
+        &self.frequencies
+    }
+
+    // Identifies the dictionary, so a block is never decoded with the wrong one.
+    // Frequencies are hashed in byte order, since a freqmap's own order is arbitrary.
+    // Only part of the hash is kept: it's stored in every block, and only needs to catch mistakes.
+    pub fn id(&self) -> DictionaryId {
+        let canonical: Vec<u8> = (0..=255u8)
+            .flat_map(|byte| self.frequencies.get(&byte).copied().unwrap_or(0).to_le_bytes())
+            .collect();
+        hash_chunk(&canonical) as DictionaryId
+    }
+}
+
+impl ByteStream for Dictionary {
+    type Data = Dictionary;
+
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError> {
+        let mut i = 0;
+        if read_bytes(bytes, &mut i, MAGIC.len(), "a dictionary")? != MAGIC {
+            return Err(WzError::Malformed(String::from("Not a dictionary!")))
+        }
+        let (frequencies, len) = Freqmap::from_stream(&bytes[i..])?;
+        let frequencies: HashMap<u8, u64> = frequencies.into();
+        // Trained dictionaries count every byte at least once. Any less, and some input would have no code.
+        if let Some(byte) = (0..=255).find(|byte| frequencies.get(byte).is_none_or(|frequency| *frequency == 0)) {
+            return Err(WzError::Malformed(format!("A dictionary needs a frequency for every byte, but byte {} has none!", byte)))
+        }
+        Ok((Dictionary { frequencies }, i + len))
+    }
+
+    fn to_stream(self) -> Vec<u8> {
+        let mut retval = MAGIC.to_vec();
+        retval.append(&mut Freqmap::from(self.frequencies).to_stream());
+        retval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::file::bytestream::ByteStream;
+    use crate::ordering::dictionary::Dictionary;
+    use crate::ordering::freqmap::Freqmap;
+
+    #[test]
+    fn test_train() {
+        let dictionary = Dictionary::train(&[b"aab", b"ba"]);
+        assert_eq!(256, dictionary.frequencies().len());
+        assert_eq!(4, dictionary.frequencies()[&b'a']);
+        assert_eq!(3, dictionary.frequencies()[&b'b']);
+        assert_eq!(1, dictionary.frequencies()[&b'c']);
+    }
+
+    #[test]
+    fn test_to_from() {
+        let dictionary = Dictionary::train(&[b"some sample text"]);
+        let stream = dictionary.clone().to_stream();
+        assert!(stream.starts_with(b"WZDICT"));
+        let loaded = Dictionary::from_whole_stream(&stream).unwrap();
+        assert_eq!(dictionary, loaded);
+        assert_eq!(dictionary.id(), loaded.id());
+        assert_ne!(dictionary.id(), Dictionary::train(&[b"other text"]).id());
+    }
+
+    #[test]
+    fn test_partial() {
+        // No frequencies at all, only some, and every byte but one zero.
+        let partial: Freqmap = (0..255).map(|byte| (byte, 1)).collect();
+        let zero: Freqmap = (0..=255).map(|byte| (byte, (byte != b'a') as u64)).collect();
+        for frequencies in [Freqmap::new(HashMap::new()), Freqmap::new(HashMap::from([(b'a', 5)])), partial, zero] {
+            let mut stream = b"WZDICT".to_vec();
+            stream.append(&mut frequencies.to_stream());
+            assert!(Dictionary::from_whole_stream(&stream).is_err());
+        }
+    }
+}
@@ -13,4 +13,52 @@ pub fn gen_frequency(bytes: &[u8]) -> HashMap<u8, u64> {
         map.insert(*curr, map.get(curr).unwrap() + 1);
         map
     })
-}
\ No newline at end of file
+}
+
+// Scale frequencies down so that they sum to no more than max_total.
+// Every byte which appeared keeps a frequency of at least one, so it can still be encoded.
+// max_total must be at least 256, so that there's room for every byte.
+pub fn scale_frequencies(freqs: &HashMap<u8, u64>, max_total: u64) -> HashMap<u8, u64> {
+    assert!(max_total >= 256);
+    let total: u64 = freqs.values().sum();
+    if total <= max_total {
+        return freqs.clone()
+    }
+
+    // Reserve one for each byte, and share out the rest in proportion.
+    let spare = (max_total - freqs.len() as u64) as u128;
+    freqs.iter().map(|(byte, freq)| {
+        let share = (*freq as u128 * spare / total as u128) as u64;
+        (*byte, share + 1)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::ordering::freq::{gen_frequency, scale_frequencies};
+
+    #[test]
+    fn test_gen_frequency() {
+        let freqs = gen_frequency(b"abbccc");
+        assert_eq!(3, freqs.len());
+        assert_eq!(3, freqs[&b'c']);
+    }
+
+    #[test]
+    fn test_scale_frequencies() {
+        let mut freqs = HashMap::new();
+        freqs.insert(0, u64::MAX / 2);
+        freqs.insert(1, 1);
+        freqs.insert(2, 1_000_000);
+
+        let scaled = scale_frequencies(&freqs, 1 << 16);
+        assert!(scaled.values().sum::<u64>() <= 1 << 16);
+        assert_eq!(1, scaled[&1]);
+        assert!(scaled[&0] > scaled[&2]);
+
+        // Small enough tables are left alone.
+        let small = gen_frequency(b"hello");
+        assert_eq!(small, scale_frequencies(&small, 1 << 16));
+    }
+}
@@ -3,15 +3,13 @@
 // This will be useful for encoding as a file.
 // Author: Will Morris
 
+use std::collections::hash_map;
 use std::collections::HashMap;
-use crate::file::bytestream::{ByteStream, LONG_LEN, long_to_bytes, min_byte_size, slice_to_long};
-
-// What's the maximum number of bytes needed to represent the contents of a freqmap in memory?
-// 9 bytes per 256 entries, plus one byte for the per-entry size field.
-pub(crate) const MAX_MAP_SIZE: usize = (LONG_LEN + 1) * 256 + 1;
-// How many bytes will it take to represent the size field for our map?
-// In this case, MAX_MAP_SIZE can be represented as u16 (with lots of spare space!).
-pub(crate) const MAP_SIZE_FIELD_LEN: usize = 2;
+use std::ops::Deref;
+use crate::file::bytestream::{ByteStream, min_byte_size};
+use crate::file::error::WzError;
+use crate::file::format::{read_entry, read_entry_width, write_entry, write_entry_width};
+use crate::ordering::symbolfreq::Symbol;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Freqmap {
@@ -28,6 +26,74 @@ impl Freqmap {
     pub fn take(self) -> HashMap<u8, u64> {
         self.data
     }
+
+    // Add other's counts to these, e.g. to combine the counts of several files.
+    // Counts too large for a u64 stay at u64::MAX, rather than wrapping around.
+    pub fn merge(&mut self, other: &Freqmap) {
+        for (byte, count) in other {
+            let total = self.data.entry(*byte).or_insert(0);
+            *total = total.saturating_add(*count);
+        }
+    }
+
+    // Multiply every count by factor, rounding to the nearest whole count.
+    // Bytes which appeared keep a count of at least one, so they can still be encoded.
+    // Counts too large for a u64 stay at u64::MAX.
+    pub fn scale(&mut self, factor: f64) {
+        assert!(factor.is_finite() && factor >= 0.0, "Can't scale frequencies by {}!", factor);
+        for count in self.data.values_mut() {
+            if *count > 0 {
+                // Casting a float to an integer saturates.
+                *count = ((*count as f64 * factor).round() as u64).max(1);
+            }
+        }
+    }
+}
+
+impl From<HashMap<u8, u64>> for Freqmap {
+    fn from(map: HashMap<u8, u64>) -> Self {
+        Freqmap::new(map)
+    }
+}
+
+impl From<Freqmap> for HashMap<u8, u64> {
+    fn from(map: Freqmap) -> Self {
+        map.take()
+    }
+}
+
+// Later counts for the same byte replace earlier ones, just as they would in a HashMap.
+impl FromIterator<(u8, u64)> for Freqmap {
+    fn from_iter<I: IntoIterator<Item = (u8, u64)>>(iter: I) -> Self {
+        Freqmap::new(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for Freqmap {
+    type Item = (u8, u64);
+    type IntoIter = hash_map::IntoIter<u8, u64>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Freqmap {
+    type Item = (&'a u8, &'a u64);
+    type IntoIter = hash_map::Iter<'a, u8, u64>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.iter()
+    }
+}
+
+// Reading a Freqmap is just reading its map: get, len, iter and so on all work on it directly.
+impl Deref for Freqmap {
+    type Target = HashMap<u8, u64>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
 }
 
 // Primary purpose of freqmap: enable serialization
@@ -36,42 +102,34 @@ impl ByteStream for Freqmap {
 
     // Given a stream of bytes containing key-value pairs.
     // Convert that stream into a hashmap of those pairs.
-    fn from_stream(bytes: &[u8]) -> Self::Data {
+    // Maps don't store their own length, so every byte is taken to be part of the map.
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError> {
         let mut map: HashMap<u8, u64> = HashMap::new();
-        let size = bytes[0] as usize;
-
-        // premature exit: too small!
-        // Extra byte for the first entry.
-        if bytes.len() <= size + 1 {
-            return Freqmap::new(map);
-        }
+        let mut i = 0;
+        let size = read_entry_width(bytes, &mut i)?;
 
-        let bound= bytes.len() - size;
         // Start adding key-value pairs after the size field.
-        let mut i = 1;
-
-        while i < bound {
-            let byte = bytes[i];
-            i += 1;
-            let val = slice_to_long(&bytes[i..i+size]);
-            i+= size;
+        while i < bytes.len() {
+            let byte = read_entry(bytes, &mut i, 1)? as u8;
+            let val = read_entry(bytes, &mut i, size)?;
             map.insert(byte, val);
         }
 
-        Freqmap::new(map)
+        Ok((map.into(), i))
     }
 
     // Convert one of these bad boys into a byte stream.
+    // Entries are written in byte order, so the same counts always make the same bytes.
     fn to_stream(self) -> Vec<u8> {
         let mut retval = Vec::new();
-        let data = self.take();
-
-        let size = trim_map(&data);
-        retval.push(size);
+        let size = trim_map(&self);
+        write_entry_width(&mut retval, size);
 
-        for (byte, value) in data {
-            retval.push(byte);
-            retval.append(&mut long_to_bytes(value, size));
+        let mut entries: Vec<(u8, u64)> = self.into_iter().collect();
+        entries.sort_unstable();
+        for (byte, value) in entries {
+            write_entry(&mut retval, byte as u64, 1);
+            write_entry(&mut retval, value, size);
         }
         retval
     }
@@ -79,7 +137,7 @@ impl ByteStream for Freqmap {
 
 // Find the minimum number of bytes needed to represent values in map
 // Useful for serialization -- we don't want to end up encoding extra zeros in the hashmaps!
-fn trim_map(map: &HashMap<u8, u64>) -> u8 {
+fn trim_map<K>(map: &HashMap<K, u64>) -> u8 {
     map.values().fold(1, |min_size: u8, datum | {
         let size = min_byte_size(*datum);
         if size > min_size {
@@ -90,17 +148,78 @@ fn trim_map(map: &HashMap<u8, u64>) -> u8 {
     })
 }
 
+// The same idea as Freqmap, for alphabets larger than a byte.
+// Since symbols can be large too, they are trimmed just like the frequencies are.
+/*
+  CONTENTS:
+  -- width of each symbol
+  -- width of each frequency
+  -- symbol-frequency pairs.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolFreqmap {
+    data: HashMap<Symbol, u64>
+}
+
+impl SymbolFreqmap {
+    pub fn new(map: HashMap<Symbol, u64>) -> Self {
+        SymbolFreqmap { data: map }
+    }
+
+    pub fn take(self) -> HashMap<Symbol, u64> {
+        self.data
+    }
+}
+
+impl ByteStream for SymbolFreqmap {
+    type Data = SymbolFreqmap;
+
+    fn from_stream(bytes: &[u8]) -> Result<(Self::Data, usize), WzError> {
+        let mut i = 0;
+        let symbol_size = read_entry_width(bytes, &mut i)?;
+        let value_size = read_entry_width(bytes, &mut i)?;
+
+        let mut map = HashMap::new();
+        while i < bytes.len() {
+            let symbol = read_entry(bytes, &mut i, symbol_size)? as Symbol;
+            let value = read_entry(bytes, &mut i, value_size)?;
+            map.insert(symbol, value);
+        }
+
+        Ok((SymbolFreqmap::new(map), i))
+    }
+
+    fn to_stream(self) -> Vec<u8> {
+        let data = self.take();
+        let symbol_size = data.keys().map(|symbol| min_byte_size(*symbol as u64)).max().unwrap_or(1);
+        let value_size = trim_map(&data);
+
+        let mut retval = vec![];
+        write_entry_width(&mut retval, symbol_size);
+        write_entry_width(&mut retval, value_size);
+        let mut entries: Vec<(Symbol, u64)> = data.into_iter().collect();
+        entries.sort_unstable();
+        for (symbol, value) in entries {
+            write_entry(&mut retval, symbol as u64, symbol_size);
+            write_entry(&mut retval, value, value_size);
+        }
+        retval
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
     use crate::file::bytestream::ByteStream;
-    use crate::ordering::freqmap::{Freqmap, trim_map};
+    use crate::file::error::WzError;
+    use crate::ordering::freqmap::{Freqmap, SymbolFreqmap, trim_map};
 
     #[test]
     fn test_empty_to() {
         // An empty map would have size 1
         let bytes = vec![1];
-        let to = Freqmap::from_stream(&bytes);
+        let (to, len) = Freqmap::from_stream(&bytes).unwrap();
+        assert_eq!(1, len);
         let from = to.to_stream();
         assert_eq!(bytes, from);
     }
@@ -113,12 +232,88 @@ mod tests {
         map.insert(1, 22);
 
         let from = Freqmap::new(map.clone()).to_stream();
-        let to = Freqmap::from_stream(&from);
+        let to = Freqmap::from_whole_stream(&from).unwrap();
 
         let to_map = to.take();
         assert_eq!(map, to_map);
     }
 
+    #[test]
+    fn test_byte_order() {
+        // However the map happens to be laid out, its entries come out sorted.
+        let map: HashMap<u8, u64> = (0..=255).rev().map(|byte| (byte, 1)).collect();
+        let from = Freqmap::new(map.clone()).to_stream();
+        let bytes: Vec<u8> = from[1..].chunks(2).map(|entry| entry[0]).collect();
+        assert_eq!((0..=255).collect::<Vec<u8>>(), bytes);
+        assert_eq!(from, Freqmap::new(map.into_iter().collect()).to_stream());
+    }
+
+    #[test]
+    fn test_conversions() {
+        let map = HashMap::from([(0, 52), (4, 14)]);
+        let freqmap = Freqmap::from(map.clone());
+        assert_eq!(Some(&52), freqmap.get(&0));
+        assert_eq!(2, freqmap.len());
+
+        let collected: Freqmap = [(4, 1), (0, 52), (4, 14)].into_iter().collect();
+        assert_eq!(freqmap, collected);
+        assert_eq!(66, (&collected).into_iter().map(|(_, count)| count).sum::<u64>());
+
+        let mut pairs: Vec<(u8, u64)> = collected.into_iter().collect();
+        pairs.sort();
+        assert_eq!(vec![(0, 52), (4, 14)], pairs);
+        assert_eq!(map, HashMap::from(freqmap));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut map = Freqmap::from(HashMap::from([(0, 52), (4, u64::MAX - 1)]));
+        map.merge(&Freqmap::from(HashMap::from([(0, 3), (4, 2), (7, 1)])));
+        assert_eq!(Freqmap::from(HashMap::from([(0, 55), (4, u64::MAX), (7, 1)])), map);
+
+        map.merge(&Freqmap::from(HashMap::new()));
+        assert_eq!(3, map.len());
+    }
+
+    #[test]
+    fn test_scale() {
+        let mut map = Freqmap::from(HashMap::from([(0, 100), (1, 3), (2, 0), (3, u64::MAX)]));
+        map.scale(0.1);
+        // 3 rounds to nothing, but still appeared. 0 never did.
+        assert_eq!(Some(&10), map.get(&0));
+        assert_eq!(Some(&1), map.get(&1));
+        assert_eq!(Some(&0), map.get(&2));
+
+        map.scale(1e30);
+        assert_eq!(Some(&u64::MAX), map.get(&3));
+        assert_eq!(Some(&0), map.get(&2));
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let from = Freqmap::new(HashMap::from([(0, 52), (4, 14)])).to_stream();
+        assert_eq!(Err(WzError::Truncated("a frequency map")), Freqmap::from_stream(&from[..from.len() - 1]));
+        assert_eq!(Err(WzError::Truncated("a frequency map")), Freqmap::from_stream(&[]));
+        assert!(matches!(Freqmap::from_stream(&[9, 0]), Err(WzError::Malformed(_))));
+        assert!(matches!(SymbolFreqmap::from_stream(&[0, 1]), Err(WzError::Malformed(_))));
+    }
+
+
+    #[test]
+    fn test_symbols_to_from() {
+        let mut map = HashMap::new();
+        map.insert(256, 3);
+        map.insert(70000, 1 << 20);
+        map.insert(0, 1);
+
+        let from = SymbolFreqmap::new(map.clone()).to_stream();
+        // Three byte symbols, three byte frequencies, two width fields.
+        assert_eq!(2 + 3 * 6, from.len());
+        assert_eq!(map, SymbolFreqmap::from_whole_stream(&from).unwrap().take());
+
+        let empty = SymbolFreqmap::new(HashMap::new()).to_stream();
+        assert!(SymbolFreqmap::from_whole_stream(&empty).unwrap().take().is_empty());
+    }
 
     #[test]
     fn test_trim_map() {
@@ -0,0 +1,154 @@
+// Built in frequency tables for common kinds of data.
+// A block coded with a preset doesn't need to store its own table,
+// which is most of the cost of compressing a small input.
+// Author: Will Morris
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preset {
+    Text = 0,
+    Json = 1,
+    Html = 2,
+    X86 = 3,
+}
+
+impl Preset {
+    // Find a preset from its name on the command line.
+    pub fn parse(name: &str) -> Option<Preset> {
+        match name {
+            "text" => { Some(Preset::Text) }
+            "json" => { Some(Preset::Json) }
+            "html" => { Some(Preset::Html) }
+            "x86" => { Some(Preset::X86) }
+            _ => { None }
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Preset> {
+        match byte {
+            0 => { Some(Preset::Text) }
+            1 => { Some(Preset::Json) }
+            2 => { Some(Preset::Html) }
+            3 => { Some(Preset::X86) }
+            _ => { None }
+        }
+    }
+
+    // Every byte has a frequency of at least one, so any input can be coded with any preset.
+    pub fn frequencies(&self) -> HashMap<u8, u64> {
+        let table = match self {
+            Preset::Text => { &TEXT }
+            Preset::Json => { &JSON }
+            Preset::Html => { &HTML }
+            Preset::X86 => { &X86 }
+        };
+        table.iter().enumerate().map(|(byte, freq)| (byte as u8, *freq as u64)).collect()
+    }
+}
+
+// Each table is indexed by byte, counted over a sample of that kind of data and scaled down.
+// TEXT: English prose. JSON: assorted schemas and metadata. HTML: documentation pages.
+// X86: x86-64 executables.
+const TEXT: [u16; 256] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 8, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    10540, 20, 1, 1, 1, 1, 1, 1, 16, 16, 1, 1, 461, 118, 506, 48,
+    35, 103, 46, 20, 18, 22, 17, 21, 20, 18, 130, 17, 1, 1, 1, 10,
+    1, 72, 30, 77, 16, 23, 62, 7, 26, 93, 2, 9, 117, 20, 31, 20,
+    69, 1, 108, 41, 191, 23, 7, 80, 1, 13, 1, 1, 1, 1, 1, 4,
+    1, 3610, 522, 1588, 1411, 6055, 921, 856, 2274, 3146, 38, 249, 1826, 1125, 3353, 3277,
+    965, 35, 2792, 3094, 4921, 1551, 456, 910, 139, 689, 15, 1, 1, 1, 1, 1,
+    261, 1, 1, 1, 1, 1, 14, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    7, 1, 7, 1, 5, 1, 1, 1, 1, 228, 1, 1, 14, 14, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 275, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+];
+
+const JSON: [u16; 256] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2538, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    12816, 1, 5693, 53, 69, 1, 1, 4, 22, 22, 13, 31, 1584, 466, 501, 1232,
+    245, 397, 222, 585, 156, 143, 163, 107, 138, 109, 1519, 1, 4, 14, 6, 5,
+    3, 154, 63, 144, 87, 97, 46, 55, 32, 227, 11, 48, 224, 103, 92, 72,
+    145, 9, 85, 168, 119, 40, 44, 33, 11, 11, 26, 86, 48, 86, 16, 749,
+    1, 2431, 422, 1223, 857, 3329, 441, 631, 896, 1863, 74, 221, 1306, 859, 1812, 1697,
+    1796, 54, 1503, 1425, 2200, 509, 149, 91, 257, 858, 80, 458, 9, 458, 1, 1,
+    1, 3, 1, 1, 1, 1, 1, 7, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 7,
+    1, 4, 1, 1, 1, 1, 1, 1, 1, 4, 1, 2, 1, 3, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 19, 8, 4, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    7, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+];
+
+const HTML: [u16; 256] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 816, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    10640, 101, 1032, 22, 4, 1, 68, 81, 163, 163, 3, 7, 226, 1518, 1808, 830,
+    661, 980, 990, 798, 823, 523, 632, 380, 483, 343, 280, 159, 1117, 601, 1119, 8,
+    23, 58, 66, 171, 17, 24, 136, 7, 47, 131, 3, 4, 166, 84, 18, 41,
+    44, 1, 46, 92, 103, 12, 24, 31, 1, 28, 1, 11, 2, 11, 12, 87,
+    24, 2219, 559, 1651, 1238, 3702, 602, 756, 1124, 1898, 37, 227, 1391, 885, 2053, 2294,
+    998, 20, 1757, 2526, 2671, 721, 387, 484, 135, 327, 77, 68, 19, 67, 1, 1,
+    60, 1, 1, 1, 1, 1, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 2, 1, 4, 1, 1, 1, 1, 50, 1, 1, 4, 4, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 64, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+];
+
+const X86: [u16; 256] = [
+    12686, 1352, 863, 437, 529, 457, 249, 268, 718, 186, 214, 157, 146, 179, 294, 762,
+    516, 191, 104, 106, 116, 118, 99, 81, 294, 82, 76, 79, 86, 89, 81, 210,
+    700, 78, 86, 56, 1558, 95, 54, 63, 275, 139, 114, 53, 96, 125, 325, 277,
+    203, 304, 83, 72, 72, 83, 64, 52, 205, 166, 79, 110, 72, 140, 43, 53,
+    292, 343, 160, 137, 535, 163, 76, 84, 3041, 442, 71, 65, 638, 201, 78, 68,
+    212, 54, 93, 139, 192, 128, 71, 66, 127, 42, 51, 75, 191, 154, 45, 95,
+    149, 464, 141, 326, 242, 758, 324, 270, 224, 506, 46, 110, 403, 213, 434, 609,
+    351, 44, 509, 422, 790, 369, 171, 87, 146, 133, 47, 55, 134, 47, 56, 51,
+    215, 108, 43, 414, 373, 278, 75, 42, 109, 1466, 37, 1180, 153, 536, 46, 44,
+    188, 30, 39, 31, 123, 33, 29, 27, 71, 31, 30, 35, 97, 29, 31, 32,
+    125, 28, 28, 29, 40, 29, 30, 27, 75, 30, 36, 31, 47, 28, 31, 36,
+    86, 31, 30, 29, 68, 29, 72, 38, 94, 60, 57, 61, 101, 36, 59, 78,
+    315, 145, 72, 205, 146, 54, 93, 154, 103, 92, 49, 45, 882, 33, 42, 43,
+    120, 71, 85, 51, 38, 38, 42, 38, 84, 60, 44, 81, 35, 33, 44, 67,
+    147, 48, 45, 38, 55, 76, 44, 45, 588, 169, 44, 173, 90, 48, 50, 65,
+    118, 47, 47, 57, 38, 39, 90, 70, 108, 68, 71, 67, 86, 101, 201, 1628,
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::ordering::presets::Preset;
+
+    #[test]
+    fn test_parse() {
+        for preset in [Preset::Text, Preset::Json, Preset::Html, Preset::X86] {
+            assert_eq!(Some(preset), Preset::from_byte(preset as u8));
+            assert_eq!(256, preset.frequencies().len());
+            assert!(preset.frequencies().values().all(|freq| *freq > 0));
+        }
+        assert_eq!(Some(Preset::Json), Preset::parse("json"));
+        assert_eq!(None, Preset::parse("klingon"));
+        assert_eq!(None, Preset::from_byte(4));
+    }
+
+    #[test]
+    fn test_shapes() {
+        let text = Preset::Text.frequencies();
+        assert!(text[&b'e'] > text[&b'z']);
+        let json = Preset::Json.frequencies();
+        assert!(json[&b'"'] > json[&b'<']);
+        let html = Preset::Html.frequencies();
+        assert!(html[&b'<'] > html[&b'{']);
+    }
+}
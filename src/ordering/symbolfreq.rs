@@ -1,19 +1,23 @@
 use std::cmp::Ordering;
 
-// An ordering of a byte to its frequency.
+// Huffman coding isn't limited to bytes -- any symbol with a frequency will do.
+// Bytes are just the symbols 0 through 255.
+pub type Symbol = u32;
+
+// An ordering of a symbol to its frequency.
 // This is useful for propagating into a heap later.
 #[derive(PartialEq, Eq, Debug, Hash)]
-pub struct ByteFreq {
-    byte: u8,
+pub struct SymbolFreq {
+    symbol: Symbol,
     frequency: u64,
 }
 
-impl ByteFreq {
-    pub fn new(byte: u8, frequency: u64) -> Self {
-        Self { byte, frequency }
+impl SymbolFreq {
+    pub fn new(symbol: Symbol, frequency: u64) -> Self {
+        Self { symbol, frequency }
     }
-    pub fn byte(&self) -> u8 {
-        self.byte
+    pub fn symbol(&self) -> Symbol {
+        self.symbol
     }
     pub fn freq(&self) -> u64 {
         self.frequency
@@ -22,15 +26,15 @@ impl ByteFreq {
 
 // Explicit ord implementation needed to ensure count considered first.
 // default ord implementation would compare based on ordering of struct fields.
-impl Ord for ByteFreq {
+impl Ord for SymbolFreq {
     fn cmp(&self, other: &Self) -> Ordering {
         self.frequency.cmp(&other.frequency)
-            .then_with(|| self.byte.cmp(&other.byte))
+            .then_with(|| self.symbol.cmp(&other.symbol))
     }
 }
 
-impl PartialOrd for ByteFreq {
+impl PartialOrd for SymbolFreq {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
-}
\ No newline at end of file
+}
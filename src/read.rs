@@ -0,0 +1,106 @@
+// Decompression through Read, laid out as flate2::read is, so WzDecoder can stand in for GzDecoder.
+// Author: Will Morris
+
+use std::io::{self, ErrorKind, Read};
+use crate::file::stream::{decode, read_frame};
+
+/// Decompresses a wz stream read from inner, a block at a time.
+/// Blocks are decoded just as `wz -x` decodes them, whatever their kind.
+/// Blocks coded with a trained dictionary fail with ErrorKind::InvalidData, and need `wz -x --dict` to extract.
+/// Checksum blocks are skipped, not checked.
+pub struct WzDecoder<R: Read> {
+    inner: R,
+    // The block being read out, and how much of it has been.
+    block: Vec<u8>,
+    read: usize,
+}
+
+impl<R: Read> WzDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        WzDecoder { inner, block: vec![], read: 0 }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from inner directly would lose the decoder its place.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Decode the next block that holds any data. False once the stream has ended.
+    fn next_block(&mut self) -> io::Result<bool> {
+        loop {
+            let Some(block) = read_frame(&mut self.inner)? else { return Ok(false) };
+            self.read = 0;
+            // Empty blocks, written for empty inputs, are passed over like indexes and checksums.
+            self.block = decode(&block).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))?;
+            if !self.block.is_empty() {
+                return Ok(true)
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for WzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read == self.block.len() && !self.next_block()? {
+            return Ok(0)
+        }
+        let len = buf.len().min(self.block.len() - self.read);
+        buf[..len].copy_from_slice(&self.block[self.read..self.read + len]);
+        self.read += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read};
+    use crate::codec::block::{BlockKind, compress_block, Method};
+    use crate::diagnostics::report::Report;
+    use crate::file::stream::{CANONICAL, frame, index};
+    use crate::read::WzDecoder;
+
+    fn read_all(stream: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut retval = vec![];
+        WzDecoder::new(stream).read_to_end(&mut retval).map(|_| retval)
+    }
+
+    #[test]
+    fn test_kinds() {
+        // Stored blocks, empty blocks, and an index.
+        let mut stream = frame(vec![1, b'h', b'i']);
+        stream.append(&mut frame(vec![]));
+        stream.append(&mut frame(vec![1, b'!']));
+        stream.append(&mut frame(index(&[2, 1])));
+        assert_eq!(b"hi!".to_vec(), read_all(&stream).unwrap());
+        assert_eq!(Vec::<u8>::new(), read_all(&[]).unwrap());
+
+        // Plain Huffman blocks too, but not those coded with a dictionary, which the decoder isn't given.
+        let bytes = b"the rain in spain".repeat(20);
+        let huffman = frame(compress_block(&bytes, &Method::new(), &mut Report::new(false)));
+        assert_eq!(bytes, read_all(&huffman).unwrap());
+        let dictionary = frame(vec![BlockKind::Dictionary as u8, 0, 0, 0, 0]);
+        assert_eq!(ErrorKind::InvalidData, read_all(&dictionary).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut stream = frame(vec![1, b'h', b'i']);
+        stream.pop();
+        assert_eq!(ErrorKind::UnexpectedEof, read_all(&stream).unwrap_err().kind());
+        // A canonical block too short for its header.
+        assert_eq!(ErrorKind::InvalidData, read_all(&frame(vec![CANONICAL, 0, 0])).unwrap_err().kind());
+        // A bit count far beyond the bits there are.
+        let mut block = vec![CANONICAL; 1 + 129];
+        block[1] = 0;
+        block.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(ErrorKind::InvalidData, read_all(&frame(block)).unwrap_err().kind());
+    }
+}
@@ -0,0 +1,65 @@
+// Gives up on a job once its time is up, for runs which must fit in a batch window.
+// Archives are finished with whatever was done in time. Everything else is stopped where it is.
+// Author: Will Morris
+
+use std::process::exit;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The exit code of timeout(1), which scripts already look for.
+pub(crate) const TIMED_OUT: i32 = 124;
+
+// Parse a length of time, such as 500ms, 30s, 5m, or 2h. Plain numbers are seconds.
+pub(crate) fn parse_duration(text: &str) -> Option<Duration> {
+    let (digits, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(split) => { text.split_at(split) }
+        None => { (text, "s") }
+    };
+    let count = digits.parse::<u64>().ok()?;
+    match unit {
+        "ms" => { Some(Duration::from_millis(count)) }
+        "s" => { Some(Duration::from_secs(count)) }
+        "m" => { Some(Duration::from_secs(count.checked_mul(60)?)) }
+        "h" => { Some(Duration::from_secs(count.checked_mul(60 * 60)?)) }
+        _ => { None }
+    }
+}
+
+// Has deadline passed? Jobs without one never run out of time.
+pub(crate) fn expired(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+// Once deadline passes, clean up and exit with TIMED_OUT, whatever the rest of the program is doing.
+pub(crate) fn watchdog<F: FnOnce() + Send + 'static>(deadline: Instant, clean_up: F) {
+    thread::spawn(move || {
+        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        clean_up();
+        exit(TIMED_OUT)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::system::deadline::{expired, parse_duration};
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Some(Duration::from_secs(30)), parse_duration("30s"));
+        assert_eq!(Some(Duration::from_secs(30)), parse_duration("30"));
+        assert_eq!(Some(Duration::from_millis(250)), parse_duration("250ms"));
+        assert_eq!(Some(Duration::from_secs(300)), parse_duration("5m"));
+        assert_eq!(Some(Duration::from_secs(7200)), parse_duration("2h"));
+        assert_eq!(None, parse_duration("s"));
+        assert_eq!(None, parse_duration("10 minutes"));
+        assert_eq!(None, parse_duration("-1s"));
+    }
+
+    #[test]
+    fn test_expired() {
+        assert!(!expired(None));
+        assert!(expired(Some(Instant::now())));
+        assert!(!expired(Some(Instant::now() + Duration::from_secs(60))));
+    }
+}
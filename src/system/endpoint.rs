@@ -0,0 +1,277 @@
+// Where input comes from and output goes: a regular file, or a named pipe, device, or unix socket.
+// With the remote feature, input can also be the body of an http:// or https:// URL.
+// Only regular files can be seeked, truncated, or synced. Everything else is read or written once, in order.
+// Author: Will Morris
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+pub(crate) enum Endpoint {
+    File(File),
+    // Named pipes and devices. Opened like files, but they can't seek.
+    Stream(File),
+    // Connected to whoever is listening on the socket.
+    #[cfg(unix)]
+    Socket(UnixStream),
+    // The body of a response, read as it arrives.
+    #[cfg(feature = "remote")]
+    Remote(ureq::BodyReader<'static>),
+}
+
+impl Endpoint {
+    // Open path to read from.
+    pub(crate) fn open(path: &str) -> Result<Endpoint, String> {
+        let error = |e| format!("Could not open input {}: {}", path, e);
+        if is_url(path) {
+            return fetch(path)
+        }
+        if is_socket(path) {
+            return connect(path).map_err(error)
+        }
+        let file = File::open(path).map_err(error)?;
+        match is_regular(path) {
+            true => { Ok(Endpoint::File(file)) }
+            false => { Ok(Endpoint::Stream(file)) }
+        }
+    }
+
+    // Open path to write to. Regular files are created, or emptied if they're already there.
+    // Pipes and devices are written as they are, since there's nothing in them to empty.
+    pub(crate) fn create(path: &str) -> Result<Endpoint, String> {
+        let error = |e| format!("Could not open output {}: {}", path, e);
+        if is_url(path) {
+            return Err(format!("Could not open output {}: URLs can only be read from", path))
+        }
+        if is_socket(path) {
+            return connect(path).map_err(error)
+        }
+        match is_regular(path) {
+            true => { File::create(path).map(Endpoint::File).map_err(error) }
+            false => { OpenOptions::new().write(true).open(path).map(Endpoint::Stream).map_err(error) }
+        }
+    }
+
+    // Make sure everything written so far is really on disk. Only files are ever on disk.
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        match self {
+            Endpoint::File(file) => { file.sync_data() }
+            _ => { Ok(()) }
+        }
+    }
+}
+
+impl Read for Endpoint {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Endpoint::File(file) | Endpoint::Stream(file) => { file.read(buf) }
+            #[cfg(unix)]
+            Endpoint::Socket(socket) => { socket.read(buf) }
+            #[cfg(feature = "remote")]
+            Endpoint::Remote(body) => { body.read(buf) }
+        }
+    }
+}
+
+impl Write for Endpoint {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Endpoint::File(file) | Endpoint::Stream(file) => { file.write(buf) }
+            #[cfg(unix)]
+            Endpoint::Socket(socket) => { socket.write(buf) }
+            #[cfg(feature = "remote")]
+            Endpoint::Remote(_) => { Err(io::Error::new(io::ErrorKind::Unsupported, "URLs can only be read from")) }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Endpoint::File(file) | Endpoint::Stream(file) => { file.flush() }
+            #[cfg(unix)]
+            Endpoint::Socket(socket) => { socket.flush() }
+            #[cfg(feature = "remote")]
+            Endpoint::Remote(_) => { Ok(()) }
+        }
+    }
+}
+
+// Is path a regular file? Paths that aren't there yet will be, once they're created.
+pub(crate) fn is_regular(path: &str) -> bool {
+    !is_url(path) && fs::metadata(path).map_or(true, |metadata| metadata.is_file())
+}
+
+// Make sure path's entry in its directory is really on disk, so a crash can't lose a file that was synced.
+// Only Unix can sync a directory. Windows keeps its directories in a journal of their own.
+#[cfg(unix)]
+pub(crate) fn sync_directory(path: &str) -> io::Result<()> {
+    let parent = match std::path::Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => { parent }
+        _ => { std::path::Path::new(".") }
+    };
+    File::open(parent)?.sync_all()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn sync_directory(_path: &str) -> io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+// Ask for url, and read its body as it arrives. Anything but a successful response is an error.
+#[cfg(feature = "remote")]
+fn fetch(url: &str) -> Result<Endpoint, String> {
+    let response = ureq::get(url).call().map_err(|e| format!("Could not fetch {}: {}", url, e))?;
+    Ok(Endpoint::Remote(response.into_body().into_reader()))
+}
+
+#[cfg(not(feature = "remote"))]
+fn fetch(url: &str) -> Result<Endpoint, String> {
+    Err(format!("Could not fetch {}: wz was built without the remote feature", url))
+}
+
+#[cfg(unix)]
+fn is_socket(path: &str) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+}
+
+#[cfg(not(unix))]
+fn is_socket(_path: &str) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn connect(path: &str) -> io::Result<Endpoint> {
+    UnixStream::connect(path).map(Endpoint::Socket)
+}
+
+#[cfg(not(unix))]
+fn connect(_path: &str) -> io::Result<Endpoint> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "sockets are not supported on this platform"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::env::temp_dir;
+    use std::ffi::CString;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::thread;
+    use crate::system::endpoint::{Endpoint, is_regular, is_url, sync_directory};
+
+    fn scratch(name: &str) -> PathBuf {
+        let path = temp_dir().join(format!("wz_endpoint_{}_{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_file() {
+        let path = scratch("file");
+        let name = path.to_str().unwrap();
+        assert!(is_regular(name));
+        let mut created = Endpoint::create(name).unwrap();
+        created.write_all(b"hello").unwrap();
+        created.sync().unwrap();
+        sync_directory(name).unwrap();
+        // Bare names are in the current directory.
+        sync_directory("no_directory_given.wz").unwrap();
+        let mut endpoint = Endpoint::open(name).unwrap();
+        assert!(matches!(endpoint, Endpoint::File(_)));
+        let mut read = vec![];
+        endpoint.read_to_end(&mut read).unwrap();
+        assert_eq!(b"hello".to_vec(), read);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_url() {
+        assert!(is_url("https://example.com/file.wz"));
+        assert!(is_url("http://localhost:8080/file.wz"));
+        assert!(!is_url("file.wz"));
+        assert!(!is_url("./https:/file.wz"));
+        // URLs are never regular files, so nothing tries to seek them.
+        assert!(!is_regular("https://example.com/file.wz"));
+        assert!(Endpoint::create("https://example.com/file.wz").is_err());
+    }
+
+    // A server which answers one request with body.
+    #[cfg(feature = "remote")]
+    fn serve_once(status: &'static str, body: &'static [u8]) -> String {
+        use std::io::BufRead;
+        use std::net::TcpListener;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file.wz", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let mut stream = reader.into_inner();
+            let head = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(body).unwrap();
+        });
+        url
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_remote() {
+        let mut read = vec![];
+        Endpoint::open(&serve_once("200 OK", b"over http")).unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(b"over http".to_vec(), read);
+        assert!(Endpoint::open(&serve_once("404 Not Found", b"")).is_err());
+    }
+
+    #[test]
+    fn test_fifo() {
+        let path = scratch("fifo");
+        let name = CString::new(path.to_str().unwrap()).unwrap();
+        // SAFETY: name is a valid C string, which mkfifo only reads.
+        assert_eq!(0, unsafe { libc::mkfifo(name.as_ptr(), 0o600) });
+        assert!(!is_regular(path.to_str().unwrap()));
+
+        // Opening either end of a pipe waits for the other, so the writer needs a thread of its own.
+        let writer_path = path.clone();
+        let writer = thread::spawn(move || {
+            let mut endpoint = Endpoint::create(writer_path.to_str().unwrap()).unwrap();
+            assert!(matches!(endpoint, Endpoint::Stream(_)));
+            endpoint.write_all(b"through a pipe").unwrap();
+            endpoint.sync().unwrap();
+        });
+        let mut read = vec![];
+        Endpoint::open(path.to_str().unwrap()).unwrap().read_to_end(&mut read).unwrap();
+        writer.join().unwrap();
+        assert_eq!(b"through a pipe".to_vec(), read);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_socket() {
+        let path = scratch("socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        assert!(!is_regular(path.to_str().unwrap()));
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut read = vec![];
+            stream.read_to_end(&mut read).unwrap();
+            read
+        });
+        let mut endpoint = Endpoint::create(path.to_str().unwrap()).unwrap();
+        assert!(matches!(endpoint, Endpoint::Socket(_)));
+        endpoint.write_all(b"through a socket").unwrap();
+        drop(endpoint);
+        assert_eq!(b"through a socket".to_vec(), server.join().unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
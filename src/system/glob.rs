@@ -0,0 +1,147 @@
+// Expands wildcards in file arguments, since cmd.exe and PowerShell leave them for the program.
+// * matches any run of characters and ? any one, within a single part of a path. Directories may have wildcards too.
+// Unix shells expand them first, so there this only sees patterns that were quoted, or matched nothing.
+// Author: Will Morris
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Expand every argument with wildcards into the files it matches, in order.
+// Arguments naming a file that's really there are left alone, wildcards and all.
+pub(crate) fn expand(args: &[String]) -> Result<Vec<String>, String> {
+    let mut retval = vec![];
+    for arg in args {
+        if !has_wildcards(arg) || Path::new(arg).exists() {
+            retval.push(arg.clone());
+            continue
+        }
+        let found = expand_pattern(arg);
+        if found.is_empty() {
+            return Err(format!("No files match {}", arg))
+        }
+        retval.extend(found);
+    }
+    Ok(retval)
+}
+
+fn has_wildcards(arg: &str) -> bool {
+    arg.contains(['*', '?'])
+}
+
+// Everything that matches pattern, one part of the path at a time.
+fn expand_pattern(pattern: &str) -> Vec<String> {
+    let mut found = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let part = component.as_os_str();
+        match part.to_str().filter(|part| has_wildcards(part)) {
+            None => { found.iter_mut().for_each(|path| path.push(part)) }
+            Some(part) => {
+                let mut matched = vec![];
+                for dir in &found {
+                    let listing = match dir.as_os_str().is_empty() {
+                        true => { Path::new(".") }
+                        false => { dir.as_path() }
+                    };
+                    let Ok(entries) = fs::read_dir(listing) else {
+                        continue
+                    };
+                    for entry in entries.flatten() {
+                        let name = entry.file_name();
+                        if name.to_str().is_some_and(|name| matches(part, name)) {
+                            matched.push(dir.join(name));
+                        }
+                    }
+                }
+                matched.sort();
+                found = matched;
+            }
+        }
+    }
+    found.into_iter().filter(|path| path.exists()).map(|path| path.to_string_lossy().into_owned()).collect()
+}
+
+// Does name match pattern? Like shells, wildcards don't match a leading '.', so hidden files stay hidden.
+fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false
+    }
+    let pattern: Vec<char> = normalize(pattern).chars().collect();
+    let name: Vec<char> = normalize(name).chars().collect();
+
+    // After a *, a mismatch backs up to let the * take one more character.
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => {
+                let Some((star_p, star_n)) = star else {
+                    return false
+                };
+                star = Some((star_p, star_n + 1));
+                p = star_p + 1;
+                n = star_n + 1;
+            }
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Windows file names ignore case, so its patterns do too.
+#[cfg(windows)]
+fn normalize(name: &str) -> String {
+    name.to_lowercase()
+}
+
+#[cfg(not(windows))]
+fn normalize(name: &str) -> String {
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::system::glob::{expand, matches};
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*.log", "app.log"));
+        assert!(!matches("*.log", ".log.log"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(!matches("*a*b*", "xxbxxaxx"));
+        assert!(matches("*", "anything"));
+        assert!(!matches("*.log", "app.log.gz"));
+        assert!(matches(".*", ".hidden"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let root = temp_dir().join(format!("wz_glob_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["day1", "day2", "other"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join("app.log"), b"").unwrap();
+            fs::write(root.join(dir).join("app.txt"), b"").unwrap();
+        }
+        let root_name = root.to_str().unwrap();
+
+        let found = expand(&[format!("{}/day?/*.log", root_name)]).unwrap();
+        assert_eq!(vec![root.join("day1/app.log"), root.join("day2/app.log")],
+                   found.iter().map(PathBuf::from).collect::<Vec<_>>());
+        // Arguments without wildcards pass through untouched, whether they're there or not.
+        assert_eq!(vec![String::from("missing.txt")], expand(&[String::from("missing.txt")]).unwrap());
+        assert!(expand(&[format!("{}/*.nothing", root_name)]).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,42 @@
+// Makes symbolic links, and finds hard ones, which each platform does its own way.
+// Author: Will Morris
+
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+// Make a symbolic link at path, holding target.
+#[cfg(unix)]
+pub(crate) fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+// Windows links to files and to directories differently, so the target has to be looked at first.
+// Targets which aren't there yet are taken to be files.
+#[cfg(windows)]
+pub(crate) fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    let resolved = path.parent().map_or(target.to_path_buf(), |parent| parent.join(target));
+    match resolved.is_dir() {
+        true => { std::os::windows::fs::symlink_dir(target, path) }
+        false => { std::os::windows::fs::symlink_file(target, path) }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn symlink(_target: &Path, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "symbolic links are not supported on this platform"))
+}
+
+// Identifies a file with more than one name, so that the same file can be recognised under each of them.
+// Files with only one name have nothing to recognise, and give None.
+#[cfg(unix)]
+pub(crate) fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.is_file() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+// Only unstable APIs give Windows file indexes, so there, hard links are archived as copies.
+#[cfg(not(unix))]
+pub(crate) fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
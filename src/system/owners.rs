@@ -0,0 +1,149 @@
+// Finds and changes who owns files, by number, and by the names those numbers have on this system.
+// Only Unix has owners of this kind.
+// Author: Will Morris
+
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+// The user and group ids owning a file.
+#[cfg(unix)]
+pub(crate) fn file_owner(metadata: &Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn file_owner(_metadata: &Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+// Only root can give files to someone else.
+#[cfg(unix)]
+pub(crate) fn is_root() -> bool {
+    // SAFETY: geteuid takes nothing, and can't fail.
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_root() -> bool {
+    false
+}
+
+#[cfg(unix)]
+pub(crate) fn chown(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    std::os::unix::fs::chown(path, Some(uid), Some(gid))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn chown(_path: &Path, _uid: u32, _gid: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "owners are not supported on this platform"))
+}
+
+#[cfg(unix)]
+pub(crate) fn user_name(uid: u32) -> Option<String> {
+    // SAFETY: lookup gives a valid passwd, buffer, and place for the result, with the buffer's true length.
+    lookup(|passwd: &mut libc::passwd, buf, result| unsafe {
+        libc::getpwuid_r(uid, passwd, buf.as_mut_ptr(), buf.len(), result)
+    // SAFETY: the name is read while lookup's buffer is alive.
+    }, |passwd| unsafe { c_string(passwd.pw_name) })
+}
+
+#[cfg(unix)]
+pub(crate) fn group_name(gid: u32) -> Option<String> {
+    // SAFETY: as above.
+    lookup(|group: &mut libc::group, buf, result| unsafe {
+        libc::getgrgid_r(gid, group, buf.as_mut_ptr(), buf.len(), result)
+    // SAFETY: as above.
+    }, |group| unsafe { c_string(group.gr_name) })
+}
+
+#[cfg(unix)]
+pub(crate) fn user_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: as above, and name is a valid C string.
+    lookup(|passwd: &mut libc::passwd, buf, result| unsafe {
+        libc::getpwnam_r(name.as_ptr(), passwd, buf.as_mut_ptr(), buf.len(), result)
+    }, |passwd| Some(passwd.pw_uid))
+}
+
+#[cfg(unix)]
+pub(crate) fn group_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: as above.
+    lookup(|group: &mut libc::group, buf, result| unsafe {
+        libc::getgrnam_r(name.as_ptr(), group, buf.as_mut_ptr(), buf.len(), result)
+    }, |group| Some(group.gr_gid))
+}
+
+// Look up an entry of the user or group database, and read what's wanted from it.
+// Entries keep their strings in buf, which is made bigger until they fit, and lives until they've been read.
+#[cfg(unix)]
+fn lookup<T, R, F, G>(mut call: F, read: G) -> Option<R>
+    where F: FnMut(&mut T, &mut [libc::c_char], &mut *mut T) -> libc::c_int,
+          G: FnOnce(&T) -> Option<R> {
+    let mut buf = vec![0; 1024];
+    loop {
+        // SAFETY: passwd and group are plain C structs, for which all zeros is a valid value.
+        let mut entry: T = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        match call(&mut entry, &mut buf, &mut result) {
+            libc::ERANGE if buf.len() < 1 << 20 => { buf.resize(buf.len() * 2, 0) }
+            0 if !result.is_null() => { return read(&entry) }
+            _ => { return None }
+        }
+    }
+}
+
+// A name from a successful lookup. It points into the lookup's buffer, which must still be alive.
+#[cfg(unix)]
+unsafe fn c_string(name: *const libc::c_char) -> Option<String> {
+    // SAFETY: the caller promises name is a C string which is still there.
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    name.to_str().ok().map(String::from)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn user_name(_uid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+pub(crate) fn group_name(_gid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+pub(crate) fn user_id(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(not(unix))]
+pub(crate) fn group_id(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+    use crate::system::owners::{file_owner, group_id, group_name, user_id, user_name};
+
+    #[test]
+    fn test_names() {
+        // Every Unix has root, as user and group 0, though not every one calls the group root.
+        assert_eq!(Some(String::from("root")), user_name(0));
+        assert_eq!(Some(0), user_id("root"));
+        let group = group_name(0).unwrap();
+        assert_eq!(Some(0), group_id(&group));
+        assert_eq!(None, user_id("no such user, surely"));
+        assert_eq!(None, user_id("nul\0in the middle"));
+
+        let path = temp_dir().join(format!("wz_owners_{}", std::process::id()));
+        fs::write(&path, b"mine").unwrap();
+        let (uid, _) = file_owner(&fs::metadata(&path).unwrap()).unwrap();
+        // SAFETY: geteuid takes nothing, and can't fail.
+        assert_eq!(unsafe { libc::geteuid() }, uid);
+        fs::remove_file(&path).unwrap();
+    }
+}
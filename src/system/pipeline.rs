@@ -0,0 +1,182 @@
+// Runs jobs on several threads at once, handing each result to a sink in the order of the jobs, as soon as it's ready.
+// Only so many results may be waiting or being worked on at once, so a sink which can't keep up holds the workers back.
+// Memory stays bounded however slow the destination, where run_all would hold every result until the end.
+// Author: Will Morris
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::panic::{AssertUnwindSafe, catch_unwind, resume_unwind};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use crate::diagnostics::report::Report;
+
+// How many jobs each thread may be ahead of the sink: one being worked on, and one done and waiting.
+pub(crate) const DEPTH_PER_THREAD: usize = 2;
+
+// What the workers and the sink share.
+struct State<R> {
+    // The next job for a worker to take.
+    next: usize,
+    // How many results the sink has taken.
+    sunk: usize,
+    // Results done but not yet sunk, by job.
+    ready: BTreeMap<usize, R>,
+    // Set once the sink fails or anything panics, so workers stop taking jobs.
+    stopped: bool,
+    // The first panic, to be resumed once every thread has stopped.
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+// Do work on every job, on as many as threads threads, and give each result to sink in the order of the jobs.
+// No more than depth jobs are ever taken before the sink has had the result of the first of them.
+// Sinking stops at the first error, which is returned once every worker has finished the job it had.
+pub(crate) fn run_bounded<T, R, F, S>(jobs: &[T], threads: usize, depth: usize, report: &mut Report, work: F,
+                                      mut sink: S) -> Result<(), String>
+    where T: Sync, R: Send, F: Fn(&T, &mut Report) -> R + Sync, S: FnMut(R, &mut Report) -> Result<(), String> {
+    let threads = threads.min(jobs.len());
+    if threads <= 1 {
+        return jobs.iter().try_for_each(|job| {
+            let result = work(job, report);
+            sink(result, report)
+        })
+    }
+
+    let depth = depth.max(threads);
+    let state = Mutex::new(State { next: 0, sunk: 0, ready: BTreeMap::new(), stopped: false, panic: None });
+    let changed = Condvar::new();
+    let lock = || state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let (result, reports) = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|_| {
+            let mut report = report.split();
+            let (state, changed, work) = (&state, &changed, &work);
+            scope.spawn(move || {
+                let lock = || state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                loop {
+                    let mut guard = lock();
+                    while !guard.stopped && guard.next < jobs.len() && guard.next >= guard.sunk + depth {
+                        guard = changed.wait(guard).unwrap_or_else(|poisoned| poisoned.into_inner());
+                    }
+                    if guard.stopped || guard.next >= jobs.len() {
+                        return report
+                    }
+                    let index = guard.next;
+                    guard.next += 1;
+                    drop(guard);
+
+                    let result = catch_unwind(AssertUnwindSafe(|| work(&jobs[index], &mut report)));
+                    let mut guard = lock();
+                    match result {
+                        Ok(result) => { guard.ready.insert(index, result); }
+                        Err(panic) => {
+                            guard.stopped = true;
+                            guard.panic.get_or_insert(panic);
+                        }
+                    }
+                    changed.notify_all();
+                }
+            })
+        }).collect();
+
+        // The sink runs here, outside the lock, so workers carry on while it writes.
+        let mut result = Ok(());
+        for index in 0..jobs.len() {
+            let mut guard = lock();
+            while !guard.stopped && !guard.ready.contains_key(&index) {
+                guard = changed.wait(guard).unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+            let Some(done) = guard.ready.remove(&index) else { break };
+            drop(guard);
+
+            // Workers waiting on the sink would wait forever if it panicked, so it's caught like theirs.
+            let sunk = catch_unwind(AssertUnwindSafe(|| sink(done, report)));
+            let mut guard = lock();
+            guard.sunk += 1;
+            match sunk {
+                Ok(sunk) => { result = sunk }
+                Err(panic) => { guard.panic.get_or_insert(panic); }
+            }
+            guard.stopped |= result.is_err() || guard.panic.is_some();
+            changed.notify_all();
+            if guard.stopped {
+                break
+            }
+        }
+        let reports: Vec<Report> = workers.into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|panic| resume_unwind(panic)))
+            .collect();
+        (result, reports)
+    });
+
+    reports.into_iter().for_each(|worker_report| report.merge(worker_report));
+    // A job which panicked takes the whole run down with it, as it would have without threads.
+    if let Some(panic) = state.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner()).panic {
+        resume_unwind(panic)
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use crate::diagnostics::report::Report;
+    use crate::system::pipeline::run_bounded;
+
+    #[test]
+    fn test_in_order() {
+        let jobs: Vec<u64> = (0..100).collect();
+        let mut report = Report::new(true);
+        for threads in [1, 8] {
+            let mut sunk = vec![];
+            // Early jobs take longest, so they finish last.
+            run_bounded(&jobs, threads, 4, &mut report, |job, report| {
+                report.time("sleeping", || thread::sleep(Duration::from_micros(100 - job)));
+                job * 2
+            }, |result, _| {
+                sunk.push(result);
+                Ok(())
+            }).unwrap();
+            assert_eq!((0..100).map(|job| job * 2).collect::<Vec<_>>(), sunk);
+        }
+        assert_eq!(Ok(()), run_bounded(&[] as &[u64], 8, 4, &mut report, |job, _| *job, |_, _| Err(String::from("never"))));
+    }
+
+    #[test]
+    fn test_bounded() {
+        // However slow the sink, no more than depth jobs are ever ahead of it.
+        let jobs: Vec<usize> = (0..50).collect();
+        let taken = AtomicUsize::new(0);
+        let mut sunk = 0;
+        let mut report = Report::new(false);
+        run_bounded(&jobs, 4, 6, &mut report, |job, _| {
+            taken.fetch_max(*job + 1, Ordering::SeqCst);
+            *job
+        }, |job, _| {
+            assert!(taken.load(Ordering::SeqCst) <= sunk + 6);
+            thread::sleep(Duration::from_micros(200));
+            assert_eq!(sunk, job);
+            sunk += 1;
+            Ok(())
+        }).unwrap();
+        assert_eq!(50, sunk);
+    }
+
+    #[test]
+    fn test_sink_fails() {
+        let jobs: Vec<usize> = (0..1000).collect();
+        let worked = AtomicUsize::new(0);
+        let mut report = Report::new(false);
+        let result = run_bounded(&jobs, 4, 8, &mut report, |job, _| {
+            worked.fetch_add(1, Ordering::SeqCst);
+            *job
+        }, |job, _| match job {
+            10 => { Err(String::from("Destination went away!")) }
+            _ => { Ok(()) }
+        });
+        assert_eq!(Err(String::from("Destination went away!")), result);
+        // Workers stop soon after, rather than doing every job.
+        assert!(worked.load(Ordering::SeqCst) <= 11 + 8);
+    }
+}
@@ -0,0 +1,53 @@
+// Lowers the scheduling priority of the program, for background jobs.
+// Both CPU and I/O priority are lowered where the platform allows it.
+// Author: Will Morris
+
+// How nice should we be? 19 is the nicest value unix allows.
+const NICENESS: i32 = 19;
+
+// Lower CPU and I/O priority for this process.
+// This is a best effort -- returns a description of whatever could not be lowered.
+pub(crate) fn lower_priority() -> Result<(), String> {
+    lower_cpu_priority()?;
+    lower_io_priority()
+}
+
+#[cfg(unix)]
+fn lower_cpu_priority() -> Result<(), String> {
+    // SAFETY: setpriority has no memory safety requirements. 0 refers to this process.
+    let status = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, NICENESS) };
+    match status {
+        0 => { Ok(()) }
+        _ => { Err(format!("Could not lower CPU priority: {}", std::io::Error::last_os_error())) }
+    }
+}
+
+#[cfg(not(unix))]
+fn lower_cpu_priority() -> Result<(), String> {
+    Err(String::from("Lowering CPU priority is not supported on this platform"))
+}
+
+// Linux lets us put ourselves in the idle I/O class,
+// Where we only get disk time when nobody else wants it.
+#[cfg(target_os = "linux")]
+fn lower_io_priority() -> Result<(), String> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+
+    // SAFETY: ioprio_set only takes integers. 0 refers to this process.
+    let status = unsafe {
+        libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0,
+                      IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT)
+    };
+    match status {
+        0 => { Ok(()) }
+        _ => { Err(format!("Could not lower I/O priority: {}", std::io::Error::last_os_error())) }
+    }
+}
+
+// Elsewhere, a low CPU priority is the best hint we have.
+#[cfg(not(target_os = "linux"))]
+fn lower_io_priority() -> Result<(), String> {
+    Ok(())
+}
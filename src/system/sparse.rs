@@ -0,0 +1,79 @@
+// Finds the holes in sparse files, so that only their data need be read.
+// Holes are found with SEEK_DATA and SEEK_HOLE, where the platform has them. Elsewhere, files are read whole.
+// Author: Will Morris
+
+use std::fs::File;
+use std::io;
+
+// The offset and length of each run of data in file, which is len bytes long, if it has any holes.
+// Files without holes, and file systems which can't say, give None.
+// Looking moves file's position, so it must be set again before reading.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd"))]
+pub(crate) fn data_extents(file: &File, len: u64) -> io::Result<Option<Vec<(u64, u64)>>> {
+    use std::os::fd::AsRawFd;
+    let fd = file.as_raw_fd();
+    let mut extents = vec![];
+    let mut offset = 0;
+    while offset < len {
+        // SAFETY: lseek only takes integers, and fd stays open for as long as file is borrowed.
+        let start = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+        if start < 0 {
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                // There's no more data, only a hole to the end.
+                Some(libc::ENXIO) => { break }
+                Some(libc::EINVAL) => { return Ok(None) }
+                _ => { return Err(error) }
+            }
+        }
+        // SAFETY: as above.
+        let end = unsafe { libc::lseek(fd, start, libc::SEEK_HOLE) };
+        if end < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        // The file may have shrunk since len was found.
+        let end = (end as u64).min(len);
+        if end > start as u64 {
+            extents.push((start as u64, end - start as u64));
+        }
+        offset = end;
+    }
+    match extents.as_slice() {
+        [] if len == 0 => { Ok(None) }
+        [(0, whole)] if *whole == len => { Ok(None) }
+        _ => { Ok(Some(extents)) }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd")))]
+pub(crate) fn data_extents(_file: &File, _len: u64) -> io::Result<Option<Vec<(u64, u64)>>> {
+    Ok(None)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{self, File};
+    use std::io::{Seek, SeekFrom, Write};
+    use crate::system::sparse::data_extents;
+
+    #[test]
+    fn test_data_extents() {
+        let path = temp_dir().join(format!("wz_sparse_{}", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"dense").unwrap();
+        assert_eq!(None, data_extents(&file, 5).unwrap());
+
+        // Not every file system keeps holes. Those that don't have nothing to find.
+        file.set_len(64 << 20).unwrap();
+        file.seek(SeekFrom::Start(32 << 20)).unwrap();
+        file.write_all(b"middle").unwrap();
+        file.sync_all().unwrap();
+        if let Some(extents) = data_extents(&file, 64 << 20).unwrap() {
+            assert_eq!(0, extents[0].0);
+            assert!(extents.iter().any(|(offset, len)| *offset <= 32 << 20 && offset + len >= (32 << 20) + 6));
+            assert!(extents.iter().map(|(_, len)| len).sum::<u64>() < 64 << 20);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+// Limits how fast bytes are read or written, so background jobs leave room for everyone else on a link.
+// Each direction has a bucket of tokens, one per byte, refilled at the rate allowed. Running out means waiting.
+// Author: Will Morris
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Reads or writes of inner, at no more than rate bytes a second on average. Without a rate, they're left alone.
+pub(crate) struct Throttled<T> {
+    inner: T,
+    bucket: Option<Bucket>,
+}
+
+struct Bucket {
+    // Bytes per second.
+    rate: u64,
+    // The most bytes moved at once, and the most tokens kept: a tenth of a second's worth.
+    // Smaller pieces keep the rate even, rather than bursting and then stalling.
+    burst: usize,
+    // Goes below zero when a read or write took more than there was. That's paid back by waiting.
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        let burst = (rate / 10).clamp(1, 1 << 20) as usize;
+        Bucket { rate, burst, tokens: burst as f64, last: Instant::now() }
+    }
+
+    // Take a token for each byte moved, waiting for any that weren't there.
+    fn spend(&mut self, moved: usize) {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * self.rate as f64;
+        self.tokens = (self.tokens + refill).min(self.burst as f64) - moved as f64;
+        self.last = now;
+        if self.tokens < 0.0 {
+            thread::sleep(Duration::from_secs_f64(-self.tokens / self.rate as f64));
+        }
+    }
+}
+
+impl<T> Throttled<T> {
+    pub(crate) fn new(inner: T, rate: Option<u64>) -> Self {
+        Throttled { inner, bucket: rate.map(Bucket::new) }
+    }
+
+    pub(crate) fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    // How much of len may be moved at once.
+    fn piece(&self, len: usize) -> usize {
+        self.bucket.as_ref().map_or(len, |bucket| len.min(bucket.burst))
+    }
+
+    fn spend(&mut self, moved: usize) {
+        if let Some(bucket) = &mut self.bucket {
+            bucket.spend(moved)
+        }
+    }
+}
+
+impl<T: Read> Read for Throttled<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.piece(buf.len());
+        let read = self.inner.read(&mut buf[..len])?;
+        self.spend(read);
+        Ok(read)
+    }
+}
+
+impl<T: Write> Write for Throttled<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.piece(buf.len());
+        let written = self.inner.write(&buf[..len])?;
+        self.spend(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Seeking moves no bytes, so it's free.
+impl<T: Seek> Seek for Throttled<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::time::{Duration, Instant};
+    use crate::system::throttle::Throttled;
+
+    #[test]
+    fn test_unlimited() {
+        let mut read = vec![];
+        Throttled::new(&[7u8; 1 << 20][..], None).read_to_end(&mut read).unwrap();
+        assert_eq!(vec![7; 1 << 20], read);
+    }
+
+    #[test]
+    fn test_limited() {
+        // A tenth of a second's worth can go at once. Three more tenths must wait.
+        let start = Instant::now();
+        let mut written = vec![];
+        let mut throttled = Throttled::new(&mut written, Some(10_000));
+        throttled.write_all(&[1; 4000]).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        assert_eq!(vec![1; 4000], written);
+
+        let start = Instant::now();
+        let mut read = vec![];
+        Throttled::new(&[2u8; 4000][..], Some(10_000)).read_to_end(&mut read).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        assert_eq!(vec![2; 4000], read);
+    }
+}
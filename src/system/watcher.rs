@@ -0,0 +1,193 @@
+// Watches a directory, compressing each file that appears or changes into a destination directory.
+// Files being written send many events, so a file is only compressed once it has been quiet for a while.
+// Author: Will Morris
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use notify::{EventKind, RecursiveMode, Watcher};
+use crate::codec::archiver::{collect_files, Symlinks};
+use crate::file::checkpoint::fingerprint;
+use crate::file::watchstate::WatchState;
+
+// Holds back each changed file until nothing has happened to it for delay.
+pub(crate) struct Debouncer {
+    delay: Duration,
+    // When each file last changed.
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl Debouncer {
+    pub(crate) fn new(delay: Duration) -> Self {
+        Debouncer { delay, pending: HashMap::new() }
+    }
+
+    // Note that path changed at now. Any earlier change is forgotten, so the wait starts again.
+    pub(crate) fn touch(&mut self, path: PathBuf, now: Instant) {
+        self.pending.insert(path, now);
+    }
+
+    // Every file which has been quiet long enough, in order. They're no longer pending.
+    pub(crate) fn ready(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut ready: Vec<PathBuf> = self.pending.iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= self.delay)
+            .map(|(path, _)| path.clone())
+            .collect();
+        ready.sort();
+        for path in &ready {
+            self.pending.remove(path);
+        }
+        ready
+    }
+}
+
+// Watch dir forever, compressing files into the same places under dest, with .wz added to their names.
+// Files which changed while nobody was watching are compressed first.
+// compress is given each file and where its output goes. A file that fails is reported, and the watch goes on.
+// Only returns if the watch itself fails.
+pub(crate) fn watch<F>(dir: &Path, dest: &Path, delay: Duration, mut compress: F) -> Result<(), String>
+    where F: FnMut(&Path, &Path) -> Result<(), String> {
+    fs::create_dir_all(dest).map_err(|e| format!("Could not create {}: {}", dest.display(), e))?;
+    // Events name absolute paths, which are only comparable to dest if both are canonical.
+    let dir = dir.canonicalize().map_err(|_| format!("File not found: {}", dir.display()))?;
+    let dest = dest.canonicalize().map_err(|_| format!("File not found: {}", dest.display()))?;
+    let mut state = WatchState::load(&dest)?;
+
+    let (sender, events) = channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|e| format!("Could not watch {}: {}", dir.display(), e))?;
+    watcher.watch(&dir, RecursiveMode::Recursive).map_err(|e| format!("Could not watch {}: {}", dir.display(), e))?;
+
+    let mut files = vec![];
+    collect_files(&dir, Symlinks::Follow, &mut files)?;
+    compress_changed(&dir, &dest, &files, &mut state, &mut compress)?;
+
+    let mut debouncer = Debouncer::new(delay);
+    loop {
+        match events.recv_timeout(delay) {
+            Ok(Ok(event)) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                for path in event.paths {
+                    debouncer.touch(path, Instant::now());
+                }
+            }
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Err(e)) => { return Err(format!("Could not watch {}: {}", dir.display(), e)) }
+            Err(RecvTimeoutError::Disconnected) => { return Err(format!("Stopped watching {}!", dir.display())) }
+        }
+        compress_changed(&dir, &dest, &debouncer.ready(Instant::now()), &mut state, &mut compress)?;
+    }
+}
+
+// Compress each of paths which is a file under dir, and has changed since it was last compressed.
+// The state is saved after each, so that a restart picks up where this left off.
+fn compress_changed<F>(dir: &Path, dest: &Path, paths: &[PathBuf], state: &mut WatchState,
+                       compress: &mut F) -> Result<(), String>
+    where F: FnMut(&Path, &Path) -> Result<(), String> {
+    for path in paths {
+        // Our own output would otherwise be compressed again, and again, when dest is inside dir.
+        if path.starts_with(dest) || !path.is_file() {
+            continue
+        }
+        let Some(name) = relative_name(dir, path) else {
+            println!("Skipping {}: watched file names must be UTF-8, below the watched directory", path.display());
+            continue
+        };
+        // Files can vanish between the event and now. If so, there's nothing to do.
+        let Ok(current) = fingerprint(&path.to_string_lossy()) else {
+            continue
+        };
+        if state.is_current(&name, current) {
+            continue
+        }
+
+        let output = dest.join(format!("{}.wz", name));
+        match compress(path, &output) {
+            Ok(()) => {
+                state.record(&name, current);
+                state.save(dest)?;
+            }
+            Err(message) => { println!("Could not compress {}: {}", path.display(), message) }
+        }
+    }
+    Ok(())
+}
+
+// The path of a file below dir, with '/' between directories, as archive members are named.
+fn relative_name(dir: &Path, path: &Path) -> Option<String> {
+    let mut parts = vec![];
+    for component in path.strip_prefix(dir).ok()?.components() {
+        match component {
+            Component::Normal(part) => { parts.push(part.to_str()?) }
+            _ => { return None }
+        }
+    }
+    Some(parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+    use crate::file::watchstate::WatchState;
+    use crate::system::watcher::{compress_changed, Debouncer, relative_name};
+
+    #[test]
+    fn test_debouncer() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(100));
+        debouncer.touch(PathBuf::from("b"), start);
+        debouncer.touch(PathBuf::from("a"), start);
+        assert!(debouncer.ready(start + Duration::from_millis(50)).is_empty());
+
+        // Another change starts the wait over.
+        debouncer.touch(PathBuf::from("b"), start + Duration::from_millis(80));
+        assert_eq!(vec![PathBuf::from("a")], debouncer.ready(start + Duration::from_millis(100)));
+        assert!(debouncer.ready(start + Duration::from_millis(150)).is_empty());
+        assert_eq!(vec![PathBuf::from("b")], debouncer.ready(start + Duration::from_millis(180)));
+    }
+
+    #[test]
+    fn test_relative_name() {
+        assert_eq!(Some(String::from("logs/app.log")), relative_name(Path::new("/data"), Path::new("/data/logs/app.log")));
+        assert_eq!(None, relative_name(Path::new("/data"), Path::new("/elsewhere/app.log")));
+    }
+
+    #[test]
+    fn test_compress_changed() {
+        let root = temp_dir().join(format!("wz_watch_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dir = root.join("in");
+        let dest = dir.join("out");
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dir.join("a.log"), b"first").unwrap();
+        fs::write(dir.join("nested/b.log"), b"second").unwrap();
+        fs::write(dest.join("old.wz"), b"output").unwrap();
+
+        let mut compressed = vec![];
+        let mut compress = |input: &Path, output: &Path| {
+            compressed.push((input.to_path_buf(), output.to_path_buf()));
+            Ok(())
+        };
+        let paths = vec![dir.join("a.log"), dir.join("nested/b.log"), dest.join("old.wz"), dir.join("gone.log")];
+        let mut state = WatchState::new();
+        compress_changed(&dir, &dest, &paths, &mut state, &mut compress).unwrap();
+        // Output, and files which aren't there, are left alone.
+        assert_eq!(vec![(dir.join("a.log"), dest.join("a.log.wz")), (dir.join("nested/b.log"), dest.join("nested/b.log.wz"))],
+                   compressed);
+
+        // Unchanged files aren't compressed twice, even by a watch started later.
+        compressed.clear();
+        let mut state = WatchState::load(&dest).unwrap();
+        fs::write(dir.join("a.log"), b"first, and more").unwrap();
+        compress_changed(&dir, &dest, &paths, &mut state, &mut |input, output| {
+            compressed.push((input.to_path_buf(), output.to_path_buf()));
+            Ok(())
+        }).unwrap();
+        assert_eq!(vec![(dir.join("a.log"), dest.join("a.log.wz"))], compressed);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
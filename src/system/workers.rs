@@ -0,0 +1,68 @@
+// Runs independent jobs several at a time, each thread taking the next job as soon as it's free.
+// This suits many small files better than splitting any one of them, since no job waits on another.
+// Results come back in the order of the jobs, whatever order they finished in, so output never depends on timing.
+// Author: Will Morris
+
+use std::panic::resume_unwind;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use crate::diagnostics::report::Report;
+
+// Do work on every job, on as many as threads threads. Each thread reports to a report of its own, merged into report.
+pub(crate) fn run_all<T, R, F>(jobs: &[T], threads: usize, report: &mut Report, work: F) -> Vec<R>
+    where T: Sync, R: Send, F: Fn(&T, &mut Report) -> R + Sync {
+    let threads = threads.min(jobs.len());
+    if threads <= 1 {
+        return jobs.iter().map(|job| work(job, report)).collect()
+    }
+
+    let next = AtomicUsize::new(0);
+    let finished: Vec<(Vec<(usize, R)>, Report)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|_| {
+            let mut report = report.split();
+            let (next, work) = (&next, &work);
+            scope.spawn(move || {
+                let mut done = vec![];
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(job) = jobs.get(index) else {
+                        return (done, report)
+                    };
+                    done.push((index, work(job, &mut report)));
+                }
+            })
+        }).collect();
+        // A job which panicked takes the whole run down with it, as it would have without threads.
+        workers.into_iter().map(|worker| worker.join().unwrap_or_else(|panic| resume_unwind(panic))).collect()
+    });
+
+    let mut results: Vec<Option<R>> = (0..jobs.len()).map(|_| None).collect();
+    for (done, worker_report) in finished {
+        report.merge(worker_report);
+        done.into_iter().for_each(|(index, result)| results[index] = Some(result));
+    }
+    // Every job was taken by some thread, so none are missing.
+    results.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use crate::diagnostics::report::Report;
+    use crate::system::workers::run_all;
+
+    #[test]
+    fn test_in_order() {
+        let jobs: Vec<u64> = (0..100).collect();
+        let mut report = Report::new(true);
+        // Early jobs take longest, so they finish last.
+        let results = run_all(&jobs, 8, &mut report, |job, report| {
+            report.time("sleeping", || thread::sleep(Duration::from_micros(100 - job)));
+            job * 2
+        });
+        assert_eq!((0..100).map(|job| job * 2).collect::<Vec<_>>(), results);
+        assert_eq!(jobs, run_all(&jobs, 1, &mut report, |job, _| *job));
+        assert!(run_all(&[] as &[u64], 8, &mut report, |job, _| *job).is_empty());
+    }
+}
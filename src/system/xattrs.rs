@@ -0,0 +1,105 @@
+// Reads and writes extended attributes, where the platform has them. ACLs are kept among them, as system.posix_acl_*.
+// Attribute names and values are bytes, with no encoding of their own.
+// Author: Will Morris
+
+use std::io;
+use std::path::Path;
+
+// Every extended attribute of path, in order of name. File systems without them have none.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn read_xattrs(path: &Path) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let path = c_path(path)?;
+    // SAFETY: path is a valid C string, and buf has room for len bytes, or is null when len is 0.
+    let names = match read_buffer(|buf, len| unsafe { libc::listxattr(path.as_ptr(), buf.cast(), len) }) {
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => { return Ok(vec![]) }
+        result => { result? }
+    };
+
+    let mut retval = vec![];
+    for name in names.split(|byte| *byte == 0).filter(|name| !name.is_empty()) {
+        let c_name = std::ffi::CString::new(name)?;
+        // SAFETY: as above, and c_name is a valid C string too.
+        match read_buffer(|buf, len| unsafe { libc::getxattr(path.as_ptr(), c_name.as_ptr(), buf.cast(), len) }) {
+            Ok(value) => { retval.push((name.to_vec(), value)) }
+            // Removed since it was listed.
+            Err(e) if e.raw_os_error() == Some(libc::ENODATA) => {}
+            Err(e) => { return Err(e) }
+        }
+    }
+    retval.sort();
+    Ok(retval)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn read_xattrs(_path: &Path) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "extended attributes are not supported on this platform"))
+}
+
+// Give path the attribute name, with value, replacing any it had.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn write_xattr(path: &Path, name: &[u8], value: &[u8]) -> io::Result<()> {
+    let path = c_path(path)?;
+    let name = std::ffi::CString::new(name)?;
+    // SAFETY: path and name are valid C strings, and value holds value.len() bytes.
+    let status = unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+    match status {
+        0 => { Ok(()) }
+        _ => { Err(io::Error::last_os_error()) }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn write_xattr(_path: &Path, _name: &[u8], _value: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "extended attributes are not supported on this platform"))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn c_path(path: &Path) -> io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(std::ffi::CString::new(path.as_os_str().as_bytes())?)
+}
+
+// Calls which fill a buffer are first asked how big it must be, with an empty one.
+// What they'd fill can grow in between, in which case they're asked again.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn read_buffer<F: Fn(*mut u8, usize) -> isize>(call: F) -> io::Result<Vec<u8>> {
+    loop {
+        let len = call(std::ptr::null_mut(), 0);
+        if len < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        let mut buf = vec![0; len as usize];
+        let filled = call(buf.as_mut_ptr(), buf.len());
+        if filled >= 0 {
+            buf.truncate(filled as usize);
+            return Ok(buf)
+        }
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::ERANGE) {
+            return Err(error)
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+    use crate::system::xattrs::{read_xattrs, write_xattr};
+
+    #[test]
+    fn test_xattrs() {
+        let path = temp_dir().join(format!("wz_xattrs_{}", std::process::id()));
+        fs::write(&path, b"tagged").unwrap();
+        // Not every file system takes user attributes. Those that don't have nothing to test.
+        if write_xattr(&path, b"user.b", b"second").is_ok() {
+            write_xattr(&path, b"user.a", &[0, 1, 2]).unwrap();
+            let xattrs = read_xattrs(&path).unwrap();
+            assert!(xattrs.contains(&(b"user.a".to_vec(), vec![0, 1, 2])));
+            let names: Vec<&[u8]> = xattrs.iter().map(|(name, _)| name.as_slice()).collect();
+            assert!(names.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        assert!(read_xattrs(&path.with_extension("missing")).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
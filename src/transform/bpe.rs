@@ -0,0 +1,143 @@
+// Byte pair encoding.
+// The most common pair of bytes is replaced by a byte which never appears, over and over,
+// until there are no spare bytes left or no pair is worth replacing.
+// Frequent digrams, and then whole words, come to cost a single symbol.
+// Author: Will Morris
+
+use crate::file::error::WzError;
+
+// Each substitution costs three bytes to record, so a pair must save more than that.
+const MIN_PAIR_COUNT: u32 = 4;
+
+// Returns the substituted bytes, and a table of (symbol, first, second) triples in the order they were made.
+pub fn bpe(bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut data = bytes.to_vec();
+    let mut table = vec![];
+    // Symbols which appear in the data, or stand for a pair, can't be used again.
+    let mut used = [false; 256];
+    data.iter().for_each(|byte| used[*byte as usize] = true);
+    let mut counts = vec![0u32; 1 << 16];
+
+    while let Some(symbol) = used.iter().position(|used| !used) {
+        counts.fill(0);
+        data.windows(2).for_each(|pair| counts[pair_index(pair[0], pair[1])] += 1);
+        let (best, count) = counts.iter().enumerate().max_by_key(|(_, count)| **count).unwrap();
+        if *count < MIN_PAIR_COUNT {
+            break
+        }
+
+        let (first, second) = ((best >> 8) as u8, best as u8);
+        let symbol = symbol as u8;
+        used[symbol as usize] = true;
+        table.extend_from_slice(&[symbol, first, second]);
+        data = replace_pair(&data, first, second, symbol);
+    }
+    (data, table)
+}
+
+// Expand every substituted symbol, including symbols within symbols.
+// A symbol can only stand for symbols made before it, or it would never finish expanding.
+// Even so, pairs of pairs grow exponentially, so expanding stops once the output would pass limit.
+pub fn unbpe(bytes: &[u8], table: &[u8], limit: usize) -> Result<Vec<u8>, WzError> {
+    if !table.len().is_multiple_of(3) {
+        return Err(WzError::Truncated("a byte pair table"))
+    }
+    let mut pairs: [Option<(u8, u8)>; 256] = [None; 256];
+    // Walking back from the last pair made, every pair already seen was made after this one.
+    for triple in table.chunks_exact(3).rev() {
+        let (symbol, first, second) = (triple[0], triple[1], triple[2]);
+        if symbol == first || symbol == second || triple.iter().any(|byte| pairs[*byte as usize].is_some()) {
+            return Err(WzError::Malformed(format!("Byte pair {} is made of itself, or of pairs made after it!", symbol)))
+        }
+        pairs[symbol as usize] = Some((first, second));
+    }
+
+    let mut retval = Vec::with_capacity(bytes.len() * 2);
+    let mut pending = vec![];
+    for byte in bytes {
+        pending.push(*byte);
+        while let Some(byte) = pending.pop() {
+            match pairs[byte as usize] {
+                // Second is pushed first, so first comes out first.
+                Some((first, second)) => {
+                    pending.push(second);
+                    pending.push(first);
+                }
+                None => { retval.push(byte) }
+            }
+            if retval.len() > limit {
+                return Err(WzError::Full("decompressed output"))
+            }
+        }
+    }
+    Ok(retval)
+}
+
+fn pair_index(first: u8, second: u8) -> usize {
+    (first as usize) << 8 | second as usize
+}
+
+// Replace every pair, left to right, with symbol.
+fn replace_pair(bytes: &[u8], first: u8, second: u8, symbol: u8) -> Vec<u8> {
+    let mut retval = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == first && bytes.get(i + 1) == Some(&second) {
+            retval.push(symbol);
+            i += 2;
+        } else {
+            retval.push(bytes[i]);
+            i += 1;
+        }
+    }
+    retval
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::bpe::{bpe, unbpe};
+
+    #[test]
+    fn test_substitution() {
+        let bytes = b"abababab".to_vec();
+        let (data, table) = bpe(&bytes);
+        // 0 is the first spare byte: ab becomes 0, and then 00 becomes 1.
+        assert_eq!(vec![0, b'a', b'b'], table[..3]);
+        assert!(data.len() < bytes.len());
+        assert_eq!(bytes, unbpe(&data, &table, usize::MAX).unwrap());
+
+        // Pairs made of themselves, or of pairs made later, would never finish expanding.
+        assert!(unbpe(&[0], &[0, 0, 1], usize::MAX).is_err());
+        assert!(unbpe(&[0], &[0, 1, 1, 1, 0, 0], usize::MAX).is_err());
+        assert!(unbpe(&[0], &[0, 1], usize::MAX).is_err());
+
+        // Each pair made of the one before doubles it, so a short table can stand for a huge output.
+        let table: Vec<u8> = (1..=200).flat_map(|symbol| [symbol, symbol - 1, symbol - 1]).collect();
+        assert!(unbpe(&[200], &table, 1 << 20).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let every_byte: Vec<u8> = (0..=255).collect();
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![5],
+            vec![9; 100],
+            b"the theme of the thesis is the theatre".repeat(20),
+            every_byte.repeat(10),
+        ];
+        for input in inputs {
+            let (data, table) = bpe(&input);
+            assert_eq!(input, unbpe(&data, &table, usize::MAX).unwrap());
+        }
+    }
+
+    // With every byte already in use, there's nothing to substitute with.
+    #[test]
+    fn test_no_spare_bytes() {
+        let bytes: Vec<u8> = (0..=255).collect::<Vec<u8>>().repeat(10);
+        let (data, table) = bpe(&bytes);
+        assert!(table.is_empty());
+        assert_eq!(bytes, data);
+    }
+}
@@ -0,0 +1,165 @@
+// The Burrows-Wheeler transform.
+// Sorting every rotation of a block and keeping the last column groups bytes by what follows them.
+// The result has the same bytes, but long runs of the same few bytes -- far easier to compress.
+// Author: Will Morris
+
+use crate::file::error::WzError;
+
+// bzip2 uses blocks of about this size. Sorting uses several words of memory per byte,
+// So much larger blocks get expensive quickly.
+pub const BWT_BLOCK_SIZE: usize = 1 << 20;
+
+// Transform bytes, returning the last column of the sorted rotations,
+// And which row of the sorted rotations holds the original block.
+pub fn bwt(bytes: &[u8]) -> (Vec<u8>, usize) {
+    let n = bytes.len();
+    let rotations = sort_rotations(bytes);
+
+    let mut primary = 0;
+    let last = rotations.iter().enumerate().map(|(row, start)| {
+        if *start == 0 {
+            primary = row;
+        }
+        // The last byte of a rotation is the one just before where it starts.
+        bytes[(*start as usize + n - 1) % n]
+    }).collect();
+
+    (last, primary)
+}
+
+// Undo the transform, given the last column and the row of the original block.
+pub fn unbwt(last: &[u8], primary: usize) -> Result<Vec<u8>, WzError> {
+    let n = last.len();
+    if n == 0 {
+        return Ok(vec![])
+    }
+    if primary >= n {
+        return Err(WzError::Malformed(format!("Row {} is past the last of {} rotations!", primary, n)))
+    }
+
+    // Where the rows starting with each byte begin, in the sorted first column.
+    let mut starts = [0usize; 256];
+    for byte in last {
+        starts[*byte as usize] += 1;
+    }
+    let mut total = 0;
+    for start in starts.iter_mut() {
+        let count = *start;
+        *start = total;
+        total += count;
+    }
+
+    // For each row, which row holds the rotation starting one byte earlier?
+    // The k'th occurrence of a byte in the last column is its k'th occurrence in the first.
+    let mut previous_row = vec![0u32; n];
+    for (row, byte) in last.iter().enumerate() {
+        previous_row[row] = starts[*byte as usize] as u32;
+        starts[*byte as usize] += 1;
+    }
+
+    // Walk backwards from the original block, one byte at a time.
+    let mut retval = vec![0u8; n];
+    let mut row = primary;
+    for i in (0..n).rev() {
+        retval[i] = last[row];
+        row = previous_row[row] as usize;
+    }
+    Ok(retval)
+}
+
+// Sort the rotations of bytes, returning where each starts, in sorted order.
+// Uses prefix doubling: once rotations are sorted by their first k bytes,
+// Sorting by the first 2k is just sorting pairs of ranks. Every pass is a linear radix sort.
+fn sort_rotations(bytes: &[u8]) -> Vec<u32> {
+    let n = bytes.len();
+    assert!(n < u32::MAX as usize, "Block too large to transform!");
+    if n == 0 {
+        return vec![]
+    }
+
+    let mut ranks: Vec<u32> = bytes.iter().map(|byte| *byte as u32).collect();
+    let mut sorted = counting_sort(&(0..n as u32).collect::<Vec<_>>(), &ranks, 256);
+    let mut by_second = vec![0u32; n];
+    let mut new_ranks = vec![0u32; n];
+    let mut num_ranks = 256;
+
+    let mut k = 1;
+    while k < n {
+        // Listing rotations in order of the rank k bytes in gives the order of their second halves.
+        for (i, start) in sorted.iter().enumerate() {
+            by_second[i] = ((*start as usize + n - k) % n) as u32;
+        }
+        // A stable sort by the first half finishes the job.
+        sorted = counting_sort(&by_second, &ranks, num_ranks);
+
+        // Rotations only share a rank if both halves match.
+        let key = |start: u32| (ranks[start as usize], ranks[(start as usize + k) % n]);
+        let mut rank = 0;
+        new_ranks[sorted[0] as usize] = 0;
+        for i in 1..n {
+            if key(sorted[i]) != key(sorted[i - 1]) {
+                rank += 1;
+            }
+            new_ranks[sorted[i] as usize] = rank;
+        }
+        std::mem::swap(&mut ranks, &mut new_ranks);
+        num_ranks = rank as usize + 1;
+
+        // Every rotation is distinct -- no need to look any further.
+        if num_ranks == n {
+            break
+        }
+        k *= 2;
+    }
+    sorted
+}
+
+// Stably sort starts by their rank. Ranks must be less than num_ranks.
+fn counting_sort(starts: &[u32], ranks: &[u32], num_ranks: usize) -> Vec<u32> {
+    let mut positions = vec![0usize; num_ranks + 1];
+    for start in starts {
+        positions[ranks[*start as usize] as usize + 1] += 1;
+    }
+    for i in 1..positions.len() {
+        positions[i] += positions[i - 1];
+    }
+
+    let mut sorted = vec![0u32; starts.len()];
+    for start in starts {
+        let position = &mut positions[ranks[*start as usize] as usize];
+        sorted[*position] = *start;
+        *position += 1;
+    }
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::bwt::{bwt, unbwt};
+
+    #[test]
+    fn test_banana() {
+        let (last, primary) = bwt(b"banana");
+        // Sorted rotations: abanan, anaban, ananab, banana, nabana, nanaba
+        assert_eq!(b"nnbaaa".to_vec(), last);
+        assert_eq!(3, primary);
+        assert_eq!(b"banana".to_vec(), unbwt(&last, primary).unwrap());
+        assert!(unbwt(&last, last.len()).is_err());
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![7],
+            vec![0; 1000],
+            b"abababababab".to_vec(),
+            b"mississippi river, mississippi state".to_vec(),
+            (0..=255).cycle().take(3000).collect(),
+        ];
+        for input in inputs {
+            let (last, primary) = bwt(&input);
+            assert_eq!(input, unbwt(&last, primary).unwrap());
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// Byte deltas.
+// Each byte is replaced by how much it differs from the one before, wrapping around.
+// Slowly changing samples, like audio or sensor readings, become a few small numbers.
+// Author: Will Morris
+
+pub fn byte_delta(bytes: &[u8]) -> Vec<u8> {
+    let mut previous = 0u8;
+    bytes.iter().map(|byte| {
+        let difference = byte.wrapping_sub(previous);
+        previous = *byte;
+        difference
+    }).collect()
+}
+
+pub fn unbyte_delta(bytes: &[u8]) -> Vec<u8> {
+    let mut previous = 0u8;
+    bytes.iter().map(|difference| {
+        previous = previous.wrapping_add(*difference);
+        previous
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::bytedelta::{byte_delta, unbyte_delta};
+
+    #[test]
+    fn test_delta() {
+        assert_eq!(vec![10, 1, 1, 255, 246], byte_delta(&[10, 11, 12, 11, 1]));
+        assert_eq!(vec![10, 11, 12, 11, 1], unbyte_delta(&[10, 1, 1, 255, 246]));
+        assert!(byte_delta(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes: Vec<u8> = (0..1000u32).map(|i| (i * 37 % 256) as u8).collect();
+        assert_eq!(bytes, unbyte_delta(&byte_delta(&bytes)));
+    }
+}
@@ -0,0 +1,81 @@
+// Splits UTF-8 text into Unicode scalar values, so each character can be coded as a single symbol.
+// Invalid UTF-8 isn't an error: each bad byte becomes a symbol of its own, past the last character.
+// Author: Will Morris
+
+use crate::file::error::WzError;
+use crate::ordering::symbolfreq::Symbol;
+
+// Symbols from here up stand for a single byte which wasn't part of a valid character.
+const INVALID_BASE: Symbol = char::MAX as Symbol + 1;
+
+pub fn to_chars(bytes: &[u8]) -> Vec<Symbol> {
+    let mut retval = Vec::with_capacity(bytes.len());
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        match std::str::from_utf8(rest) {
+            Ok(text) => {
+                retval.extend(text.chars().map(|c| c as Symbol));
+                break
+            }
+            Err(error) => {
+                let (valid, invalid) = rest.split_at(error.valid_up_to());
+                let text = std::str::from_utf8(valid).unwrap();
+                retval.extend(text.chars().map(|c| c as Symbol));
+
+                // A truncated character at the very end has no error length: all of it is bad.
+                let bad = error.error_len().unwrap_or(invalid.len());
+                retval.extend(invalid[..bad].iter().map(|byte| INVALID_BASE + *byte as Symbol));
+                rest = &invalid[bad..];
+            }
+        }
+    }
+    retval
+}
+
+// Surrogates aren't characters, and there are only so many bytes, so some symbols can't be turned back.
+pub fn from_chars(symbols: &[Symbol]) -> Result<Vec<u8>, WzError> {
+    let mut retval = Vec::with_capacity(symbols.len());
+    let mut buf = [0u8; 4];
+    for symbol in symbols {
+        match char::from_u32(*symbol) {
+            Some(c) => { retval.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()) }
+            None => {
+                let byte = symbol.checked_sub(INVALID_BASE).and_then(|byte| u8::try_from(byte).ok())
+                    .ok_or_else(|| WzError::Malformed(format!("Symbol {} isn't a character or a byte!", symbol)))?;
+                retval.push(byte)
+            }
+        }
+    }
+    Ok(retval)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::chars::{from_chars, INVALID_BASE, to_chars};
+
+    #[test]
+    fn test_chars() {
+        assert_eq!(vec![104, 233, 0x4e16], to_chars("hé世".as_bytes()));
+    }
+
+    #[test]
+    fn test_invalid() {
+        // A lone continuation byte, then a truncated character at the end.
+        let bytes = b"a\x80b\xe4\xb8".to_vec();
+        let symbols = to_chars(&bytes);
+        assert_eq!(vec![97, INVALID_BASE + 0x80, 98, INVALID_BASE + 0xe4, INVALID_BASE + 0xb8], symbols);
+        assert_eq!(bytes, from_chars(&symbols).unwrap());
+
+        // A surrogate, and a symbol past the last byte.
+        assert!(from_chars(&[0xd800]).is_err());
+        assert!(from_chars(&[INVALID_BASE + 256]).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "Ελληνικά, русский, 日本語, and plain ASCII too. 🎉".as_bytes().to_vec();
+        assert_eq!(text, from_chars(&to_chars(&text)).unwrap());
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(bytes, from_chars(&to_chars(&bytes)).unwrap());
+    }
+}
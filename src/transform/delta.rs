@@ -0,0 +1,212 @@
+// Deltas between two versions of a file.
+// The new version is described as copies from the old one, with whatever's new inserted between them.
+// Author: Will Morris
+
+use std::collections::HashMap;
+use crate::file::bytestream::{read_varint, write_varint};
+
+// Shorter copies cost more to describe than the bytes they replace.
+const MIN_COPY: usize = 16;
+// The old version is only indexed every STEP bytes, to save memory.
+// Any copy of at least MIN_COPY + STEP bytes still covers an indexed position, and is extended back from there.
+const STEP: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Instruction {
+    // Copy len bytes of the old version, starting at offset.
+    Copy { offset: usize, len: usize },
+    Insert(Vec<u8>),
+}
+
+// Describe new in terms of old.
+pub(crate) fn diff(old: &[u8], new: &[u8]) -> Vec<Instruction> {
+    let mut index: HashMap<u64, usize> = HashMap::new();
+    for offset in (0..old.len().saturating_sub(MIN_COPY - 1)).step_by(STEP) {
+        index.entry(hash(&old[offset..offset + MIN_COPY])).or_insert(offset);
+    }
+
+    let mut instructions = vec![];
+    let mut pending = vec![];
+    // Where the last copy ended. Edits often leave the old and new versions in step after it.
+    let mut expected = 0;
+    let mut i = 0;
+    while i < new.len() {
+        // Try carrying on from the last copy, as if what's pending was inserted, or replaced as many old bytes.
+        // Then try wherever the next few bytes were first seen.
+        let inserted = Some(expected);
+        let replaced = Some(expected + pending.len());
+        let seen = new.get(i..i + MIN_COPY).and_then(|window| index.get(&hash(window)).copied());
+        let best = [inserted, replaced, seen].iter().flatten()
+            .map(|offset| (*offset, match_len(old, *offset, &new[i..])))
+            .max_by_key(|(_, len)| *len);
+
+        match best {
+            Some((mut offset, len)) if len >= MIN_COPY => {
+                // The copy may have started earlier, in what was about to be inserted.
+                let mut start = i;
+                while offset > 0 && !pending.is_empty() && old[offset - 1] == new[start - 1] {
+                    offset -= 1;
+                    start -= 1;
+                    pending.pop();
+                }
+                if !pending.is_empty() {
+                    instructions.push(Instruction::Insert(std::mem::take(&mut pending)));
+                }
+                let len = len + (i - start);
+                instructions.push(Instruction::Copy { offset, len });
+                expected = offset + len;
+                i = start + len;
+            }
+            _ => {
+                pending.push(new[i]);
+                i += 1;
+            }
+        }
+    }
+    if !pending.is_empty() {
+        instructions.push(Instruction::Insert(pending));
+    }
+    instructions
+}
+
+// Split instructions into what to do, and the bytes to insert.
+// Kept apart, each compresses better than the two mixed together.
+// Each instruction is a number, with the low bit saying whether it's a copy, and the rest its length.
+// Copies then give their offset, relative to where the last copy ended.
+pub(crate) fn to_streams(instructions: &[Instruction]) -> (Vec<u8>, Vec<u8>) {
+    let mut control = vec![];
+    let mut data = vec![];
+    let mut expected = 0;
+    for instruction in instructions {
+        match instruction {
+            Instruction::Copy { offset, len } => {
+                write_varint(&mut control, (*len as u64) << 1 | 1);
+                write_varint(&mut control, zigzag(*offset as i64 - expected as i64));
+                expected = offset + len;
+            }
+            Instruction::Insert(bytes) => {
+                write_varint(&mut control, (bytes.len() as u64) << 1);
+                data.extend_from_slice(bytes);
+            }
+        }
+    }
+    (control, data)
+}
+
+// Rebuild the new version from the old one.
+pub(crate) fn apply(old: &[u8], instructions: &[Instruction]) -> Result<Vec<u8>, String> {
+    let mut retval = vec![];
+    for instruction in instructions {
+        match instruction {
+            Instruction::Copy { offset, len } => {
+                let copied = offset.checked_add(*len).and_then(|end| old.get(*offset..end)).ok_or("Delta copies past the end of the old file!")?;
+                retval.extend_from_slice(copied);
+            }
+            Instruction::Insert(bytes) => { retval.extend_from_slice(bytes) }
+        }
+    }
+    Ok(retval)
+}
+
+// Rebuild instructions from what to_streams made of them.
+pub(crate) fn from_streams(control: &[u8], data: &[u8]) -> Result<Vec<Instruction>, String> {
+    let mut instructions = vec![];
+    let mut expected = 0i64;
+    let mut i = 0;
+    let mut j = 0usize;
+    while i < control.len() {
+        let value = read_varint(control, &mut i)?;
+        let len = (value >> 1) as usize;
+        match value & 1 {
+            1 => {
+                let offset = expected.checked_add(unzigzag(read_varint(control, &mut i)?))
+                    .and_then(|offset| usize::try_from(offset).ok())
+                    .ok_or("Delta copies from outside the old file!")?;
+                instructions.push(Instruction::Copy { offset, len });
+                expected = offset.saturating_add(len) as i64;
+            }
+            _ => {
+                let bytes = j.checked_add(len).and_then(|end| data.get(j..end)).ok_or("Delta is missing inserted bytes!")?;
+                instructions.push(Instruction::Insert(bytes.to_vec()));
+                j += len;
+            }
+        }
+    }
+    Ok(instructions)
+}
+
+fn match_len(old: &[u8], offset: usize, new: &[u8]) -> usize {
+    old.get(offset..).map_or(0, |old| old.iter().zip(new).take_while(|(a, b)| a == b).count())
+}
+
+// 64-bit FNV-1a.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// Small numbers of either sign become small unsigned numbers.
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::delta::{apply, diff, from_streams, Instruction, to_streams, unzigzag, zigzag};
+
+    #[test]
+    fn test_diff() {
+        let old: Vec<u8> = (0..4000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let mut new = old[1000..3000].to_vec();
+        new.extend_from_slice(b"something entirely new");
+        new.extend_from_slice(&old[..1000]);
+        new[500] ^= 0xff;
+
+        // The changed byte splits the first copy in two. The copy after it carries on in step.
+        let expected = vec![
+            Instruction::Copy { offset: 1000, len: 500 },
+            Instruction::Insert(vec![new[500]]),
+            Instruction::Copy { offset: 1501, len: 1499 },
+            Instruction::Insert(b"something entirely new".to_vec()),
+            Instruction::Copy { offset: 0, len: 1000 },
+        ];
+        assert_eq!(expected, diff(&old, &new));
+        assert_eq!(new, apply(&old, &expected).unwrap());
+
+        assert_eq!(Vec::<Instruction>::new(), diff(&old, b""));
+        assert_eq!(vec![Instruction::Insert(new.clone())], diff(b"", &new));
+    }
+
+    #[test]
+    fn test_streams() {
+        let instructions = vec![
+            Instruction::Copy { offset: 100, len: 20 },
+            Instruction::Insert(b"new".to_vec()),
+            Instruction::Copy { offset: 0, len: 200 },
+        ];
+        let (control, data) = to_streams(&instructions);
+        assert_eq!(b"new".to_vec(), data);
+        // 200 is past seven bits, and so takes two bytes once shifted. 0 is 120 before the last copy's end.
+        assert_eq!(vec![41, 200, 1, 6, 145, 3, 239, 1], control);
+        assert_eq!(instructions, from_streams(&control, &data).unwrap());
+
+        // Neither stream may end early.
+        assert!(from_streams(&control[..1], &data).is_err());
+        assert!(from_streams(&control, b"ne").is_err());
+        assert!(apply(b"too short", &instructions).is_err());
+    }
+
+    #[test]
+    fn test_zigzag() {
+        assert_eq!(0, zigzag(0));
+        assert_eq!(1, zigzag(-1));
+        assert_eq!(2, zigzag(1));
+        assert_eq!(u64::MAX, zigzag(i64::MIN));
+        for value in [0, 1, -1, 1000, -1000, i64::MAX, i64::MIN] {
+            assert_eq!(value, unzigzag(zigzag(value)));
+        }
+    }
+}
@@ -0,0 +1,231 @@
+// Filters rearrange a block's bytes before anything else is done to it.
+// Which filter was used is stored with the block, so it can be undone on the way out.
+// Every filter is found by its id here, so adding one means implementing Filter and registering it below.
+// Author: Will Morris
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use crate::file::bytestream::{read_bytes, read_long};
+use crate::file::error::WzError;
+use crate::transform::bpe::{bpe, unbpe};
+use crate::transform::bwt::{bwt, unbwt};
+use crate::transform::bytedelta::{byte_delta, unbyte_delta};
+use crate::transform::mtf::{mtf, unmtf};
+use crate::transform::rle::{rle, unrle};
+use crate::transform::transpose::{transpose, untranspose};
+
+/*
+  CONTENTS:
+  -- filter id
+  -- any parameters the filter needs.
+ */
+// Filters are shared by every thread compressing at once, like coders.
+pub trait Filter: Debug + Send + Sync {
+    // Stored with each filtered block, to find the filter again.
+    fn id(&self) -> u8;
+
+    // Anything else needed to rebuild the filter, i.e. a record width.
+    fn params(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    // Returns the filtered bytes, and anything else needed to undo the filter.
+    fn encode(&self, bytes: &[u8]) -> (Vec<u8>, Vec<u8>);
+
+    // Undo the filter. Whatever was stored with a block may be corrupt, so this must never panic.
+    // Filters which can grow a block stop as soon as the output would pass limit bytes.
+    fn decode(&self, bytes: &[u8], extra: &[u8], limit: usize) -> Result<Vec<u8>, WzError>;
+}
+
+// Filters are the same if they'd be stored the same.
+impl PartialEq for dyn Filter {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id() && self.params() == other.params()
+    }
+}
+
+// Run-length encoding, for long runs of one byte.
+#[derive(Debug)]
+pub struct Rle;
+
+impl Filter for Rle {
+    fn id(&self) -> u8 {
+        0
+    }
+
+    fn encode(&self, bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        (rle(bytes), vec![])
+    }
+
+    fn decode(&self, bytes: &[u8], _: &[u8], limit: usize) -> Result<Vec<u8>, WzError> {
+        unrle(bytes, limit)
+    }
+}
+
+// Store fixed size records column by column, so matching fields of each record sit together.
+#[derive(Debug)]
+pub struct Transpose(pub usize);
+
+impl Filter for Transpose {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn params(&self) -> Vec<u8> {
+        (self.0 as u64).to_le_bytes().to_vec()
+    }
+
+    fn encode(&self, bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        (transpose(bytes, self.0), vec![])
+    }
+
+    fn decode(&self, bytes: &[u8], _: &[u8], _: usize) -> Result<Vec<u8>, WzError> {
+        Ok(untranspose(bytes, self.0))
+    }
+}
+
+// Byte pair encoding, which swaps common pairs of bytes for spare ones.
+#[derive(Debug)]
+pub struct Bpe;
+
+impl Filter for Bpe {
+    fn id(&self) -> u8 {
+        2
+    }
+
+    fn encode(&self, bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        bpe(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8], extra: &[u8], limit: usize) -> Result<Vec<u8>, WzError> {
+        unbpe(bytes, extra, limit)
+    }
+}
+
+// The difference between each byte and the one before, for slowly changing samples.
+#[derive(Debug)]
+pub struct Delta;
+
+impl Filter for Delta {
+    fn id(&self) -> u8 {
+        3
+    }
+
+    fn encode(&self, bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        (byte_delta(bytes), vec![])
+    }
+
+    fn decode(&self, bytes: &[u8], _: &[u8], _: usize) -> Result<Vec<u8>, WzError> {
+        Ok(unbyte_delta(bytes))
+    }
+}
+
+// The Burrows-Wheeler transform, then move-to-front. The row of the original block is kept to undo it.
+#[derive(Debug)]
+pub struct Bwt;
+
+impl Filter for Bwt {
+    fn id(&self) -> u8 {
+        4
+    }
+
+    fn encode(&self, bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let (last, primary) = bwt(bytes);
+        (mtf(&last), (primary as u64).to_le_bytes().to_vec())
+    }
+
+    fn decode(&self, bytes: &[u8], extra: &[u8], _: usize) -> Result<Vec<u8>, WzError> {
+        let primary = read_long(extra, &mut 0)?;
+        unbwt(&unmtf(bytes), usize::try_from(primary).map_err(|_| WzError::Invalid("Burrows-Wheeler row"))?)
+    }
+}
+
+// Find a filter from its name on the command line.
+// Filters with a parameter take it after a colon, i.e. transpose:16.
+pub fn parse_filter(name: &str) -> Option<Arc<dyn Filter>> {
+    match name.split_once(':') {
+        None if name == "rle" => { Some(Arc::new(Rle)) }
+        None if name == "bpe" => { Some(Arc::new(Bpe)) }
+        None if name == "delta" => { Some(Arc::new(Delta)) }
+        None if name == "bwt" => { Some(Arc::new(Bwt)) }
+        Some(("transpose", width)) => {
+            match width.parse() {
+                Ok(width) if width > 0 => { Some(Arc::new(Transpose(width))) }
+                _ => { None }
+            }
+        }
+        _ => { None }
+    }
+}
+
+// Find a chain of filters, separated by commas, i.e. transpose:4,delta.
+// They're applied in the order they're named.
+pub fn parse_filters(names: &str) -> Option<Vec<Arc<dyn Filter>>> {
+    names.split(',').map(parse_filter).collect()
+}
+
+pub fn filter_to_stream(filter: &dyn Filter) -> Vec<u8> {
+    let mut retval = vec![filter.id()];
+    retval.append(&mut filter.params());
+    retval
+}
+
+// Rebuild a filter from all of bytes.
+pub fn filter_from_stream(bytes: &[u8]) -> Result<Arc<dyn Filter>, WzError> {
+    let mut i = 0;
+    let filter: Arc<dyn Filter> = match read_bytes(bytes, &mut i, 1, "a filter")?[0] {
+        0 => { Arc::new(Rle) }
+        // Records are at least a byte wide, just as on the command line.
+        1 => {
+            match read_long(bytes, &mut i)? {
+                0 => { return Err(WzError::Malformed(String::from("Records can't be 0 bytes wide!"))) }
+                width => { Arc::new(Transpose(usize::try_from(width).map_err(|_| WzError::Invalid("record width"))?)) }
+            }
+        }
+        2 => { Arc::new(Bpe) }
+        3 => { Arc::new(Delta) }
+        4 => { Arc::new(Bwt) }
+        id => { return Err(WzError::Malformed(format!("Unknown filter: {}", id))) }
+    };
+    match i == bytes.len() {
+        true => { Ok(filter) }
+        false => { Err(WzError::Malformed(format!("{} unexpected bytes after the filter!", bytes.len() - i))) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::transform::filter::{Bpe, Bwt, Delta, Filter, filter_from_stream, filter_to_stream, parse_filter, parse_filters, Rle, Transpose};
+
+    #[test]
+    fn test_parse() {
+        let rle: Arc<dyn Filter> = Arc::new(Rle);
+        let transpose: Arc<dyn Filter> = Arc::new(Transpose(12));
+        assert_eq!(Some(rle.clone()), parse_filter("rle"));
+        assert_eq!(Some(transpose.clone()), parse_filter("transpose:12"));
+        assert_eq!(None, parse_filter("transpose:0"));
+        assert_eq!(None, parse_filter("transpose"));
+        assert_eq!(Some(2), parse_filter("bpe").map(|filter| filter.id()));
+        assert_eq!(None, parse_filter("rle:3"));
+        assert_eq!(None, parse_filter("sideways"));
+
+        assert_eq!(Some(vec![transpose, rle]), parse_filters("transpose:12,rle"));
+        assert_eq!(None, parse_filters("rle,"));
+    }
+
+    #[test]
+    fn test_to_from() {
+        let bytes = b"zzzzzzzzzzzzzzzzzzzzzzzzzz top".to_vec();
+        let filters: [Arc<dyn Filter>; 5] = [Arc::new(Rle), Arc::new(Transpose(3)), Arc::new(Bpe), Arc::new(Delta), Arc::new(Bwt)];
+        for filter in filters {
+            assert!(*filter == *filter_from_stream(&filter_to_stream(filter.as_ref())).unwrap());
+            let (filtered, extra) = filter.encode(&bytes);
+            assert_eq!(bytes, filter.decode(&filtered, &extra, usize::MAX).unwrap());
+        }
+        assert!(filter_from_stream(&[9]).is_err());
+        assert!(filter_from_stream(&[0, 0]).is_err());
+        assert!(filter_from_stream(&filter_to_stream(&Transpose(0))).is_err());
+        assert!(Bwt.decode(b"abc", &[], usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+// LZ77 match finding.
+// Repeated strings are replaced by (length, distance) pairs pointing back at an earlier copy.
+// Huffman coding then squeezes the literals and pairs, much like deflate does.
+// Author: Will Morris
+
+use crate::file::error::WzError;
+
+// Shorter matches cost more to describe than the literals they replace.
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+// How far back a match may point, unless a format says otherwise.
+pub const WINDOW_SIZE: usize = 1 << 20;
+// How many earlier positions to try before settling for the best match found so far.
+const MAX_CHAIN: usize = 32;
+const HASH_BITS: u32 = 16;
+// Marks an empty hash chain.
+const NONE: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token {
+    Literal(u8),
+    // Copy length bytes, starting distance bytes back.
+    Match { length: usize, distance: usize },
+}
+
+// Turn bytes into a series of literals and matches, greedily taking the longest match at each step.
+// No match points more than window bytes back.
+pub fn find_matches(bytes: &[u8], window: usize) -> Vec<Token> {
+    assert!(bytes.len() < NONE as usize, "Block too large to match!");
+    // The most recent position with each hash, and for each position, the one before it.
+    let mut heads = vec![NONE; 1 << HASH_BITS];
+    let mut chains = vec![NONE; window.min(bytes.len())];
+
+    let mut tokens = vec![];
+    let mut position = 0;
+    while position < bytes.len() {
+        let (length, distance) = longest_match(bytes, position, window, &heads, &chains);
+        if length >= MIN_MATCH {
+            tokens.push(Token::Match { length, distance });
+            for skipped in position..position + length {
+                insert(bytes, skipped, &mut heads, &mut chains);
+            }
+            position += length;
+        } else {
+            tokens.push(Token::Literal(bytes[position]));
+            insert(bytes, position, &mut heads, &mut chains);
+            position += 1;
+        }
+    }
+    tokens
+}
+
+// Rebuild the original bytes from a series of tokens.
+// A match can't point back before the first byte, or at nothing at all.
+// Matches can be long, so one too many for limit is caught before it's copied.
+pub fn expand(tokens: &[Token], limit: usize) -> Result<Vec<u8>, WzError> {
+    let mut retval = vec![];
+    for token in tokens {
+        let length = match token {
+            Token::Literal(_) => { 1 }
+            Token::Match { length, .. } => { *length }
+        };
+        if retval.len().saturating_add(length) > limit {
+            return Err(WzError::Full("decompressed output"))
+        }
+        match token {
+            Token::Literal(byte) => { retval.push(*byte) }
+            // Matches may overlap the bytes they produce, so copy one at a time.
+            Token::Match { length, distance } => {
+                let start = match retval.len().checked_sub(*distance) {
+                    Some(start) if *distance > 0 => { start }
+                    _ => { return Err(WzError::Malformed(format!("LZ77 match points {} bytes back, after only {}!", distance, retval.len()))) }
+                };
+                for i in 0..*length {
+                    retval.push(retval[start + i]);
+                }
+            }
+        }
+    }
+    Ok(retval)
+}
+
+// Walk the hash chain for position, returning the longest (length, distance) found.
+fn longest_match(bytes: &[u8], position: usize, window: usize, heads: &[u32], chains: &[u32]) -> (usize, usize) {
+    if position + MIN_MATCH > bytes.len() {
+        return (0, 0)
+    }
+    let max_length = MAX_MATCH.min(bytes.len() - position);
+
+    let mut best = (0, 0);
+    let mut candidate = heads[hash(&bytes[position..])];
+    for _ in 0..MAX_CHAIN {
+        if candidate == NONE {
+            break
+        }
+        let start = candidate as usize;
+        let distance = position - start;
+        if distance > window {
+            break
+        }
+
+        let length = bytes[start..].iter().zip(&bytes[position..position + max_length])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best.0 {
+            best = (length, distance);
+            if length == max_length {
+                break
+            }
+        }
+
+        // Chains only ever point backwards. Anything else is a slot reused by a newer position.
+        let next = chains[start % chains.len()];
+        if next >= candidate {
+            break
+        }
+        candidate = next;
+    }
+    best
+}
+
+// Make position the newest entry in its hash chain.
+fn insert(bytes: &[u8], position: usize, heads: &mut [u32], chains: &mut [u32]) {
+    if position + MIN_MATCH <= bytes.len() {
+        let hash = hash(&bytes[position..]);
+        let slot = position % chains.len();
+        chains[slot] = heads[hash];
+        heads[hash] = position as u32;
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+// Large values are coded as a bucket, plus some extra bits for where in the bucket they fall.
+// Buckets double in size every two codes, just like deflate's distance codes:
+// 0, 1, 2, 3, 4-5, 6-7, 8-11, 12-15, 16-23 ...
+// Returns (code, number of extra bits, extra bits).
+pub fn bucket(value: usize) -> (u32, u32, usize) {
+    if value < 4 {
+        return (value as u32, 0, 0)
+    }
+    let log = usize::BITS - 1 - value.leading_zeros();
+    let half = (value >> (log - 1)) & 1;
+    let extra_bits = log - 1;
+    let base = (2 | half) << extra_bits;
+    (2 * log + half as u32, extra_bits, value - base)
+}
+
+// Given a bucket code, get the smallest value in it and the number of extra bits that follow.
+pub fn bucket_base(code: u32) -> (usize, u32) {
+    if code < 4 {
+        return (code as usize, 0)
+    }
+    let log = code / 2;
+    let half = (code % 2) as usize;
+    let extra_bits = log - 1;
+    ((2 | half) << extra_bits, extra_bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::lz77::{bucket, bucket_base, expand, find_matches, Token, WINDOW_SIZE};
+
+    #[test]
+    fn test_matches() {
+        let bytes = b"abcabcabcabc";
+        let tokens = find_matches(bytes, WINDOW_SIZE);
+        assert_eq!(Token::Literal(b'a'), tokens[0]);
+        // The repeats overlap the bytes they produce.
+        assert_eq!(Token::Match { length: 9, distance: 3 }, tokens[3]);
+        assert_eq!(4, tokens.len());
+        assert_eq!(bytes.to_vec(), expand(&tokens, usize::MAX).unwrap());
+
+        // Pointing back past the start.
+        assert!(expand(&[Token::Literal(b'a'), Token::Match { length: 3, distance: 2 }], usize::MAX).is_err());
+        assert!(expand(&[Token::Literal(b'a'), Token::Match { length: 3, distance: 0 }], usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_window() {
+        let mut bytes = b"far away".to_vec();
+        bytes.extend(vec![b'-'; 100]);
+        bytes.extend_from_slice(b"far away");
+        let tokens = find_matches(&bytes, 50);
+        assert!(tokens.iter().all(|token| match token {
+            Token::Match { distance, .. } => { *distance <= 50 }
+            Token::Literal(_) => { true }
+        }));
+        assert_eq!(bytes, expand(&tokens, usize::MAX).unwrap());
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![1, 2],
+            vec![0; 10000],
+            b"it was the best of times, it was the worst of times".to_vec(),
+            (0..=255).cycle().take(5000).collect(),
+        ];
+        for input in inputs {
+            assert_eq!(input, expand(&find_matches(&input, WINDOW_SIZE), usize::MAX).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_buckets() {
+        for value in 0..100_000 {
+            let (code, extra_bits, extra) = bucket(value);
+            let (base, base_bits) = bucket_base(code);
+            assert_eq!(extra_bits, base_bits);
+            assert!(extra < 1 << extra_bits);
+            assert_eq!(value, base + extra);
+        }
+    }
+}
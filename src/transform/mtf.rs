@@ -0,0 +1,47 @@
+// Move-to-front coding.
+// Each byte is replaced by how recently it was last seen, so the runs left by the
+// Burrows-Wheeler transform become runs of small numbers -- mostly zeros.
+// Author: Will Morris
+
+// The initial order is every byte, in order.
+fn initial_order() -> [u8; 256] {
+    std::array::from_fn(|i| i as u8)
+}
+
+pub fn mtf(bytes: &[u8]) -> Vec<u8> {
+    let mut order = initial_order();
+    bytes.iter().map(|byte| {
+        let index = order.iter().position(|candidate| candidate == byte).unwrap();
+        order.copy_within(0..index, 1);
+        order[0] = *byte;
+        index as u8
+    }).collect()
+}
+
+pub fn unmtf(indices: &[u8]) -> Vec<u8> {
+    let mut order = initial_order();
+    indices.iter().map(|index| {
+        let index = *index as usize;
+        let byte = order[index];
+        order.copy_within(0..index, 1);
+        order[0] = byte;
+        byte
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::mtf::{mtf, unmtf};
+
+    #[test]
+    fn test_mtf() {
+        assert_eq!(vec![98, 0, 98, 0, 1], mtf(b"bbaab"));
+        assert_eq!(b"bbaab".to_vec(), unmtf(&[98, 0, 98, 0, 1]));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes: Vec<u8> = (0..=255).rev().chain(0..=255).collect();
+        assert_eq!(bytes, unmtf(&mtf(&bytes)));
+    }
+}
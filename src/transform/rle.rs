@@ -0,0 +1,93 @@
+// Run-length encoding.
+// Long runs of one byte are squashed down before they reach the frequency model,
+// which would otherwise spend at least a bit on every byte of the run.
+// Author: Will Morris
+
+use crate::file::error::WzError;
+
+// After this many identical bytes, a count of further repeats follows.
+const RUN_START: usize = 4;
+// A count byte can describe at most this many further repeats.
+const MAX_EXTRA: usize = u8::MAX as usize;
+
+// Short runs are left alone. Runs of RUN_START or more become RUN_START bytes, then a count.
+pub fn rle(bytes: &[u8]) -> Vec<u8> {
+    let mut retval = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        let run = bytes[i..].iter()
+            .take(RUN_START + MAX_EXTRA)
+            .take_while(|candidate| **candidate == byte)
+            .count();
+
+        if run < RUN_START {
+            retval.extend(std::iter::repeat_n(byte, run));
+        } else {
+            retval.extend(std::iter::repeat_n(byte, RUN_START));
+            retval.push((run - RUN_START) as u8);
+        }
+        i += run;
+    }
+    retval
+}
+
+// Runs can make the output far bigger than bytes, so it stops once it would pass limit.
+pub fn unrle(bytes: &[u8], limit: usize) -> Result<Vec<u8>, WzError> {
+    let mut retval = Vec::with_capacity(bytes.len());
+    // How many times the last byte has been seen in a row.
+    let mut run = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        i += 1;
+        run = match retval.last() {
+            Some(last) if *last == byte => { run + 1 }
+            _ => { 1 }
+        };
+        retval.push(byte);
+
+        if run == RUN_START {
+            let extra = *bytes.get(i).ok_or(WzError::Truncated("a run's count"))? as usize;
+            retval.extend(std::iter::repeat_n(byte, extra));
+            i += 1;
+            run = 0;
+        }
+        if retval.len() > limit {
+            return Err(WzError::Full("decompressed output"))
+        }
+    }
+    Ok(retval)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::rle::{rle, unrle};
+
+    #[test]
+    fn test_runs() {
+        assert_eq!(b"abbbb\x00c".to_vec(), rle(b"abbbbc"));
+        assert_eq!(b"aaaa\x03".to_vec(), rle(b"aaaaaaa"));
+        // Short runs are untouched.
+        assert_eq!(b"aabbbc".to_vec(), rle(b"aabbbc"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut long = vec![0; 1000];
+        long.extend_from_slice(b"xyzzzzzzz");
+        long.extend(vec![7; 259]);
+        long.extend(vec![7; 260]);
+        let inputs: Vec<Vec<u8>> = vec![vec![], vec![1], b"aaaa".to_vec(), b"aaaab".to_vec(), long];
+        for input in inputs {
+            assert_eq!(input, unrle(&rle(&input), usize::MAX).unwrap());
+        }
+        // 1000 zeros shrink to a few runs of 259.
+        assert!(rle(&[0; 1000]).len() < 30);
+        // A run cut off before its count.
+        assert!(unrle(b"aaaa", usize::MAX).is_err());
+        // A run past the limit stops there.
+        assert!(unrle(b"aaaa\xff", 100).is_err());
+        assert_eq!(259, unrle(b"aaaa\xff", 259).unwrap().len());
+    }
+}
@@ -0,0 +1,52 @@
+// Transposition of fixed size records.
+// A table of records is stored field by field instead of record by record:
+// the first byte of every record, then the second byte of every record, and so on.
+// Similar bytes end up next to each other, which suits binary tables well.
+// Author: Will Morris
+
+// Rearrange bytes as a table of width byte records, read column by column.
+// Any partial record at the end is left where it is.
+pub fn transpose(bytes: &[u8], width: usize) -> Vec<u8> {
+    let rows = bytes.len() / width;
+    let table = rows * width;
+
+    let mut retval = Vec::with_capacity(bytes.len());
+    for column in 0..width {
+        retval.extend((0..rows).map(|row| bytes[row * width + column]));
+    }
+    retval.extend_from_slice(&bytes[table..]);
+    retval
+}
+
+pub fn untranspose(bytes: &[u8], width: usize) -> Vec<u8> {
+    let rows = bytes.len() / width;
+    let table = rows * width;
+
+    let mut retval = vec![0; bytes.len()];
+    for (i, byte) in bytes[..table].iter().enumerate() {
+        let (column, row) = (i / rows, i % rows);
+        retval[row * width + column] = *byte;
+    }
+    retval[table..].copy_from_slice(&bytes[table..]);
+    retval
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::transpose::{transpose, untranspose};
+
+    #[test]
+    fn test_transpose() {
+        assert_eq!(b"adgbehcfixy".to_vec(), transpose(b"abcdefghixy", 3));
+        assert_eq!(b"abcdefghixy".to_vec(), untranspose(b"adgbehcfixy", 3));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+        for width in [1, 2, 3, 4, 16, 999, 1000, 5000] {
+            assert_eq!(bytes, untranspose(&transpose(&bytes, width), width));
+        }
+        assert!(transpose(&[], 4).is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+// Splits text into words, so that each word can be coded as a single symbol.
+// Words are runs of letters and digits, and runs of whitespace. Anything else stands alone.
+// Author: Will Morris
+
+use std::collections::HashMap;
+use crate::file::bytestream::read_bytes;
+use crate::file::error::WzError;
+use crate::ordering::symbolfreq::Symbol;
+
+// Lengths are stored in a byte, so longer runs are split.
+const MAX_WORD_LEN: usize = u8::MAX as usize;
+
+// Returns each distinct word, in order of first appearance, and the input as indices into them.
+pub fn tokenize(bytes: &[u8]) -> (Vec<Vec<u8>>, Vec<Symbol>) {
+    let mut dictionary = vec![];
+    let mut indices: HashMap<&[u8], Symbol> = HashMap::new();
+    let mut tokens = vec![];
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let class = class(bytes[i]);
+        let len = match class {
+            Class::Other => { 1 }
+            _ => {
+                bytes[i..].iter()
+                    .take(MAX_WORD_LEN)
+                    .take_while(|byte| self::class(**byte) == class)
+                    .count()
+            }
+        };
+
+        let word = &bytes[i..i + len];
+        let index = *indices.entry(word).or_insert_with(|| {
+            dictionary.push(word.to_vec());
+            (dictionary.len() - 1) as Symbol
+        });
+        tokens.push(index);
+        i += len;
+    }
+    (dictionary, tokens)
+}
+
+/*
+  CONTENTS:
+  -- for each word, its length in a byte, then its bytes.
+ */
+pub fn dictionary_to_stream(dictionary: &[Vec<u8>]) -> Vec<u8> {
+    let mut retval = vec![];
+    for word in dictionary {
+        retval.push(word.len() as u8);
+        retval.extend_from_slice(word);
+    }
+    retval
+}
+
+pub fn dictionary_from_stream(bytes: &[u8]) -> Result<Vec<Vec<u8>>, WzError> {
+    let mut dictionary = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let len = bytes[i] as usize;
+        i += 1;
+        dictionary.push(read_bytes(bytes, &mut i, len, "a word")?.to_vec());
+    }
+    Ok(dictionary)
+}
+
+#[derive(PartialEq)]
+enum Class {
+    Word,
+    Space,
+    Other,
+}
+
+fn class(byte: u8) -> Class {
+    if byte.is_ascii_alphanumeric() {
+        Class::Word
+    } else if byte.is_ascii_whitespace() {
+        Class::Space
+    } else {
+        Class::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transform::words::{dictionary_from_stream, dictionary_to_stream, tokenize};
+
+    #[test]
+    fn test_tokenize() {
+        let (dictionary, tokens) = tokenize(b"the cat, the  hat!!");
+        let words: Vec<&[u8]> = dictionary.iter().map(|word| word.as_slice()).collect();
+        assert_eq!(vec![&b"the"[..], b" ", b"cat", b",", b"  ", b"hat", b"!"], words);
+        assert_eq!(vec![0, 1, 2, 3, 1, 0, 4, 5, 6, 6], tokens);
+    }
+
+    #[test]
+    fn test_long_words() {
+        let bytes = vec![b'a'; 600];
+        let (dictionary, tokens) = tokenize(&bytes);
+        assert_eq!(2, dictionary.len());
+        assert_eq!(vec![0, 0, 1], tokens);
+    }
+
+    #[test]
+    fn test_to_from() {
+        let (dictionary, _) = tokenize(b"one two three, four");
+        let stream = dictionary_to_stream(&dictionary);
+        assert_eq!(dictionary, dictionary_from_stream(&stream).unwrap());
+        assert!(dictionary_from_stream(&stream[..stream.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use std::io;
+use std::io::{Read, Write};
+use crate::encoding::bitreader::BitReader;
+use crate::encoding::bitwriter::BitWriter;
+
+// Author: Will Morris
+// An adaptive Huffman tree, using the FGK algorithm.
+// Rather than counting every byte up front, the tree starts empty and grows as bytes stream through.
+// The encoder and decoder make exactly the same updates in exactly the same order,
+// So they always agree on the code -- no frequency table needs to be stored at all!
+
+// The tree holds at most one leaf per byte, plus an NYT leaf, plus internal nodes to join them.
+const MAX_NODES: usize = 256 * 2 + 1;
+// The root always has the highest number.
+const ROOT: usize = MAX_NODES - 1;
+
+// Nodes are numbered so that weights never decrease as the number increases,
+// And so that siblings are always numbered next to each other.
+// A node's number is its index in these vectors.
+pub struct AdaptiveTree {
+    weights: Vec<u64>,
+    parents: Vec<Option<usize>>,
+    // Left and right child of internal nodes.
+    children: Vec<Option<(usize, usize)>>,
+    // The byte at each leaf.
+    bytes: Vec<Option<u8>>,
+    // Where the leaf for each byte currently is, if it has been seen yet.
+    leaves: [Option<usize>; 256],
+    // The "not yet transmitted" leaf stands in for every byte which hasn't been seen.
+    nyt: usize,
+}
+
+impl AdaptiveTree {
+    // Create a tree which has seen nothing. It is just an NYT leaf.
+    pub fn new() -> Self {
+        AdaptiveTree {
+            weights: vec![0; MAX_NODES],
+            parents: vec![None; MAX_NODES],
+            children: vec![None; MAX_NODES],
+            bytes: vec![None; MAX_NODES],
+            leaves: [None; 256],
+            nyt: ROOT,
+        }
+    }
+
+    // Write the code for byte to writer, then update the tree.
+    pub fn encode<W: Write>(&mut self, byte: u8, writer: &mut BitWriter<W>) -> io::Result<()> {
+        match self.leaves[byte as usize] {
+            Some(leaf) => { self.write_path(leaf, writer)? }
+            // A new byte is sent as the NYT code, then the byte itself.
+            None => {
+                self.write_path(self.nyt, writer)?;
+                // Most significant bit first.
+                writer.write_bits(byte.reverse_bits() as u64, 8)?;
+            }
+        }
+        self.update(byte);
+        Ok(())
+    }
+
+    // Read the next byte from reader, then update the tree.
+    // Returns none if the reader runs dry partway through a code.
+    pub fn decode<R: Read>(&mut self, reader: &mut BitReader<R>) -> Option<u8> {
+        let mut current = ROOT;
+        while let Some((left, right)) = self.children[current] {
+            current = match reader.read_bit()? {
+                0 => { left }
+                _ => { right }
+            };
+        }
+
+        let byte = match self.bytes[current] {
+            Some(byte) => { byte }
+            None => {
+                let mut byte = 0;
+                for _ in 0..8 {
+                    byte = (byte << 1) | reader.read_bit()?;
+                }
+                byte
+            }
+        };
+        self.update(byte);
+        Some(byte)
+    }
+
+    // Write the path from the root to node.
+    fn write_path<W: Write>(&self, node: usize, writer: &mut BitWriter<W>) -> io::Result<()> {
+        let mut path = vec![];
+        let mut current = node;
+        while let Some(parent) = self.parents[current] {
+            let (_, right) = self.children[parent].unwrap();
+            path.push((current == right) as u64);
+            current = parent;
+        }
+        path.iter().rev().try_for_each(|bit| writer.write_bits(*bit, 1))
+    }
+
+    // Record one more occurrence of byte, keeping the tree a valid Huffman tree.
+    fn update(&mut self, byte: u8) {
+        let mut current = match self.leaves[byte as usize] {
+            Some(leaf) => { leaf }
+            // Split the NYT leaf into a new NYT leaf and a leaf for this byte.
+            None => {
+                let parent = self.nyt;
+                let (nyt, leaf) = (parent - 2, parent - 1);
+                self.children[parent] = Some((nyt, leaf));
+                self.parents[nyt] = Some(parent);
+                self.parents[leaf] = Some(parent);
+                self.bytes[leaf] = Some(byte);
+                self.leaves[byte as usize] = Some(leaf);
+                self.nyt = nyt;
+                leaf
+            }
+        };
+
+        loop {
+            // Before incrementing, move to the highest numbered node of the same weight.
+            // That way, the weights stay in order afterwards.
+            let leader = self.block_leader(current);
+            if leader != current && Some(leader) != self.parents[current] {
+                self.swap(current, leader);
+                current = leader;
+            }
+
+            self.weights[current] += 1;
+            match self.parents[current] {
+                Some(parent) => { current = parent }
+                None => { break }
+            }
+        }
+    }
+
+    // Find the highest numbered node with the same weight as node.
+    fn block_leader(&self, node: usize) -> usize {
+        let mut leader = node;
+        while leader < ROOT && self.weights[leader + 1] == self.weights[node] {
+            leader += 1;
+        }
+        leader
+    }
+
+    // Swap the subtrees numbered first and second.
+    // Each number keeps its parent -- everything else moves.
+    fn swap(&mut self, first: usize, second: usize) {
+        self.weights.swap(first, second);
+        self.children.swap(first, second);
+        self.bytes.swap(first, second);
+
+        for node in [first, second] {
+            if let Some((left, right)) = self.children[node] {
+                self.parents[left] = Some(node);
+                self.parents[right] = Some(node);
+            }
+            if let Some(byte) = self.bytes[node] {
+                self.leaves[byte as usize] = Some(node);
+            }
+        }
+    }
+}
+
+impl Default for AdaptiveTree {
+    fn default() -> Self {
+        AdaptiveTree::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::bitreader::BitReader;
+    use crate::encoding::bitsequence::BitSequence;
+    use crate::encoding::bitwriter::BitWriter;
+    use crate::file::bytestream::ByteStream;
+    use crate::tree::adaptive::AdaptiveTree;
+
+    fn round_trip(bytes: &[u8]) -> BitSequence {
+        let mut encoder = AdaptiveTree::new();
+        let mut writer = BitWriter::new(vec![]);
+        for byte in bytes {
+            encoder.encode(*byte, &mut writer).unwrap();
+        }
+        let seq = BitSequence::from_whole_stream(&writer.into_packed_stream()).unwrap();
+
+        let mut decoder = AdaptiveTree::new();
+        let mut reader = BitReader::from_seq(&seq);
+        let mut decoded = vec![];
+        while let Some(byte) = decoder.decode(&mut reader) {
+            decoded.push(byte);
+        }
+        assert_eq!(bytes, decoded);
+        seq
+    }
+
+    #[test]
+    fn test_single_byte() {
+        // The first byte is always sent as-is.
+        assert_eq!(8, round_trip(b"a").len());
+    }
+
+    #[test]
+    fn test_text() {
+        let text = b"abracadabra, said the adaptive huffman tree, abracadabra!";
+        let seq = round_trip(text);
+        assert!(seq.len() < text.len() as u64 * 8);
+    }
+
+    #[test]
+    fn test_every_byte() {
+        let bytes: Vec<u8> = (0..=255).chain((0..=255).rev()).chain(0..=255).collect();
+        round_trip(&bytes);
+    }
+
+    #[test]
+    fn test_skewed() {
+        let mut bytes = vec![0u8; 5000];
+        bytes.extend((0..200).map(|i| (i % 17) as u8));
+        let seq = round_trip(&bytes);
+        // Almost every byte is a zero, which should quickly cost only a bit or two.
+        assert!(seq.len() < bytes.len() as u64 * 2);
+    }
+}
@@ -1,33 +1,78 @@
 use std::cmp::{min, Ordering};
 use std::collections::{BinaryHeap, HashMap};
 use std::fmt::{Display, Formatter};
-use crate::encoding::bitsequence::BitSequence;
-use crate::ordering::bytefreq::ByteFreq;
+use crate::encoding::bitsequence::{Bit, BitSequence};
+use crate::encoding::code::{Code, CodeTable};
+use crate::encoding::decodingtable::DecodingTable;
+use crate::ordering::symbolfreq::{Symbol, SymbolFreq};
 use crate::tree::node::Node::{Internal, Leaf};
 
 // Author: Will Morris
 // A node represents either an internal node, with a left and right child,
-// Or a leaf node, with a byte:contents frequency.
-// To get the value of a node, descend left and right.
-#[derive(Hash, Eq, PartialEq)]
+// Or a leaf node, with a symbol:contents frequency.
+// Children are not owned by their parent -- they are indices into the tree's arena.
+#[derive(Hash, Eq, PartialEq, Debug)]
 pub enum Node {
-    Internal { left: Box<Node>, right: Box<Node> },
-    Leaf { contents: ByteFreq },
+    Internal { left: usize, right: usize },
+    Leaf { contents: SymbolFreq },
 }
 
-// ****** NODE CONSTRUCTORS ****** //
+// A Huffman tree.
+// All nodes live in a single flat vector, and refer to each other by index.
+// This keeps the whole tree in one allocation, and lets us walk it without recursion.
+#[derive(Debug)]
+pub struct Tree {
+    nodes: Vec<Node>,
+    root: usize,
+}
+
+// While building the tree, the heap orders node indices by their weight.
+// Caching the weight here means we never have to walk a subtree to compare it.
+#[derive(Eq, PartialEq)]
+struct Weight {
+    freq: u64,
+    // TIEBREAKER
+    // What if two nodes have the same frequency?
+    // Whichever node contains the minimum symbol wins out!
+    min_symbol: Symbol,
+    index: usize,
+}
+
+// ****** TREE CONSTRUCTORS ****** //
+
+// HUFFMAN TREE GENERATORS ARE THE ONLY PUBLIC CONSTRUCTORS
+// Build a tree over bytes.
+pub fn huffman(ordering: &HashMap<u8, u64>) -> Option<Tree> {
+    huffman_from_iter(ordering.iter().map(|(byte, count)| (*byte, *count)))
+}
+
+// Build a tree over bytes, from any source of counts: an array histogram, a stream of counts, and so on.
+// A byte given more than once has its counts added together. Bytes given a count of zero still get a code.
+pub fn huffman_from_iter(ordering: impl IntoIterator<Item = (u8, u64)>) -> Option<Tree> {
+    let mut counts: [Option<u64>; 256] = [None; 256];
+    for (byte, count) in ordering {
+        let total = counts[byte as usize].get_or_insert(0);
+        *total = total.saturating_add(count);
+    }
+    let symbols = counts.iter().enumerate()
+        .filter_map(|(byte, count)| count.map(|count| (byte as Symbol, count)));
+    build(symbols)
+}
+
+// Build a tree over any alphabet of symbols.
+pub fn huffman_symbols(ordering: &HashMap<Symbol, u64>) -> Option<Tree> {
+    build(ordering.iter().map(|(symbol, count)| (*symbol, *count)))
+}
 
-// HUFFMAN TREE GENERATOR IS ONLY PUBLIC CONSTRUCTOR
-pub fn huffman(ordering: &HashMap<u8, u64>) -> Option<Node> {
+// Build a tree from symbols which are each given once.
+fn build(ordering: impl Iterator<Item = (Symbol, u64)>) -> Option<Tree> {
     // Prepare base heap with all elements sorted by frequency.
     // These are all the leaf nodes.
-
-    // Note that the binary heap is a MAX HEAP, not a min heap.
-    // This means that the first items to be removed will be those with the highest precedence.
-    // Need to reverse.
-    let mut heap = ordering.iter().fold(
-        BinaryHeap::new(), | mut heap, (byte, count) | {
-            heap.push(leaf(ByteFreq::new(*byte, *count)));
+    let mut nodes = Vec::with_capacity(ordering.size_hint().0 * 2);
+    let mut heap = ordering.fold(
+        BinaryHeap::new(), | mut heap, (symbol, count) | {
+            heap.push(Weight { freq: count, min_symbol: symbol, index: nodes.len() });
+            nodes.push(Leaf { contents: SymbolFreq::new(symbol, count) });
             heap
         });
 
@@ -36,146 +81,251 @@ pub fn huffman(ordering: &HashMap<u8, u64>) -> Option<Node> {
         let left = heap.pop().unwrap();
         let right = heap.pop().unwrap();
 
-        let parent = internal(Box::from(left), Box::from(right));
-        heap.push(parent);
+        heap.push(Weight {
+            // Frequencies read from a corrupt block may be anything.
+            freq: left.freq.saturating_add(right.freq),
+            min_symbol: min(left.min_symbol, right.min_symbol),
+            index: nodes.len(),
+        });
+        nodes.push(Internal { left: left.index, right: right.index });
     }
 
     // The last element in the heap is the root node!
     // Note: if no frequencies supplied, this will be none.
-    heap.pop()
+    heap.pop().map(|root| Tree { nodes, root: root.index })
 }
 
-// PRIVATE CONSTRUCTORS USED DURING CREATION OF HUFFMAN TREE
-fn leaf(contents: ByteFreq) -> Node { Leaf { contents } }
-
-// Note that internal nodes do consume their children.
-fn internal(left: Box<Node>, right: Box<Node>) -> Node { Internal { left, right } }
-
 
 // PUBLIC INSTANCE METHODS
-impl Node {
-    // Public interface to generate the BitSequence for the encoding of each byte.
-    pub fn gen_encoding(&self) -> HashMap<u8, BitSequence> {
-        let mut encoding: HashMap<u8, BitSequence> = HashMap::new();
-        // When a leaf is encountered, mark the value to the path traversed.
-        let mut visit_fn = | node: &Node, path: &BitSequence | {
-            if let Leaf { contents } = node {
-                encoding.insert(contents.byte(), path.clone());
+impl Tree {
+    // Public interface to generate the code for each byte.
+    // Bytes which never appeared in the tree have no code.
+    // Counts read from a block or dictionary may be anything, and may build a tree too deep for a Code to hold,
+    // in which case there's no table at all. Decoding walks the tree itself, so it has no such limit.
+    // NOTE: the tree must have been built over bytes!
+    pub fn gen_encoding(&self) -> Option<CodeTable> {
+        let mut table = CodeTable::new();
+        for (symbol, path) in self.gen_symbol_encoding().into_iter().enumerate() {
+            if let Some(path) = path {
+                table.insert(symbol as u8, Code::from_seq(&path)?);
             }
+        }
+        Some(table)
+    }
+
+    // Generate the BitSequence for the encoding of each symbol, indexed by symbol.
+    pub fn gen_symbol_encoding(&self) -> Vec<Option<BitSequence>> {
+        let mut encoding: Vec<Option<BitSequence>> = vec![];
+        let mut insert = | symbol: Symbol, path: BitSequence | {
+            let symbol = symbol as usize;
+            if symbol >= encoding.len() {
+                encoding.resize(symbol + 1, None);
+            }
+            encoding[symbol] = Some(path);
         };
 
-        match self {
-            Internal { .. } => { self.visit_node(BitSequence::new(), &mut visit_fn) }
+        match &self.nodes[self.root] {
+            // When a leaf is encountered, mark the value to the path traversed.
+            Internal { .. } => {
+                self.visit_nodes(&mut | node: &Node, path: &BitSequence | {
+                    if let Leaf { contents } = node {
+                        insert(contents.symbol(), path.clone());
+                    }
+                })
+            }
             // Edge case: only one node and a path hasn't been formed yet!
             // In this case, encode as 0.
-            Leaf { contents } => {
-                encoding.insert(contents.byte(), BitSequence::from_bits(&[0]));
-            }
+            Leaf { contents } => { insert(contents.symbol(), BitSequence::from_bits(&[0])) }
         }
         encoding
     }
 
-    // Public interface to generate the BitSequence for the decoding of each byte.
-    pub fn gen_decoding(&self) -> HashMap<BitSequence, u8> {
-        let mut decoding: HashMap<BitSequence, u8> = HashMap::new();
+    // Public interface to generate the table for decoding each symbol.
+    pub fn gen_decoding(&self) -> DecodingTable {
+        let mut decoding = DecodingTable::new();
         // When a leaf node is encountered, mark the path traversed to its value.
         let mut visit_fn = | node: &Node, path: &BitSequence | {
             if let Leaf { contents } = node {
-                decoding.insert(path.clone(), contents.byte());
+                decoding.insert(path, contents.symbol());
             }
         };
 
-        match self {
-            Internal { .. } => { self.visit_node(BitSequence::new(), &mut visit_fn) }
+        match &self.nodes[self.root] {
+            Internal { .. } => { self.visit_nodes(&mut visit_fn) }
             Leaf { contents } => {
-                decoding.insert(BitSequence::from_bits(&[0]), contents.byte());
+                decoding.insert(&BitSequence::from_bits(&[0]), contents.symbol());
             }
         }
         decoding
     }
 
-    // Generate paths to all leaf nodes.
-    // The visit fns may then do what they will with these paths.
-    // This is particularly useful when:
-    // 1. You want to traverse with some sort of shared state (i.e. a decoding map)
-    // 2. The paths you took to get to nodes are important.
-    fn visit_node(&self, path: BitSequence, visit_fn: &mut impl FnMut(&Node, &BitSequence)) {
-        match self {
-            // If it is an internal node, descend left and right, making this with 0 and 1.
-            Internal { left, right } => {
-                let mut left_path = path.clone();
-                left_path.append_bit(0);
-                let mut right_path = path.clone();
-                right_path.append_bit(1);
-
-                left.visit_node(left_path, visit_fn);
-                right.visit_node(right_path, visit_fn);
-            }
-            // If we've hit a leaf node, add the encoding to the bad boy!
-            Leaf { .. } => { visit_fn(self, &path); }
+    // ****** STATISTICS ****** //
+    // Depths are code lengths, so a lone leaf counts as one deep, just as it's coded with one bit.
+
+    // How long the longest code is.
+    pub fn max_depth(&self) -> u64 {
+        self.leaf_depths().iter().map(|(_, _, depth)| *depth).max().unwrap_or(0)
+    }
+
+    // How many symbols have codes.
+    pub fn leaf_count(&self) -> usize {
+        self.nodes.iter().filter(|node| matches!(node, Leaf { .. })).count()
+    }
+
+    // The sum of each leaf's frequency times its depth.
+    // This is how many bits it takes to code what the tree was built from, which Huffman trees keep as small as possible.
+    pub fn weighted_path_length(&self) -> u128 {
+        self.leaf_depths().iter().map(|(_, freq, depth)| *freq as u128 * *depth as u128).sum()
+    }
+
+    // How many bits each byte of something with these frequencies would take, on average.
+    // None if there's nothing to code, or if some byte which appears has no code.
+    pub fn expected_bits(&self, freqs: &HashMap<u8, u64>) -> Option<f64> {
+        let depths: HashMap<Symbol, u64> = self.leaf_depths().iter()
+            .map(|(symbol, _, depth)| (*symbol, *depth))
+            .collect();
+
+        let mut bits = 0u128;
+        let mut total = 0u128;
+        for (byte, count) in freqs.iter().filter(|(_, count)| **count > 0) {
+            bits += *depths.get(&(*byte as Symbol))? as u128 * *count as u128;
+            total += *count as u128;
+        }
+        match total {
+            0 => { None }
+            _ => { Some(bits as f64 / total as f64) }
         }
     }
-}
 
+    // How many bytes of memory the tree takes, nodes and all.
+    pub fn memory_size(&self) -> usize {
+        size_of::<Self>() + self.nodes.capacity() * size_of::<Node>()
+    }
+
+    // Every leaf's symbol and frequency, with how deep it is.
+    fn leaf_depths(&self) -> Vec<(Symbol, u64, u64)> {
+        let mut depths = vec![];
+        self.visit_nodes(&mut | node: &Node, path: &BitSequence | {
+            if let Leaf { contents } = node {
+                depths.push((contents.symbol(), contents.freq(), path.len().max(1)));
+            }
+        });
+        depths
+    }
 
-// NODE ATTR ACCESSORS
-// useful for comparison
-impl Node {
-    // These simple visitors are easier to write without using the visitor closure.
-    fn freq(&self) -> u64 {
-        match self {
-            Internal {  left, right, .. } => {
-                left.freq() + right.freq()
+    // Render the tree in Graphviz's DOT language, e.g. for dot -Tsvg.
+    // Nodes are named by their index, and edges are labelled with their bit.
+    pub fn to_dot(&self) -> String {
+        let weights = self.weights();
+        let mut retval = String::from("digraph tree {\n");
+        for (index, _, bit, parent) in self.preorder() {
+            if let (Some(bit), Some(parent)) = (bit, parent) {
+                retval.push_str(&format!("  n{} -> n{} [label=\"{}\"];\n", parent, index, bit));
             }
-            Leaf { contents  } => {
-                contents.freq()
+            match &self.nodes[index] {
+                Internal { .. } => { retval.push_str(&format!("  n{} [label=\"{}\"];\n", index, weights[index])) }
+                Leaf { contents } => {
+                    retval.push_str(&format!("  n{} [shape=box, label=\"{}: {}\"];\n", index, contents.symbol(), contents.freq()))
+                }
             }
         }
+        retval.push_str("}\n");
+        retval
     }
 
-    // TIEBREAKER
-    // What if two nodes have the same frequency?
-    // Whichever node contains the minimum byte wins out!
-    // For breaking ties in a node, we need the minimum byte.
-    fn min_byte(&self) -> u8 {
-        match self {
-            Internal { left, right } => {
-                min(left.min_byte(), right.min_byte())
+    // The weight of every node, by index.
+    // Children are always built before their parents, so one pass in order covers them all.
+    fn weights(&self) -> Vec<u64> {
+        let mut weights: Vec<u64> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let weight = match node {
+                Internal { left, right } => { weights[*left] + weights[*right] }
+                Leaf { contents } => { contents.freq() }
+            };
+            weights.push(weight);
+        }
+        weights
+    }
+
+    // Every node, parents before children and left before right.
+    // Each comes with its depth, the bit leading to it, and its parent's index. The root has neither.
+    fn preorder(&self) -> Vec<(usize, usize, Option<Bit>, Option<usize>)> {
+        let mut retval = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![(self.root, 0, None, None)];
+        while let Some((index, depth, bit, parent)) = stack.pop() {
+            retval.push((index, depth, bit, parent));
+            if let Internal { left, right } = &self.nodes[index] {
+                stack.push((*right, depth + 1, Some(1), Some(index)));
+                stack.push((*left, depth + 1, Some(0), Some(index)));
             }
-            Leaf { contents } => {
-                contents.byte()
+        }
+        retval
+    }
+
+    // Generate paths to all leaf nodes.
+    // The visit fns may then do what they will with these paths.
+    // This is particularly useful when:
+    // 1. You want to traverse with some sort of shared state (i.e. a decoding map)
+    // 2. The paths you took to get to nodes are important.
+    // Walks with an explicit stack, so even a pathologically deep tree can't blow the call stack.
+    fn visit_nodes(&self, visit_fn: &mut impl FnMut(&Node, &BitSequence)) {
+        // One path is shared by the whole walk.
+        // Each node remembers how deep its parent was, and cuts the path back to there before adding its own bit.
+        let mut path = BitSequence::new();
+        let mut stack = vec![(self.root, 0, None)];
+
+        while let Some((index, depth, bit)) = stack.pop() {
+            path.truncate(depth);
+            if let Some(bit) = bit {
+                path.append_bit(bit);
+            }
+
+            let node = &self.nodes[index];
+            match node {
+                // If it is an internal node, descend left and right, making this with 0 and 1.
+                // Right is pushed first so that left is visited first.
+                Internal { left, right } => {
+                    stack.push((*right, path.len(), Some(1)));
+                    stack.push((*left, path.len(), Some(0)));
+                }
+                // If we've hit a leaf node, add the encoding to the bad boy!
+                Leaf { .. } => { visit_fn(node, &path); }
             }
         }
     }
 }
 
-impl Display for Node {
+// One line per node, indented by depth, with the bit leading to it.
+// Internal nodes show their weight in parentheses. Leaves show their symbol and frequency.
+impl Display for Tree {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let mut visit_fn = | node: &Node, _path: &BitSequence | {
-            if let Leaf { contents } = node {
-                f.write_fmt(format_args!
-                    ("{}: {}", contents.byte(), contents.freq())).unwrap();
+        let weights = self.weights();
+        for (index, depth, bit, _) in self.preorder() {
+            write!(f, "{:width$}", "", width = depth * 2)?;
+            if let Some(bit) = bit {
+                write!(f, "{} ", bit)?;
             }
-        };
-
-        self.visit_node(BitSequence::new(), &mut visit_fn);
+            match &self.nodes[index] {
+                Internal { .. } => { writeln!(f, "({})", weights[index])? }
+                Leaf { contents } => { writeln!(f, "{}: {}", contents.symbol(), contents.freq())? }
+            }
+        }
         Ok(())
     }
 }
 
-
 // ****** ORD IMPLEMENTATIONS ****** //
 
-impl Ord for Node {
+impl Ord for Weight {
     // NOTE: nodes are done with a MIN HEAP!
     fn cmp(&self, other: &Self) -> Ordering {
-        other.freq().cmp(&self.freq())
-            .then_with(|| other.min_byte().cmp(&self.min_byte()))
+        other.freq.cmp(&self.freq)
+            .then_with(|| other.min_symbol.cmp(&self.min_symbol))
     }
 }
 
 // PartialOrd must be implemented or weird things will happen!
-impl PartialOrd for Node {
+impl PartialOrd for Weight {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
@@ -188,8 +338,9 @@ impl PartialOrd for Node {
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
-    use crate::encoding::bitsequence::BitSequence;
-    use crate::tree::node::{huffman};
+    use crate::encoding::bitreader::BitReader;
+    use crate::encoding::code::{Code, CodeTable};
+    use crate::tree::node::{huffman, huffman_from_iter, huffman_symbols};
 
     // Test that the tree generates an encoding for a single charACTER.
     #[test]
@@ -203,9 +354,9 @@ mod tests {
            1: 0
          */
 
-        let mut expected_encoding: HashMap<u8, BitSequence> = HashMap::new();
-        expected_encoding.insert(byte, BitSequence::from_bits(&[0]));
-        let actual_encoding: HashMap<u8, BitSequence> = huffman(&freq).unwrap().gen_encoding();
+        let mut expected_encoding = CodeTable::new();
+        expected_encoding.insert(byte, Code::new(0, 1));
+        let actual_encoding = huffman(&freq).unwrap().gen_encoding().unwrap();
 
         assert_eq!(expected_encoding, actual_encoding);
     }
@@ -226,17 +377,136 @@ mod tests {
         freq.insert(5, 2);
         freq.insert(8, 1);
         freq.insert(9, 1);
-        let encoding = huffman(&freq).unwrap().gen_encoding();
-
-        assert_eq!(2, encoding.get(&1).unwrap().length());
-        assert_eq!(3, encoding.get(&0).unwrap().length());
-        assert_eq!(3, encoding.get(&2).unwrap().length());
-        assert_eq!(2, encoding.get(&3).unwrap().length());
-        assert_eq!(4, encoding.get(&5).unwrap().length());
-        assert_eq!(5, encoding.get(&4).unwrap().length());
-        assert_eq!(5, encoding.get(&8).unwrap().length());
-        assert_eq!(4, encoding.get(&9).unwrap().length());
-        assert_eq!(5, encoding.get(&7).unwrap().length());
-        assert_eq!(5, encoding.get(&6).unwrap().length());
-    }
-}
\ No newline at end of file
+        let encoding = huffman(&freq).unwrap().gen_encoding().unwrap();
+
+        assert_eq!(2, encoding.get(1).unwrap().len());
+        assert_eq!(3, encoding.get(0).unwrap().len());
+        assert_eq!(3, encoding.get(2).unwrap().len());
+        assert_eq!(2, encoding.get(3).unwrap().len());
+        assert_eq!(4, encoding.get(5).unwrap().len());
+        assert_eq!(5, encoding.get(4).unwrap().len());
+        assert_eq!(5, encoding.get(8).unwrap().len());
+        assert_eq!(4, encoding.get(9).unwrap().len());
+        assert_eq!(5, encoding.get(7).unwrap().len());
+        assert_eq!(5, encoding.get(6).unwrap().len());
+    }
+
+    // A lopsided frequency table builds a deep, skinny tree.
+    // Every byte must still get its own unique path.
+    #[test]
+    fn test_deep_tree() {
+        let mut freq: HashMap<u8, u64> = HashMap::new();
+        for i in 0..40 {
+            freq.insert(i, 1 << i);
+        }
+        let tree = huffman(&freq).unwrap();
+        let encoding = tree.gen_encoding().unwrap();
+        let decoding = tree.gen_decoding();
+
+        assert_eq!(39, encoding.get(0).unwrap().len());
+        assert_eq!(1, encoding.get(39).unwrap().len());
+        for (byte, code) in encoding.iter() {
+            let path = code.to_seq();
+            let mut reader = BitReader::from_seq(&path);
+            assert_eq!(Some(byte), decoding.decode_next(&mut reader));
+        }
+    }
+
+    // Counts which saturate build a chain as long as there are bytes, too deep for any Code.
+    // It can't be coded with, but it can still be decoded.
+    #[test]
+    fn test_too_deep() {
+        let freq: HashMap<u8, u64> = (0..=255).map(|byte| (byte, u64::MAX)).collect();
+        let tree = huffman(&freq).unwrap();
+        assert!(tree.max_depth() > Code::MAX_LEN as u64);
+        assert_eq!(None, tree.gen_encoding());
+
+        let decoding = tree.gen_decoding();
+        for (byte, path) in tree.gen_symbol_encoding().iter().enumerate() {
+            let path = path.as_ref().unwrap();
+            assert_eq!(Some(byte as u8), decoding.decode_next(&mut BitReader::from_seq(path)));
+        }
+    }
+
+    // Counts from an iterator build the same tree as the same counts in a map.
+    #[test]
+    fn test_from_iter() {
+        let freq: HashMap<u8, u64> = HashMap::from([(1, 11), (0, 4), (2, 5), (3, 6), (4, 1), (5, 2), (9, 0)]);
+        let expected = huffman(&freq).unwrap().gen_encoding().unwrap();
+
+        let mut histogram = [0u64; 256];
+        freq.iter().for_each(|(byte, count)| histogram[*byte as usize] = *count);
+        let present = histogram.iter().enumerate()
+            .map(|(byte, count)| (byte as u8, *count))
+            .filter(|(byte, _)| freq.contains_key(byte));
+        assert_eq!(expected, huffman_from_iter(present).unwrap().gen_encoding().unwrap());
+
+        // Repeated bytes add up.
+        let repeated = [(1, 5), (0, 4), (2, 5), (3, 6), (4, 1), (5, 2), (9, 0), (1, 6)];
+        assert_eq!(expected, huffman_from_iter(repeated).unwrap().gen_encoding().unwrap());
+        assert!(huffman_from_iter([]).is_none());
+    }
+
+    #[test]
+    fn test_statistics() {
+        // The same frequencies as test_encoding.
+        let freq: HashMap<u8, u64> = HashMap::from([(1, 11), (0, 4), (2, 5), (3, 6), (4, 1), (6, 1), (7, 1), (5, 2), (8, 1), (9, 1)]);
+        let tree = huffman(&freq).unwrap();
+        assert_eq!(5, tree.max_depth());
+        assert_eq!(10, tree.leaf_count());
+        assert_eq!(93, tree.weighted_path_length());
+        assert_eq!(Some(93.0 / 33.0), tree.expected_bits(&freq));
+
+        // Bytes which never appear don't count, but bytes without codes can't be coded at all.
+        assert_eq!(Some(2.0), tree.expected_bits(&HashMap::from([(1, 3), (3, 1), (10, 0)])));
+        assert_eq!(None, tree.expected_bits(&HashMap::from([(10, 1)])));
+        assert_eq!(None, tree.expected_bits(&HashMap::new()));
+
+        let single = huffman(&HashMap::from([(7, 5)])).unwrap();
+        assert_eq!(1, single.max_depth());
+        assert_eq!(1, single.leaf_count());
+        assert_eq!(5, single.weighted_path_length());
+    }
+
+    #[test]
+    fn test_display() {
+        let tree = huffman(&HashMap::from([(0, 1), (1, 1), (2, 2)])).unwrap();
+        assert_eq!("(4)\n  0 (2)\n    0 0: 1\n    1 1: 1\n  1 2: 2\n", tree.to_string());
+        assert_eq!("7: 5\n", huffman(&HashMap::from([(7, 5)])).unwrap().to_string());
+    }
+
+    #[test]
+    fn test_dot() {
+        let tree = huffman(&HashMap::from([(0, 1), (1, 1), (2, 2)])).unwrap();
+        let expected = "digraph tree {
+  n4 [label=\"4\"];
+  n4 -> n3 [label=\"0\"];
+  n3 [label=\"2\"];
+  n3 -> n0 [label=\"0\"];
+  n0 [shape=box, label=\"0: 1\"];
+  n3 -> n1 [label=\"1\"];
+  n1 [shape=box, label=\"1: 1\"];
+  n4 -> n2 [label=\"1\"];
+  n2 [shape=box, label=\"2: 2\"];
+}
+";
+        assert_eq!(expected, tree.to_dot());
+    }
+
+    // Symbols beyond the byte range get codes too.
+    #[test]
+    fn test_symbols() {
+        let mut freq: HashMap<u32, u64> = HashMap::new();
+        freq.insert(1000, 10);
+        freq.insert(70000, 5);
+        freq.insert(3, 5);
+        let tree = huffman_symbols(&freq).unwrap();
+        let encoding = tree.gen_symbol_encoding();
+        let decoding = tree.gen_decoding();
+
+        assert_eq!(70001, encoding.len());
+        assert_eq!(1, encoding[1000].as_ref().unwrap().len());
+        let mut reader = BitReader::from_seq(encoding[70000].as_ref().unwrap());
+        assert_eq!(Some(70000), decoding.decode_symbol(&mut reader));
+    }
+}
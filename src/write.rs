@@ -0,0 +1,227 @@
+// Compression through Write, laid out as flate2::write is, so WzEncoder can stand in for GzEncoder.
+// Author: Will Morris
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use crate::compression::Compression;
+use crate::encoding::fixed::{encode_counted_into, encode_into, HEADER_LEN, MAX_LEN};
+use crate::file::error::WzError;
+use crate::file::stream::{CANONICAL, frame, index, RAW};
+
+/// Compresses everything written to it into a wz stream, written on to inner.
+/// Input is buffered a block at a time, and each block canonically Huffman coded, or stored if that's smaller.
+/// The stream is only whole once finished: finish, try_finish, or dropping the encoder, which ignores any error.
+/// `wz -x` extracts what it writes, and read::WzDecoder reads it back.
+pub struct WzEncoder<W: Write> {
+    // Only taken by finish.
+    inner: Option<W>,
+    level: Compression,
+    // Input short of a whole block, waiting for more.
+    pending: Vec<u8>,
+    // Uncompressed length of each block written, for the index.
+    block_lens: Vec<u64>,
+    finished: bool,
+}
+
+impl<W: Write> WzEncoder<W> {
+    pub fn new(inner: W, level: Compression) -> Self {
+        WzEncoder { inner: Some(inner), level, pending: vec![], block_lens: vec![], finished: false }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Writing to inner directly would corrupt the stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Write whatever is buffered, then the index that ends the stream. Nothing more may be written after.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(())
+        }
+        self.write_block()?;
+        let framed = frame(index(&self.block_lens));
+        self.get_mut().write_all(&framed)?;
+        self.finished = true;
+        self.get_mut().flush()
+    }
+
+    /// Finish the stream, handing back inner.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    // Code whatever is pending as one block, and write it.
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(())
+        }
+        let block = encode_block(&self.pending, None, self.level);
+        self.block_lens.push(self.pending.len() as u64);
+        self.pending.clear();
+        self.get_mut().write_all(&frame(block))
+    }
+}
+
+impl<W: Write> Write for WzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("The stream has already been finished!"))
+        }
+        let take = buf.len().min(self.level.block_size() - self.pending.len());
+        self.pending.extend_from_slice(&buf[..take]);
+        if self.pending.len() == self.level.block_size() {
+            self.write_block()?;
+        }
+        Ok(take)
+    }
+
+    /// Write what's buffered as a block of its own, so a reader has everything written so far.
+    /// Each flush ends a block, and short blocks compress less well, so flush only when a reader is waiting.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.write_block()?;
+        }
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for WzEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/// Compress bytes into a whole wz stream, with codes built from frequencies counted beforehand, rather than from bytes.
+/// The counting can then be done elsewhere, or once for many inputs alike. Every byte in bytes needs a frequency.
+/// The stream is a single block, coded or stored as WzEncoder would, so `wz -x` and read::WzDecoder both read it.
+pub fn compress_with_frequencies(bytes: &[u8], frequencies: &HashMap<u8, u64>) -> Result<Vec<u8>, WzError> {
+    let mut counts = [0; 256];
+    frequencies.iter().for_each(|(byte, frequency)| counts[*byte as usize] = *frequency);
+    if let Some(byte) = bytes.iter().find(|byte| counts[**byte as usize] == 0) {
+        return Err(WzError::Malformed(format!("Byte {} has no frequency!", byte)))
+    }
+
+    // Like WzEncoder's, an empty stream is just its index.
+    let mut retval = vec![];
+    let mut block_lens = vec![];
+    if !bytes.is_empty() {
+        retval.append(&mut frame(encode_block(bytes, Some(&counts), Compression::best())));
+        block_lens.push(bytes.len() as u64);
+    }
+    retval.append(&mut frame(index(&block_lens)));
+    Ok(retval)
+}
+
+// A block for bytes, Huffman coded unless that would be no smaller than storing them.
+// Codes are built from counts when they're given, or else from bytes themselves.
+fn encode_block(bytes: &[u8], counts: Option<&[u64; 256]>, level: Compression) -> Vec<u8> {
+    let mut retval = vec![CANONICAL];
+    if level != Compression::None {
+        // No code is longer than MAX_LEN bits.
+        retval.resize(1 + HEADER_LEN + (bytes.len() * MAX_LEN).div_ceil(8), 0);
+        let coded = match counts {
+            Some(counts) => { encode_counted_into(bytes, counts, &mut retval[1..]) }
+            None => { encode_into(bytes, &mut retval[1..]) }
+        };
+        if let Ok(len) = coded {
+            retval.truncate(1 + len);
+            if retval.len() < 1 + bytes.len() {
+                return retval
+            }
+        }
+    }
+    retval.clear();
+    retval.push(RAW);
+    retval.extend_from_slice(bytes);
+    retval
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use crate::compression::Compression;
+    use crate::file::stream::{CANONICAL, RAW, read_frame};
+    use crate::read::WzDecoder;
+    use crate::write::{compress_with_frequencies, WzEncoder};
+
+    fn text() -> Vec<u8> {
+        b"the quick brown fox jumps over the lazy dog, again and again. ".repeat(5000)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = text();
+        for level in [Compression::none(), Compression::fast(), Compression::default(), Compression::best()] {
+            let mut encoder = WzEncoder::new(vec![], level);
+            // Written in awkward pieces, across block boundaries.
+            text.chunks(1000).for_each(|piece| encoder.write_all(piece).unwrap());
+            let compressed = encoder.finish().unwrap();
+            match level {
+                Compression::None => { assert!(compressed.len() > text.len()) }
+                _ => { assert!(compressed.len() < text.len() * 3 / 4) }
+            }
+
+            let mut decompressed = vec![];
+            WzDecoder::new(&compressed[..]).read_to_end(&mut decompressed).unwrap();
+            assert_eq!(text, decompressed);
+        }
+    }
+
+    #[test]
+    fn test_blocks() {
+        // Flushing ends a block. Bytes Huffman coding can't shrink are stored.
+        let mut encoder = WzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&text()[..1000]).unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(&[7]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut reader = &compressed[..];
+        assert_eq!(CANONICAL, read_frame(&mut reader).unwrap().unwrap()[0]);
+        assert_eq!(vec![RAW, 7], read_frame(&mut reader).unwrap().unwrap());
+
+        // An empty stream is just its index.
+        let empty = WzEncoder::new(vec![], Compression::default()).finish().unwrap();
+        let mut decompressed = vec![];
+        WzDecoder::new(&empty[..]).read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed.is_empty());
+
+        // Dropping finishes the stream too.
+        let mut out = vec![];
+        {
+            let mut encoder = WzEncoder::new(&mut out, Compression::fast());
+            encoder.write_all(b"dropped").unwrap();
+        }
+        let mut decompressed = vec![];
+        WzDecoder::new(&out[..]).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(b"dropped".to_vec(), decompressed);
+    }
+
+    #[test]
+    fn test_compress_with_frequencies() {
+        let text = text();
+        // Counted from a sample, rather than from the text itself.
+        let frequencies = b"the quick brown fox jumps over the lazy dog, again. ".iter()
+            .fold(HashMap::new(), |mut frequencies, byte| {
+                *frequencies.entry(*byte).or_insert(0) += 1;
+                frequencies
+            });
+        let compressed = compress_with_frequencies(&text, &frequencies).unwrap();
+        assert!(compressed.len() < text.len() * 3 / 4);
+        let mut decompressed = vec![];
+        WzDecoder::new(&compressed[..]).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(text, decompressed);
+
+        // A byte with no frequency has no code.
+        assert!(compress_with_frequencies(b"fox #1", &frequencies).is_err());
+        let empty = compress_with_frequencies(b"", &frequencies).unwrap();
+        assert_eq!(0, WzDecoder::new(&empty[..]).read_to_end(&mut decompressed).unwrap());
+    }
+}
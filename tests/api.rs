@@ -0,0 +1,67 @@
+// The library's encoder and decoder against wz itself: each must read what the other writes.
+// Author: Will Morris
+
+mod common;
+
+use common::wz;
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use will_zip::file::blocks::read_range;
+use will_zip::read::WzDecoder;
+use will_zip::write::{compress_with_frequencies, WzEncoder};
+use will_zip::Compression;
+
+fn text() -> Vec<u8> {
+    let mut bytes: Vec<u8> = (0..=255).collect();
+    bytes.extend_from_slice(&b"pack my box with five dozen liquor jugs\n".repeat(40));
+    bytes.repeat(100)
+}
+
+#[test]
+fn test_wz_extracts() {
+    let text = text();
+    for level in [Compression::none(), Compression::fast(), Compression::best()] {
+        let mut encoder = WzEncoder::new(vec![], level);
+        encoder.write_all(&text).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(text, wz(&["-x", "-r", "-p"], compressed));
+    }
+}
+
+// The decoder reads blocks of every kind, just as wz -x does.
+#[test]
+fn test_decoder_reads_wz() {
+    let text = text();
+    for kind in [&[][..], &["--canonical"], &["--msb-first"], &["--lz77"], &["--filter", "rle,bwt"]] {
+        let compressed = wz(&[&["-z", "-r", "-p", "--block-size", "16K"], kind].concat(), text.clone());
+        let mut decompressed = vec![];
+        WzDecoder::new(&compressed[..]).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(text, decompressed);
+    }
+}
+
+#[test]
+fn test_read_range() {
+    let text = text();
+    for kind in [&[][..], &["--canonical"]] {
+        let compressed = wz(&[&["-z", "-r", "-p", "--block-size", "16K"], kind].concat(), text.clone());
+        let mut reader = Cursor::new(compressed);
+        assert_eq!(text[20000..50000], read_range(&mut reader, 20000, 30000).unwrap());
+        assert_eq!(text[text.len() - 10..], read_range(&mut reader, text.len() as u64 - 10, 100).unwrap());
+    }
+}
+
+// Frequencies counted once, from a sample, code every input after it without counting again.
+#[test]
+fn test_compress_with_frequencies() {
+    let text = text();
+    let mut frequencies = HashMap::new();
+    (0..=255).for_each(|byte| { frequencies.insert(byte, 1); });
+    b"pack my box with five dozen liquor jugs\n".iter().for_each(|byte| *frequencies.get_mut(byte).unwrap() += 40);
+
+    let compressed = compress_with_frequencies(&text, &frequencies).unwrap();
+    assert!(compressed.len() < text.len());
+    assert_eq!(text, wz(&["-x", "-r", "-p"], compressed));
+    frequencies.remove(&b'\n');
+    assert!(compress_with_frequencies(&text, &frequencies).is_err());
+}
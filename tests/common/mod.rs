@@ -0,0 +1,23 @@
+// Helpers shared by the tests which run wz itself.
+// Author: Will Morris
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+// Run wz with args, feeding it input, and return what it printed, so long as it succeeded.
+pub fn wz(args: &[&str], input: Vec<u8>) -> Vec<u8> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_will_zip"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Writing everything before reading anything could fill both pipes, and wait forever.
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(output.status.success(), "wz {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stdout));
+    output.stdout
+}
@@ -0,0 +1,54 @@
+// Runs wz itself, piping bytes through stdin and stdout, as scripts do.
+// Every byte must come out exactly as it went in, whatever the platform makes of line endings.
+// Author: Will Morris
+
+mod common;
+
+use common::wz;
+
+// Text mode would turn \n into \r\n on the way out, or \r\n into \n on the way in, or stop at ^Z.
+fn awkward_bytes() -> Vec<u8> {
+    let mut bytes: Vec<u8> = (0..=255).collect();
+    bytes.extend_from_slice(b"line\r\nline\nline\r\r\n\x1a after ^Z\n");
+    bytes.repeat(200)
+}
+
+#[test]
+fn test_round_trip() {
+    let bytes = awkward_bytes();
+    let compressed = wz(&["-z", "-r", "-p"], bytes.clone());
+    assert!(!compressed.is_empty());
+    assert_eq!(bytes, wz(&["-x", "-r", "-p"], compressed));
+}
+
+#[test]
+fn test_round_trip_checked() {
+    let bytes = awkward_bytes();
+    let compressed = wz(&["-z", "-r", "-p", "--checksum", "crc32", "--block-size", "4K"], bytes.clone());
+    assert_eq!(bytes, wz(&["-x", "-r", "-p"], compressed));
+}
+
+#[test]
+fn test_gzip() {
+    let bytes = awkward_bytes();
+    let compressed = wz(&["-z", "-r", "-p", "--gzip"], bytes.clone());
+    // gzip's magic number, untranslated.
+    assert_eq!([0x1f, 0x8b], compressed[..2]);
+    assert_eq!(bytes, wz(&["-x", "-r", "-p"], compressed));
+}
+
+#[test]
+fn test_empty() {
+    let compressed = wz(&["-z", "-r", "-p"], vec![]);
+    assert!(wz(&["-x", "-r", "-p"], compressed).is_empty());
+}
+
+#[test]
+fn test_deterministic() {
+    // Each run hashes differently, but the same input must still compress to the same bytes, or rsync has nothing to match.
+    // Skewed enough to be Huffman coded, rather than stored raw.
+    let bytes = b"the same input, compressed the same way ".repeat(500);
+    for args in [&["-z", "-r", "-p"][..], &["-z", "-r", "-p", "--rsyncable"]] {
+        assert_eq!(wz(args, bytes.clone()), wz(args, bytes.clone()));
+    }
+}
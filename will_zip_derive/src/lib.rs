@@ -0,0 +1,133 @@
+// #[derive(ByteStream)], for structs made of other ByteStreams.
+// Each field is written in order, prefixed by its length just as append_sized does it.
+// Structs are parsed straight from the token stream, so there's nothing to depend on.
+// Author: Will Morris
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+// Where the generated code finds the trait and its helpers.
+// will_zip names itself too, so this works inside the library as well as out.
+const BYTESTREAM: &str = "::will_zip::file::bytestream";
+
+struct Field {
+    name: String,
+    ty: String,
+}
+
+// Every field must be a ByteStream whose Data is its own type.
+// Fields are read back in the order they were declared, so reordering them changes the format.
+#[proc_macro_derive(ByteStream)]
+pub fn derive_bytestream(input: TokenStream) -> TokenStream {
+    let generated = match parse_struct(input) {
+        Ok((name, fields)) => { generate(&name, &fields) }
+        Err(message) => { format!("compile_error!({:?});", message) }
+    };
+    generated.parse().unwrap()
+}
+
+// Get the name and fields of a struct with named fields.
+fn parse_struct(input: TokenStream) -> Result<(String, Vec<Field>), String> {
+    let mut tokens = input.into_iter().peekable();
+    skip_attributes_and_visibility(&mut tokens);
+
+    match tokens.next() {
+        Some(TokenTree::Ident(ident)) if ident.to_string() == "struct" => {}
+        _ => { return Err(String::from("ByteStream can only be derived for structs!")) }
+    }
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(ident)) => { ident.to_string() }
+        _ => { return Err(String::from("Expected a struct name!")) }
+    };
+    match tokens.next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => {
+            Ok((name, parse_fields(group.stream())?))
+        }
+        Some(TokenTree::Punct(punct)) if punct.as_char() == '<' => {
+            Err(String::from("ByteStream can't be derived for generic structs!"))
+        }
+        _ => { Err(String::from("ByteStream can only be derived for structs with named fields!")) }
+    }
+}
+
+// Split the body of a struct into its fields.
+// Types may hold commas of their own, e.g. HashMap<u8, u64>, so only those outside angle brackets end a field.
+fn parse_fields(body: TokenStream) -> Result<Vec<Field>, String> {
+    let mut fields = vec![];
+    let mut tokens = body.into_iter().peekable();
+    loop {
+        skip_attributes_and_visibility(&mut tokens);
+        let name = match tokens.next() {
+            Some(TokenTree::Ident(ident)) => { ident.to_string() }
+            None => { return Ok(fields) }
+            Some(other) => { return Err(format!("Expected a field name, found {}!", other)) }
+        };
+        match tokens.next() {
+            Some(TokenTree::Punct(punct)) if punct.as_char() == ':' => {}
+            _ => { return Err(format!("Expected a type for {}!", name)) }
+        }
+
+        let mut ty = TokenStream::new();
+        let mut depth = 0;
+        for token in tokens.by_ref() {
+            if let TokenTree::Punct(punct) = &token {
+                match punct.as_char() {
+                    ',' if depth == 0 => { break }
+                    '<' => { depth += 1 }
+                    // The > of -> doesn't close anything.
+                    '>' if depth > 0 => { depth -= 1 }
+                    _ => {}
+                }
+            }
+            ty.extend([token]);
+        }
+        fields.push(Field { name, ty: ty.to_string() });
+    }
+}
+
+// Attributes (including doc comments) and pub(...) don't matter to the format.
+fn skip_attributes_and_visibility(tokens: &mut std::iter::Peekable<impl Iterator<Item = TokenTree>>) {
+    loop {
+        match tokens.peek() {
+            Some(TokenTree::Punct(punct)) if punct.as_char() == '#' => {
+                tokens.next();
+                tokens.next();
+            }
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "pub" => {
+                tokens.next();
+                if let Some(TokenTree::Group(group)) = tokens.peek() {
+                    if group.delimiter() == Delimiter::Parenthesis {
+                        tokens.next();
+                    }
+                }
+            }
+            _ => { return }
+        }
+    }
+}
+
+fn generate(name: &str, fields: &[Field]) -> String {
+    // Struct expressions evaluate their fields in the order they're written, so fields are read in order.
+    let reads: Vec<String> = fields.iter().map(|field| format!(
+        "{}: <{} as {BYTESTREAM}::ByteStream>::from_whole_stream({BYTESTREAM}::read_sized(bytes, &mut i)?)?,",
+        field.name, field.ty)).collect();
+    let writes: Vec<String> = fields.iter().map(|field| format!(
+        "{BYTESTREAM}::append_sized(&mut retval, &mut <{} as {BYTESTREAM}::ByteStream>::to_stream(self.{}));",
+        field.ty, field.name)).collect();
+
+    format!("
+        impl {BYTESTREAM}::ByteStream for {name} {{
+            type Data = {name};
+
+            fn from_stream(bytes: &[u8]) -> ::std::result::Result<(Self::Data, usize), ::will_zip::file::error::WzError> {{
+                let mut i = 0;
+                let data = {name} {{ {} }};
+                ::std::result::Result::Ok((data, i))
+            }}
+
+            fn to_stream(self) -> ::std::vec::Vec<u8> {{
+                let mut retval = ::std::vec::Vec::new();
+                {}
+                retval
+            }}
+        }}", reads.join(" "), writes.join(" "))
+}